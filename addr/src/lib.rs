@@ -0,0 +1,138 @@
+//! Which IP addresses are safe to reach out to
+//!
+//! The server fetches `did:web` documents and the MCP server probes
+//! endpoints, both at hosts an agent names. Neither may be pointed at the
+//! machine itself or its network, so every resolved address goes through
+//! [`is_private`] first.
+//!
+//! Ranges follow the IANA special-purpose address registries. Addresses that
+//! carry an IPv4 address (IPv4-mapped and -compatible, NAT64, 6to4) are
+//! judged by the address they carry.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Loopback, private, link-local and other addresses that aren't on the
+/// public internet
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_private_v4(v4),
+        IpAddr::V6(v6) => is_private_v6(v6),
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    // "This network" (0.0.0.0/8)
+    a == 0
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        // Carrier-grade NAT (100.64.0.0/10)
+        || (a == 100 && (b & 0xc0) == 64)
+        // IETF protocol assignments (192.0.0.0/24)
+        || (a == 192 && b == 0 && c == 0)
+        // Documentation (192.0.2.0/24, 198.51.100.0/24, 203.0.113.0/24)
+        || (a == 192 && b == 0 && c == 2)
+        || (a == 198 && b == 51 && c == 100)
+        || (a == 203 && b == 0 && c == 113)
+        // Benchmarking (198.18.0.0/15)
+        || (a == 198 && (b & 0xfe) == 18)
+        // Multicast (224.0.0.0/4), reserved (240.0.0.0/4) and broadcast
+        || a >= 224
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = embedded_v4(ip) {
+        return is_private_v4(v4);
+    }
+    let s = ip.segments();
+    ip.is_unspecified()
+        || ip.is_loopback()
+        // Unique local (fc00::/7)
+        || (s[0] & 0xfe00) == 0xfc00
+        // Link-local (fe80::/10) and the deprecated site-local (fec0::/10)
+        || (s[0] & 0xff80) == 0xfe80
+        // Multicast (ff00::/8)
+        || (s[0] & 0xff00) == 0xff00
+        // Discard-only (100::/64)
+        || s[..4] == [0x0100, 0, 0, 0]
+        // Teredo (2001::/32), benchmarking (2001:2::/48), documentation
+        // (2001:db8::/32)
+        || (s[0] == 0x2001 && (s[1] == 0 || (s[1] == 2 && s[2] == 0) || s[1] == 0x0db8))
+}
+
+/// The IPv4 address an IPv6 address stands for: IPv4-mapped (::ffff:0:0/96),
+/// IPv4-compatible (::/96), NAT64 (64:ff9b::/96) or 6to4 (2002::/16)
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let s = ip.segments();
+    let low = |hi: u16, lo: u16| Ipv4Addr::from(((hi as u32) << 16) | lo as u32);
+    match s {
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] => Some(low(hi, lo)),
+        // Leave :: and ::1 to the IPv6 checks
+        [0, 0, 0, 0, 0, 0, hi, lo] if hi != 0 => Some(low(hi, lo)),
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(low(hi, lo)),
+        [0x2002, hi, lo, ..] => Some(low(hi, lo)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_purpose_addresses_are_private() {
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.1.2.3",
+            "100.64.0.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "172.16.0.1",
+            "192.0.0.8",
+            "192.0.2.1",
+            "192.168.0.1",
+            "198.18.0.1",
+            "198.51.100.1",
+            "203.0.113.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "fec0::1",
+            "ff02::1",
+            "2001:db8::1",
+            "2001::1",
+            "100::1",
+            "::ffff:10.0.0.1",
+            "::ffff:127.0.0.1",
+            "::10.0.0.1",
+            "64:ff9b::a00:1",
+            "2002:c0a8:1::1",
+        ] {
+            assert!(is_private(ip.parse().unwrap()), "{} is private", ip);
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_not_private() {
+        for ip in [
+            "1.1.1.1",
+            "8.8.8.8",
+            "100.128.0.1",
+            "198.20.0.1",
+            "223.255.255.1",
+            "2001:4860:4860::8888",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(!is_private(ip.parse().unwrap()), "{} is public", ip);
+        }
+    }
+}
@@ -13,6 +13,7 @@ use serde::Deserialize;
 use std::path::PathBuf;
 
 use agent_id::RootKey;
+use agent_reach_client::{check_did, check_endpoint};
 use agent_id_handshake::{
     messages::{Hello, ProofAccepted},
     protocol::sign_proof,
@@ -113,6 +114,30 @@ fn load_identity(path: Option<PathBuf>) -> Result<RootKey> {
     Ok(root_key)
 }
 
+// ============================================================================
+// API versioning
+// ============================================================================
+
+#[derive(Deserialize)]
+struct VersionResponse {
+    api_versions: Vec<String>,
+}
+
+/// Base URL of the server's API: under `/v1` when the server lists it in
+/// `GET /version`, else the server root, as on servers that predate
+/// API versioning
+async fn api_base(client: &Client, server: &str) -> String {
+    let server = server.trim_end_matches('/');
+    let versions = match client.get(format!("{}/version", server)).send().await {
+        Ok(response) if response.status().is_success() => response.json::<VersionResponse>().await.ok(),
+        _ => None,
+    };
+    match versions {
+        Some(versions) if versions.api_versions.iter().any(|v| v == "v1") => format!("{}/v1", server),
+        _ => server.to_string(),
+    }
+}
+
 // ============================================================================
 // Commands
 // ============================================================================
@@ -124,11 +149,12 @@ async fn cmd_auth(server: String, identity: Option<PathBuf>) -> Result<()> {
 
     eprintln!("Authenticating with {}...", server);
     eprintln!("  DID: {}", did);
+    let base = api_base(&client, &server).await;
 
     // Hello
     let hello = Hello::new(did.to_string());
     let challenge: Challenge = client
-        .post(format!("{}/hello", server))
+        .post(format!("{}/hello", base))
         .json(&hello)
         .send()
         .await?
@@ -142,7 +168,7 @@ async fn cmd_auth(server: String, identity: Option<PathBuf>) -> Result<()> {
     // Proof
     let proof = sign_proof(&challenge, &did, &key, Some(challenge.issuer.clone()))?;
     let accepted: ProofAccepted = client
-        .post(format!("{}/proof", server))
+        .post(format!("{}/proof", base))
         .json(&proof)
         .send()
         .await?
@@ -167,14 +193,16 @@ struct RegisterResponse {
 }
 
 async fn cmd_register(server: String, endpoint: String, ttl: u64, session: String) -> Result<()> {
+    check_endpoint(&endpoint)?;
     let client = Client::new();
 
     eprintln!("Registering endpoint...");
     eprintln!("  Endpoint: {}", endpoint);
     eprintln!("  TTL: {}s", ttl);
 
+    let base = api_base(&client, &server).await;
     let response: RegisterResponse = client
-        .post(format!("{}/register", server))
+        .post(format!("{}/register", base))
         .header("Authorization", format!("Bearer {}", session))
         .json(&serde_json::json!({
             "endpoint": endpoint,
@@ -206,11 +234,13 @@ struct LookupResponse {
 }
 
 async fn cmd_lookup(server: String, did: String) -> Result<()> {
+    check_did(&did)?;
     let client = Client::new();
 
+    let base = api_base(&client, &server).await;
     let encoded_did = urlencoding::encode(&did);
     let response: LookupResponse = client
-        .get(format!("{}/lookup/{}", server, encoded_did))
+        .get(format!("{}/lookup/{}", base, encoded_did))
         .send()
         .await?
         .error_for_status()
@@ -239,8 +269,9 @@ async fn cmd_deregister(server: String, session: String) -> Result<()> {
 
     eprintln!("Deregistering...");
 
+    let base = api_base(&client, &server).await;
     let response: DeregisterResponse = client
-        .post(format!("{}/deregister", server))
+        .post(format!("{}/deregister", base))
         .header("Authorization", format!("Bearer {}", session))
         .send()
         .await?
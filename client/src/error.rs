@@ -0,0 +1,115 @@
+//! Client errors
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A DID or endpoint was malformed, so nothing was sent
+    #[error(transparent)]
+    InvalidInput(#[from] crate::InvalidInput),
+
+    /// The registry could not be reached
+    #[error("{context}: {source}")]
+    Network {
+        context: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// The registry's certificate doesn't carry a pinned public key
+    #[error("{context}: certificate pin mismatch; the registry's public key matches none of the pinned hashes")]
+    PinMismatch { context: String },
+
+    /// The registry answered with a body that doesn't decode
+    #[error("{context}: {message}")]
+    InvalidResponse { context: String, message: String },
+
+    /// The registry refused the request
+    #[error(transparent)]
+    Registry(#[from] RegistryError),
+
+    /// The registry refused a handshake step (`Hello` or `Proof`)
+    #[error("{step} failed: {source}")]
+    Handshake {
+        step: &'static str,
+        #[source]
+        source: RegistryError,
+    },
+
+    /// The challenge couldn't be signed
+    #[error("Failed to create proof: {0}")]
+    Proof(String),
+}
+
+impl Error {
+    pub(crate) fn network(context: &str, source: reqwest::Error) -> Self {
+        if crate::pin::is_mismatch(&source) {
+            return Self::PinMismatch { context: context.to_string() };
+        }
+        Self::Network { context: context.to_string(), source }
+    }
+
+    pub(crate) fn invalid_response(context: &str, message: impl ToString) -> Self {
+        Self::InvalidResponse { context: context.to_string(), message: message.to_string() }
+    }
+
+    /// The registry's refusal, for errors that came from one
+    pub fn registry(&self) -> Option<&RegistryError> {
+        match self {
+            Error::Registry(e) | Error::Handshake { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A non-success registry response
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{message}")]
+pub struct RegistryError {
+    pub status: StatusCode,
+    /// Stable code the registry gives some errors, e.g. `session_expired`
+    pub code: Option<String>,
+    pub message: String,
+    /// Protocols the registry speaks, with `unsupported_protocol`
+    pub supported: Vec<String>,
+    /// When the registration ran out (Unix seconds), with `expired`
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    code: Option<String>,
+    #[serde(default)]
+    supported: Vec<String>,
+    expires_at: Option<i64>,
+}
+
+impl RegistryError {
+    /// Read the error body of a non-success response
+    pub(crate) async fn from_response(resp: reqwest::Response) -> Self {
+        let status = resp.status();
+        match resp.json::<ErrorResponse>().await {
+            Ok(body) => Self {
+                status,
+                code: body.code,
+                message: body.error,
+                supported: body.supported,
+                expires_at: body.expires_at,
+            },
+            Err(_) => Self {
+                status,
+                code: None,
+                message: format!("Registry returned HTTP {}", status),
+                supported: Vec::new(),
+                expires_at: None,
+            },
+        }
+    }
+
+    /// Whether the registry gave the error this stable code
+    pub fn is(&self, code: &str) -> bool {
+        self.code.as_deref() == Some(code)
+    }
+}
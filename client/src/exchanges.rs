@@ -0,0 +1,251 @@
+//! Recent registry exchanges, kept for troubleshooting
+//!
+//! A [`ReachClient`](crate::ReachClient) given an [`ExchangeLog`] records
+//! each request it sends and the answer it got. Nothing is recorded
+//! otherwise. Records are meant to be safe to show, so:
+//!
+//! - the `Authorization` header is redacted;
+//! - session IDs and fields that look like key material are redacted from
+//!   JSON and CBOR bodies;
+//! - bodies are cut short.
+//!
+//! The agent's own key is never sent to the registry, so it can't be in a
+//! record.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use reqwest::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::CBOR;
+
+/// Longest body kept, in bytes
+pub const BODY_LIMIT: usize = 2048;
+
+/// What redacted values are replaced with
+const REDACTED: &str = "[redacted]";
+
+/// Body fields redacted wherever they appear: bearer tokens and anything
+/// named like key material
+const SECRET_FIELDS: &[&str] = &["session_id", "secret", "secret_key", "private_key", "seed"];
+
+/// One request to a registry and how it went
+#[derive(Debug, Clone, Serialize)]
+pub struct Exchange {
+    /// When the request was sent (Unix seconds)
+    pub at: i64,
+    pub method: String,
+    pub url: String,
+    /// Request headers, `Authorization` redacted
+    pub request_headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<String>,
+    /// Response status, absent when no response came
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_body: Option<String>,
+    /// Why no response came
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time from sending the request to reading the whole answer (milliseconds)
+    pub duration_ms: u64,
+}
+
+impl Exchange {
+    /// Record of `request`, before it's sent
+    pub(crate) fn request(request: &reqwest::Request) -> Self {
+        let headers = request.headers();
+        Self {
+            at: crate::types::now(),
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            request_headers: redacted_headers(headers),
+            request_body: request.body().and_then(|body| body.as_bytes()).map(|bytes| render_body(headers, bytes)),
+            status: None,
+            response_body: None,
+            error: None,
+            duration_ms: 0,
+        }
+    }
+
+    /// Add the response's status and body
+    pub(crate) fn response(&mut self, status: reqwest::StatusCode, headers: &HeaderMap, body: &[u8]) {
+        self.status = Some(status.as_u16());
+        if !body.is_empty() {
+            self.response_body = Some(render_body(headers, body));
+        }
+    }
+}
+
+/// The last few exchanges, oldest dropped first
+///
+/// Shared by every client it's given to.
+#[derive(Debug)]
+pub struct ExchangeLog {
+    capacity: usize,
+    exchanges: Mutex<VecDeque<Exchange>>,
+}
+
+impl ExchangeLog {
+    /// Log keeping the last `capacity` exchanges
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, exchanges: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Up to `count` of the latest exchanges, newest first
+    pub fn recent(&self, count: usize) -> Vec<Exchange> {
+        self.lock().iter().rev().take(count).cloned().collect()
+    }
+
+    pub(crate) fn record(&self, exchange: Exchange) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut exchanges = self.lock();
+        if exchanges.len() == self.capacity {
+            exchanges.pop_front();
+        }
+        exchanges.push_back(exchange);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Exchange>> {
+        self.exchanges.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn redacted_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if name == AUTHORIZATION {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// A body as text: structured bodies with their secrets redacted, other text
+/// as is, anything else by size; cut to [`BODY_LIMIT`]
+fn render_body(headers: &HeaderMap, bytes: &[u8]) -> String {
+    let is_cbor = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with(CBOR));
+    let structured = if is_cbor {
+        ciborium::from_reader::<Value, _>(bytes).ok()
+    } else {
+        serde_json::from_slice::<Value>(bytes).ok()
+    };
+    match structured {
+        Some(mut value) => {
+            redact(&mut value);
+            truncate(value.to_string())
+        }
+        None => match std::str::from_utf8(bytes) {
+            Ok(text) => truncate(text.to_string()),
+            Err(_) => format!("<{} bytes>", bytes.len()),
+        },
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (name, field) in object.iter_mut() {
+                if SECRET_FIELDS.contains(&name.to_ascii_lowercase().as_str()) {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() <= BODY_LIMIT {
+        return text;
+    }
+    let total = text.len();
+    let mut end = BODY_LIMIT;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text.push_str(&format!("… ({} bytes in all)", total));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+
+    use super::*;
+
+    fn exchange(url: &str) -> Exchange {
+        let request = reqwest::Request::new(reqwest::Method::GET, url.parse().unwrap());
+        Exchange::request(&request)
+    }
+
+    #[test]
+    fn only_the_latest_exchanges_are_kept() {
+        let log = ExchangeLog::new(2);
+        for n in 0..3 {
+            log.record(exchange(&format!("http://registry.example/{}", n)));
+        }
+        let urls: Vec<_> = log.recent(10).into_iter().map(|e| e.url).collect();
+        assert_eq!(urls, ["http://registry.example/2", "http://registry.example/1"]);
+        assert_eq!(log.recent(1).len(), 1);
+
+        let off = ExchangeLog::new(0);
+        off.record(exchange("http://registry.example/"));
+        assert!(off.recent(10).is_empty());
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        let mut request = reqwest::Request::new(reqwest::Method::POST, "http://registry.example/v1/proof".parse().unwrap());
+        request.headers_mut().insert(AUTHORIZATION, HeaderValue::from_static("Bearer session-1"));
+        request.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        *request.body_mut() = Some(json!({ "did": "did:key:z6Mk", "private_key": "c2VjcmV0" }).to_string().into());
+        let mut exchange = Exchange::request(&request);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let accepted = json!({ "session_id": "session-1", "nested": [{ "Secret": "x" }] }).to_string();
+        exchange.response(reqwest::StatusCode::OK, &headers, accepted.as_bytes());
+
+        assert_eq!(exchange.request_headers["authorization"], REDACTED);
+        assert_eq!(exchange.request_headers["content-type"], "application/json");
+        assert_eq!(exchange.request_body.as_deref(), Some(r#"{"did":"did:key:z6Mk","private_key":"[redacted]"}"#));
+        assert_eq!(exchange.status, Some(200));
+        assert_eq!(exchange.response_body.as_deref(), Some(r#"{"nested":[{"Secret":"[redacted]"}],"session_id":"[redacted]"}"#));
+        assert!(!serde_json::to_string(&exchange).unwrap().contains("session-1"));
+    }
+
+    #[test]
+    fn bodies_are_cut_short() {
+        let headers = HeaderMap::new();
+        let long = "é".repeat(BODY_LIMIT);
+        let rendered = render_body(&headers, long.as_bytes());
+        assert!(rendered.starts_with("éé"));
+        assert!(rendered.ends_with(&format!("… ({} bytes in all)", long.len())));
+        assert!(rendered.len() < BODY_LIMIT + 32);
+
+        assert_eq!(render_body(&headers, &[0xff, 0xfe]), "<2 bytes>");
+        assert_eq!(render_body(&headers, b"Not Found"), "Not Found");
+    }
+}
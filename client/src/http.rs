@@ -0,0 +1,203 @@
+//! HTTP client settings for reaching registries from restricted networks
+//!
+//! Proxies come from `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and
+//! `NO_PROXY` (or their lowercase forms), which reqwest reads itself. On
+//! top of that, [`HttpSettings`] adds root certificates to trust, for
+//! registries and proxies behind a private CA, pins the registry's public
+//! key, and can turn certificate checks off for lab setups.
+
+use std::path::PathBuf;
+
+use reqwest::Certificate;
+
+use crate::pin;
+
+/// Environment variable naming a PEM file of extra root certificates
+pub const CA_BUNDLE_ENV: &str = "REACH_CA_BUNDLE";
+
+/// Environment variable that, set to `1` or `true`, disables certificate checks
+pub const INSECURE_SKIP_VERIFY_ENV: &str = "REACH_INSECURE_SKIP_VERIFY";
+
+/// Environment variable holding comma-separated public key pins
+pub const PIN_SHA256_ENV: &str = "REACH_PIN_SHA256";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpSettingsError {
+    #[error("Failed to read CA bundle {path}: {source}")]
+    ReadCaBundle {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid CA bundle {path}: {reason}")]
+    InvalidCaBundle { path: PathBuf, reason: String },
+
+    #[error("Invalid public key pin: {0}")]
+    InvalidPin(String),
+
+    #[error("Failed to build HTTP client: {0}")]
+    Build(#[source] reqwest::Error),
+}
+
+/// How to build the HTTP client used to reach registries
+#[derive(Debug, Clone, Default)]
+pub struct HttpSettings {
+    /// PEM file of root certificates to trust besides the built-in ones
+    pub ca_bundle: Option<PathBuf>,
+    /// Accept any certificate; only for lab environments. Pins are still
+    /// enforced
+    pub insecure_skip_verify: bool,
+    /// Base64 SHA-256 hashes of DER SubjectPublicKeyInfos; the registry's
+    /// certificate must carry one of these keys
+    pub pins: Vec<String>,
+}
+
+impl HttpSettings {
+    /// Settings from `REACH_CA_BUNDLE`, `REACH_INSECURE_SKIP_VERIFY` and
+    /// `REACH_PIN_SHA256`
+    pub fn from_env() -> Self {
+        let ca_bundle = std::env::var_os(CA_BUNDLE_ENV).filter(|path| !path.is_empty()).map(PathBuf::from);
+        let insecure_skip_verify = std::env::var(INSECURE_SKIP_VERIFY_ENV)
+            .is_ok_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"));
+        let pins = std::env::var(PIN_SHA256_ENV)
+            .map(|pins| pins.split(',').map(str::trim).filter(|pin| !pin.is_empty()).map(str::to_owned).collect())
+            .unwrap_or_default();
+        Self { ca_bundle, insecure_skip_verify, pins }
+    }
+
+    /// Apply the settings to `builder`
+    pub fn configure(&self, mut builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder, HttpSettingsError> {
+        let extra_roots = self.extra_roots()?;
+        if self.insecure_skip_verify {
+            tracing::warn!(
+                "{} is set: TLS certificates are NOT verified, so anyone on the network path can impersonate \
+                 the registry. Only use this in lab environments",
+                INSECURE_SKIP_VERIFY_ENV
+            );
+        }
+
+        // Pinning needs its own TLS setup, which then also does the rest
+        if !self.pins.is_empty() {
+            let pins = self.pins.iter().map(|pin| pin::parse(pin)).collect::<Result<Vec<_>, _>>()
+                .map_err(HttpSettingsError::InvalidPin)?;
+            tracing::debug!(pins = pins.len(), "Pinning the registry's public key");
+            let config = pin::client_config(&extra_roots, pins, !self.insecure_skip_verify);
+            return Ok(builder.use_preconfigured_tls(config));
+        }
+
+        for der in &extra_roots {
+            let certificate = Certificate::from_der(der).map_err(|e| HttpSettingsError::InvalidCaBundle {
+                path: self.ca_bundle.clone().unwrap_or_default(),
+                reason: e.to_string(),
+            })?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        if self.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder)
+    }
+
+    /// DER certificates from the CA bundle, if one is configured
+    fn extra_roots(&self) -> Result<Vec<Vec<u8>>, HttpSettingsError> {
+        let Some(path) = &self.ca_bundle else {
+            return Ok(Vec::new());
+        };
+        let pem = std::fs::read(path)
+            .map_err(|source| HttpSettingsError::ReadCaBundle { path: path.clone(), source })?;
+        let invalid = |reason: String| HttpSettingsError::InvalidCaBundle { path: path.clone(), reason };
+        let certificates = rustls_pemfile::certs(&mut pem.as_slice()).map_err(|e| invalid(e.to_string()))?;
+        if certificates.is_empty() {
+            return Err(invalid("no certificates found".to_string()));
+        }
+        tracing::debug!(path = %path.display(), certificates = certificates.len(), "Trusting extra root certificates");
+        Ok(certificates)
+    }
+
+    /// An HTTP client with the settings applied
+    pub fn client(&self) -> Result<reqwest::Client, HttpSettingsError> {
+        self.configure(reqwest::Client::builder())?.build().map_err(HttpSettingsError::Build)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::Path;
+
+    use axum::{routing::get, Router};
+    use axum_server::tls_rustls::RustlsConfig;
+
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/tls").join(name)
+    }
+
+    /// An HTTPS server whose certificate is signed by the test CA
+    async fn tls_server() -> String {
+        let _ = server_rustls::crypto::ring::default_provider().install_default();
+        let config = RustlsConfig::from_pem_file(testdata("cert.pem"), testdata("key.pem")).await.unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        tokio::spawn(axum_server::from_tcp_rustls(listener, config).serve(app.into_make_service()));
+        format!("https://localhost:{}/health", addr.port())
+    }
+
+    #[tokio::test]
+    async fn extra_roots_are_trusted() {
+        let url = tls_server().await;
+
+        assert!(HttpSettings::default().client().unwrap().get(&url).send().await.is_err());
+
+        let settings = HttpSettings { ca_bundle: Some(testdata("ca.pem")), ..Default::default() };
+        let body = settings.client().unwrap().get(&url).send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, "ok");
+    }
+
+    #[tokio::test]
+    async fn verification_can_be_skipped() {
+        let url = tls_server().await;
+
+        let settings = HttpSettings { insecure_skip_verify: true, ..Default::default() };
+        let resp = settings.client().unwrap().get(&url).send().await.unwrap();
+        assert!(resp.status().is_success());
+    }
+
+    #[test]
+    fn unreadable_bundles_are_reported() {
+        let missing = HttpSettings { ca_bundle: Some(testdata("missing.pem")), ..Default::default() };
+        assert!(matches!(missing.client(), Err(HttpSettingsError::ReadCaBundle { .. })));
+
+        let not_pem = HttpSettings { ca_bundle: Some(testdata("key.pem")), ..Default::default() };
+        assert!(matches!(not_pem.client(), Err(HttpSettingsError::InvalidCaBundle { .. })));
+    }
+
+    #[tokio::test]
+    async fn pins_are_enforced_on_top_of_the_usual_checks() {
+        let url = tls_server().await;
+        let pinned = |pin: &str| HttpSettings {
+            ca_bundle: Some(testdata("ca.pem")),
+            pins: vec![pin.to_string()],
+            ..Default::default()
+        };
+
+        let settings = pinned("eRvNRazNqhIgubLB9QpSKT///+g8Xcg9ToaYVOOkCYw=");
+        let resp = settings.client().unwrap().get(&url).send().await.unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(resp.version(), reqwest::Version::HTTP_2, "pinned connections still offer h2");
+
+        // The CA's key is trusted but not pinned
+        let settings = pinned("UqcP1AWbjlVDFYHZKNgAKVVM03LK/6FPBZBYhqTeCC0=");
+        let err = settings.client().unwrap().get(&url).send().await.unwrap_err();
+        assert!(pin::is_mismatch(&err), "{:?}", err);
+
+        // A matching pin doesn't stand in for the chain check
+        let settings = HttpSettings { ca_bundle: None, ..pinned("eRvNRazNqhIgubLB9QpSKT///+g8Xcg9ToaYVOOkCYw=") };
+        let err = settings.client().unwrap().get(&url).send().await.unwrap_err();
+        assert!(!pin::is_mismatch(&err));
+
+        assert!(matches!(pinned("not a pin").client(), Err(HttpSettingsError::InvalidPin(_))));
+    }
+}
@@ -0,0 +1,869 @@
+//! agent-reach-client: typed async client for the agent-reach discovery registry
+//!
+//! [`ReachClient`] runs the agent-id handshake, keeps the session it gets and
+//! renews it when the registry stops accepting it, so callers only deal in
+//! registrations and lookups.
+//!
+//! ```no_run
+//! use agent_id::RootKey;
+//! use agent_reach_client::{ReachClient, RegisterRequest};
+//!
+//! # async fn run() -> Result<(), agent_reach_client::Error> {
+//! let client = ReachClient::new("https://reach.agent-id.ai", RootKey::generate());
+//!
+//! client.register(&RegisterRequest::new("wss://my-agent.example/inbox")).await?;
+//!
+//! let peer = client.lookup("did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F").await?;
+//! println!("reach {} at {}", peer.did, peer.endpoint);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use agent_id::RootKey;
+use agent_id_handshake::{
+    messages::{Challenge, Hello, ProofAccepted},
+    protocol::sign_proof,
+};
+use reqwest::{Method, RequestBuilder, Response, ResponseBuilderExt, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::OnceCell;
+use tracing::info;
+
+mod error;
+mod exchanges;
+mod http;
+mod pin;
+mod types;
+mod validate;
+
+pub use error::{Error, RegistryError};
+pub use exchanges::{Exchange, ExchangeLog};
+pub use http::{HttpSettings, HttpSettingsError, CA_BUNDLE_ENV, INSECURE_SKIP_VERIFY_ENV, PIN_SHA256_ENV};
+pub use validate::{
+    check_did, check_endpoint, check_metadata, InvalidInput, ENDPOINT_SCHEMES, MAX_DESCRIPTION_LEN, MAX_NAME_LEN,
+    MAX_TAGS, MAX_TAG_LEN,
+};
+pub use types::{
+    AgentsPage, ComponentHealth, Endpoint, Lookup, LookupResponse, PublicKey, ReadinessResponse, RegisterRequest,
+    RegisterResponse, Scope, Session, StatsResponse, StatusCounts, VersionResponse,
+};
+
+use types::{AgentsResponse, ResolveResponse};
+
+/// Registry protocols this client speaks, advertised in Hello
+pub const PROTOCOLS: &[&str] = &["reach/1"];
+
+/// Header the registry uses to correlate requests with its logs
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Media type of CBOR bodies
+const CBOR: &str = "application/cbor";
+
+/// API version this client speaks, and the path prefix it's served under
+const API_VERSION: &str = "v1";
+const API_PREFIX: &str = "/v1";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Run `f`, sending `request_id` as the `X-Request-Id` of every registry
+/// request it makes, so they can be found in the registry's logs
+pub async fn with_request_id<F: Future>(request_id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(request_id, f).await
+}
+
+/// Client for one registry, acting as one agent
+///
+/// Cloning is cheap and clones share the session.
+#[derive(Clone)]
+pub struct ReachClient {
+    http: reqwest::Client,
+    registry_url: String,
+    key: Arc<RootKey>,
+    /// Speak CBOR to the endpoints that support it
+    cbor: bool,
+    /// Scope asked for when authenticating
+    scope: Scope,
+    /// Path prefix the registry serves the API under, once probed
+    api_prefix: Arc<OnceCell<&'static str>>,
+    session: Arc<Mutex<Option<Session>>>,
+    /// The last registration stored, which [`renew`](Self::renew) sends again
+    registration: Arc<Mutex<Option<RegisterRequest>>>,
+    /// Where to record exchanges with the registry, if anywhere
+    exchanges: Option<Arc<ExchangeLog>>,
+}
+
+impl ReachClient {
+    /// Client for the registry at `registry_url` (its root, without `/v1`)
+    pub fn new(registry_url: impl Into<String>, key: impl Into<Arc<RootKey>>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            registry_url: registry_url.into().trim_end_matches('/').to_string(),
+            key: key.into(),
+            cbor: false,
+            scope: Scope::Write,
+            api_prefix: Arc::new(OnceCell::new()),
+            session: Arc::new(Mutex::new(None)),
+            registration: Arc::new(Mutex::new(None)),
+            exchanges: None,
+        }
+    }
+
+    /// Send requests with `http` instead of a default client
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Send registrations and read lookups as CBOR
+    pub fn with_cbor(mut self, cbor: bool) -> Self {
+        self.cbor = cbor;
+        self
+    }
+
+    /// Scope to ask for when authenticating
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Record each exchange with the registry in `log`, redacted as
+    /// [`ExchangeLog`] describes
+    pub fn with_exchange_log(mut self, log: Arc<ExchangeLog>) -> Self {
+        self.exchanges = Some(log);
+        self
+    }
+
+    pub fn registry_url(&self) -> &str {
+        &self.registry_url
+    }
+
+    /// Client for another registry with the same key and settings, starting
+    /// without a session
+    pub fn for_registry(&self, registry_url: impl Into<String>) -> Self {
+        let client = Self::new(registry_url, self.key.clone())
+            .with_http_client(self.http.clone())
+            .with_cbor(self.cbor)
+            .with_scope(self.scope);
+        match &self.exchanges {
+            Some(log) => client.with_exchange_log(log.clone()),
+            None => client,
+        }
+    }
+
+    /// DID this client acts as
+    pub fn did(&self) -> String {
+        self.key.did().to_string()
+    }
+
+    /// The current session, if it hasn't expired
+    pub fn session(&self) -> Option<Session> {
+        self.lock_session().clone().filter(Session::is_valid)
+    }
+
+    /// Adopt a session obtained earlier, e.g. one saved across restarts, or
+    /// forget the current one with `None`
+    pub fn set_session(&self, session: Option<Session>) {
+        *self.lock_session() = session;
+    }
+
+    fn lock_session(&self) -> std::sync::MutexGuard<'_, Option<Session>> {
+        self.session.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Path prefix for registry requests: `/v1`, or none for registries
+    /// that predate API versioning and have no `GET /version`
+    ///
+    /// The registry is asked once; if it can't be reached, `/v1` is assumed
+    /// for now and it's asked again next time.
+    async fn api_prefix(&self) -> &'static str {
+        let probed = self.api_prefix.get_or_try_init(|| async {
+            let context = "Failed to probe API version";
+            let resp = self.send(self.http.get(format!("{}/version", self.registry_url)), context).await?;
+            if resp.status() == StatusCode::NOT_FOUND {
+                return Ok("");
+            }
+            let resp = resp.error_for_status().map_err(|e| Error::network(context, e))?;
+            let version: VersionResponse = resp.json().await.map_err(|e| Error::invalid_response(context, e))?;
+            let prefix = if version.api_versions.iter().any(|v| v == API_VERSION) { API_PREFIX } else { "" };
+            Ok::<_, Error>(prefix)
+        }).await;
+        match probed {
+            Ok(prefix) => prefix,
+            Err(e) => {
+                tracing::debug!(error = %e, "Failed to probe registry API version");
+                API_PREFIX
+            }
+        }
+    }
+
+    /// Start a registry request, tagged with the current request ID
+    async fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = format!("{}{}{}", self.registry_url, self.api_prefix().await, path);
+        let builder = self.http.request(method, url);
+        match REQUEST_ID.try_with(Clone::clone) {
+            Ok(request_id) => builder.header(REQUEST_ID_HEADER, request_id),
+            Err(_) => builder,
+        }
+    }
+
+    /// Send a request, recording the exchange if there's a log to record it in
+    async fn send(&self, builder: RequestBuilder, context: &str) -> Result<Response> {
+        let Some(log) = &self.exchanges else {
+            return builder.send().await.map_err(|e| Error::network(context, e));
+        };
+        let request = builder.build().map_err(|e| Error::network(context, e))?;
+        let mut exchange = Exchange::request(&request);
+        let started = Instant::now();
+        let result = self.read_response(request).await;
+        exchange.duration_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok((resp, body)) => exchange.response(resp.status(), resp.headers(), body),
+            Err(e) => exchange.error = Some(e.to_string()),
+        }
+        log.record(exchange);
+
+        let (resp, body) = result.map_err(|e| Error::network(context, e))?;
+        Ok(Response::from(resp.map(|()| body)))
+    }
+
+    /// Send a request and read the whole answer, so it can be recorded
+    /// before the caller reads it
+    async fn read_response(&self, request: reqwest::Request) -> Result<(::http::Response<()>, Vec<u8>), reqwest::Error> {
+        let resp = self.http.execute(request).await?;
+        let mut head = ::http::Response::builder()
+            .status(resp.status())
+            .version(resp.version())
+            .url(resp.url().clone());
+        if let Some(headers) = head.headers_mut() {
+            *headers = resp.headers().clone();
+        }
+        let body = resp.bytes().await?.to_vec();
+        // Only an invalid status or header fails this, and those came from a response
+        Ok((head.body(()).expect("parts of a valid response"), body))
+    }
+
+    /// Attach a request body, as CBOR when configured to speak it
+    fn with_body<T: Serialize>(&self, builder: RequestBuilder, body: &T) -> RequestBuilder {
+        if !self.cbor {
+            return builder.json(body);
+        }
+        let mut bytes = Vec::new();
+        match ciborium::into_writer(body, &mut bytes) {
+            Ok(()) => builder
+                .header(reqwest::header::CONTENT_TYPE, CBOR)
+                .header(reqwest::header::ACCEPT, CBOR)
+                .body(bytes),
+            // Only types serde can't represent fail to encode; JSON would too
+            Err(_) => builder.json(body),
+        }
+    }
+
+    /// Run the handshake and keep the session it yields
+    ///
+    /// Other calls authenticate on their own when they need to; this is
+    /// for starting a session up front.
+    pub async fn authenticate(&self) -> Result<Session> {
+        info!("Authenticating with registry...");
+
+        let mut hello = Hello::new(self.did());
+        hello.capabilities = Some(vec![self.scope.capability().to_string()]);
+        hello.protocols.extend(PROTOCOLS.iter().map(|p| p.to_string()));
+
+        let request = self.request(Method::POST, "/hello").await.json(&hello);
+        let resp = self.send(request, "Failed to send Hello").await?;
+        if !resp.status().is_success() {
+            let source = RegistryError::from_response(resp).await;
+            return Err(Error::Handshake { step: "Hello", source });
+        }
+        let challenge: Challenge = resp.json().await
+            .map_err(|e| Error::invalid_response("Failed to parse Challenge", e))?;
+
+        info!("Received challenge, signing proof...");
+
+        let proof = sign_proof(&challenge, &self.key.did(), &self.key, Some(challenge.issuer.clone()))
+            .map_err(|e| Error::Proof(e.to_string()))?;
+
+        let request = self.request(Method::POST, "/proof").await.json(&proof);
+        let resp = self.send(request, "Failed to send Proof").await?;
+        if !resp.status().is_success() {
+            let source = RegistryError::from_response(resp).await;
+            return Err(Error::Handshake { step: "Proof", source });
+        }
+        let accepted: ProofAccepted = resp.json().await
+            .map_err(|e| Error::invalid_response("Failed to parse ProofAccepted", e))?;
+
+        info!("Authentication successful");
+
+        let session = Session {
+            session_id: accepted.session_id,
+            expires_at: accepted.session_expires_at / 1000,
+        };
+        self.set_session(Some(session.clone()));
+        Ok(session)
+    }
+
+    /// ID of the current session, authenticating first if there is none
+    async fn session_id(&self) -> Result<String> {
+        match self.session() {
+            Some(session) => Ok(session.session_id),
+            None => Ok(self.authenticate().await?.session_id),
+        }
+    }
+
+    /// Send a request with the session token, re-authenticating once if the
+    /// session expired or the registry no longer recognises it (e.g. after
+    /// it restarted)
+    ///
+    /// `build` adds anything else the request needs to the authorized one.
+    async fn send_authenticated<F>(&self, context: &str, method: Method, path: &str, build: F) -> Result<Response>
+    where
+        F: Fn(RequestBuilder) -> RequestBuilder,
+    {
+        let session_id = self.session_id().await?;
+        let request = self.request(method.clone(), path).await.bearer_auth(session_id);
+        let resp = self.send(build(request), context).await?;
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        let error = RegistryError::from_response(resp).await;
+        if error.is("session_expired") {
+            info!("Session expired, re-authenticating");
+        } else {
+            info!(error = %error, "Session rejected, re-authenticating");
+        }
+        self.set_session(None);
+        let session_id = self.authenticate().await?.session_id;
+        let request = self.request(method, path).await.bearer_auth(session_id);
+        self.send(build(request), context).await
+    }
+
+    /// Register this agent, or with `dry_run` set only have the registry
+    /// check that it would accept the registration
+    ///
+    /// Each call sends its own `Idempotency-Key`, reused if it's retried
+    /// after renewing the session, so a retry never registers twice.
+    ///
+    /// ```no_run
+    /// # use agent_reach_client::{ReachClient, RegisterRequest};
+    /// # async fn run(client: ReachClient) -> Result<(), agent_reach_client::Error> {
+    /// let request = RegisterRequest { ttl: Some(600), ..RegisterRequest::new("wss://my-agent.example") };
+    /// let registered = client.register(&request).await?;
+    /// println!("registered until {}", registered.expires_at);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn register(&self, request: &RegisterRequest) -> Result<RegisterResponse> {
+        check_endpoint(&request.endpoint)?;
+        check_metadata(request)?;
+        let path = if request.dry_run { "/register?dry_run=true" } else { "/register" };
+        let idempotency_key = uuid::Uuid::new_v4().to_string();
+        let resp = self.send_authenticated("Failed to send register", Method::POST, path, |builder| {
+            let builder = builder.header("Idempotency-Key", idempotency_key.as_str());
+            self.with_body(builder, request)
+        }).await?;
+
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        let response = read_body(resp, "Failed to parse response").await?;
+        if !request.dry_run {
+            *self.registration.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(request.clone());
+        }
+        Ok(response)
+    }
+
+    /// Renew this agent's registration for another TTL
+    ///
+    /// Sends the last registration this client (or a clone) stored again.
+    /// Without one, it re-registers what the registry holds for the agent,
+    /// which keeps its handle and endpoints but gets the registry's default
+    /// TTL.
+    ///
+    /// ```no_run
+    /// # use agent_reach_client::ReachClient;
+    /// # async fn run(client: ReachClient) -> Result<(), agent_reach_client::Error> {
+    /// let renewed = client.renew().await?;
+    /// println!("now expires at {}", renewed.expires_at);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn renew(&self) -> Result<RegisterResponse> {
+        let last = self.registration.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let request = match last {
+            Some(request) => request,
+            None => RegisterRequest::from_lookup(&self.me().await?),
+        };
+        self.register(&request).await
+    }
+
+    /// Look up an agent by DID
+    ///
+    /// ```no_run
+    /// # use agent_reach_client::ReachClient;
+    /// # async fn run(client: ReachClient) -> Result<(), agent_reach_client::Error> {
+    /// let peer = client.lookup("did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F").await?;
+    /// if let Some(fingerprint) = peer.public_key.as_ref().and_then(|key| key.fingerprint()) {
+    ///     println!("{} has key {}", peer.did, fingerprint);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn lookup(&self, did: &str) -> Result<LookupResponse> {
+        match self.lookup_if_changed(did, None).await? {
+            Lookup::Found { entry, .. } => Ok(*entry),
+            Lookup::NotModified => Err(Error::invalid_response("Failed to lookup", "registry returned 304 unasked")),
+        }
+    }
+
+    /// Look up an agent by DID, sending `etag` from an earlier lookup so an
+    /// unchanged entry costs a bodiless 304
+    pub async fn lookup_if_changed(&self, did: &str, etag: Option<&str>) -> Result<Lookup> {
+        check_did(did)?;
+        let mut request = self.request(Method::GET, &format!("/lookup/{}", urlencoding::encode(did))).await;
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if self.cbor {
+            request = request.header(reqwest::header::ACCEPT, CBOR);
+        }
+        let resp = self.send(request, "Failed to lookup").await?;
+
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Lookup::NotModified);
+        }
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        let etag = resp
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        let entry = read_body(resp, "Failed to parse response").await?;
+        Ok(Lookup::Found { entry: Box::new(entry), etag })
+    }
+
+    /// Find the agent holding a `name@domain` handle
+    pub async fn resolve(&self, handle: &str) -> Result<LookupResponse> {
+        let request = self.request(Method::GET, &format!("/resolve?handle={}", urlencoding::encode(handle))).await;
+        let resp = self.send(request, "Failed to resolve handle").await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        let resolved: ResolveResponse = read_body(resp, "Failed to parse response").await?;
+        Ok(resolved.agent)
+    }
+
+    /// This agent's own registration
+    pub async fn me(&self) -> Result<LookupResponse> {
+        let resp = self.send_authenticated("Failed to fetch registration", Method::GET, "/me", |builder| builder)
+            .await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        read_body(resp, "Failed to parse response").await
+    }
+
+    /// Remove this agent's registration and end its session
+    ///
+    /// ```no_run
+    /// # use agent_reach_client::ReachClient;
+    /// # async fn run(client: ReachClient) -> Result<(), agent_reach_client::Error> {
+    /// client.deregister().await?;
+    /// assert!(client.session().is_none());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn deregister(&self) -> Result<()> {
+        let resp = self.send_authenticated("Failed to deregister", Method::POST, "/deregister", |builder| builder)
+            .await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        self.logout().await;
+        Ok(())
+    }
+
+    /// End the current session at the registry, so a leaked copy stops
+    /// working; failures are only logged since the session expires anyway
+    pub async fn logout(&self) {
+        let Some(session) = self.lock_session().take() else {
+            return;
+        };
+        let request = self.request(Method::POST, "/logout").await.bearer_auth(session.session_id);
+        let result = self.send(request, "Failed to end session").await;
+        match result {
+            Ok(resp) if resp.status().is_success() || resp.status() == StatusCode::UNAUTHORIZED => {}
+            Ok(resp) => tracing::warn!(status = %resp.status(), "Failed to end session"),
+            Err(e) => tracing::warn!(error = %e, "Failed to end session"),
+        }
+    }
+
+    /// Every live registration; registries that don't list them answer 404
+    pub async fn agents(&self) -> Result<Vec<LookupResponse>> {
+        let request = self.request(Method::GET, "/agents").await;
+        let resp = self.send(request, "Failed to list agents").await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        let listed: AgentsResponse = read_body(resp, "Failed to parse response").await?;
+        Ok(listed.agents)
+    }
+
+    /// Up to `limit` live registrations, skipping the first `offset`.
+    /// Registries that don't page their listing send all of it, and the
+    /// page is cut from that.
+    pub async fn agents_page(&self, offset: usize, limit: usize) -> Result<AgentsPage> {
+        let path = format!("/agents?offset={}&limit={}", offset, limit);
+        let request = self.request(Method::GET, &path).await;
+        let resp = self.send(request, "Failed to list agents").await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        let listed: AgentsResponse = read_body(resp, "Failed to parse response").await?;
+        Ok(match listed.total {
+            Some(total) => AgentsPage { agents: listed.agents, total },
+            None => AgentsPage {
+                total: listed.agents.len(),
+                agents: listed.agents.into_iter().skip(offset).take(limit).collect(),
+            },
+        })
+    }
+
+    /// Registry-wide counts; registries with private stats refuse this
+    pub async fn stats(&self) -> Result<StatsResponse> {
+        let request = self.request(Method::GET, "/stats").await;
+        let resp = self.send(request, "Failed to fetch stats").await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        resp.json().await.map_err(|e| Error::invalid_response("Failed to parse response", e))
+    }
+
+    /// The registry's version and the API versions it serves
+    pub async fn version(&self) -> Result<VersionResponse> {
+        let request = self.http.get(format!("{}/version", self.registry_url));
+        let resp = self.send(request, "Failed to fetch version").await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        resp.json().await.map_err(|e| Error::invalid_response("Failed to parse response", e))
+    }
+
+    /// Check the registry answers at all
+    pub async fn health(&self) -> Result<()> {
+        let request = self.request(Method::GET, "/health").await;
+        let resp = self.send(request, "Failed to reach registry").await?;
+        if !resp.status().is_success() {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        Ok(())
+    }
+
+    /// Readiness of the registry's storage and background tasks, including
+    /// when it's degraded; registries without readiness checks answer 404
+    pub async fn readiness(&self) -> Result<ReadinessResponse> {
+        let request = self.request(Method::GET, "/health/ready").await;
+        let resp = self.send(request, "Failed to check readiness").await?;
+        if !resp.status().is_success() && resp.status() != StatusCode::SERVICE_UNAVAILABLE {
+            return Err(RegistryError::from_response(resp).await.into());
+        }
+        resp.json().await.map_err(|e| Error::invalid_response("Failed to parse response", e))
+    }
+}
+
+/// Decode a response body as CBOR or JSON, going by its content type rather
+/// than what was asked for, since older registries only answer in JSON
+async fn read_body<T: DeserializeOwned>(resp: Response, context: &str) -> Result<T> {
+    let is_cbor = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with(CBOR));
+    if !is_cbor {
+        return resp.json().await.map_err(|e| Error::invalid_response(context, e));
+    }
+    let bytes = resp.bytes().await.map_err(|e| Error::invalid_response(context, e))?;
+    ciborium::from_reader(bytes.as_ref()).map_err(|e| Error::invalid_response(context, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use agent_id_handshake::messages::CounterProof;
+    use axum::{extract::State, http::{HeaderMap, StatusCode}, routing::{get, post}, Json, Router};
+    use serde_json::json;
+
+    use super::*;
+
+    /// What the mock registry has seen
+    #[derive(Default)]
+    struct Registry {
+        proofs: AtomicUsize,
+        registers: AtomicUsize,
+        deregisters: AtomicUsize,
+        logouts: AtomicUsize,
+        endpoint: Mutex<Option<String>>,
+        /// Body of the last registration accepted
+        registered: Mutex<Option<serde_json::Value>>,
+    }
+
+    fn accepted(n: usize) -> ProofAccepted {
+        ProofAccepted {
+            type_: "ProofAccepted".to_string(),
+            version: "1.0".to_string(),
+            session_id: format!("session-{}", n),
+            counter_proof: CounterProof {
+                challenge_hash: String::new(),
+                responder_did: "did:key:registry".to_string(),
+                signing_key: String::new(),
+                signature: String::new(),
+            },
+            session_expires_at: (types::now() + 300) * 1000,
+        }
+    }
+
+    /// A versioned registry that hands out `session-1`, `session-2`, ... and
+    /// treats `session-1` as expired
+    async fn mock_registry() -> (String, Arc<Registry>) {
+        fn authorized(headers: &HeaderMap) -> bool {
+            headers.get("authorization").is_some_and(|v| v != "Bearer session-1")
+        }
+
+        let registry = Arc::new(Registry::default());
+        let app = Router::new()
+            .route("/version", get(|| async { Json(json!({ "version": "0.1.0", "api_versions": ["v1"] })) }))
+            .route("/v1/hello", post(|Json(hello): Json<Hello>| async move {
+                assert!(hello.protocols.iter().any(|p| p == "reach/1"));
+                Json(Challenge::new("did:key:registry".to_string(), hello.did))
+            }))
+            .route("/v1/proof", post(|State(registry): State<Arc<Registry>>| async move {
+                Json(accepted(registry.proofs.fetch_add(1, Ordering::SeqCst) + 1))
+            }))
+            .route("/v1/register", post(|State(registry): State<Arc<Registry>>, headers: HeaderMap, Json(body): Json<serde_json::Value>| async move {
+                registry.registers.fetch_add(1, Ordering::SeqCst);
+                if !authorized(&headers) {
+                    let body = json!({ "error": "Session expired", "code": "session_expired" });
+                    return (StatusCode::UNAUTHORIZED, Json(body));
+                }
+                *registry.endpoint.lock().unwrap() = body["endpoint"].as_str().map(str::to_owned);
+                *registry.registered.lock().unwrap() = Some(body);
+                (StatusCode::OK, Json(json!({ "ok": true, "did": "did:key:me", "expires_at": 4102444800i64, "ttl": 3600 })))
+            }))
+            .route("/v1/me", get(|State(registry): State<Arc<Registry>>, headers: HeaderMap| async move {
+                assert!(authorized(&headers));
+                let endpoint = registry.endpoint.lock().unwrap().clone();
+                match endpoint {
+                    Some(endpoint) => (StatusCode::OK, Json(json!({ "did": "did:key:me", "endpoint": endpoint, "expires_at": 4102444800i64 }))),
+                    None => (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" }))),
+                }
+            }))
+            .route("/v1/lookup/:did", get(|State(registry): State<Arc<Registry>>, headers: HeaderMap| async move {
+                let endpoint = registry.endpoint.lock().unwrap().clone();
+                match endpoint {
+                    Some(_) if headers.get("if-none-match").is_some_and(|v| v == "\"1\"") => {
+                        (StatusCode::NOT_MODIFIED, HeaderMap::new(), Json(json!(null)))
+                    }
+                    Some(endpoint) => {
+                        let mut headers = HeaderMap::new();
+                        headers.insert("etag", "\"1\"".parse().unwrap());
+                        (StatusCode::OK, headers, Json(json!({ "did": "did:key:me", "endpoint": endpoint, "expires_at": 4102444800i64 })))
+                    }
+                    None => (StatusCode::NOT_FOUND, HeaderMap::new(), Json(json!({ "error": "Agent not found" }))),
+                }
+            }))
+            .route("/v1/deregister", post(|State(registry): State<Arc<Registry>>, headers: HeaderMap| async move {
+                assert!(authorized(&headers));
+                registry.deregisters.fetch_add(1, Ordering::SeqCst);
+                registry.endpoint.lock().unwrap().take();
+                Json(json!({ "ok": true }))
+            }))
+            .route("/v1/logout", post(|State(registry): State<Arc<Registry>>| async move {
+                registry.logouts.fetch_add(1, Ordering::SeqCst);
+                StatusCode::NO_CONTENT
+            }))
+            .with_state(registry.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, registry)
+    }
+
+    #[tokio::test]
+    async fn register_lookup_renew_deregister() {
+        let (url, registry) = mock_registry().await;
+        let client = ReachClient::new(url, RootKey::generate());
+
+        // The first session is refused, so registering renews it once
+        let request = RegisterRequest {
+            ttl: Some(600),
+            handle: Some("me@example.com".to_string()),
+            endpoints: vec![Endpoint::new("wss://backup.example")],
+            ..RegisterRequest::new("wss://me.example")
+        };
+        let registered = client.register(&request).await.unwrap();
+        assert_eq!(registered.ttl, 3600);
+        assert_eq!(registry.proofs.load(Ordering::SeqCst), 2);
+        assert_eq!(client.session().unwrap().session_id, "session-2");
+
+        let entry = client.lookup(&client.did()).await.unwrap();
+        assert_eq!(entry.endpoint, "wss://me.example");
+        assert!(matches!(client.lookup_if_changed(&client.did(), Some("\"1\"")).await.unwrap(), Lookup::NotModified));
+
+        *registry.registered.lock().unwrap() = None;
+        client.renew().await.unwrap();
+        assert_eq!(registry.registers.load(Ordering::SeqCst), 3);
+        assert_eq!(registry.proofs.load(Ordering::SeqCst), 2, "the session is reused");
+        let renewed = registry.registered.lock().unwrap().clone().unwrap();
+        assert_eq!(renewed, serde_json::to_value(&request).unwrap(), "renewal resends the registration");
+
+        client.deregister().await.unwrap();
+        assert_eq!(registry.deregisters.load(Ordering::SeqCst), 1);
+        assert_eq!(registry.logouts.load(Ordering::SeqCst), 1);
+        assert!(client.session().is_none());
+
+        let err = client.lookup(&client.did()).await.unwrap_err();
+        assert_eq!(err.registry().map(|e| e.status.as_u16()), Some(404));
+    }
+
+    #[tokio::test]
+    async fn exchanges_are_recorded_when_asked() {
+        let (url, _) = mock_registry().await;
+        let log = Arc::new(ExchangeLog::new(4));
+        let client = ReachClient::new(url.clone(), RootKey::generate()).with_exchange_log(log.clone());
+
+        client.register(&RegisterRequest::new("wss://me.example")).await.unwrap();
+        let entry = client.lookup(&client.did()).await.unwrap();
+        assert_eq!(entry.endpoint, "wss://me.example", "recorded responses still reach the caller");
+
+        // version, hello, proof, 401 register, hello, proof, register, lookup
+        let recent = log.recent(10);
+        assert_eq!(recent.len(), 4);
+        let lookup = &recent[0];
+        assert_eq!(lookup.method, "GET");
+        assert!(lookup.url.starts_with(&format!("{}/v1/lookup/", url)));
+        assert_eq!(lookup.status, Some(200));
+        assert!(lookup.response_body.as_deref().unwrap().contains("wss://me.example"));
+
+        let register = &recent[1];
+        assert_eq!(register.request_headers["authorization"], "[redacted]");
+        assert_eq!(register.request_body.as_deref(), Some(r#"{"endpoint":"wss://me.example"}"#));
+        let proof = &recent[2];
+        assert!(proof.response_body.as_deref().unwrap().contains(r#""session_id":"[redacted]""#));
+
+        // Clients for other registries share the log
+        let down = client.for_registry("http://127.0.0.1:1");
+        assert!(down.health().await.is_err());
+        let failed = &log.recent(1)[0];
+        assert_eq!(failed.status, None);
+        assert!(failed.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn listings_are_paged_by_older_registries_too() {
+        let app = Router::new().route("/agents", get(|| async {
+            let agents: Vec<_> = (0..5)
+                .map(|i| json!({ "did": format!("did:key:{}", i), "endpoint": "wss://agent", "expires_at": 4102444800i64 }))
+                .collect();
+            Json(json!({ "agents": agents }))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let page = ReachClient::new(url, RootKey::generate()).agents_page(3, 10).await.unwrap();
+        assert_eq!(page.total, 5);
+        assert_eq!(page.agents.iter().map(|agent| agent.did.as_str()).collect::<Vec<_>>(), ["did:key:3", "did:key:4"]);
+    }
+
+    #[tokio::test]
+    async fn handshake_refusals_name_the_step() {
+        let app = Router::new().route("/hello", post(|| async {
+            (StatusCode::BAD_REQUEST, Json(json!({ "error": "No shared protocol", "code": "unsupported_protocol", "supported": ["reach/2"] })))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let err = ReachClient::new(url, RootKey::generate()).authenticate().await.unwrap_err();
+        assert_eq!(err.to_string(), "Hello failed: No shared protocol");
+        let refusal = err.registry().unwrap();
+        assert!(refusal.is("unsupported_protocol"));
+        assert_eq!(refusal.supported, ["reach/2"]);
+    }
+
+    #[tokio::test]
+    async fn degraded_registries_still_report_readiness() {
+        let app = Router::new()
+            .route("/version", get(|| async { Json(json!({ "version": "0.1.0", "api_versions": ["v1"] })) }))
+            .route("/v1/health", get(|| async { "ok" }))
+            .route("/v1/health/ready", get(|| async {
+                let body = json!({
+                    "status": "degraded",
+                    "version": "0.1.0",
+                    "components": {
+                        "storage": { "status": "down", "detail": "No answer within 2s", "latency_ms": 2001 },
+                        "purge": { "status": "ok", "last_run_secs": 12 },
+                    },
+                });
+                (StatusCode::SERVICE_UNAVAILABLE, Json(body))
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = ReachClient::new(url, RootKey::generate());
+        client.health().await.unwrap();
+        let version = client.version().await.unwrap();
+        assert_eq!(version.version, "0.1.0");
+        assert_eq!(version.api_versions, ["v1"]);
+
+        let readiness = client.readiness().await.unwrap();
+        assert!(!readiness.is_ready());
+        assert_eq!(readiness.components["storage"].detail.as_deref(), Some("No answer within 2s"));
+        assert_eq!(readiness.components["purge"].last_run_secs, Some(12));
+    }
+
+    #[tokio::test]
+    async fn pin_mismatches_are_their_own_error() {
+        let settings = HttpSettings {
+            ca_bundle: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/ca.pem").into()),
+            pins: vec!["UqcP1AWbjlVDFYHZKNgAKVVM03LK/6FPBZBYhqTeCC0=".to_string()],
+            ..Default::default()
+        };
+        let http = settings.client().unwrap();
+
+        // Pins don't apply to plain-HTTP registries
+        let (url, _) = mock_registry().await;
+        let client = ReachClient::new(url, RootKey::generate()).with_http_client(http.clone());
+        client.version().await.unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _ = server_rustls::crypto::ring::default_provider().install_default();
+        let tls = axum_server::tls_rustls::RustlsConfig::from_pem_file(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/cert.pem"),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/key.pem"),
+        )
+        .await
+        .unwrap();
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        tokio::spawn(axum_server::from_tcp_rustls(listener.into_std().unwrap(), tls).serve(app.into_make_service()));
+
+        let client = ReachClient::new(format!("https://localhost:{}", addr.port()), RootKey::generate()).with_http_client(http);
+        let err = client.health().await.unwrap_err();
+        assert!(matches!(err, Error::PinMismatch { .. }), "{:?}", err);
+        assert!(err.to_string().contains("certificate pin mismatch"));
+    }
+}
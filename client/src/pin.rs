@@ -0,0 +1,129 @@
+//! Public key pinning for registry connections
+//!
+//! A pin is the base64 SHA-256 of a certificate's DER SubjectPublicKeyInfo,
+//! as printed by `openssl x509 -pubkey -noout -in cert.pem | openssl pkey
+//! -pubin -outform der | openssl dgst -sha256 -binary | base64`. With pins
+//! set, the registry's certificate must still pass the usual checks and its
+//! key must also match one of them, so a certificate from a compromised CA
+//! is refused. Plain-HTTP registries have no certificate to pin.
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, CertificateError, OwnedTrustAnchor, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+use x509_cert::der::{Decode, Encode};
+
+/// Reason given when a certificate's key matches no pin
+pub(crate) const PIN_MISMATCH: &str = "certificate pin mismatch";
+
+/// The verifier's error for a certificate whose key matches no pin
+#[derive(Debug)]
+struct PinMismatch;
+
+impl std::fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(PIN_MISMATCH)
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+/// Decode one pin
+pub(crate) fn parse(pin: &str) -> Result<[u8; 32], String> {
+    STANDARD
+        .decode(pin.trim())
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .ok_or_else(|| format!("{} is not a base64 SHA-256 hash", pin.trim()))
+}
+
+/// TLS settings that check the certificate chain against the built-in and
+/// `extra_roots` (unless `verify_chain` is off) and then the pins
+pub(crate) fn client_config(extra_roots: &[Vec<u8>], pins: Vec<[u8; 32]>, verify_chain: bool) -> rustls::ClientConfig {
+    let chain = verify_chain.then(|| {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+        }));
+        roots.add_parsable_certificates(extra_roots);
+        WebPkiVerifier::new(roots, None)
+    });
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier { chain, pins }))
+        .with_no_client_auth();
+    // As reqwest offers on the connections it sets up itself
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config
+}
+
+/// Whether a request failed because the registry's key matched no pin
+pub(crate) fn is_mismatch(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut next = Some(error);
+    while let Some(error) = next {
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(other))) = error.downcast_ref() {
+            if other.downcast_ref::<PinMismatch>().is_some() {
+                return true;
+            }
+        }
+        // The TLS error reaches reqwest inside I/O errors, whose source
+        // skips over the error they wrap
+        next = match error.downcast_ref::<std::io::Error>() {
+            Some(io) => io.get_ref().map(|inner| inner as &(dyn std::error::Error + 'static)),
+            None => error.source(),
+        };
+    }
+    false
+}
+
+struct PinnedVerifier {
+    /// Usual certificate checks, unless verification is turned off
+    chain: Option<WebPkiVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(chain) = &self.chain {
+            chain.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        }
+        match spki_sha256(&end_entity.0) {
+            Some(hash) if self.pins.contains(&hash) => Ok(ServerCertVerified::assertion()),
+            Some(_) => Err(rustls::Error::InvalidCertificate(CertificateError::Other(Arc::new(PinMismatch)))),
+            None => Err(rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding)),
+        }
+    }
+}
+
+/// SHA-256 of a DER certificate's SubjectPublicKeyInfo
+fn spki_sha256(der: &[u8]) -> Option<[u8; 32]> {
+    let certificate = x509_cert::Certificate::from_der(der).ok()?;
+    let spki = certificate.tbs_certificate.subject_public_key_info.to_der().ok()?;
+    Some(Sha256::digest(spki).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins_hash_the_public_key() {
+        let pem = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/cert.pem")).unwrap();
+        let der = rustls_pemfile::certs(&mut pem.as_slice()).unwrap().remove(0);
+
+        // From openssl, as in the module docs
+        assert_eq!(spki_sha256(&der), Some(parse("eRvNRazNqhIgubLB9QpSKT///+g8Xcg9ToaYVOOkCYw=").unwrap()));
+        assert!(parse("c2hvcnQ=").is_err());
+    }
+}
@@ -0,0 +1,398 @@
+//! Registry wire types
+//!
+//! These mirror the server's request and response bodies. Fields added by
+//! newer registries are optional here so older ones still decode.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::{STANDARD, STANDARD_NO_PAD}, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Sessions this close to expiry are treated as expired
+const SESSION_EXPIRY_MARGIN_SECS: i64 = 10;
+
+/// Current Unix time in seconds
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Session scope asked for in Hello
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// Lookups only; the registry refuses registrations
+    Read,
+    #[default]
+    Write,
+}
+
+impl Scope {
+    /// Hello capability asking for this scope
+    pub fn capability(self) -> &'static str {
+        match self {
+            Scope::Read => "scope:read",
+            Scope::Write => "scope:write",
+        }
+    }
+}
+
+/// An authenticated registry session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// Bearer token for authenticated requests
+    pub session_id: String,
+    /// Unix timestamp (seconds)
+    pub expires_at: i64,
+}
+
+impl Session {
+    /// Whether the session has comfortably more than a few seconds left
+    pub fn is_valid(&self) -> bool {
+        now() < self.expires_at - SESSION_EXPIRY_MARGIN_SECS
+    }
+}
+
+/// Body of `POST /register`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RegisterRequest {
+    /// Where to reach this agent, as an absolute URI
+    pub endpoint: String,
+    /// Time-to-live in seconds; the registry's default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+    /// Human-readable `name@domain` that resolves to this agent's DID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+    /// Weight of `endpoint` among `endpoints`; 1 when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// Region `endpoint` serves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Further endpoints sharing the agent's traffic with `endpoint`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<Endpoint>,
+    /// Display name, for registries that keep one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// What the agent does, for registries that keep it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Labels to find the agent by, for registries that keep them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Only have the registry check that it would accept the registration
+    #[serde(skip)]
+    pub dry_run: bool,
+}
+
+impl RegisterRequest {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A request registering what `entry` holds again. The TTL isn't part
+    /// of a lookup, so the registry's default applies.
+    pub fn from_lookup(entry: &LookupResponse) -> Self {
+        let (primary, others) = match entry.endpoints.split_first() {
+            Some((primary, others)) if primary.uri == entry.endpoint => (Some(primary), others),
+            _ => (None, entry.endpoints.as_slice()),
+        };
+        Self {
+            endpoint: entry.endpoint.clone(),
+            handle: entry.handle.clone(),
+            weight: primary.and_then(|endpoint| endpoint.weight),
+            region: primary.and_then(|endpoint| endpoint.region.clone()).or_else(|| entry.region.clone()),
+            endpoints: others.to_vec(),
+            ..Default::default()
+        }
+    }
+}
+
+/// One of several endpoints an agent can be reached at
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub uri: String,
+    /// Relative share of weighted lookups; 1 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// Region the endpoint serves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+impl Endpoint {
+    pub fn new(uri: impl Into<String>) -> Self {
+        Self {
+            uri: uri.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// What the registry made of a registration; older registries send only `ok`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RegisterResponse {
+    pub expires_at: i64,
+    /// TTL the registration got, after the registry's bounds (seconds)
+    pub ttl: u64,
+    /// How the registry changed the request, such as clamping the TTL
+    pub adjustments: Vec<String>,
+    /// Whether this was a dry run that stored nothing
+    pub dry_run: bool,
+    /// The display name stored, from registries that keep one
+    pub name: Option<String>,
+    /// The description stored, from registries that keep one
+    pub description: Option<String>,
+    /// The tags stored, from registries that keep them
+    pub tags: Vec<String>,
+}
+
+/// A registered agent
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LookupResponse {
+    pub did: String,
+    pub endpoint: String,
+    /// Unix timestamp (seconds)
+    pub expires_at: i64,
+    /// Seconds left until `expires_at` by the registry's clock when it
+    /// answered, from registries that report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_remaining_secs: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+    /// Every endpoint, `endpoint` first, when the agent registered several
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<Endpoint>,
+    /// Region of `endpoint`, when it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Key behind the DID, from registries that decode it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<PublicKey>,
+}
+
+impl LookupResponse {
+    /// Seconds until the registration expires: the registry's count when it
+    /// gave one, which local clock skew doesn't affect, else by this clock
+    pub fn ttl_remaining(&self) -> i64 {
+        self.ttl_remaining_secs.unwrap_or_else(|| (self.expires_at - now()).max(0))
+    }
+}
+
+/// An agent's public key as the registry reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicKey {
+    /// Raw key bytes, standard base64
+    pub base64: String,
+    /// Multibase form, as in the DID
+    pub multibase: String,
+}
+
+impl PublicKey {
+    /// SHA-256 of the raw key, `SHA256:` then unpadded base64, for pinning
+    pub fn fingerprint(&self) -> Option<String> {
+        let raw = STANDARD.decode(&self.base64).ok()?;
+        Some(format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(raw))))
+    }
+}
+
+/// Result of a conditional lookup
+#[derive(Debug, Clone)]
+pub enum Lookup {
+    /// The entry, with the registry's ETag for revalidating it later
+    Found {
+        entry: Box<LookupResponse>,
+        etag: Option<String>,
+    },
+    /// The entry still matches the ETag that was sent
+    NotModified,
+}
+
+/// Registration counts by status
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusCounts {
+    pub online: u64,
+    pub idle: u64,
+    pub expired: u64,
+}
+
+/// Body of `GET /stats`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatsResponse {
+    pub total_entries: u64,
+    pub entries: StatusCounts,
+    pub registrations_last_hour: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_sessions: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_challenges: Option<u64>,
+    pub uptime_secs: u64,
+}
+
+/// Body of `GET /version`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VersionResponse {
+    /// Server version
+    #[serde(default)]
+    pub version: String,
+    /// API versions served, each under its own path prefix
+    pub api_versions: Vec<String>,
+}
+
+/// Body of `GET /health/ready`, on 503 as well as 200
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReadinessResponse {
+    /// `ok`, or `degraded` when some component is down
+    pub status: String,
+    pub version: String,
+    /// `storage`, then each background task by name
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+impl ReadinessResponse {
+    pub fn is_ready(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// One component's part of a readiness check
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ComponentHealth {
+    /// `ok` or `down`
+    pub status: String,
+    /// What went wrong, when down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// How long the storage backend took to answer (milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Seconds since a background task last finished a round
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ResolveResponse {
+    pub agent: LookupResponse,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AgentsResponse {
+    pub agents: Vec<LookupResponse>,
+    /// Live registrations in all, from registries that page the listing
+    #[serde(default)]
+    pub total: Option<usize>,
+}
+
+/// A page of the registry's listing
+#[derive(Debug, Clone)]
+pub struct AgentsPage {
+    /// Ordered by DID, on registries that page the listing
+    pub agents: Vec<LookupResponse>,
+    /// Live registrations in all
+    pub total: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use agent_reach_server::types as server;
+
+    use super::*;
+
+    #[test]
+    fn register_requests_decode_as_the_server_reads_them() {
+        let request = RegisterRequest {
+            ttl: Some(600),
+            handle: Some("alice@example.com".to_string()),
+            weight: Some(3),
+            region: Some("eu-west".to_string()),
+            endpoints: vec![Endpoint { weight: Some(1), region: Some("us-east".to_string()), ..Endpoint::new("wss://two.example") }],
+            ..RegisterRequest::new("wss://one.example")
+        };
+
+        let decoded: server::RegisterRequest = serde_json::from_value(serde_json::to_value(&request).unwrap()).unwrap();
+
+        assert_eq!(decoded.endpoint, "wss://one.example");
+        assert_eq!(decoded.ttl, 600);
+        assert_eq!(decoded.handle.as_deref(), Some("alice@example.com"));
+        assert_eq!(decoded.weight, Some(3));
+        assert_eq!(decoded.region.as_deref(), Some("eu-west"));
+        assert_eq!(decoded.endpoints.len(), 1);
+        assert_eq!(decoded.endpoints[0].uri, "wss://two.example");
+        assert_eq!(decoded.endpoints[0].weight, Some(1));
+        assert_eq!(decoded.endpoints[0].region.as_deref(), Some("us-east"));
+    }
+
+    #[test]
+    fn server_responses_decode() {
+        let now = now();
+        let entry = server::RegistryEntry {
+            did: "did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F".to_string(),
+            endpoint: "wss://one.example".to_string(),
+            registered_at: now,
+            expires_at: now + 600,
+            last_seen: now,
+            handle: Some("alice@example.com".to_string()),
+            endpoints: vec![
+                server::Endpoint { uri: "wss://one.example".to_string(), weight: Some(3), region: Some("eu-west".to_string()), registered_at: Some(now) },
+                server::Endpoint { uri: "wss://two.example".to_string(), weight: None, region: None, registered_at: Some(now) },
+            ],
+            version: 4,
+            registered_by: None,
+        };
+        let lookup: LookupResponse = serde_json::from_value(serde_json::to_value(server::LookupResponse::from(entry)).unwrap()).unwrap();
+
+        assert_eq!(lookup.endpoint, "wss://one.example");
+        assert_eq!(lookup.handle.as_deref(), Some("alice@example.com"));
+        assert_eq!(lookup.endpoints.len(), 2);
+        assert!(lookup.public_key.and_then(|key| key.fingerprint()).is_some());
+
+        let response = server::RegisterResponse {
+            ok: true,
+            did: lookup.did.clone(),
+            expires_at: now + 600,
+            ttl: 600,
+            version: 4,
+            handle: None,
+            adjustments: vec!["ttl clamped from 30 to 600".to_string()],
+            dry_run: false,
+        };
+        let decoded: RegisterResponse = serde_json::from_value(serde_json::to_value(response).unwrap()).unwrap();
+        assert_eq!(decoded.expires_at, now + 600);
+        assert_eq!(decoded.ttl, 600);
+        assert_eq!(decoded.adjustments.len(), 1);
+    }
+
+    #[test]
+    fn lookups_become_the_same_registration() {
+        let lookup: LookupResponse = serde_json::from_value(serde_json::json!({
+            "did": "did:key:me",
+            "endpoint": "wss://one.example",
+            "expires_at": 4102444800i64,
+            "handle": "alice@example.com",
+            "endpoints": [
+                { "uri": "wss://one.example", "weight": 3, "region": "eu-west", "registered_at": 1 },
+                { "uri": "wss://two.example", "registered_at": 1 },
+            ],
+        }))
+        .unwrap();
+
+        let request = RegisterRequest::from_lookup(&lookup);
+
+        assert_eq!(request.endpoint, "wss://one.example");
+        assert_eq!(request.handle.as_deref(), Some("alice@example.com"));
+        assert_eq!(request.weight, Some(3));
+        assert_eq!(request.region.as_deref(), Some("eu-west"));
+        assert_eq!(request.endpoints, [Endpoint::new("wss://two.example")]);
+        assert_eq!(request.ttl, None);
+    }
+}
@@ -0,0 +1,207 @@
+//! Checks on DIDs, endpoints and registration metadata before they're sent
+//! to a registry
+//!
+//! A typo caught here costs no round trip, and the error says what's wrong
+//! rather than leaving the registry to answer 404 or 400. The common slips,
+//! a DID without its `did:key:` prefix or an endpoint without its scheme,
+//! come with the input as it was probably meant.
+
+use reqwest::Url;
+
+use crate::types::RegisterRequest;
+
+/// Endpoint URI schemes agents commonly register. Others are accepted too;
+/// these are what an endpoint missing its scheme is offered
+pub const ENDPOINT_SCHEMES: &[&str] = &["wss", "ws", "https", "http", "coaps", "coap"];
+
+/// Longest registration `name` sent (bytes)
+pub const MAX_NAME_LEN: usize = 128;
+
+/// Longest registration `description` sent (bytes)
+pub const MAX_DESCRIPTION_LEN: usize = 1024;
+
+/// Most `tags` sent with a registration
+pub const MAX_TAGS: usize = 32;
+
+/// Longest tag sent (bytes)
+pub const MAX_TAG_LEN: usize = 64;
+
+/// Why a DID or endpoint was refused before reaching the registry
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message}")]
+pub struct InvalidInput {
+    pub message: String,
+    /// The input as it was probably meant, for common mistakes
+    pub suggestion: Option<String>,
+}
+
+impl InvalidInput {
+    fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into(), suggestion: None }
+    }
+
+    fn did_you_mean(message: impl Into<String>, suggestion: String) -> Self {
+        Self { message: format!("{}; did you mean {}?", message.into(), suggestion), suggestion: Some(suggestion) }
+    }
+}
+
+/// Check `did` is a well-formed DID, and for `did:key` that it decodes to
+/// a key
+pub fn check_did(did: &str) -> Result<(), InvalidInput> {
+    let Some(rest) = did.strip_prefix("did:") else {
+        // A bare multibase key, or one that lost just `did:`
+        for candidate in [format!("did:key:{}", did), format!("did:{}", did)] {
+            if candidate.parse::<agent_id::Did>().is_ok() {
+                return Err(InvalidInput::did_you_mean(format!("{} is missing its did: prefix", did), candidate));
+            }
+        }
+        return Err(InvalidInput::new(format!("{} is not a DID; DIDs look like did:key:z6Mk...", did)));
+    };
+    let Some((method, id)) = rest.split_once(':') else {
+        return Err(InvalidInput::new(format!("{} has no method; expected did:<method>:<id>", did)));
+    };
+    if method.is_empty() || !method.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()) {
+        return Err(InvalidInput::new(format!(
+            "{} has method {:?}; methods are lowercase letters and digits",
+            did, method
+        )));
+    }
+    if id.is_empty() {
+        return Err(InvalidInput::new(format!("{} has no method-specific id", did)));
+    }
+    if method == "key" {
+        did.parse::<agent_id::Did>()
+            .map_err(|e| InvalidInput::new(format!("{} is not a valid did:key DID: {}", did, e)))?;
+    }
+    Ok(())
+}
+
+/// Check `endpoint` is an absolute URI, with a host when it has an
+/// authority (`scheme://host...`)
+///
+/// Any scheme is accepted, so `grpc://`, `nats://` and `did:web:...`
+/// endpoints pass; [`ENDPOINT_SCHEMES`] only feeds the suggestion when the
+/// scheme is missing.
+pub fn check_endpoint(endpoint: &str) -> Result<(), InvalidInput> {
+    if endpoint.trim().is_empty() {
+        return Err(InvalidInput::new("Endpoint is empty"));
+    }
+    if missing_scheme(endpoint) {
+        let scheme = if endpoint.contains('/') { "https" } else { "wss" };
+        return Err(InvalidInput::did_you_mean(
+            format!("Endpoint {} has no scheme", endpoint),
+            format!("{}://{}", scheme, endpoint),
+        ));
+    }
+    let url = Url::parse(endpoint)
+        .map_err(|e| InvalidInput::new(format!("Endpoint {} is not a valid URI: {}", endpoint, e)))?;
+    if url.has_authority() && url.host_str().is_none_or(str::is_empty) {
+        return Err(InvalidInput::new(format!("Endpoint {} has no host", endpoint)));
+    }
+    Ok(())
+}
+
+/// Check a registration's name, description and tags are within
+/// [`MAX_NAME_LEN`], [`MAX_DESCRIPTION_LEN`], [`MAX_TAGS`] and
+/// [`MAX_TAG_LEN`], so they can't push the request past a registry's body
+/// limit
+pub fn check_metadata(request: &RegisterRequest) -> Result<(), InvalidInput> {
+    fn check_len(field: &str, value: &str, max: usize) -> Result<(), InvalidInput> {
+        if value.len() > max {
+            return Err(InvalidInput::new(format!("{} is {} bytes; at most {} are allowed", field, value.len(), max)));
+        }
+        Ok(())
+    }
+
+    if let Some(name) = &request.name {
+        check_len("Name", name, MAX_NAME_LEN)?;
+    }
+    if let Some(description) = &request.description {
+        check_len("Description", description, MAX_DESCRIPTION_LEN)?;
+    }
+    if request.tags.len() > MAX_TAGS {
+        return Err(InvalidInput::new(format!("{} tags given; at most {} are allowed", request.tags.len(), MAX_TAGS)));
+    }
+    for tag in &request.tags {
+        check_len(&format!("Tag {:?}", tag), tag, MAX_TAG_LEN)?;
+    }
+    Ok(())
+}
+
+/// Whether `endpoint` lacks a scheme. `agent.example:8080` parses as a URI
+/// whose scheme is `agent.example`, so a "scheme" with a dot in it, or one
+/// followed by a port number, is taken to be a host.
+fn missing_scheme(endpoint: &str) -> bool {
+    let Some((scheme, rest)) = endpoint.split_once(':') else {
+        return true;
+    };
+    let port = rest.split('/').next().unwrap_or_default();
+    scheme.contains('.') || (!port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DID: &str = "did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F";
+
+    #[test]
+    fn dids_missing_their_prefix_get_a_suggestion() {
+        assert!(check_did(DID).is_ok());
+        assert!(check_did("did:web:example.com").is_ok());
+
+        let bare = DID.strip_prefix("did:key:").unwrap();
+        assert_eq!(check_did(bare).unwrap_err().suggestion.as_deref(), Some(DID));
+        assert_eq!(check_did(&DID[4..]).unwrap_err().suggestion.as_deref(), Some(DID));
+
+        let err = check_did("did:key:z6Mkk").unwrap_err();
+        assert!(err.message.contains("not a valid did:key DID"), "{}", err);
+        assert!(err.suggestion.is_none());
+        assert!(check_did("did:Key:z6Mk").unwrap_err().message.contains("method \"Key\""));
+        assert!(check_did("alice").is_err());
+    }
+
+    #[test]
+    fn endpoints_need_a_scheme() {
+        for ok in [
+            "wss://agent.example:8080/inbox",
+            "https://agent.example",
+            "coap://sensor:5683",
+            "grpc://agent.example:50051",
+            "nats://broker.example/agents.inbox",
+            "did:web:agent.example",
+        ] {
+            assert!(check_endpoint(ok).is_ok(), "{}", ok);
+        }
+
+        let err = check_endpoint("agent.example:8080").unwrap_err();
+        assert_eq!(err.suggestion.as_deref(), Some("wss://agent.example:8080"));
+        assert_eq!(check_endpoint("agent.example/inbox").unwrap_err().suggestion.as_deref(), Some("https://agent.example/inbox"));
+        assert_eq!(check_endpoint("localhost:8080").unwrap_err().suggestion.as_deref(), Some("wss://localhost:8080"));
+        assert!(check_endpoint("https://").is_err());
+        assert!(check_endpoint("grpc://").unwrap_err().message.contains("no host"));
+        assert!(check_endpoint("grpc://:50051").is_err());
+        assert!(check_endpoint(" ").is_err());
+    }
+
+    #[test]
+    fn metadata_is_bounded() {
+        let request = RegisterRequest {
+            name: Some("n".repeat(MAX_NAME_LEN)),
+            description: Some("d".repeat(MAX_DESCRIPTION_LEN)),
+            tags: vec!["t".repeat(MAX_TAG_LEN); MAX_TAGS],
+            ..RegisterRequest::new("wss://agent.example")
+        };
+        assert!(check_metadata(&request).is_ok());
+
+        let long_name = RegisterRequest { name: Some("n".repeat(MAX_NAME_LEN + 1)), ..request.clone() };
+        assert!(check_metadata(&long_name).unwrap_err().message.starts_with("Name is 129 bytes"));
+        let long_description = RegisterRequest { description: Some("d".repeat(MAX_DESCRIPTION_LEN + 1)), ..request.clone() };
+        assert!(check_metadata(&long_description).is_err());
+        let mut too_many = request.clone();
+        too_many.tags.push("extra".to_string());
+        assert!(check_metadata(&too_many).unwrap_err().message.contains("at most 32"));
+        let long_tag = RegisterRequest { tags: vec!["t".repeat(MAX_TAG_LEN + 1)], ..request };
+        assert!(check_metadata(&long_tag).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Log formatting shared by the agent-reach binaries
+//!
+//! Logs are human-readable by default. Setting `REACH_LOG_FORMAT=json` emits
+//! one JSON object per line, with event fields such as `did` and `session`
+//! kept as structured keys. Each binary installs its own subscriber, with its
+//! own filter and writer, around [`fmt_layer`].
+
+use tracing::Subscriber;
+use tracing_subscriber::{fmt::MakeWriter, registry::LookupSpan, Layer};
+
+/// Env var selecting the log format
+pub const LOG_FORMAT_ENV: &str = "REACH_LOG_FORMAT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl LogFormat {
+    /// Parse the `REACH_LOG_FORMAT` value; anything other than `json` is pretty
+    pub fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some(v) if v.eq_ignore_ascii_case("json") => LogFormat::Json,
+            _ => LogFormat::Pretty,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::parse(std::env::var(LOG_FORMAT_ENV).ok().as_deref())
+    }
+}
+
+/// An error and its causes on a single line, so line-based log parsers see
+/// one event rather than a fragment per cause
+pub fn error_chain(error: &anyhow::Error) -> String {
+    format!("{:#}", error).replace('\n', " ")
+}
+
+/// Formatting layer for the chosen format
+pub fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(format: LogFormat) -> String {
+        capture_with(format, || {
+            tracing::info!(did = "did:key:z6Mktest", session = "abc", "Session created");
+        })
+    }
+
+    fn capture_with(format: LogFormat, log: impl FnOnce()) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(fmt_layer(format, move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, log);
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn parses_format() {
+        assert_eq!(LogFormat::parse(None), LogFormat::Pretty);
+        assert_eq!(LogFormat::parse(Some("pretty")), LogFormat::Pretty);
+        assert_eq!(LogFormat::parse(Some("json")), LogFormat::Json);
+        assert_eq!(LogFormat::parse(Some("JSON")), LogFormat::Json);
+    }
+
+    #[test]
+    fn json_format_keeps_fields_structured() {
+        let line: serde_json::Value = serde_json::from_str(capture(LogFormat::Json).trim()).unwrap();
+        assert_eq!(line["fields"]["did"], "did:key:z6Mktest");
+        assert_eq!(line["fields"]["session"], "abc");
+        assert_eq!(line["fields"]["message"], "Session created");
+    }
+
+    #[test]
+    fn pretty_format_is_not_json() {
+        let output = capture(LogFormat::Pretty);
+        assert!(output.contains("Session created"));
+        assert!(serde_json::from_str::<serde_json::Value>(output.trim()).is_err());
+    }
+
+    #[test]
+    fn error_chain_is_one_field_on_one_line() {
+        let error = anyhow::anyhow!("connection refused\n(is the database up?)").context("Failed to open registry");
+        let output = capture_with(LogFormat::Pretty, || {
+            tracing::error!(error = %error_chain(&error), "agent-reach-server failed");
+        });
+
+        assert_eq!(output.lines().count(), 1, "{}", output);
+        assert!(output.contains("Failed to open registry: connection refused (is the database up?)"));
+    }
+}
@@ -0,0 +1,278 @@
+//! On-disk cache of registry sessions and recent lookups
+//!
+//! Lives next to the identity file so restarts don't need a fresh handshake
+//! or re-resolve known peers. The cache is best-effort: a missing, corrupt
+//! or unreadable file is treated as empty.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use agent_reach_client::{PublicKey, Session};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Number of lookup results kept
+const MAX_LOOKUPS: usize = 64;
+
+/// Cache file location for a given identity file
+///
+/// Derived from the identity file name so each identity gets its own cache
+/// (`identity.json` -> `identity.reach-cache.json`).
+pub fn cache_path(identity_path: &Path) -> PathBuf {
+    let stem = identity_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "identity".to_string());
+    identity_path.with_file_name(format!("{}.reach-cache.json", stem))
+}
+
+/// A resolved peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedLookup {
+    /// Registry that answered
+    #[serde(default)]
+    pub registry: String,
+    pub did: String,
+    pub endpoint: String,
+    pub expires_at: i64,
+    /// Registry's validator for the entry, sent back to skip unchanged bodies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// Normalized handle the agent registered, so handle resolutions can be
+    /// served from the same entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+    /// Key the registry decoded from the DID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<PublicKey>,
+}
+
+impl CachedLookup {
+    pub fn is_fresh(&self) -> bool {
+        chrono::Utc::now().timestamp() < self.expires_at
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ClientCache {
+    /// DID the sessions were issued to
+    did: String,
+    /// Session with each registry, by URL
+    #[serde(default)]
+    sessions: BTreeMap<String, Session>,
+    /// Most recently used first
+    #[serde(default)]
+    lookups: VecDeque<CachedLookup>,
+}
+
+impl ClientCache {
+    /// Load the cache, discarding it if it belongs to another DID and
+    /// dropping what came from registries other than `registry_urls`
+    pub fn load(path: &Path, registry_urls: &[String], did: &str) -> Self {
+        let empty = Self {
+            did: did.to_string(),
+            ..Default::default()
+        };
+
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return empty,
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Ignoring unreadable cache file");
+                return empty;
+            }
+        };
+
+        let mut cache: Self = match serde_json::from_str(&content) {
+            Ok(cache) => cache,
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "Ignoring corrupt cache file");
+                return empty;
+            }
+        };
+
+        if cache.did != did {
+            return empty;
+        }
+
+        cache.retain_registries(registry_urls);
+        cache.sessions.retain(|_, session| session.is_valid());
+        cache.lookups.retain(CachedLookup::is_fresh);
+        cache
+    }
+
+    /// Forget sessions with and lookups from registries not in `registry_urls`
+    pub fn retain_registries(&mut self, registry_urls: &[String]) {
+        self.sessions.retain(|url, _| registry_urls.contains(url));
+        self.lookups.retain(|lookup| registry_urls.contains(&lookup.registry));
+    }
+
+    /// Write the cache atomically; the file holds a session token so it is
+    /// only readable by the owner
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        let tmp = path.with_extension("json.tmp");
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(&tmp)?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// The cached session with `registry_url`, if it hasn't expired
+    pub fn session(&self, registry_url: &str) -> Option<&Session> {
+        self.sessions.get(registry_url).filter(|s| s.is_valid())
+    }
+
+    pub fn set_session(&mut self, registry_url: &str, session: Option<Session>) {
+        match session {
+            Some(session) => self.sessions.insert(registry_url.to_string(), session),
+            None => self.sessions.remove(registry_url),
+        };
+    }
+
+    /// A fresh cached lookup, marked as most recently used
+    pub fn lookup(&mut self, did: &str) -> Option<CachedLookup> {
+        let index = self.lookups.iter().position(|l| l.did == did)?;
+        let entry = self.lookups.remove(index)?;
+        if !entry.is_fresh() {
+            return None;
+        }
+        self.lookups.push_front(entry.clone());
+        Some(entry)
+    }
+
+    /// A fresh cached lookup for the agent holding `handle`, marked as most
+    /// recently used
+    pub fn resolve_handle(&mut self, handle: &str) -> Option<CachedLookup> {
+        let did = self.lookups.iter().find(|l| l.handle.as_deref() == Some(handle))?.did.clone();
+        self.lookup(&did)
+    }
+
+    /// ETag of a fresh cached lookup `registry_url` answered, for
+    /// revalidating it there
+    pub fn etag(&self, did: &str, registry_url: &str) -> Option<String> {
+        self.lookups
+            .iter()
+            .find(|l| l.did == did && l.registry == registry_url && l.is_fresh())
+            .and_then(|l| l.etag.clone())
+    }
+
+    pub fn insert_lookup(&mut self, entry: CachedLookup) {
+        self.remove_lookup(&entry.did);
+        // A handle belongs to one DID at a time
+        if let Some(handle) = &entry.handle {
+            self.remove_handle(handle);
+        }
+        self.lookups.push_front(entry);
+        self.lookups.truncate(MAX_LOOKUPS);
+    }
+
+    pub fn remove_lookup(&mut self, did: &str) {
+        self.lookups.retain(|l| l.did != did);
+    }
+
+    pub fn remove_handle(&mut self, handle: &str) {
+        self.lookups.retain(|l| l.handle.as_deref() != Some(handle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTRY: &str = "https://registry.example";
+    const FUTURE: i64 = 4102444800;
+
+    fn path() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("agent-reach-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        cache_path(&dir.join("identity.json"))
+    }
+
+    fn entry(did: &str, handle: Option<&str>, expires_at: i64) -> CachedLookup {
+        CachedLookup {
+            registry: REGISTRY.to_string(),
+            did: did.to_string(),
+            endpoint: format!("wss://{}", did),
+            expires_at,
+            etag: Some(format!("\"{}\"", did)),
+            handle: handle.map(str::to_owned),
+            public_key: None,
+        }
+    }
+
+    fn registries() -> Vec<String> {
+        vec![REGISTRY.to_string()]
+    }
+
+    #[test]
+    fn the_cache_file_is_named_after_the_identity() {
+        assert_eq!(cache_path(Path::new("/keys/work.json")), Path::new("/keys/work.reach-cache.json"));
+    }
+
+    #[test]
+    fn saved_caches_load_back_for_the_same_did() {
+        let path = path();
+        let mut cache = ClientCache::load(&path, &registries(), "did:key:me");
+        cache.set_session(REGISTRY, Some(Session { session_id: "session-1".to_string(), expires_at: FUTURE }));
+        cache.set_session("https://old.example", Some(Session { session_id: "session-2".to_string(), expires_at: FUTURE }));
+        cache.insert_lookup(entry("did:key:alice", Some("alice@example.com"), FUTURE));
+        cache.insert_lookup(entry("did:key:stale", None, 1));
+        cache.save(&path).unwrap();
+
+        let mut loaded = ClientCache::load(&path, &registries(), "did:key:me");
+        assert_eq!(loaded.session(REGISTRY).unwrap().session_id, "session-1");
+        assert!(loaded.session("https://old.example").is_none(), "other registries are dropped");
+        assert_eq!(loaded.etag("did:key:alice", REGISTRY).as_deref(), Some("\"did:key:alice\""));
+        assert_eq!(loaded.resolve_handle("alice@example.com").unwrap().did, "did:key:alice");
+        assert!(loaded.lookup("did:key:stale").is_none(), "stale lookups are dropped");
+
+        let other = ClientCache::load(&path, &registries(), "did:key:someone-else");
+        assert!(other.session(REGISTRY).is_none());
+    }
+
+    #[test]
+    fn corrupt_files_load_as_empty() {
+        let path = path();
+        fs::write(&path, "{ not json").unwrap();
+        let mut cache = ClientCache::load(&path, &registries(), "did:key:me");
+        assert!(cache.session(REGISTRY).is_none());
+        assert!(cache.lookup("did:key:alice").is_none());
+    }
+
+    #[test]
+    fn a_handle_belongs_to_the_latest_did() {
+        let mut cache = ClientCache::default();
+        cache.insert_lookup(entry("did:key:alice", Some("shared@example.com"), FUTURE));
+        cache.insert_lookup(entry("did:key:bob", Some("shared@example.com"), FUTURE));
+        assert!(cache.lookup("did:key:alice").is_none());
+        assert_eq!(cache.resolve_handle("shared@example.com").unwrap().did, "did:key:bob");
+
+        cache.remove_handle("shared@example.com");
+        assert!(cache.lookup("did:key:bob").is_none());
+    }
+
+    #[test]
+    fn the_least_recently_used_lookup_is_dropped() {
+        let mut cache = ClientCache::default();
+        for n in 0..MAX_LOOKUPS {
+            cache.insert_lookup(entry(&format!("did:key:{}", n), None, FUTURE));
+        }
+        // Using the oldest makes the second oldest the one to go
+        assert!(cache.lookup("did:key:0").is_some());
+        cache.insert_lookup(entry("did:key:new", None, FUTURE));
+        assert!(cache.lookup("did:key:0").is_some());
+        assert!(cache.lookup("did:key:1").is_none());
+    }
+}
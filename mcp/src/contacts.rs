@@ -0,0 +1,223 @@
+//! Local address book of agents the user wants to reach again
+//!
+//! Lives next to the identity file, like the cache, but holds the user's own
+//! data: a corrupt file is reported rather than replaced. Every change
+//! re-reads the file and writes it back through a temporary file and a
+//! rename, so concurrent writers never leave a half-written file behind.
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Contacts file location for a given identity file
+/// (`identity.json` -> `identity.contacts.json`)
+pub fn contacts_path(identity_path: &Path) -> PathBuf {
+    let stem = identity_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "identity".to_string());
+    identity_path.with_file_name(format!("{}.contacts.json", stem))
+}
+
+/// A saved agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contact {
+    pub did: String,
+    /// Short name that can be given in place of the DID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// When the contact was saved (Unix seconds)
+    pub added_at: i64,
+}
+
+impl Contact {
+    /// Whether `name` is this contact's DID or, ignoring case, its nickname
+    fn is(&self, name: &str) -> bool {
+        self.did == name || self.nickname.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContactError {
+    #[error("Nickname {nickname:?} already belongs to {did}")]
+    NicknameTaken { nickname: String, did: String },
+    #[error("Nickname {0:?} is not allowed: {1}")]
+    InvalidNickname(String, &'static str),
+    #[error("Contacts file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ContactsFile {
+    contacts: Vec<Contact>,
+}
+
+/// The contacts file, serializing this process's changes to it
+pub struct ContactBook {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl ContactBook {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, lock: Mutex::new(()) }
+    }
+
+    /// Every contact, in the order they were added
+    pub fn list(&self) -> Result<Vec<Contact>, ContactError> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(self.load()?.contacts)
+    }
+
+    /// The contact with this DID or nickname
+    pub fn find(&self, name: &str) -> Result<Option<Contact>, ContactError> {
+        Ok(self.list()?.into_iter().find(|c| c.is(name)))
+    }
+
+    /// Save a contact, replacing any earlier one for the same DID
+    pub fn add(&self, contact: Contact) -> Result<(), ContactError> {
+        if let Some(nickname) = &contact.nickname {
+            validate_nickname(nickname)?;
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut file = self.load()?;
+        if let Some(nickname) = &contact.nickname {
+            if let Some(holder) = file.contacts.iter().find(|c| c.did != contact.did && c.is(nickname)) {
+                return Err(ContactError::NicknameTaken { nickname: nickname.clone(), did: holder.did.clone() });
+            }
+        }
+        match file.contacts.iter_mut().find(|c| c.did == contact.did) {
+            Some(existing) => *existing = contact,
+            None => file.contacts.push(contact),
+        }
+        self.save(&file)
+    }
+
+    /// Forget the contact with this DID or nickname, returning it
+    pub fn remove(&self, name: &str) -> Result<Option<Contact>, ContactError> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut file = self.load()?;
+        let Some(index) = file.contacts.iter().position(|c| c.is(name)) else {
+            return Ok(None);
+        };
+        let removed = file.contacts.remove(index);
+        self.save(&file)?;
+        Ok(Some(removed))
+    }
+
+    fn io_error(&self, source: std::io::Error) -> ContactError {
+        ContactError::Io { path: self.path.clone(), source }
+    }
+
+    fn load(&self) -> Result<ContactsFile, ContactError> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ContactsFile::default()),
+            Err(e) => return Err(self.io_error(e)),
+        };
+        serde_json::from_str(&content).map_err(|e| self.io_error(std::io::Error::new(ErrorKind::InvalidData, e)))
+    }
+
+    /// Write the file atomically; the temporary file is unique to this
+    /// process so two writers never share one
+    fn save(&self, file: &ContactsFile) -> Result<(), ContactError> {
+        let content = serde_json::to_vec_pretty(file).map_err(|e| self.io_error(e.into()))?;
+        let tmp = self.path.with_extension(format!("json.{}.tmp", std::process::id()));
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let write = || {
+            let mut out = options.open(&tmp)?;
+            out.write_all(&content)?;
+            out.sync_all()?;
+            fs::rename(&tmp, &self.path)
+        };
+        write().map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            self.io_error(e)
+        })
+    }
+}
+
+/// Nicknames stand in for DIDs, so they can't look like one
+fn validate_nickname(nickname: &str) -> Result<(), ContactError> {
+    let invalid = |reason| Err(ContactError::InvalidNickname(nickname.to_string(), reason));
+    if nickname.trim().is_empty() {
+        return invalid("it is empty");
+    }
+    if nickname.starts_with("did:") {
+        return invalid("it looks like a DID");
+    }
+    if nickname.trim() != nickname {
+        return invalid("it has leading or trailing spaces");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> ContactBook {
+        let dir = std::env::temp_dir().join(format!("agent-reach-contacts-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        ContactBook::new(contacts_path(&dir.join("identity.json")))
+    }
+
+    fn contact(did: &str, nickname: Option<&str>) -> Contact {
+        Contact { did: did.to_string(), nickname: nickname.map(str::to_owned), notes: None, added_at: 0 }
+    }
+
+    #[test]
+    fn contacts_are_found_by_did_or_nickname() {
+        let book = book();
+        book.add(contact("did:key:alice", Some("Alice"))).unwrap();
+        book.add(contact("did:key:bob", None)).unwrap();
+
+        assert_eq!(book.find("alice").unwrap().unwrap().did, "did:key:alice");
+        assert_eq!(book.find("did:key:bob").unwrap().unwrap().did, "did:key:bob");
+        assert!(book.find("carol").unwrap().is_none());
+
+        // Re-adding a DID replaces it
+        book.add(contact("did:key:alice", Some("al"))).unwrap();
+        assert_eq!(book.list().unwrap().len(), 2);
+        assert!(book.find("alice").unwrap().is_none());
+
+        assert_eq!(book.remove("al").unwrap().unwrap().did, "did:key:alice");
+        assert_eq!(book.list().unwrap(), vec![contact("did:key:bob", None)]);
+    }
+
+    #[test]
+    fn nicknames_are_unique_and_not_dids() {
+        let book = book();
+        book.add(contact("did:key:alice", Some("alice"))).unwrap();
+
+        let err = book.add(contact("did:key:mallory", Some("ALICE"))).unwrap_err();
+        assert!(matches!(err, ContactError::NicknameTaken { did, .. } if did == "did:key:alice"));
+        assert!(matches!(book.add(contact("did:key:x", Some("did:key:y"))), Err(ContactError::InvalidNickname(..))));
+    }
+
+    #[test]
+    fn corrupt_files_are_reported_not_replaced() {
+        let book = book();
+        fs::write(&book.path, "not json").unwrap();
+
+        assert!(matches!(book.add(contact("did:key:alice", None)), Err(ContactError::Io { .. })));
+        assert_eq!(fs::read_to_string(&book.path).unwrap(), "not json");
+    }
+}
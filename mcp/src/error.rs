@@ -0,0 +1,167 @@
+//! Typed tool errors
+//!
+//! Every failure carries a stable machine-readable code so calling agents
+//! don't have to parse the message text.
+
+use agent_reach_client::{Error, InvalidInput, RegistryError, PROTOCOLS};
+
+use crate::contacts::ContactError;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Stable error codes reported in tool results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Missing or malformed tool arguments
+    InvalidParams,
+    /// The DID has no registration
+    NotFound,
+    /// The registration exists but its TTL has passed
+    Expired,
+    /// The registry rejected the handshake or session
+    Unauthorized,
+    /// The session lapsed; a new handshake is needed
+    SessionExpired,
+    /// The registry speaks none of this client's protocols
+    UnsupportedProtocol,
+    /// The registry refused a conflicting write
+    Conflict,
+    /// The registry could not be reached
+    NetworkError,
+    /// The registry's certificate doesn't carry a pinned public key
+    PinMismatch,
+    /// The registry returned an unexpected error or response
+    RegistryError,
+    /// The contacts file couldn't be read or written
+    StorageError,
+    /// An endpoint failed its liveness probe
+    Unreachable,
+    /// The tool name is not recognised
+    UnknownTool,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct ToolError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Extra result-specific fields (e.g. the DID and endpoint that failed)
+    pub fields: Map<String, Value>,
+}
+
+impl ToolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            fields: Map::new(),
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidParams, message)
+    }
+
+    /// Attach a result-specific field
+    pub fn with_field(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.fields.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Classify a registry refusal by its stable code, or else its status
+    fn from_registry(error: RegistryError) -> Self {
+        if error.is("session_expired") {
+            return Self::new(ErrorCode::SessionExpired, error.message);
+        }
+        if error.is("unsupported_protocol") {
+            return Self::new(ErrorCode::UnsupportedProtocol, error.message).with_field("supported", error.supported);
+        }
+        let code = match error.status.as_u16() {
+            400 => ErrorCode::InvalidParams,
+            401 | 403 => ErrorCode::Unauthorized,
+            404 => ErrorCode::NotFound,
+            409 => ErrorCode::Conflict,
+            410 => ErrorCode::Expired,
+            _ => ErrorCode::RegistryError,
+        };
+        match error.expires_at {
+            Some(expires_at) if code == ErrorCode::Expired => Self::new(code, error.message).with_field("expires_at", expires_at),
+            _ => Self::new(code, error.message),
+        }
+    }
+
+    /// Structured form: `{"ok": false, "code": ..., "message": ..., ...fields}`
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("ok".to_string(), Value::Bool(false));
+        object.insert("code".to_string(), serde_json::to_value(self.code).unwrap_or(Value::Null));
+        object.insert("message".to_string(), Value::String(self.message.clone()));
+        object.extend(self.fields.clone());
+        Value::Object(object)
+    }
+}
+
+impl From<Error> for ToolError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::InvalidInput(e) => e.into(),
+            Error::Network { .. } => Self::new(ErrorCode::NetworkError, e.to_string()),
+            Error::PinMismatch { .. } => Self::new(ErrorCode::PinMismatch, e.to_string()),
+            Error::InvalidResponse { .. } => Self::new(ErrorCode::RegistryError, e.to_string()),
+            Error::Registry(error) => Self::from_registry(error),
+            Error::Handshake { source, .. } if source.is("unsupported_protocol") => {
+                let message = format!(
+                    "Registry shares no protocol with this client (which speaks {}); {}. Upgrade agent-reach-mcp or use another registry",
+                    PROTOCOLS.join(", "),
+                    source
+                );
+                Self { message, ..Self::from_registry(source) }
+            }
+            // The registry failing mid-handshake isn't a refusal
+            Error::Handshake { ref source, .. } if source.status.is_server_error() => {
+                Self::new(ErrorCode::RegistryError, e.to_string())
+            }
+            Error::Handshake { .. } | Error::Proof(_) => Self::new(ErrorCode::Unauthorized, e.to_string()),
+        }
+    }
+}
+
+impl From<InvalidInput> for ToolError {
+    fn from(e: InvalidInput) -> Self {
+        let error = Self::invalid_params(e.message);
+        match e.suggestion {
+            Some(suggestion) => error.with_field("suggestion", suggestion),
+            None => error,
+        }
+    }
+}
+
+impl From<ContactError> for ToolError {
+    fn from(e: ContactError) -> Self {
+        let code = match e {
+            ContactError::NicknameTaken { .. } => ErrorCode::Conflict,
+            ContactError::InvalidNickname(..) => ErrorCode::InvalidParams,
+            ContactError::Io { .. } => ErrorCode::StorageError,
+        };
+        Self::new(code, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_json_has_code_and_fields() {
+        let error = ToolError::new(ErrorCode::NotFound, "Agent not found in registry")
+            .with_field("did", "did:key:z6Mkexample");
+
+        assert_eq!(error.to_json(), serde_json::json!({
+            "ok": false,
+            "code": "not_found",
+            "message": "Agent not found in registry",
+            "did": "did:key:z6Mkexample",
+        }));
+    }
+}
@@ -0,0 +1,230 @@
+//! MCP over HTTP, for running the server as a network service
+//!
+//! Speaks the SSE transport rmcp clients use: `GET /sse` opens an event
+//! stream whose first `endpoint` event names the URL to POST the client's
+//! messages to, and the server's messages arrive as `message` events. Each
+//! stream is its own MCP session, served by a clone of the server that
+//! shares the identity, registries and their sessions, cache and contacts,
+//! so `reach_switch_registry` in one session moves them all. Sessions are
+//! capped in number and closed once their client goes quiet.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{stream, SinkExt, StreamExt};
+use rmcp::{
+    handler::server::ServerHandler,
+    model::{ClientJsonRpcMessage, ServerJsonRpcMessage},
+    ServiceExt,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::PollSender;
+
+/// Environment variable holding the bearer token HTTP clients must send
+pub const TOKEN_ENV: &str = "REACH_MCP_TOKEN";
+
+/// Where clients open their event stream
+pub const SSE_PATH: &str = "/sse";
+
+/// Where clients POST their messages, with `?sessionId=`
+pub const MESSAGE_PATH: &str = "/message";
+
+/// Messages buffered per session and direction
+const CHANNEL_SIZE: usize = 64;
+
+/// Sessions open at once by default
+pub const DEFAULT_MAX_SESSIONS: usize = 64;
+
+/// Default time a session may go without a client message (seconds)
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 1800;
+
+/// Bounds on the sessions the server keeps open
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLimits {
+    /// Most sessions open at once; `GET /sse` gets 503 beyond it
+    pub max_sessions: usize,
+    /// How long a session may go without a client message before it's closed
+    pub idle_timeout: Duration,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+        }
+    }
+}
+
+/// An open session
+struct Session {
+    /// The way into the session's MCP service; dropping it ends the service
+    to_server: mpsc::Sender<ClientJsonRpcMessage>,
+    /// When the client last posted a message
+    last_active: Instant,
+}
+
+/// Open sessions, by ID
+type Sessions = Arc<Mutex<HashMap<String, Session>>>;
+
+fn lock(sessions: &Sessions) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+    sessions.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The bearer token from the environment, if one is set
+pub fn token_from_env() -> Option<String> {
+    std::env::var(TOKEN_ENV).ok().filter(|token| !token.is_empty())
+}
+
+#[derive(Clone)]
+struct App<S> {
+    server: S,
+    sessions: Sessions,
+    limits: SessionLimits,
+}
+
+/// Routes serving `server` to every client that connects, within `limits`;
+/// with a `token`, both routes want `Authorization: Bearer <token>`
+pub fn router<S: ServerHandler>(server: S, token: Option<String>, limits: SessionLimits) -> Router {
+    let app = App { server, sessions: Sessions::default(), limits };
+    let router = Router::new()
+        .route(SSE_PATH, get(open_session::<S>))
+        .route(MESSAGE_PATH, post(post_message::<S>))
+        .with_state(app);
+    match token {
+        Some(token) => router.layer(middleware::from_fn_with_state(Arc::<str>::from(token), require_token)),
+        None => router,
+    }
+}
+
+/// Reject requests without the configured bearer token
+async fn require_token(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => next.run(request).await,
+        _ => (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "Missing or wrong bearer token")
+            .into_response(),
+    }
+}
+
+/// Compare without short-circuiting, so timing doesn't leak the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Forgets its session when the client's event stream goes away, which
+/// ends the session's MCP service
+struct SessionGuard {
+    id: String,
+    sessions: Sessions,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        lock(&self.sessions).remove(&self.id);
+    }
+}
+
+/// Close session `id` once its client has posted nothing for `idle_timeout`
+async fn expire_when_idle(sessions: Sessions, id: String, idle_timeout: Duration) {
+    let mut deadline = Instant::now() + idle_timeout;
+    loop {
+        tokio::time::sleep_until(deadline).await;
+        let mut open = lock(&sessions);
+        let Some(session) = open.get(&id) else {
+            return;
+        };
+        deadline = session.last_active + idle_timeout;
+        if deadline <= Instant::now() {
+            open.remove(&id);
+            tracing::debug!(session = %id, "MCP session idle, closing");
+            return;
+        }
+    }
+}
+
+/// GET /sse: start a session and stream the server's messages
+async fn open_session<S: ServerHandler>(State(app): State<App<S>>) -> Response {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let (to_server, from_client) = mpsc::channel::<ClientJsonRpcMessage>(CHANNEL_SIZE);
+    let (to_client, from_server) = mpsc::channel::<ServerJsonRpcMessage>(CHANNEL_SIZE);
+    {
+        let mut sessions = lock(&app.sessions);
+        if sessions.len() >= app.limits.max_sessions {
+            tracing::warn!(max_sessions = app.limits.max_sessions, "Refusing MCP session, too many open");
+            return (StatusCode::SERVICE_UNAVAILABLE, "Too many open sessions").into_response();
+        }
+        sessions.insert(id.clone(), Session { to_server, last_active: Instant::now() });
+    }
+    tokio::spawn(expire_when_idle(app.sessions.clone(), id.clone(), app.limits.idle_timeout));
+
+    let server = app.server.clone();
+    let session = id.clone();
+    tokio::spawn(async move {
+        let to_client = PollSender::new(to_client).sink_map_err(std::io::Error::other);
+        let transport = (to_client, ReceiverStream::new(from_client));
+        match server.serve(transport).await {
+            Ok(running) => {
+                let _ = running.waiting().await;
+            }
+            Err(e) => tracing::warn!(session = %session, error = %e, "MCP session failed to start"),
+        }
+        tracing::debug!(session = %session, "MCP session closed");
+    });
+    tracing::debug!(session = %id, "MCP session opened");
+
+    let endpoint = Event::default().event("endpoint").data(format!("{}?sessionId={}", MESSAGE_PATH, id));
+    let guard = SessionGuard { id, sessions: app.sessions };
+    let messages = ReceiverStream::new(from_server).map(move |message| {
+        // The guard lives as long as the stream
+        let _ = &guard;
+        Event::default().event("message").json_data(message)
+    });
+    Sse::new(stream::once(async { Ok(endpoint) }).chain(messages))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageParams {
+    session_id: String,
+}
+
+/// POST /message: hand a client message to its session
+async fn post_message<S: ServerHandler>(
+    State(app): State<App<S>>,
+    Query(params): Query<MessageParams>,
+    Json(message): Json<ClientJsonRpcMessage>,
+) -> StatusCode {
+    let session = lock(&app.sessions).get_mut(&params.session_id).map(|session| {
+        session.last_active = Instant::now();
+        session.to_server.clone()
+    });
+    let Some(session) = session else {
+        return StatusCode::NOT_FOUND;
+    };
+    match session.send(message).await {
+        Ok(()) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::GONE,
+    }
+}
@@ -0,0 +1,360 @@
+//! Identity file handling (same format as agent-id-mcp)
+//!
+//! Version 1 files hold the private key as plain base64. Version 2 files hold
+//! it encrypted with XChaCha20-Poly1305 under a key derived from a passphrase
+//! with argon2id.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use agent_id::RootKey;
+
+/// Plaintext identity file version
+const VERSION_PLAINTEXT: u32 = 1;
+
+/// Encrypted identity file version
+const VERSION_ENCRYPTED: u32 = 2;
+
+/// Passphrase given directly
+pub const PASSPHRASE_ENV: &str = "REACH_IDENTITY_PASSPHRASE";
+
+/// File containing the passphrase
+pub const PASSPHRASE_FILE_ENV: &str = "REACH_IDENTITY_PASSPHRASE_FILE";
+
+/// Command that prints the passphrase on stdout
+pub const ASKPASS_ENV: &str = "REACH_IDENTITY_ASKPASS";
+
+/// Stored identity format (matches agent-id-mcp)
+#[derive(Serialize, Deserialize)]
+struct StoredIdentity {
+    version: u32,
+    did: String,
+    /// Base64 private key (version 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private_key: Option<String>,
+    /// Encrypted private key (version 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_key: Option<EncryptedKey>,
+    created: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedKey {
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Default identity directory (same as agent-id-mcp)
+fn identity_dir() -> PathBuf {
+    directories::ProjectDirs::from("ai", "agent-id", "agent-id")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("~/.agent-id"))
+}
+
+/// Check a profile name from `--profile` or `REACH_PROFILE`
+///
+/// The name becomes part of a file name in the identity directory, so path
+/// separators and `..` are refused rather than letting it point elsewhere.
+pub fn parse_profile(profile: &str) -> Result<String, String> {
+    if profile.is_empty() {
+        return Err("profile name is empty".to_string());
+    }
+    if profile.contains(['/', '\\', '\0']) || profile.contains("..") {
+        return Err(format!("profile name '{}' must not contain '/', '\\' or '..'", profile));
+    }
+    Ok(profile.to_string())
+}
+
+/// Identity file location: an explicit path, a named profile, or the default
+pub fn identity_path(explicit: Option<PathBuf>, profile: Option<&str>) -> PathBuf {
+    match (explicit, profile) {
+        (Some(path), _) => path,
+        (None, Some(profile)) => identity_dir().join(format!("identity.{}.json", profile)),
+        (None, None) => identity_dir().join("identity.json"),
+    }
+}
+
+/// Read the passphrase from the environment, a file, or an askpass command
+///
+/// Returns `None` if no passphrase source is configured.
+pub fn passphrase_from_env() -> Result<Option<String>> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(Some(passphrase));
+    }
+
+    if let Ok(path) = std::env::var(PASSPHRASE_FILE_ENV) {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read passphrase file {}", path))?;
+        return Ok(Some(trim_newline(content)));
+    }
+
+    if let Ok(program) = std::env::var(ASKPASS_ENV) {
+        let output = Command::new(&program)
+            .arg("agent-reach-mcp identity passphrase: ")
+            .output()
+            .with_context(|| format!("Failed to run askpass command {}", program))?;
+        if !output.status.success() {
+            bail!("Askpass command {} exited with {}", program, output.status);
+        }
+        let passphrase = String::from_utf8(output.stdout)
+            .context("Askpass command returned a non-UTF-8 passphrase")?;
+        return Ok(Some(trim_newline(passphrase)));
+    }
+
+    Ok(None)
+}
+
+fn trim_newline(mut s: String) -> String {
+    while s.ends_with('\n') || s.ends_with('\r') {
+        s.pop();
+    }
+    s
+}
+
+/// Load identity from disk, decrypting it if needed
+pub fn load_identity(path: &Path) -> Result<RootKey> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read identity from {}", path.display()))?;
+    let stored: StoredIdentity = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse identity file {}", path.display()))?;
+
+    let key_bytes = match stored.version {
+        VERSION_PLAINTEXT => {
+            let private_key = stored.private_key.as_deref()
+                .context("Identity file has no private_key")?;
+            BASE64.decode(private_key)
+                .context("Failed to decode private key")?
+        }
+        VERSION_ENCRYPTED => {
+            let encrypted = stored.encrypted_key.as_ref()
+                .context("Identity file has no encrypted_key")?;
+            let passphrase = passphrase_from_env()?.with_context(|| format!(
+                "Identity file {} is encrypted; set {}, {} or {}",
+                path.display(), PASSPHRASE_ENV, PASSPHRASE_FILE_ENV, ASKPASS_ENV
+            ))?;
+            decrypt_key(encrypted, &stored.did, &passphrase).with_context(|| format!(
+                "Failed to decrypt identity {}", path.display()
+            ))?
+        }
+        other => bail!("Unsupported identity file version {} in {}", other, path.display()),
+    };
+
+    let key_array: [u8; 32] = key_bytes.try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid private key length"))?;
+    let key = RootKey::from_bytes(&key_array)
+        .context("Failed to create key from bytes")?;
+
+    if key.did().to_string() != stored.did {
+        bail!("Identity file {} is inconsistent: key does not match DID", path.display());
+    }
+    Ok(key)
+}
+
+/// Generate a new identity and write it to `path`, encrypted if a
+/// passphrase is configured
+///
+/// The file is written to a temporary file first and then linked into
+/// place, so it is never left half-written and an existing identity is
+/// never overwritten.
+pub fn generate_identity(path: &Path) -> Result<RootKey> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create identity directory {}", dir.display()))?;
+    }
+
+    let key = RootKey::generate();
+    let stored = stored_identity(&key, passphrase_from_env()?.as_deref(), chrono::Utc::now().to_rfc3339())?;
+    let content = serde_json::to_vec_pretty(&stored)?;
+
+    write_new_private_file(path, &content)
+        .with_context(|| format!("Failed to write identity to {}", path.display()))?;
+
+    Ok(key)
+}
+
+/// Rewrite a plaintext identity file in encrypted form
+pub fn encrypt_identity_file(path: &Path, passphrase: &str) -> Result<()> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read identity from {}", path.display()))?;
+    let stored: StoredIdentity = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse identity file {}", path.display()))?;
+    if stored.version == VERSION_ENCRYPTED {
+        bail!("Identity file {} is already encrypted", path.display());
+    }
+
+    let key = load_identity(path)?;
+    let encrypted = stored_identity(&key, Some(passphrase), stored.created)?;
+    let content = serde_json::to_vec_pretty(&encrypted)?;
+
+    replace_private_file(path, &content)
+        .with_context(|| format!("Failed to write identity to {}", path.display()))
+}
+
+fn stored_identity(key: &RootKey, passphrase: Option<&str>, created: String) -> Result<StoredIdentity> {
+    let did = key.did().to_string();
+    let (version, private_key, encrypted_key) = match passphrase {
+        Some(passphrase) => (VERSION_ENCRYPTED, None, Some(encrypt_key(&key.to_bytes(), &did, passphrase)?)),
+        None => (VERSION_PLAINTEXT, Some(BASE64.encode(key.to_bytes())), None),
+    };
+
+    Ok(StoredIdentity { version, did, private_key, encrypted_key, created })
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+fn encrypt_key(secret: &[u8], did: &str, passphrase: &str) -> Result<EncryptedKey> {
+    let params = Params::default();
+    let salt: [u8; 16] = rand::random();
+    let nonce: [u8; 24] = rand::random();
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt, params.clone())?.into());
+    // The DID is bound as associated data so the key can't be swapped into another file
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg: secret, aad: did.as_bytes() })
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+
+    Ok(EncryptedKey {
+        kdf: "argon2id".to_string(),
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        salt: BASE64.encode(salt),
+        cipher: "xchacha20poly1305".to_string(),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+fn decrypt_key(encrypted: &EncryptedKey, did: &str, passphrase: &str) -> Result<Vec<u8>> {
+    if encrypted.kdf != "argon2id" || encrypted.cipher != "xchacha20poly1305" {
+        bail!("Unsupported encryption scheme {}/{}", encrypted.kdf, encrypted.cipher);
+    }
+
+    let params = Params::new(encrypted.m_cost, encrypted.t_cost, encrypted.p_cost, None)
+        .map_err(|e| anyhow::anyhow!("Invalid key derivation parameters: {}", e))?;
+    let salt = BASE64.decode(&encrypted.salt).context("Invalid salt")?;
+    let nonce = BASE64.decode(&encrypted.nonce).context("Invalid nonce")?;
+    let ciphertext = BASE64.decode(&encrypted.ciphertext).context("Invalid ciphertext")?;
+    if nonce.len() != 24 {
+        bail!("Invalid nonce length");
+    }
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt, params)?.into());
+    cipher
+        .decrypt(XNonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: did.as_bytes() })
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase or corrupted identity file"))
+}
+
+fn private_open_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+/// Write `content` to a fresh owner-only temporary file beside `path`
+fn write_private_tmp(path: &Path, content: &[u8]) -> std::io::Result<PathBuf> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    let mut file = private_open_options().open(&tmp)?;
+    let result = file.write_all(content).and_then(|_| file.sync_all());
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(tmp)
+}
+
+/// Atomically create `path` with owner-only permissions, failing if it exists
+fn write_new_private_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = write_private_tmp(path, content)?;
+    // hard_link fails if the target exists, unlike rename
+    let result = fs::hard_link(&tmp, path);
+    let _ = fs::remove_file(&tmp);
+    result
+}
+
+/// Atomically replace `path` with owner-only permissions
+fn replace_private_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = write_private_tmp(path, content)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_stay_in_the_identity_directory() {
+        assert_eq!(parse_profile("work").unwrap(), "work");
+        assert_eq!(parse_profile("team.bot-2").unwrap(), "team.bot-2");
+        for name in ["", "../work", "a/b", "a\\b", "..", "x..y"] {
+            assert!(parse_profile(name).is_err(), "{:?} is refused", name);
+        }
+        assert_eq!(identity_path(None, Some("work")), identity_dir().join("identity.work.json"));
+    }
+
+    #[test]
+    fn encrypted_keys_need_the_passphrase_and_their_did() {
+        let key = RootKey::generate();
+        let did = key.did().to_string();
+        let encrypted = encrypt_key(&key.to_bytes(), &did, "correct horse").unwrap();
+
+        assert_eq!(decrypt_key(&encrypted, &did, "correct horse").unwrap(), key.to_bytes());
+        let wrong = decrypt_key(&encrypted, &did, "battery staple").unwrap_err();
+        assert_eq!(wrong.to_string(), "Wrong passphrase or corrupted identity file");
+        // The DID is bound in, so the key can't be moved to another file
+        let other = RootKey::generate().did().to_string();
+        assert!(decrypt_key(&encrypted, &other, "correct horse").is_err());
+    }
+
+    #[test]
+    fn plaintext_identity_files_are_encrypted_in_place() {
+        let dir = std::env::temp_dir().join(format!("agent-reach-identity-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("identity.json");
+        let key = RootKey::generate();
+        let plaintext = stored_identity(&key, None, "2026-01-01T00:00:00Z".to_string()).unwrap();
+        write_new_private_file(&path, &serde_json::to_vec(&plaintext).unwrap()).unwrap();
+        assert_eq!(load_identity(&path).unwrap().did().to_string(), key.did().to_string());
+
+        encrypt_identity_file(&path, "correct horse").unwrap();
+        let stored: StoredIdentity = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(stored.version, VERSION_ENCRYPTED);
+        assert!(stored.private_key.is_none());
+        assert_eq!(stored.created, "2026-01-01T00:00:00Z");
+        let encrypted = stored.encrypted_key.unwrap();
+        assert_eq!(decrypt_key(&encrypted, &stored.did, "correct horse").unwrap(), key.to_bytes());
+
+        let again = encrypt_identity_file(&path, "correct horse").unwrap_err();
+        assert!(again.to_string().contains("already encrypted"), "{}", again);
+    }
+}
@@ -0,0 +1,19 @@
+//! Log output setup
+//!
+//! Formatting comes from `agent-reach-logging`, shared with the registry
+//! server.
+
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+pub use agent_reach_logging::error_chain;
+use agent_reach_logging::{fmt_layer, LogFormat};
+
+/// Install the global subscriber, honouring `RUST_LOG` and `REACH_LOG_FORMAT`
+///
+/// Logs go to stderr since stdout carries the MCP transport.
+pub fn init() {
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt_layer(LogFormat::from_env(), std::io::stderr))
+        .init();
+}
@@ -1,260 +1,1171 @@
 //! agent-reach-mcp: MCP server for agent-reach discovery registry
 
-use std::sync::Arc;
-use std::fs;
+use std::net::SocketAddr;
+use std::sync::{Arc, PoisonError, RwLock};
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::{Parser, Subcommand};
 use rmcp::{
     Error as McpError, ServiceExt,
     model::{
         ServerCapabilities, Implementation, ServerInfo, Tool, CallToolResult,
-        Content, ListToolsResult, CallToolRequestParam, PaginatedRequestParam,
-        ToolsCapability,
+        ListToolsResult, CallToolRequestParam, PaginatedRequestParam,
+        ToolsCapability, ResourcesCapability, ListResourcesResult,
+        ReadResourceRequestParam, ReadResourceResult,
     },
     handler::server::ServerHandler,
-    service::{RequestContext, RoleServer},
+    service::{Peer, RequestContext, RoleServer},
     transport::stdio,
 };
-use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use serde_json::{json, Value};
+use tokio::sync::{Mutex, OnceCell};
 use tracing::info;
 
 use agent_id::RootKey;
-use agent_id_handshake::{
-    messages::{Hello, Challenge, ProofAccepted},
-    protocol::sign_proof,
-};
+use agent_reach_client::{check_did, check_endpoint, check_metadata, ExchangeLog, HttpSettings, Lookup, LookupResponse, ReachClient, RegisterRequest, RegisterResponse};
+
+mod cache;
+mod contacts;
+mod error;
+mod http;
+mod identity;
+mod logging;
+mod output;
+mod ping;
+mod resources;
+
+use cache::{CachedLookup, ClientCache};
+use contacts::{Contact, ContactBook};
+use error::{ErrorCode, ToolError};
+use output::{Format, ToolOutput};
+use resources::ResourceUri;
 
 /// Default registry URL
 const DEFAULT_REGISTRY_URL: &str = "https://reach.agent-id.ai";
 
-/// Stored identity format (matches agent-id-mcp)
-#[derive(Serialize, Deserialize)]
-struct StoredIdentity {
-    version: u32,
-    did: String,
-    private_key: String,
-    created: String,
+/// Registry exchanges kept for `reach_debug_last_request`
+const DEBUG_EXCHANGES: usize = 20;
+
+#[derive(Parser)]
+#[command(name = "agent-reach-mcp")]
+#[command(about = "MCP server for agent-reach discovery registry")]
+struct Cli {
+    /// Path to the identity file
+    #[arg(long, env = "REACH_IDENTITY_PATH")]
+    identity: Option<PathBuf>,
+
+    /// Identity profile name, stored as identity.<profile>.json in the default directory
+    #[arg(long, env = "REACH_PROFILE", conflicts_with = "identity", value_parser = identity::parse_profile)]
+    profile: Option<String>,
+
+    /// Generate a new identity if the identity file doesn't exist
+    #[arg(long, env = "REACH_AUTO_GENERATE_IDENTITY", value_parser = clap::builder::BoolishValueParser::new())]
+    generate_identity: bool,
+
+    /// Send registrations and read lookups as CBOR instead of JSON
+    #[arg(long, env = "REACH_CBOR", value_parser = clap::builder::BoolishValueParser::new())]
+    cbor: bool,
+
+    /// Session scope to ask for; `read` sessions can look up but not register
+    #[arg(long, env = "REACH_SCOPE", value_enum, default_value_t = Scope::Write)]
+    scope: Scope,
+
+    /// How MCP clients connect: spawn this process (`stdio`) or over the network (`http`)
+    #[arg(long, env = "REACH_MCP_TRANSPORT", value_enum, default_value_t = Transport::Stdio)]
+    transport: Transport,
+
+    /// Address the HTTP transport listens on
+    #[arg(long, env = "REACH_MCP_LISTEN", default_value = "127.0.0.1:8900")]
+    listen: SocketAddr,
+
+    /// Most MCP sessions the HTTP transport keeps open at once
+    #[arg(long, env = "REACH_MCP_MAX_SESSIONS", default_value_t = http::DEFAULT_MAX_SESSIONS)]
+    max_sessions: usize,
+
+    /// Seconds an HTTP session may go without a client message before it's closed
+    #[arg(long, env = "REACH_MCP_SESSION_IDLE_TIMEOUT", default_value_t = http::DEFAULT_IDLE_TIMEOUT_SECS)]
+    session_idle_timeout: u64,
+
+    /// Keep the last few registry requests and responses, redacted, for
+    /// `reach_debug_last_request`
+    #[arg(long, env = "REACH_DEBUG", value_parser = clap::builder::BoolishValueParser::new())]
+    debug: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encrypt a plaintext identity file in place, using the configured passphrase
+    EncryptIdentity,
 }
 
-/// Identity file location (same as agent-id-mcp)
-fn identity_path() -> PathBuf {
-    directories::ProjectDirs::from("ai", "agent-id", "agent-id")
-        .map(|dirs| dirs.data_dir().join("identity.json"))
-        .unwrap_or_else(|| PathBuf::from("~/.agent-id/identity.json"))
+/// MCP transport
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Transport {
+    /// MCP over stdin and stdout
+    Stdio,
+    /// MCP over HTTP with server-sent events
+    Http,
 }
 
-/// Load identity from disk
-fn load_identity() -> Result<RootKey> {
-    let path = identity_path();
-    let content = fs::read_to_string(&path)
-        .with_context(|| format!("Failed to read identity from {:?}", path))?;
-    let stored: StoredIdentity = serde_json::from_str(&content)
-        .context("Failed to parse identity file")?;
-    
-    let key_bytes = BASE64.decode(&stored.private_key)
-        .context("Failed to decode private key")?;
-    let key_array: [u8; 32] = key_bytes.try_into()
-        .map_err(|_| anyhow::anyhow!("Invalid private key length"))?;
-    let key = RootKey::from_bytes(&key_array)
-        .context("Failed to create key from bytes")?;
-    Ok(key)
+/// Session scope requested in Hello
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Scope {
+    Read,
+    Write,
+}
+
+impl From<Scope> for agent_reach_client::Scope {
+    fn from(scope: Scope) -> Self {
+        match scope {
+            Scope::Read => Self::Read,
+            Scope::Write => Self::Write,
+        }
+    }
 }
 
 /// MCP Server state
 #[derive(Clone)]
 struct ReachMcpServer {
     key: Arc<RootKey>,
-    client: reqwest::Client,
-    registry_url: String,
-    session: Arc<RwLock<Option<String>>>,
+    identity_path: PathBuf,
+    /// Registries the tools talk to, replaced by `reach_switch_registry`
+    registries: Arc<RwLock<Registries>>,
+    cache: Arc<Mutex<ClientCache>>,
+    cache_path: PathBuf,
+    contacts: Arc<ContactBook>,
+    /// Recent registry exchanges, when recording them is on
+    exchanges: Option<Arc<ExchangeLog>>,
+    /// The connected MCP client, for notifications
+    peer: Option<Peer<RoleServer>>,
 }
 
-#[derive(Deserialize)]
-struct ProofAcceptedResponse {
-    session_id: String,
+/// A set of registries to use together
+#[derive(Clone)]
+struct Registries {
+    /// Registries to register with and look agents up in, in lookup order;
+    /// the first also answers the single-registry tools. Never empty
+    clients: Vec<ReachClient>,
+    /// Whether the first registry lists its agents, once it has answered
+    lists_agents: Arc<OnceCell<bool>>,
 }
 
-#[derive(Deserialize)]
-struct LookupResponse {
-    did: String,
-    endpoint: String,
+impl Registries {
+    fn new(clients: Vec<ReachClient>) -> Self {
+        assert!(!clients.is_empty(), "at least one registry");
+        Self { clients, lists_agents: Arc::new(OnceCell::new()) }
+    }
+
+    fn urls(&self) -> Vec<String> {
+        self.clients.iter().map(|registry| registry.registry_url().to_string()).collect()
+    }
 }
 
-#[derive(Deserialize)]
-struct ErrorResponse {
-    error: String,
+impl From<CachedLookup> for LookupResponse {
+    fn from(cached: CachedLookup) -> Self {
+        Self {
+            did: cached.did,
+            endpoint: cached.endpoint,
+            expires_at: cached.expires_at,
+            ttl_remaining_secs: None,
+            handle: cached.handle,
+            endpoints: Vec::new(),
+            region: None,
+            public_key: cached.public_key,
+        }
+    }
+}
+
+/// Registries named in `REACH_REGISTRY_URLS`, or else the one in
+/// `REACH_REGISTRY_URL`
+fn registry_urls_from_env() -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for url in std::env::var("REACH_REGISTRY_URLS").unwrap_or_default().split(',').map(str::trim) {
+        if !url.is_empty() && !urls.iter().any(|known| known == url) {
+            urls.push(url.to_string());
+        }
+    }
+    if urls.is_empty() {
+        urls.push(std::env::var("REACH_REGISTRY_URL").unwrap_or_else(|_| DEFAULT_REGISTRY_URL.to_string()));
+    }
+    urls
+}
+
+/// A registry root URL from user input, or advice on fixing it
+fn parse_registry_url(input: &str) -> Result<String, ToolError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(ToolError::invalid_params("Registry URL is empty"));
+    }
+    if !input.contains("://") {
+        return Err(ToolError::invalid_params(format!(
+            "{} has no scheme; did you mean https://{}?",
+            input, input
+        )));
+    }
+    let url = reqwest::Url::parse(input)
+        .map_err(|e| ToolError::invalid_params(format!("{} is not a valid URL: {}", input, e)))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ToolError::invalid_params(format!(
+            "Registries are reached over https:// or http://, not {}://",
+            url.scheme()
+        )));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(ToolError::invalid_params(format!("{} has no host", input)));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(ToolError::invalid_params(format!(
+            "Give the registry's root URL, without a query or fragment, not {}",
+            input
+        )));
+    }
+    let root = input.trim_end_matches('/');
+    if let Some(root) = root.strip_suffix("/v1") {
+        return Err(ToolError::invalid_params(format!(
+            "Give the registry's root URL; the API version is found automatically. Try {}",
+            root
+        )));
+    }
+    Ok(root.to_string())
+}
+
+/// Each registry's answer to a call made on all of them, in `registries`
+/// order
+type Outcomes<T> = Vec<(String, Result<T, ToolError>)>;
+
+/// One error for a call every registry failed, listing what each said when
+/// there were several
+///
+/// Prefers an error other than not found or expired, since a registry that
+/// couldn't answer might have had the agent.
+fn combined_error(mut errors: Vec<(String, ToolError)>) -> ToolError {
+    if errors.len() == 1 {
+        return errors.remove(0).1;
+    }
+    let registries: serde_json::Map<String, Value> = errors
+        .iter()
+        .map(|(registry, e)| (registry.clone(), Value::String(e.message.clone())))
+        .collect();
+    let index = errors
+        .iter()
+        .position(|(_, e)| !matches!(e.code, ErrorCode::NotFound | ErrorCode::Expired))
+        .unwrap_or(0);
+    errors.remove(index).1.with_field("registries", registries)
 }
 
 impl ReachMcpServer {
-    fn new(key: RootKey) -> Self {
+    /// Server for the registries in `REACH_REGISTRY_URLS` or
+    /// `REACH_REGISTRY_URL`, reached with the proxy and TLS settings from the
+    /// environment
+    fn new(key: RootKey, identity_path: PathBuf) -> Result<Self> {
+        let registry_urls = registry_urls_from_env();
+        let settings = HttpSettings::from_env();
+        if !settings.pins.is_empty() {
+            for registry_url in registry_urls.iter().filter(|url| url.starts_with("http://")) {
+                tracing::warn!(registry = %registry_url, "The registry is plain HTTP, so its key pins don't apply");
+            }
+        }
+        let http = settings.client()?;
+        Ok(Self::with_registries(key, identity_path, registry_urls).with_http_client(http))
+    }
+
+    fn with_registries(key: RootKey, identity_path: PathBuf, registry_urls: Vec<String>) -> Self {
+        let key = Arc::new(key);
+        let cache_path = cache::cache_path(&identity_path);
+        let cache = ClientCache::load(&cache_path, &registry_urls, &key.did().to_string());
+        let registries = registry_urls
+            .into_iter()
+            .map(|registry_url| {
+                let registry = ReachClient::new(registry_url, key.clone());
+                registry.set_session(cache.session(registry.registry_url()).cloned());
+                registry
+            })
+            .collect();
+        let contacts = ContactBook::new(contacts::contacts_path(&identity_path));
+
         Self {
-            key: Arc::new(key),
-            client: reqwest::Client::new(),
-            registry_url: std::env::var("REACH_REGISTRY_URL")
-                .unwrap_or_else(|_| DEFAULT_REGISTRY_URL.to_string()),
-            session: Arc::new(RwLock::new(None)),
+            key,
+            identity_path,
+            registries: Arc::new(RwLock::new(Registries::new(registries))),
+            cache: Arc::new(Mutex::new(cache)),
+            cache_path,
+            contacts: Arc::new(contacts),
+            exchanges: None,
+            peer: None,
         }
     }
 
-    async fn authenticate(&self) -> Result<String, String> {
-        // Check existing session
-        if let Some(ref session_id) = *self.session.read().await {
-            return Ok(session_id.clone());
+    fn with_http_client(self, http: reqwest::Client) -> Self {
+        self.map_registries(|registry| registry.with_http_client(http.clone()))
+    }
+
+    fn with_cbor(self, cbor: bool) -> Self {
+        self.map_registries(|registry| registry.with_cbor(cbor))
+    }
+
+    fn with_scope(self, scope: Scope) -> Self {
+        self.map_registries(|registry| registry.with_scope(scope.into()))
+    }
+
+    /// Record the last few exchanges with the registries, or with `false`
+    /// none at all
+    fn with_debug(mut self, debug: bool) -> Self {
+        if !debug {
+            return self;
         }
+        let log = Arc::new(ExchangeLog::new(DEBUG_EXCHANGES));
+        self.exchanges = Some(log.clone());
+        self.map_registries(|registry| registry.with_exchange_log(log.clone()))
+    }
 
-        info!("Authenticating with registry...");
+    /// Rebuild each registry's client with `f`
+    fn map_registries(self, f: impl Fn(ReachClient) -> ReachClient) -> Self {
+        {
+            let mut registries = self.registries.write().unwrap_or_else(PoisonError::into_inner);
+            registries.clients = std::mem::take(&mut registries.clients).into_iter().map(f).collect();
+        }
+        self
+    }
 
-        // Step 1: Send Hello
-        let hello = Hello::new(self.key.did().to_string());
+    /// The registries as they are now; a switch during the call doesn't
+    /// affect it
+    fn registries(&self) -> Registries {
+        self.registries.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
 
-        let resp = self.client
-            .post(format!("{}/hello", self.registry_url))
-            .json(&hello)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send Hello: {}", e))?;
+    /// The first registry, which answers the tools that ask just one
+    fn registry(&self) -> ReachClient {
+        self.registries.read().unwrap_or_else(PoisonError::into_inner).clients[0].clone()
+    }
 
-        let status = resp.status(); if !status.is_success() { tracing::error!("Deregister failed with status: {}", status);
-            let error = resp.text().await.unwrap_or_default();
-            return Err(format!("Hello failed: {}", error));
+    /// Persist the cache, logging rather than failing on errors
+    fn save_cache(&self, cache: &ClientCache) {
+        if let Err(e) = cache.save(&self.cache_path) {
+            tracing::warn!(path = %self.cache_path.display(), error = %e, "Failed to write cache file");
         }
+    }
 
-        let challenge: Challenge = resp.json().await
-            .map_err(|e| format!("Failed to parse Challenge: {}", e))?;
+    /// Cache the session with `registry` if a call changed it, so restarts
+    /// reuse it
+    async fn save_session(&self, registry: &ReachClient) {
+        let session = registry.session();
+        let mut cache = self.cache.lock().await;
+        if cache.session(registry.registry_url()) != session.as_ref() {
+            cache.set_session(registry.registry_url(), session);
+            self.save_cache(&cache);
+        }
+    }
 
-        info!("Received challenge, signing proof...");
+    /// Tell the client the resource list changed, after this agent's
+    /// registration or its contacts did
+    async fn resources_changed(&self) {
+        if let Some(peer) = &self.peer {
+            if let Err(e) = peer.notify_resource_list_changed().await {
+                tracing::debug!(error = %e, "Failed to notify resource list change");
+            }
+        }
+    }
 
-        // Step 2: Create and send Proof
-        let my_did = self.key.did();
-        let proof = sign_proof(&challenge, &my_did, &self.key, Some(challenge.issuer.clone()))
-            .map_err(|e| format!("Failed to create proof: {}", e))?;
+    /// Whether the registry serves `GET /agents`; asked again next time if
+    /// it can't be reached
+    async fn lists_agents(&self) -> bool {
+        let registries = self.registries();
+        let answered = registries.lists_agents.get_or_try_init(|| async {
+            match registries.clients[0].agents_page(0, 1).await {
+                Ok(_) => Ok(true),
+                Err(agent_reach_client::Error::Registry(_)) => Ok(false),
+                Err(e) => Err(e),
+            }
+        }).await;
+        answered.copied().unwrap_or(false)
+    }
 
-        let resp = self.client
-            .post(format!("{}/proof", self.registry_url))
-            .json(&proof)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send Proof: {}", e))?;
+    /// Register `endpoint` with every registry at once, or with `dry_run`
+    /// only have them check that they would accept it
+    ///
+    /// Fails only if every registry refused; otherwise each one's answer is
+    /// returned in `registries` order.
+    async fn register_impl(&self, request: &RegisterRequest) -> Result<Outcomes<RegisterResponse>, ToolError> {
+        let endpoint = request.endpoint.as_str();
+        check_endpoint(endpoint).map_err(|e| ToolError::from(e).with_field("endpoint", endpoint))?;
+        check_metadata(request)?;
+        let outcomes = self
+            .on_every_registry(|registry| async move {
+                let registered = registry.register(request).await;
+                self.save_session(&registry).await;
+                Ok(registered?)
+            })
+            .await?;
 
-        let status = resp.status(); if !status.is_success() { tracing::error!("Deregister failed with status: {}", status);
-            let error = resp.text().await.unwrap_or_default();
-            return Err(format!("Proof failed: {}", error));
+        if !request.dry_run {
+            let mut cache = self.cache.lock().await;
+            cache.remove_lookup(&self.key.did().to_string());
+            self.save_cache(&cache);
         }
 
-        let accepted: ProofAcceptedResponse = resp.json().await
-            .map_err(|e| format!("Failed to parse ProofAccepted: {}", e))?;
+        Ok(outcomes)
+    }
 
-        info!("Authentication successful");
+    /// Run `call` against every registry concurrently, failing only if it
+    /// failed everywhere
+    async fn on_every_registry<T, F, Fut>(&self, call: F) -> Result<Outcomes<T>, ToolError>
+    where
+        F: Fn(ReachClient) -> Fut,
+        Fut: std::future::Future<Output = Result<T, ToolError>>,
+    {
+        let registries = self.registries();
+        let results = futures::future::join_all(registries.clients.iter().cloned().map(call)).await;
+        let outcomes: Outcomes<T> = registries.urls().into_iter().zip(results).collect();
+        if outcomes.iter().any(|(_, result)| result.is_ok()) {
+            return Ok(outcomes);
+        }
+        Err(combined_error(outcomes.into_iter().filter_map(|(registry, result)| Some((registry, result.err()?))).collect()))
+    }
 
-        *self.session.write().await = Some(accepted.session_id.clone());
+    /// Resolve a DID to its registry entry and the registry that has it,
+    /// serving fresh results from cache unless `force_refresh` is set
+    ///
+    /// Registries are asked in order until one has the DID.
+    async fn lookup_impl(&self, did: &str, force_refresh: bool) -> Result<(LookupResponse, String), ToolError> {
+        check_did(did).map_err(|e| ToolError::from(e).with_field("did", did))?;
+        if !force_refresh {
+            if let Some(cached) = self.cache.lock().await.lookup(did) {
+                let registry = cached.registry.clone();
+                return Ok((cached.into(), registry));
+            }
+        }
 
-        Ok(accepted.session_id)
+        let mut errors = Vec::new();
+        for registry in &self.registries().clients {
+            match self.lookup_at(registry, did).await {
+                Ok(lookup) => return Ok((lookup, registry.registry_url().to_string())),
+                Err(e) => errors.push((registry.registry_url().to_string(), e)),
+            }
+        }
+        Err(combined_error(errors))
     }
 
-    async fn handle_register(&self, args: serde_json::Map<String, serde_json::Value>) -> Result<String, String> {
-        let endpoint = args.get("endpoint")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| "Missing required parameter: endpoint".to_string())?;
+    /// Ask `registry` for a DID's entry
+    ///
+    /// A refresh of an entry cached from this registry sends its ETag, so an
+    /// unchanged entry costs a 304 instead of a full response.
+    async fn lookup_at(&self, registry: &ReachClient, did: &str) -> Result<LookupResponse, ToolError> {
+        let etag = self.cache.lock().await.etag(did, registry.registry_url());
 
-        let session_id = self.authenticate().await?;
+        let (lookup, etag) = match registry.lookup_if_changed(did, etag.as_deref()).await {
+            Ok(Lookup::Found { entry, etag }) => (*entry, etag),
+            Ok(Lookup::NotModified) => {
+                if let Some(cached) = self.cache.lock().await.lookup(did) {
+                    return Ok(cached.into());
+                }
+                return Err(ToolError::new(ErrorCode::RegistryError, "Registry returned 304 for an uncached lookup")
+                    .with_field("did", did));
+            }
+            Err(e) => {
+                let error = ToolError::from(e);
+                if matches!(error.code, ErrorCode::NotFound | ErrorCode::Expired) {
+                    let mut cache = self.cache.lock().await;
+                    cache.remove_lookup(did);
+                    self.save_cache(&cache);
+                }
+                return Err(error.with_field("did", did));
+            }
+        };
 
-        #[derive(Serialize)]
-        struct RegisterRequest { endpoint: String }
+        let mut cache = self.cache.lock().await;
+        cache.insert_lookup(CachedLookup {
+            registry: registry.registry_url().to_string(),
+            did: lookup.did.clone(),
+            endpoint: lookup.endpoint.clone(),
+            expires_at: lookup.expires_at,
+            etag,
+            handle: lookup.handle.clone(),
+            public_key: lookup.public_key.clone(),
+        });
+        self.save_cache(&cache);
 
-        let resp = self.client
-            .post(format!("{}/register", self.registry_url))
-            .header("Authorization", format!("Bearer {}", session_id))
-            .json(&RegisterRequest { endpoint: endpoint.to_string() })
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send register: {}", e))?;
+        Ok(lookup)
+    }
 
-        let status = resp.status(); if !status.is_success() { tracing::error!("Deregister failed with status: {}", status);
-            let error: ErrorResponse = resp.json().await
-                .unwrap_or(ErrorResponse { error: "Unknown error".to_string() });
-            return Err(error.error);
+    /// Resolve a `name@domain` handle to its agent, sharing the lookup cache
+    /// with DID lookups unless `force_refresh` is set
+    async fn resolve_impl(&self, handle: &str, force_refresh: bool) -> Result<LookupResponse, ToolError> {
+        // The registry stores handles lowercased
+        let handle = handle.trim().to_lowercase();
+        if !force_refresh {
+            if let Some(cached) = self.cache.lock().await.resolve_handle(&handle) {
+                return Ok(cached.into());
+            }
         }
 
-        Ok(format!("✓ Registered {} at endpoint: {}", self.key.did(), endpoint))
+        let registry = self.registry();
+        let lookup = match registry.resolve(&handle).await {
+            Ok(lookup) => lookup,
+            Err(e) => {
+                let error = ToolError::from(e);
+                if matches!(error.code, ErrorCode::NotFound | ErrorCode::Expired) {
+                    let mut cache = self.cache.lock().await;
+                    cache.remove_handle(&handle);
+                    self.save_cache(&cache);
+                }
+                let error = match error.code {
+                    ErrorCode::NotFound => {
+                        ToolError::new(ErrorCode::NotFound, format!("No agent registered for handle {}", handle))
+                    }
+                    _ => error,
+                };
+                return Err(error.with_field("handle", handle.as_str()));
+            }
+        };
+
+        let mut cache = self.cache.lock().await;
+        // Keep the DID's ETag; a stale one only costs a full response later
+        let etag = cache.etag(&lookup.did, registry.registry_url());
+        cache.insert_lookup(CachedLookup {
+            registry: registry.registry_url().to_string(),
+            did: lookup.did.clone(),
+            endpoint: lookup.endpoint.clone(),
+            expires_at: lookup.expires_at,
+            etag,
+            handle: Some(handle),
+            public_key: lookup.public_key.clone(),
+        });
+        self.save_cache(&cache);
+
+        Ok(lookup)
+    }
+
+    /// This agent's own registration, from the session's DID
+    async fn me_impl(&self) -> Result<LookupResponse, ToolError> {
+        let registry = self.registry();
+        let me = registry.me().await;
+        self.save_session(&registry).await;
+        Ok(me?)
     }
 
-    async fn handle_lookup(&self, args: serde_json::Map<String, serde_json::Value>) -> Result<String, String> {
-        let did = args.get("did")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| "Missing required parameter: did".to_string())?;
+    /// Deregister from every registry at once, failing only if every one
+    /// refused
+    async fn deregister_impl(&self) -> Result<Outcomes<()>, ToolError> {
+        let outcomes = self
+            .on_every_registry(|registry| async move {
+                let deregistered = registry.deregister().await;
+                self.save_session(&registry).await;
+                Ok(deregistered?)
+            })
+            .await?;
 
-        let encoded_did = urlencoding::encode(did);
-        let resp = self.client
-            .get(format!("{}/lookup/{}", self.registry_url, encoded_did))
-            .send()
-            .await
-            .map_err(|e| format!("Failed to lookup: {}", e))?;
+        let mut cache = self.cache.lock().await;
+        cache.remove_lookup(&self.key.did().to_string());
+        self.save_cache(&cache);
 
-        if resp.status().as_u16() == 404 {
-            return Err("Agent not found in registry".to_string());
+        Ok(outcomes)
+    }
+
+    /// Point every tool at the registry at `registry_url` alone, once it
+    /// passes a health check, starting over without a session
+    ///
+    /// Sessions with and lookups from the registries left behind are dropped
+    /// from the cache. Returns the registries used before.
+    async fn switch_registry_impl(&self, registry_url: &str) -> Result<Vec<String>, ToolError> {
+        let registry_url = parse_registry_url(registry_url)?;
+        let registry = self.registry().for_registry(registry_url.as_str());
+        if let Err(e) = registry.health().await {
+            let error = ToolError::from(e);
+            let message = format!("Registry {} failed its health check: {}", registry_url, error.message);
+            return Err(ToolError { message, ..error }.with_field("registry", registry_url.as_str()));
         }
 
-        let status = resp.status(); if !status.is_success() { tracing::error!("Deregister failed with status: {}", status);
-            let error: ErrorResponse = resp.json().await
-                .unwrap_or(ErrorResponse { error: "Unknown error".to_string() });
-            return Err(error.error);
+        let previous = {
+            let mut registries = self.registries.write().unwrap_or_else(PoisonError::into_inner);
+            std::mem::replace(&mut *registries, Registries::new(vec![registry]))
+        };
+        let mut cache = self.cache.lock().await;
+        cache.set_session(&registry_url, None);
+        cache.retain_registries(&[registry_url]);
+        self.save_cache(&cache);
+
+        Ok(previous.urls())
+    }
+
+    async fn handle_register(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let endpoint = required_str(args, "endpoint")?;
+        let dry_run = args.get("dry_run")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let ttl = match args.get("ttl") {
+            None | Some(Value::Null) => None,
+            Some(ttl) => Some(ttl.as_u64().filter(|&ttl| ttl > 0).ok_or_else(|| {
+                ToolError::invalid_params(format!("ttl must be a positive number of seconds, not {}", ttl))
+            })?),
+        };
+        let request = RegisterRequest {
+            ttl,
+            name: optional_str(args, "name")?.map(str::to_owned),
+            description: optional_str(args, "description")?.map(str::to_owned),
+            tags: string_list(args, "tags")?,
+            dry_run,
+            ..RegisterRequest::new(endpoint)
+        };
+
+        let outcomes = self.register_impl(&request).await?;
+        let (registry_lines, registries) = registry_report(&outcomes);
+        let Some(registered) = outcomes.into_iter().find_map(|(_, result)| result.ok()) else {
+            return Err(ToolError::new(ErrorCode::RegistryError, "No registry accepted the registration"));
+        };
+
+        let did = self.key.did().to_string();
+        let ignored = ignored_metadata(&request, &registered);
+        let metadata = metadata_lines(&registered, &ignored);
+        if dry_run {
+            let mut summary = format!(
+                "✓ Registry would accept {} at endpoint: {}{}\n  TTL: {}s, expiring at {}",
+                did, endpoint, metadata, registered.ttl, registered.expires_at
+            );
+            for adjustment in &registered.adjustments {
+                summary.push_str(&format!("\n  Note: {}", adjustment));
+            }
+            summary.push_str(&registry_lines);
+            let mut data = json!({
+                "did": did,
+                "endpoint": endpoint,
+                "dry_run": true,
+                "ttl": registered.ttl,
+                "expires_at": registered.expires_at,
+                "adjustments": registered.adjustments,
+                "registries": registries,
+            });
+            add_metadata(&mut data, &registered, &ignored);
+            return Ok(ToolOutput::new(summary, data));
         }
+        self.resources_changed().await;
 
-        let lookup: LookupResponse = resp.json().await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+        let mut summary = format!("✓ Registered {} at endpoint: {}{}", did, endpoint, metadata);
+        if let Some(ttl) = ttl {
+            // Older registries don't say what TTL they granted
+            let granted = if registered.ttl > 0 { registered.ttl } else { ttl };
+            summary.push_str(&format!("\n  TTL: {}s", granted));
+        }
+        for adjustment in &registered.adjustments {
+            summary.push_str(&format!("\n  Note: {}", adjustment));
+        }
+        summary.push_str(&registry_lines);
+        let mut data = json!({ "did": did, "endpoint": endpoint, "registries": registries });
+        add_metadata(&mut data, &registered, &ignored);
+        if ttl.is_some() && registered.ttl > 0 {
+            data["ttl"] = json!(registered.ttl);
+            data["expires_at"] = json!(registered.expires_at);
+        }
+        Ok(ToolOutput::new(summary, data))
+    }
 
-        Ok(format!("✓ Found {}\n  Endpoint: {}", lookup.did, lookup.endpoint))
+    /// The DID a lookup argument names: the argument itself, or the DID of
+    /// the contact it's the nickname of
+    fn resolve_contact(&self, name: &str) -> Result<(String, Option<Contact>), ToolError> {
+        if name.starts_with("did:") {
+            return Ok((name.to_string(), None));
+        }
+        if let Some(contact) = self.contacts.find(name)? {
+            return Ok((contact.did.clone(), Some(contact)));
+        }
+        // Perhaps a DID that lost its prefix rather than a nickname
+        match check_did(name) {
+            Err(e) if e.suggestion.is_some() => Err(e.into()),
+            _ => Err(ToolError::invalid_params(format!(
+                "No contact named {}; give a DID or the nickname of a saved contact",
+                name
+            ))),
+        }
     }
 
-    async fn handle_deregister(&self) -> Result<String, String> {
-        let session_id = self.authenticate().await?;
+    async fn handle_lookup(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let (did, contact) = self.resolve_contact(required_str(args, "did")?)?;
+        let force_refresh = args.get("force_refresh")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
 
-        let resp = self.client
-            .post(format!("{}/deregister", self.registry_url))
-            .header("Authorization", format!("Bearer {}", session_id))
-            .send()
-            .await
-            .map_err(|e| format!("Failed to deregister: {}", e))?;
+        let (lookup, registry) = self.lookup_impl(&did, force_refresh).await?;
 
-        let status = resp.status(); if !status.is_success() { tracing::error!("Deregister failed with status: {}", status);
-            let error: ErrorResponse = resp.json().await
-                .unwrap_or(ErrorResponse { error: "Unknown error".to_string() });
-            return Err(error.error);
+        let ttl_remaining = lookup.ttl_remaining();
+        let mut summary = format!(
+            "✓ Found {}\n  Endpoint: {}\n  Expires in: {}",
+            lookup.did, lookup.endpoint, format_duration(ttl_remaining as u64)
+        );
+        if self.registries().clients.len() > 1 {
+            summary.push_str(&format!("\n  Registry: {}", registry));
+        }
+        let fingerprint = lookup.public_key.as_ref().and_then(|key| key.fingerprint());
+        if let Some(fingerprint) = &fingerprint {
+            summary.push_str(&format!("\n  Key fingerprint: {}", fingerprint));
         }
+        Ok(ToolOutput::new(
+            summary,
+            json!({
+                "did": lookup.did,
+                "endpoint": lookup.endpoint,
+                "expires_at": lookup.expires_at,
+                "ttl_remaining_secs": ttl_remaining,
+                "public_key": lookup.public_key.map(|key| key.multibase),
+                "key_fingerprint": fingerprint,
+                "contact": contact.and_then(|c| c.nickname),
+                "registry": registry,
+            }),
+        ))
+    }
 
-        *self.session.write().await = None;
+    async fn handle_contact_add(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let did = required_str(args, "did")?;
+        check_did(did)?;
+        let optional_str = |name| args.get(name).and_then(|v| v.as_str()).map(str::to_owned);
+        let nickname = optional_str("nickname");
+        let notes = optional_str("notes");
+        let verify = args.get("verify")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
 
-        Ok(format!("✓ Deregistered {}", self.key.did()))
+        let endpoint = if verify { Some(self.lookup_impl(did, false).await?.0.endpoint) } else { None };
+        self.contacts.add(Contact {
+            did: did.to_string(),
+            nickname: nickname.clone(),
+            notes: notes.clone(),
+            added_at: chrono::Utc::now().timestamp(),
+        })?;
+        self.resources_changed().await;
+
+        let mut summary = format!("✓ Saved contact {}", nickname.as_deref().unwrap_or(did));
+        if nickname.is_some() {
+            summary.push_str(&format!("\n  DID: {}", did));
+        }
+        if let Some(endpoint) = &endpoint {
+            summary.push_str(&format!("\n  Endpoint: {}", endpoint));
+        }
+        Ok(ToolOutput::new(
+            summary,
+            json!({ "did": did, "nickname": nickname, "notes": notes, "endpoint": endpoint }),
+        ))
+    }
+
+    async fn handle_contact_list(&self) -> Result<ToolOutput, ToolError> {
+        let contacts = self.contacts.list()?;
+
+        let mut summary = match contacts.len() {
+            0 => "No saved contacts".to_string(),
+            n => format!("{} saved contact{}", n, if n == 1 { "" } else { "s" }),
+        };
+        for contact in &contacts {
+            match &contact.nickname {
+                Some(nickname) => summary.push_str(&format!("\n  {} ({})", nickname, contact.did)),
+                None => summary.push_str(&format!("\n  {}", contact.did)),
+            }
+            if let Some(notes) = &contact.notes {
+                summary.push_str(&format!(" - {}", notes));
+            }
+        }
+        Ok(ToolOutput::new(summary, json!({ "contacts": contacts })))
+    }
+
+    async fn handle_contact_remove(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let name = required_str(args, "did")?;
+
+        let removed = self.contacts.remove(name)?
+            .ok_or_else(|| ToolError::new(ErrorCode::NotFound, format!("No contact {}", name)))?;
+        self.resources_changed().await;
+        Ok(ToolOutput::new(
+            format!("✓ Removed contact {}", removed.nickname.as_deref().unwrap_or(&removed.did)),
+            json!({ "did": removed.did, "nickname": removed.nickname }),
+        ))
+    }
+
+    async fn handle_resolve_handle(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let handle = required_str(args, "handle")?;
+        let force_refresh = args.get("force_refresh")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let lookup = self.resolve_impl(handle, force_refresh).await?;
+        let handle = lookup.handle.clone().unwrap_or_else(|| handle.trim().to_lowercase());
+
+        Ok(ToolOutput::new(
+            format!("✓ {} is {}\n  Endpoint: {}", handle, lookup.did, lookup.endpoint),
+            json!({ "handle": handle, "did": lookup.did, "endpoint": lookup.endpoint, "expires_at": lookup.expires_at }),
+        ))
     }
 
-    async fn handle_status(&self) -> Result<String, String> {
+    async fn handle_ping(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let did = required_str(args, "did")?;
+        let timeout_ms = args.get("timeout_ms")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(ping::DEFAULT_TIMEOUT_MS)
+            .clamp(1, ping::MAX_TIMEOUT_MS);
+        let force_refresh = args.get("force_refresh")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let (lookup, _) = self.lookup_impl(did, force_refresh).await?;
+
+        let outcome = ping::ping(
+            &lookup.endpoint,
+            Duration::from_millis(timeout_ms),
+            ping::allow_private_from_env(),
+        )
+        .await
+        .map_err(|reason| {
+            ToolError::new(
+                ErrorCode::Unreachable,
+                format!("{} is unreachable\n  Endpoint: {}\n  Reason: {}", lookup.did, lookup.endpoint, reason),
+            )
+            .with_field("did", lookup.did.as_str())
+            .with_field("endpoint", lookup.endpoint.as_str())
+            .with_field("reason", reason)
+        })?;
+
+        let latency_ms = outcome.latency.as_millis() as u64;
+        Ok(ToolOutput::new(
+            format!(
+                "✓ {} is reachable\n  Endpoint: {}\n  Latency: {}ms ({})",
+                lookup.did, lookup.endpoint, latency_ms, outcome.detail
+            ),
+            json!({
+                "did": lookup.did,
+                "endpoint": lookup.endpoint,
+                "latency_ms": latency_ms,
+                "detail": outcome.detail,
+            }),
+        ))
+    }
+
+    async fn handle_deregister(&self) -> Result<ToolOutput, ToolError> {
+        let outcomes = self.deregister_impl().await?;
+        self.resources_changed().await;
+
         let did = self.key.did().to_string();
-        let encoded_did = urlencoding::encode(&did);
+        let (registry_lines, registries) = registry_report(&outcomes);
+        Ok(ToolOutput::new(
+            format!("✓ Deregistered {}{}", did, registry_lines),
+            json!({ "did": did, "registries": registries }),
+        ))
+    }
 
-        let resp = self.client
-            .get(format!("{}/lookup/{}", self.registry_url, encoded_did))
-            .send()
-            .await
-            .map_err(|e| format!("Failed to check status: {}", e))?;
+    async fn handle_status(&self) -> Result<ToolOutput, ToolError> {
+        let did = self.key.did().to_string();
 
-        if resp.status().as_u16() == 404 {
-            return Ok(format!("○ Not registered\n  DID: {}", did));
+        match self.me_impl().await {
+            Ok(lookup) => {
+                let ttl_remaining = lookup.ttl_remaining();
+                Ok(ToolOutput::new(
+                    format!(
+                        "✓ Registered\n  DID: {}\n  Endpoint: {}\n  Expires in: {}",
+                        lookup.did, lookup.endpoint, format_duration(ttl_remaining as u64)
+                    ),
+                    json!({
+                        "registered": true,
+                        "did": lookup.did,
+                        "endpoint": lookup.endpoint,
+                        "expires_at": lookup.expires_at,
+                        "ttl_remaining_secs": ttl_remaining,
+                    }),
+                ))
+            }
+            Err(e) if e.code == ErrorCode::Expired => Ok(ToolOutput::new(
+                format!("○ Registration expired\n  DID: {}", did),
+                json!({ "registered": false, "expired": true, "did": did, "expires_at": e.fields.get("expires_at") }),
+            )),
+            Err(e) if e.code == ErrorCode::NotFound => Ok(ToolOutput::new(
+                format!("○ Not registered\n  DID: {}", did),
+                json!({ "registered": false, "did": did }),
+            )),
+            Err(e) => Err(e),
         }
+    }
 
-        if resp.status().is_success() {
-            let lookup: LookupResponse = resp.json().await
-                .map_err(|e| format!("Failed to parse response: {}", e))?;
-            return Ok(format!("✓ Registered\n  DID: {}\n  Endpoint: {}", lookup.did, lookup.endpoint));
+    async fn handle_registry_stats(&self) -> Result<ToolOutput, ToolError> {
+        let stats = self.registry().stats().await?;
+
+        let mut summary = format!(
+            "Registry {}\n  Agents: {} ({} online, {} idle, {} expired)\n  Registrations in the last hour: {}",
+            self.registry().registry_url(),
+            stats.total_entries,
+            stats.entries.online,
+            stats.entries.idle,
+            stats.entries.expired,
+            stats.registrations_last_hour,
+        );
+        if let Some(sessions) = stats.active_sessions {
+            summary.push_str(&format!("\n  Active sessions: {}", sessions));
+        }
+        if let Some(challenges) = stats.pending_challenges {
+            summary.push_str(&format!("\n  Pending handshakes: {}", challenges));
         }
+        summary.push_str(&format!("\n  Uptime: {}", format_duration(stats.uptime_secs)));
 
-        Err("Failed to check status".to_string())
+        let data = serde_json::to_value(&stats)
+            .map_err(|e| ToolError::new(ErrorCode::RegistryError, format!("Failed to encode stats: {}", e)))?;
+        Ok(ToolOutput::new(summary, data))
     }
 
-    async fn handle_whoami(&self) -> Result<String, String> {
-        Ok(format!("Your DID: {}", self.key.did()))
+    async fn handle_registry_health(&self) -> Result<ToolOutput, ToolError> {
+        let registry = self.registry();
+        let started = std::time::Instant::now();
+        registry.health().await?;
+        let latency_ms = started.elapsed().as_millis() as u64;
+
+        // Older registries lack these; being up is still worth reporting
+        let version = match registry.version().await {
+            Ok(version) => Some(version),
+            Err(e) => {
+                tracing::debug!(error = %e, "Registry didn't report its version");
+                None
+            }
+        };
+        let readiness = match registry.readiness().await {
+            Ok(readiness) => Some(readiness),
+            Err(e) => {
+                tracing::debug!(error = %e, "Registry didn't report readiness");
+                None
+            }
+        };
+
+        let mark = if readiness.as_ref().is_none_or(|r| r.is_ready()) { "✓" } else { "✗" };
+        let mut summary = format!("{} Registry {} is up\n  Latency: {}ms", mark, registry.registry_url(), latency_ms);
+        if let Some(version) = &version {
+            summary.push_str(&format!("\n  Version: {} (API {})", version.version, version.api_versions.join(", ")));
+        }
+        match &readiness {
+            Some(readiness) => {
+                summary.push_str(&format!("\n  Readiness: {}", readiness.status));
+                for (name, component) in &readiness.components {
+                    summary.push_str(&format!("\n    {}: {}", name, component.status));
+                    if let Some(latency_ms) = component.latency_ms {
+                        summary.push_str(&format!(", {}ms", latency_ms));
+                    }
+                    if let Some(secs) = component.last_run_secs {
+                        summary.push_str(&format!(", last ran {}s ago", secs));
+                    }
+                    if let Some(detail) = &component.detail {
+                        summary.push_str(&format!(" ({})", detail));
+                    }
+                }
+            }
+            None => summary.push_str("\n  Readiness: not reported"),
+        }
+
+        Ok(ToolOutput::new(
+            summary,
+            json!({
+                "registry": registry.registry_url(),
+                "reachable": true,
+                "latency_ms": latency_ms,
+                "version": version.as_ref().map(|v| &v.version),
+                "api_versions": version.as_ref().map(|v| &v.api_versions),
+                "status": readiness.as_ref().map(|r| &r.status),
+                "components": readiness.as_ref().map(|r| &r.components),
+            }),
+        ))
+    }
+
+    /// Contents of a `reach://` resource
+    async fn read_resource_impl(&self, uri: &str) -> Result<Value, ToolError> {
+        match ResourceUri::parse(uri) {
+            Some(ResourceUri::Me) => Ok(self.handle_status().await?.data),
+            Some(ResourceUri::Contact(did)) => {
+                let contact = self.contacts.find(&did)?
+                    .ok_or_else(|| ToolError::new(ErrorCode::NotFound, format!("No contact {}", did)))?;
+                Ok(json!(contact))
+            }
+            Some(ResourceUri::Registry(page)) => {
+                let offset = (page - 1)
+                    .checked_mul(resources::PAGE_SIZE)
+                    .ok_or_else(|| ToolError::invalid_params(format!("No registry page {}", page)))?;
+                let listed = self.registry().agents_page(offset, resources::PAGE_SIZE).await?;
+                let next = (offset.saturating_add(resources::PAGE_SIZE) < listed.total)
+                    .then(|| resources::registry_page_uri(page + 1));
+                Ok(json!({ "page": page, "total": listed.total, "agents": listed.agents, "next": next }))
+            }
+            None => Err(ToolError::new(ErrorCode::NotFound, format!("Unknown resource {}", uri))),
+        }
+    }
+
+    async fn handle_switch_registry(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let previous = self.switch_registry_impl(required_str(args, "url")?).await?;
+        self.resources_changed().await;
+
+        let registry = self.registry().registry_url().to_string();
+        Ok(ToolOutput::new(
+            format!("✓ Switched to registry {}\n  Previously: {}", registry, previous.join(", ")),
+            json!({ "registry": registry, "previous": previous }),
+        ))
+    }
+
+    async fn handle_whoami(&self) -> Result<ToolOutput, ToolError> {
+        let did = self.key.did().to_string();
+        let identity_file = self.identity_path.display().to_string();
+        let registries = self.registries().urls();
+        let label = if registries.len() > 1 { "Registries" } else { "Registry" };
+        Ok(ToolOutput::new(
+            format!("Your DID: {}\n  Identity file: {}\n  {}: {}", did, identity_file, label, registries.join(", ")),
+            json!({ "did": did, "identity_file": identity_file, "registry": registries[0], "registries": registries }),
+        ))
+    }
+
+    async fn handle_debug_last_request(&self, args: &Args) -> Result<ToolOutput, ToolError> {
+        let Some(log) = &self.exchanges else {
+            return Ok(ToolOutput::new(
+                "Request recording is off; restart with REACH_DEBUG=1 to turn it on".to_string(),
+                json!({ "recording": false, "exchanges": [] }),
+            ));
+        };
+        let count = args.get("count")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1)
+            .clamp(1, log.capacity() as u64);
+
+        let exchanges = log.recent(count as usize);
+        let mut summary = match exchanges.len() {
+            0 => "No registry requests recorded yet".to_string(),
+            1 => "Last registry request:".to_string(),
+            n => format!("Last {} registry requests, newest first:", n),
+        };
+        for exchange in &exchanges {
+            let outcome = match (exchange.status, &exchange.error) {
+                (Some(status), _) => status.to_string(),
+                (None, Some(error)) => format!("failed: {}", error),
+                (None, None) => "no response".to_string(),
+            };
+            summary.push_str(&format!(
+                "\n  {} {} → {} ({} ms)",
+                exchange.method, exchange.url, outcome, exchange.duration_ms
+            ));
+        }
+        Ok(ToolOutput::new(summary, json!({ "recording": true, "exchanges": exchanges })))
+    }
+}
+
+type Args = serde_json::Map<String, Value>;
+
+fn required_str<'a>(args: &'a Args, name: &str) -> Result<&'a str, ToolError> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ToolError::invalid_params(format!("Missing required parameter: {}", name)))
+}
+
+/// A string argument that may be left out; blank counts as left out
+fn optional_str<'a>(args: &'a Args, name: &str) -> Result<Option<&'a str>, ToolError> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.trim()).filter(|value| !value.is_empty())),
+        Some(other) => Err(ToolError::invalid_params(format!("{} must be a string, not {}", name, other))),
+    }
+}
+
+/// A list-of-strings argument that may be left out, without blanks or
+/// repeats
+fn string_list(args: &Args, name: &str) -> Result<Vec<String>, ToolError> {
+    let items = match args.get(name) {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::Array(items)) => items,
+        Some(other) => return Err(ToolError::invalid_params(format!("{} must be a list of strings, not {}", name, other))),
+    };
+    let mut list: Vec<String> = Vec::new();
+    for item in items {
+        let item = item
+            .as_str()
+            .ok_or_else(|| ToolError::invalid_params(format!("{} must be a list of strings, not {}", name, item)))?
+            .trim();
+        if !item.is_empty() && !list.iter().any(|known| known == item) {
+            list.push(item.to_string());
+        }
+    }
+    Ok(list)
+}
+
+/// The metadata `request` sent that the registry didn't echo back in
+/// `registered`, and so didn't store
+fn ignored_metadata(request: &RegisterRequest, registered: &RegisterResponse) -> Vec<&'static str> {
+    let mut ignored = Vec::new();
+    if request.name.is_some() && registered.name.is_none() {
+        ignored.push("name");
+    }
+    if request.description.is_some() && registered.description.is_none() {
+        ignored.push("description");
+    }
+    if !request.tags.is_empty() && registered.tags.is_empty() {
+        ignored.push("tags");
+    }
+    ignored
+}
+
+/// Summary lines for the metadata the registry stored, and a note naming
+/// what it ignored
+fn metadata_lines(registered: &RegisterResponse, ignored: &[&str]) -> String {
+    let mut lines = String::new();
+    if let Some(name) = &registered.name {
+        lines.push_str(&format!("\n  Name: {}", name));
+    }
+    if let Some(description) = &registered.description {
+        lines.push_str(&format!("\n  Description: {}", description));
+    }
+    if !registered.tags.is_empty() {
+        lines.push_str(&format!("\n  Tags: {}", registered.tags.join(", ")));
+    }
+    if !ignored.is_empty() {
+        lines.push_str(&format!("\n  Note: the registry doesn't keep {}; ignored", ignored.join(", ")));
+    }
+    lines
+}
+
+/// Add the metadata the registry stored, and what it ignored, to a
+/// registration's structured result
+fn add_metadata(data: &mut Value, registered: &RegisterResponse, ignored: &[&str]) {
+    if let Some(name) = &registered.name {
+        data["name"] = json!(name);
+    }
+    if let Some(description) = &registered.description {
+        data["description"] = json!(description);
+    }
+    if !registered.tags.is_empty() {
+        data["tags"] = json!(registered.tags);
+    }
+    if !ignored.is_empty() {
+        data["ignored"] = json!(ignored);
+    }
+}
+
+/// How each registry answered a call made on all of them: a line apiece
+/// for the summary when there are several, and a list for the structured
+/// result
+fn registry_report<T>(outcomes: &Outcomes<T>) -> (String, Value) {
+    let mut lines = String::new();
+    let mut report = Vec::new();
+    for (registry, result) in outcomes {
+        match result {
+            Ok(_) => {
+                lines.push_str(&format!("\n  ✓ {}", registry));
+                report.push(json!({ "registry": registry, "ok": true }));
+            }
+            Err(e) => {
+                lines.push_str(&format!("\n  ✗ {}: {}", registry, e));
+                report.push(json!({ "registry": registry, "ok": false, "code": e.code, "message": e.message }));
+            }
+        }
+    }
+    if outcomes.len() < 2 {
+        lines.clear();
+    }
+    (lines, Value::Array(report))
+}
+
+/// Render seconds as days, hours and minutes, e.g. `2d 3h 5m`
+fn format_duration(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
     }
 }
 
@@ -266,6 +1177,10 @@ impl ServerHandler for ReachMcpServer {
                 tools: Some(ToolsCapability {
                     list_changed: Some(false),
                 }),
+                resources: Some(ResourcesCapability {
+                    subscribe: None,
+                    list_changed: Some(true),
+                }),
                 ..Default::default()
             },
             server_info: Implementation {
@@ -276,61 +1191,216 @@ impl ServerHandler for ReachMcpServer {
         }
     }
 
-    fn list_tools(
+    async fn list_tools(
         &self,
         _params: PaginatedRequestParam,
         _context: RequestContext<RoleServer>,
-    ) -> impl std::future::Future<Output = Result<ListToolsResult, McpError>> + Send + '_ {
-        async move {
-            let tools = vec![
-                Tool {
-                    name: "reach_register".into(),
-                    description: "Register your endpoint in the discovery registry".into(),
-                    input_schema: serde_json::json!({
-                        "type": "object",
-                        "properties": {
-                            "endpoint": {"type": "string", "description": "Endpoint URL"}
-                        },
-                        "required": ["endpoint"]
-                    }).as_object().cloned().unwrap().into(),
-                },
-                Tool {
-                    name: "reach_lookup".into(),
-                    description: "Look up an agent's endpoint by DID".into(),
-                    input_schema: serde_json::json!({
-                        "type": "object",
-                        "properties": {
-                            "did": {"type": "string", "description": "DID to look up"}
-                        },
-                        "required": ["did"]
-                    }).as_object().cloned().unwrap().into(),
-                },
-                Tool {
-                    name: "reach_deregister".into(),
-                    description: "Remove your registration".into(),
-                    input_schema: serde_json::json!({
-                        "type": "object",
-                        "properties": {}
-                    }).as_object().cloned().unwrap().into(),
-                },
-                Tool {
-                    name: "reach_status".into(),
-                    description: "Check your registration status".into(),
-                    input_schema: serde_json::json!({
-                        "type": "object",
-                        "properties": {}
-                    }).as_object().cloned().unwrap().into(),
-                },
-                Tool {
-                    name: "reach_whoami".into(),
-                    description: "Show your DID".into(),
-                    input_schema: serde_json::json!({
-                        "type": "object",
-                        "properties": {}
-                    }).as_object().cloned().unwrap().into(),
-                },
-            ];
-            Ok(ListToolsResult { tools, next_cursor: None })
+    ) -> Result<ListToolsResult, McpError> {
+        let mut tools = vec![
+            Tool {
+                name: "reach_register".into(),
+                description: "Register your endpoint in the discovery registry".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "endpoint": {"type": "string", "description": "Endpoint URL"},
+                        "name": {"type": "string", "maxLength": 128, "description": "Display name for your agent, for registries that keep one"},
+                        "description": {"type": "string", "maxLength": 1024, "description": "What your agent does, for registries that keep it"},
+                        "tags": {"type": "array", "items": {"type": "string", "maxLength": 64}, "maxItems": 32, "description": "Labels others can find your agent by, for registries that keep them"},
+                        "ttl": {"type": "integer", "description": "Seconds the registration lasts; the registry's default when left out, within its bounds"},
+                        "dry_run": {"type": "boolean", "description": "Only check that the registry would accept the registration"}
+                    },
+                    "required": ["endpoint"]
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_lookup".into(),
+                description: "Look up an agent's endpoint by DID".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "did": {"type": "string", "description": "DID to look up, or the nickname of a saved contact"},
+                        "force_refresh": {"type": "boolean", "description": "Bypass the local lookup cache"}
+                    },
+                    "required": ["did"]
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_resolve_handle".into(),
+                description: "Find an agent's DID and endpoint from a human-readable handle like alice@example.com".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "handle": {"type": "string", "description": "Handle to resolve, as name@domain"},
+                        "force_refresh": {"type": "boolean", "description": "Bypass the local lookup cache"}
+                    },
+                    "required": ["handle"]
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_ping".into(),
+                description: "Check that an agent's registered endpoint is actually reachable".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "did": {"type": "string", "description": "DID of the agent to ping"},
+                        "timeout_ms": {"type": "integer", "description": "Probe timeout in milliseconds (default 5000, max 30000)"},
+                        "force_refresh": {"type": "boolean", "description": "Bypass the local lookup cache"}
+                    },
+                    "required": ["did"]
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_deregister".into(),
+                description: "Remove your registration".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_status".into(),
+                description: "Check your registration status".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_registry_stats".into(),
+                description: "Summarize the registry: how many agents are registered and active, recent registrations and uptime".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_registry_health".into(),
+                description: "Check the registry is up: how fast it answers, its version and API versions, and whether its storage and background tasks are healthy".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_contact_add".into(),
+                description: "Save an agent to your contacts, optionally under a nickname that reach_lookup accepts in place of its DID".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "did": {"type": "string", "description": "DID of the agent"},
+                        "nickname": {"type": "string", "description": "Short name for the agent"},
+                        "notes": {"type": "string", "description": "Free-form notes"},
+                        "verify": {"type": "boolean", "description": "Look the DID up first and only save it if it's registered"}
+                    },
+                    "required": ["did"]
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_contact_list".into(),
+                description: "List your saved contacts".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_contact_remove".into(),
+                description: "Remove a saved contact".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "did": {"type": "string", "description": "DID or nickname of the contact"}
+                    },
+                    "required": ["did"]
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_switch_registry".into(),
+                description: "Point the tools at another registry for the rest of this run, once it answers a health check. Starts a new session there".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "url": {"type": "string", "description": "The registry's root URL, e.g. https://reach.example.com"}
+                    },
+                    "required": ["url"]
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_whoami".into(),
+                description: "Show your DID, which identity file is in use and which registry the tools talk to".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {}
+                }).as_object().cloned().unwrap().into(),
+            },
+            Tool {
+                name: "reach_debug_last_request".into(),
+                description: "Show the latest requests to the registry and its answers, with credentials redacted and bodies cut short. Only recorded when the server runs with REACH_DEBUG=1".into(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "count": {"type": "integer", "description": "How many of the latest requests to show, newest first (default 1, max 20)"}
+                    }
+                }).as_object().cloned().unwrap().into(),
+            },
+        ];
+        output::add_format_arg(&mut tools);
+        Ok(ListToolsResult { tools, next_cursor: None })
+    }
+
+    fn get_peer(&self) -> Option<Peer<RoleServer>> {
+        self.peer.clone()
+    }
+
+    fn set_peer(&mut self, peer: Peer<RoleServer>) {
+        self.peer = Some(peer);
+    }
+
+    async fn list_resources(
+        &self,
+        _params: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let mut resources = vec![resources::resource(
+            resources::SELF_URI.to_string(),
+            "Your registration",
+            "Whether your agent is registered, and at which endpoint",
+        )];
+        let contacts = self.contacts.list()
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        for contact in contacts {
+            let name = contact.nickname.clone().unwrap_or_else(|| contact.did.clone());
+            let description = contact.notes.clone().unwrap_or_else(|| format!("Saved contact {}", contact.did));
+            resources.push(resources::resource(resources::contact_uri(&contact.did), name, description));
+        }
+        if self.lists_agents().await {
+            resources.push(resources::resource(
+                resources::REGISTRY_URI.to_string(),
+                "Registry listing",
+                format!("Live registrations, {} per page; each page links the next", resources::PAGE_SIZE),
+            ));
+        }
+        Ok(ListResourcesResult { resources, next_cursor: None })
+    }
+
+    async fn read_resource(
+        &self,
+        params: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let result = agent_reach_client::with_request_id(request_id.clone(), self.read_resource_impl(&params.uri)).await;
+        match result {
+            Ok(value) => Ok(ReadResourceResult { contents: vec![resources::json_contents(&params.uri, &value)] }),
+            Err(e) => {
+                let e = e.with_field("request_id", request_id.as_str());
+                let data = Some(e.to_json());
+                match e.code {
+                    ErrorCode::NotFound => Err(McpError::resource_not_found(e.message, data)),
+                    _ => Err(McpError::internal_error(e.message, data)),
+                }
+            }
         }
     }
 
@@ -342,52 +1412,1047 @@ impl ServerHandler for ReachMcpServer {
         let this = self.clone();
         async move {
             let args = params.arguments.unwrap_or_default();
-
-            let result = match params.name.as_ref() {
-                "reach_register" => this.handle_register(args).await,
-                "reach_lookup" => this.handle_lookup(args).await,
-                "reach_deregister" => this.handle_deregister().await,
-                "reach_status" => this.handle_status().await,
-                "reach_whoami" => this.handle_whoami().await,
-                _ => Err(format!("Unknown tool: {}", params.name)),
+            let request_id = uuid::Uuid::new_v4().to_string();
+            let format = match Format::from_args(&args) {
+                Ok(format) => format,
+                Err(e) => return Format::Text.render(Err(e), &request_id),
             };
 
-            match result {
-                Ok(text) => Ok(CallToolResult {
-                    content: vec![Content::text(text)],
-                    is_error: Some(false),
-                }),
-                Err(e) => Ok(CallToolResult {
-                    content: vec![Content::text(e)],
-                    is_error: Some(true),
-                }),
+            let result = agent_reach_client::with_request_id(request_id.clone(), async {
+                match params.name.as_ref() {
+                    "reach_register" => this.handle_register(&args).await,
+                    "reach_lookup" => this.handle_lookup(&args).await,
+                    "reach_resolve_handle" => this.handle_resolve_handle(&args).await,
+                    "reach_ping" => this.handle_ping(&args).await,
+                    "reach_deregister" => this.handle_deregister().await,
+                    "reach_status" => this.handle_status().await,
+                    "reach_registry_stats" => this.handle_registry_stats().await,
+                    "reach_registry_health" => this.handle_registry_health().await,
+                    "reach_contact_add" => this.handle_contact_add(&args).await,
+                    "reach_contact_list" => this.handle_contact_list().await,
+                    "reach_contact_remove" => this.handle_contact_remove(&args).await,
+                    "reach_switch_registry" => this.handle_switch_registry(&args).await,
+                    "reach_whoami" => this.handle_whoami().await,
+                    "reach_debug_last_request" => this.handle_debug_last_request(&args).await,
+                    _ => Err(ToolError::new(ErrorCode::UnknownTool, format!("Unknown tool: {}", params.name))),
+                }
+            }).await;
+
+            if let Err(e) = &result {
+                tracing::debug!(request_id = %request_id, code = ?e.code, "Tool call failed");
             }
+            format.render(result, &request_id)
         }
     }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .with_writer(std::io::stderr)
-        .init();
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    info!("Starting agent-reach-mcp...");
+    logging::init();
 
-    let key = load_identity().context(
-        "Failed to load identity. Run agent-id-mcp and use 'identity_generate' first."
-    )?;
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!(error = %logging::error_chain(&e), "agent-reach-mcp failed");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    info!(did = %key.did(), "Loaded identity");
+async fn run(cli: Cli) -> Result<()> {
+    info!("Starting agent-reach-mcp...");
 
-    let server = ReachMcpServer::new(key);
+    let identity_path = identity::identity_path(cli.identity, cli.profile.as_deref());
 
-    info!("MCP server ready");
+    if let Some(Command::EncryptIdentity) = cli.command {
+        let passphrase = identity::passphrase_from_env()?.with_context(|| format!(
+            "No passphrase configured; set {}, {} or {}",
+            identity::PASSPHRASE_ENV, identity::PASSPHRASE_FILE_ENV, identity::ASKPASS_ENV
+        ))?;
+        identity::encrypt_identity_file(&identity_path, &passphrase)?;
+        eprintln!("Encrypted identity file {}", identity_path.display());
+        return Ok(());
+    }
+    let key = if cli.generate_identity && !identity_path.exists() {
+        let key = identity::generate_identity(&identity_path)?;
+        eprintln!(
+            "Generated a new identity {} in {}. Back up this file: it is the only copy of your key",
+            key.did(),
+            identity_path.display()
+        );
+        key
+    } else {
+        let key = identity::load_identity(&identity_path).with_context(|| format!(
+            "Failed to load identity from {}. Run agent-id-mcp and use 'identity_generate' first, \
+             or start with --generate-identity.",
+            identity_path.display()
+        ))?;
+        info!(did = %key.did(), path = %identity_path.display(), "Loaded identity");
+        key
+    };
 
-    let transport = stdio();
-    let running = server.serve(transport).await?;
-    running.waiting().await?;
+    let server = ReachMcpServer::new(key, identity_path)?
+        .with_cbor(cli.cbor)
+        .with_scope(cli.scope)
+        .with_debug(cli.debug);
+
+    match cli.transport {
+        Transport::Stdio => {
+            info!("MCP server ready");
+            let running = server.serve(stdio()).await?;
+            running.waiting().await?;
+        }
+        Transport::Http => {
+            let token = http::token_from_env();
+            if token.is_none() && !cli.listen.ip().is_loopback() {
+                tracing::warn!(
+                    listen = %cli.listen,
+                    "Serving MCP without authentication on a non-loopback address; set {}",
+                    http::TOKEN_ENV
+                );
+            }
+            let listener = tokio::net::TcpListener::bind(cli.listen)
+                .await
+                .with_context(|| format!("Failed to listen on {}", cli.listen))?;
+            info!(listen = %cli.listen, authenticated = token.is_some(), "MCP server ready");
+            let limits = http::SessionLimits {
+                max_sessions: cli.max_sessions,
+                idle_timeout: Duration::from_secs(cli.session_idle_timeout),
+            };
+            axum::serve(listener, http::router(server, token, limits)).await?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use axum::{extract::{Query, State}, http::{HeaderMap, StatusCode}, routing::{get, post}, Json, Router};
+
+    use agent_id_handshake::messages::{Challenge, CounterProof, Hello, ProofAccepted};
+
+    use super::*;
+
+    const ALICE: &str = "did:key:z6MkeXBLjYiSvqnhFb6D7sHm8yKm4jV45wwBFRaatf1cfZ76";
+    const BOB: &str = "did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F";
+
+    /// What the fake registry has seen
+    #[derive(Default)]
+    struct Calls {
+        hellos: AtomicUsize,
+        proofs: AtomicUsize,
+        registers: AtomicUsize,
+        lookups: AtomicUsize,
+        deregisters: AtomicUsize,
+        /// Idempotency key of the first /register
+        idempotency_keys: tokio::sync::Mutex<Option<String>>,
+        /// Body of the last /register
+        register_body: tokio::sync::Mutex<Option<Value>>,
+        /// Endpoint of the one registration it holds
+        endpoint: tokio::sync::Mutex<Option<String>>,
+    }
+
+    /// How the fake registry answers the handshake
+    #[derive(Clone, Copy, Debug, Default)]
+    enum Handshake {
+        #[default]
+        Accept,
+        /// `/hello` fails with a 500
+        HelloFails,
+        /// `/hello` answers 200 with a body that isn't a challenge
+        GarbledChallenge,
+        /// `/proof` refuses every proof
+        ProofRefused,
+    }
+
+    /// Which of the sessions it handed out the fake registry accepts
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    enum Sessions {
+        /// All of them
+        Valid,
+        /// All but `session-1`, so the first authenticated call renews
+        #[default]
+        FirstExpired,
+        /// None, as if each expired straight away
+        AllExpired,
+    }
+
+    #[derive(Clone, Copy, Debug, Default)]
+    struct Script {
+        handshake: Handshake,
+        sessions: Sessions,
+    }
+
+    /// A registry that runs the handshake as `script` says, hands out
+    /// `session-1`, `session-2`, ... and keeps one registration, answering
+    /// lookups of any DID with it
+    async fn fake_registry(script: Script) -> (String, Arc<Calls>) {
+        async fn authorized(calls: &Calls, headers: &HeaderMap, sessions: Sessions) -> bool {
+            let issued = calls.proofs.load(Ordering::SeqCst);
+            let first = match sessions {
+                Sessions::Valid => 1,
+                Sessions::FirstExpired => 2,
+                Sessions::AllExpired => return false,
+            };
+            headers.get("authorization").is_some_and(|v| (first..=issued).any(|n| *v == format!("Bearer session-{}", n)))
+        }
+        fn expired() -> (StatusCode, Json<Value>) {
+            let body = json!({ "error": "Session expired", "code": "session_expired", "action": "reauthenticate" });
+            (StatusCode::UNAUTHORIZED, Json(body))
+        }
+
+        let calls = Arc::new(Calls::default());
+        let app = Router::new()
+            .route("/hello", post(move |State(calls): State<Arc<Calls>>, Json(hello): Json<Hello>| async move {
+                calls.hellos.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(hello.capabilities, Some(vec!["scope:write".to_string()]), "write scope by default");
+                assert!(hello.protocols.iter().any(|p| p == "reach/1"), "advertises reach/1");
+                match script.handshake {
+                    Handshake::HelloFails => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "Challenge store unavailable" }))),
+                    Handshake::GarbledChallenge => (StatusCode::OK, Json(json!({ "nonce": 42 }))),
+                    _ => (StatusCode::OK, Json(json!(Challenge::new("did:key:registry".to_string(), hello.did)))),
+                }
+            }))
+            .route("/proof", post(move |State(calls): State<Arc<Calls>>| async move {
+                if let Handshake::ProofRefused = script.handshake {
+                    return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "Invalid signature" })));
+                }
+                let n = calls.proofs.fetch_add(1, Ordering::SeqCst) + 1;
+                let accepted = ProofAccepted {
+                    type_: "ProofAccepted".to_string(),
+                    version: "1.0".to_string(),
+                    session_id: format!("session-{}", n),
+                    counter_proof: CounterProof {
+                        challenge_hash: String::new(),
+                        responder_did: "did:key:registry".to_string(),
+                        signing_key: String::new(),
+                        signature: String::new(),
+                    },
+                    session_expires_at: (chrono::Utc::now().timestamp() + 300) * 1000,
+                };
+                (StatusCode::OK, Json(json!(accepted)))
+            }))
+            .route("/register", post(move |State(calls): State<Arc<Calls>>, headers: HeaderMap, Json(body): Json<Value>| async move {
+                calls.registers.fetch_add(1, Ordering::SeqCst);
+                *calls.register_body.lock().await = Some(body.clone());
+                let key = headers["idempotency-key"].to_str().unwrap().to_string();
+                assert_eq!(*calls.idempotency_keys.lock().await.get_or_insert(key.clone()), key, "retries reuse the key");
+                if !authorized(&calls, &headers, script.sessions).await {
+                    return expired();
+                }
+                *calls.endpoint.lock().await = body["endpoint"].as_str().map(str::to_owned);
+                let ttl = body["ttl"].as_u64().unwrap_or(3600);
+                (StatusCode::OK, Json(json!({ "ok": true, "expires_at": 4102444800i64, "ttl": ttl })))
+            }))
+            .route("/lookup/:did", get(|State(calls): State<Arc<Calls>>, axum::extract::Path(did): axum::extract::Path<String>| async move {
+                calls.lookups.fetch_add(1, Ordering::SeqCst);
+                match calls.endpoint.lock().await.clone() {
+                    Some(endpoint) => (StatusCode::OK, Json(json!({ "did": did, "endpoint": endpoint, "expires_at": 4102444800i64 }))),
+                    None => (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" }))),
+                }
+            }))
+            .route("/deregister", post(move |State(calls): State<Arc<Calls>>, headers: HeaderMap| async move {
+                calls.deregisters.fetch_add(1, Ordering::SeqCst);
+                if !authorized(&calls, &headers, script.sessions).await {
+                    return expired();
+                }
+                calls.endpoint.lock().await.take();
+                (StatusCode::OK, Json(json!({ "ok": true })))
+            }))
+            .route("/logout", post(|| async { StatusCode::NO_CONTENT }))
+            .with_state(calls.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, calls)
+    }
+
+    fn server(registry_url: String) -> ReachMcpServer {
+        multi_server(vec![registry_url])
+    }
+
+    #[tokio::test]
+    async fn one_handshake_serves_a_registration_and_its_removal() {
+        let (url, calls) = fake_registry(Script { sessions: Sessions::Valid, ..Default::default() }).await;
+        let server = server(url.clone());
+        let did = server.key.did().to_string();
+
+        let outcomes = server.register_impl(&RegisterRequest::new("wss://agent.example")).await.unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].1.as_ref().unwrap().ttl, 3600);
+        let session = server.cache.lock().await.session(&url).map(|s| s.session_id.clone());
+        assert_eq!(session.as_deref(), Some("session-1"), "the session is cached");
+
+        let (lookup, registry) = server.lookup_impl(&did, false).await.unwrap();
+        assert_eq!((lookup.endpoint.as_str(), registry.as_str()), ("wss://agent.example", url.as_str()));
+
+        server.deregister_impl().await.unwrap();
+        assert_eq!(calls.deregisters.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            (calls.hellos.load(Ordering::SeqCst), calls.proofs.load(Ordering::SeqCst)),
+            (1, 1),
+            "the second call reuses the first call's session"
+        );
+
+        // Deregistering drops the cached lookup, so this asks the registry
+        let err = server.lookup_impl(&did, false).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NotFound);
+        assert_eq!(err.fields["did"], did.as_str());
+        assert_eq!(calls.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn lookups_of_unknown_dids_are_not_found() {
+        let (url, calls) = fake_registry(Script { sessions: Sessions::Valid, ..Default::default() }).await;
+        let server = server(url.clone());
+
+        {
+            let mut cache = server.cache.lock().await;
+            cache.insert_lookup(CachedLookup {
+                registry: url.clone(),
+                did: ALICE.to_string(),
+                endpoint: "wss://alice".to_string(),
+                expires_at: 4102444800,
+                etag: None,
+                handle: None,
+                public_key: None,
+            });
+            server.save_cache(&cache);
+        }
+        let err = server.lookup_impl(ALICE, true).await.unwrap_err();
+        assert_eq!((err.code, err.message.as_str()), (ErrorCode::NotFound, "Agent not found"));
+        assert!(server.cache.lock().await.lookup(ALICE).is_none());
+        // The removal is saved, so a restart doesn't bring the entry back
+        let did = server.key.did().to_string();
+        assert!(ClientCache::load(&server.cache_path, &[url], &did).lookup(ALICE).is_none());
+        assert_eq!(calls.hellos.load(Ordering::SeqCst), 0, "lookups need no session");
+    }
+
+    #[tokio::test]
+    async fn handshake_failures_stop_before_registering() {
+        let cases = [
+            (Handshake::HelloFails, ErrorCode::RegistryError, "Hello failed: Challenge store unavailable"),
+            (Handshake::GarbledChallenge, ErrorCode::RegistryError, "Failed to parse Challenge"),
+            (Handshake::ProofRefused, ErrorCode::Unauthorized, "Proof failed: Invalid signature"),
+        ];
+        for (handshake, code, message) in cases {
+            let (url, calls) = fake_registry(Script { handshake, ..Default::default() }).await;
+            let server = server(url.clone());
+
+            let err = server.register_impl(&RegisterRequest::new("wss://agent.example")).await.unwrap_err();
+            assert_eq!(err.code, code, "{:?}: {}", handshake, err);
+            assert!(err.message.starts_with(message), "{:?}: {}", handshake, err);
+            assert_eq!(calls.hellos.load(Ordering::SeqCst), 1, "{:?}", handshake);
+            assert_eq!(calls.registers.load(Ordering::SeqCst), 0, "{:?}", handshake);
+            assert!(server.cache.lock().await.session(&url).is_none(), "{:?}", handshake);
+
+            let err = server.deregister_impl().await.unwrap_err();
+            assert_eq!(err.code, code, "{:?}: {}", handshake, err);
+            assert_eq!(calls.deregisters.load(Ordering::SeqCst), 0, "{:?}", handshake);
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_renewed_and_the_call_retried() {
+        let (url, calls) = fake_registry(Script::default()).await;
+        let server = server(url.clone());
+
+        server.register_impl(&RegisterRequest::new("wss://agent.example")).await.unwrap();
+        assert_eq!(calls.proofs.load(Ordering::SeqCst), 2);
+        assert_eq!(calls.registers.load(Ordering::SeqCst), 2);
+        let session = server.cache.lock().await.session(&url).map(|s| s.session_id.clone());
+        assert_eq!(session.as_deref(), Some("session-2"));
+    }
+
+    #[tokio::test]
+    async fn renewal_is_attempted_once() {
+        let (url, calls) = fake_registry(Script { sessions: Sessions::AllExpired, ..Default::default() }).await;
+        let server = server(url);
+
+        let err = server.register_impl(&RegisterRequest::new("wss://agent.example")).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::SessionExpired);
+        assert_eq!(calls.proofs.load(Ordering::SeqCst), 2);
+        assert_eq!(calls.registers.load(Ordering::SeqCst), 2);
+    }
+
+    fn args(value: Value) -> Args {
+        value.as_object().cloned().unwrap()
+    }
+
+    /// The URL of a registry nobody is listening at
+    async fn unreachable_registry() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    fn multi_server(registry_urls: Vec<String>) -> ReachMcpServer {
+        let dir = std::env::temp_dir().join(format!("agent-reach-mcp-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        ReachMcpServer::with_registries(RootKey::generate(), dir.join("identity.json"), registry_urls)
+    }
+
+    #[tokio::test]
+    async fn registrations_carry_metadata() {
+        let (url, calls) = fake_registry(Script::default()).await;
+        let server = server(url);
+
+        let registration = args(json!({
+            "endpoint": "wss://agent.example",
+            "name": "Alice's agent",
+            "description": " Books meetings ",
+            "tags": ["calendar", "scheduling", "calendar", ""],
+            "ttl": 600,
+        }));
+        let output = server.handle_register(&registration).await.unwrap();
+        assert_eq!(
+            calls.register_body.lock().await.clone().unwrap(),
+            json!({
+                "endpoint": "wss://agent.example",
+                "ttl": 600,
+                "name": "Alice's agent",
+                "description": "Books meetings",
+                "tags": ["calendar", "scheduling"],
+            })
+        );
+        // This registry echoes none of it back, so none of it is reported as stored
+        assert_eq!(
+            output.summary,
+            "✓ Registered ".to_string() + &server.key.did().to_string()
+                + " at endpoint: wss://agent.example\n  Note: the registry doesn't keep name, description, tags; ignored\n  TTL: 600s"
+        );
+        assert!(output.data.get("tags").is_none());
+        assert_eq!(output.data["ignored"], json!(["name", "description", "tags"]));
+
+        // What a registry echoes is reported as stored
+        let registered = RegisterResponse {
+            name: Some("Alice's agent".to_string()),
+            tags: vec!["calendar".to_string()],
+            ..Default::default()
+        };
+        let request = RegisterRequest {
+            name: Some("Alice's agent".to_string()),
+            description: Some("Books meetings".to_string()),
+            tags: vec!["calendar".to_string()],
+            ..RegisterRequest::new("wss://agent.example")
+        };
+        let ignored = ignored_metadata(&request, &registered);
+        assert_eq!(
+            metadata_lines(&registered, &ignored),
+            "\n  Name: Alice's agent\n  Tags: calendar\n  Note: the registry doesn't keep description; ignored"
+        );
+
+        // Only the endpoint is needed, and nothing else is sent without it
+        let (url, calls) = fake_registry(Script::default()).await;
+        let server = self::server(url);
+        server.handle_register(&args(json!({ "endpoint": "wss://agent.example" }))).await.unwrap();
+        assert_eq!(calls.register_body.lock().await.clone().unwrap(), json!({ "endpoint": "wss://agent.example" }));
+
+        let too_many_tags: Vec<_> = (0..=agent_reach_client::MAX_TAGS).map(|i| format!("tag{}", i)).collect();
+        let long_name = "n".repeat(agent_reach_client::MAX_NAME_LEN + 1);
+        for bad in [
+            json!({ "tags": "calendar" }),
+            json!({ "tags": [1] }),
+            json!({ "tags": too_many_tags }),
+            json!({ "name": 7 }),
+            json!({ "name": long_name }),
+            json!({ "ttl": 0 }),
+            json!({ "ttl": "600" }),
+        ] {
+            let mut registration = args(bad.clone());
+            registration.insert("endpoint".to_string(), json!("wss://agent.example"));
+            let err = server.handle_register(&registration).await.unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidParams, "{}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn registrations_report_each_registry() {
+        let (url, calls) = fake_registry(Script::default()).await;
+        let down = unreachable_registry().await;
+        let server = multi_server(vec![url.clone(), down.clone()]);
+
+        let output = server.handle_register(&args(json!({ "endpoint": "wss://agent.example" }))).await.unwrap();
+        assert_eq!(calls.registers.load(Ordering::SeqCst), 2);
+        assert_eq!(output.data["registries"][0], json!({ "registry": url, "ok": true }));
+        assert_eq!(output.data["registries"][1]["ok"], false);
+        assert_eq!(output.data["registries"][1]["code"], "network_error");
+        assert!(output.summary.contains(&format!("✗ {}", down)), "{}", output.summary);
+        assert!(server.cache.lock().await.session(&url).is_some());
+
+        // With every registry refusing, the call fails and says why for each
+        let server = multi_server(vec![down.clone(), unreachable_registry().await]);
+        let err = server.register_impl(&RegisterRequest::new("wss://agent.example")).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NetworkError);
+        assert_eq!(err.fields["registries"].as_object().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn lookups_fall_through_to_later_registries() {
+        let empty = Router::new().route("/lookup/:did", get(|| async {
+            (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" })))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let empty_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, empty).await });
+        let app = Router::new().route("/lookup/:did", get(|axum::extract::Path(did): axum::extract::Path<String>| async move {
+            if did != ALICE {
+                return (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" })));
+            }
+            (StatusCode::OK, Json(json!({ "did": ALICE, "endpoint": "wss://a", "status": "online", "expires_at": 4102444800i64 })))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let server = multi_server(vec![empty_url.clone(), url.clone()]);
+        let output = server.handle_lookup(&args(json!({ "did": ALICE }))).await.unwrap();
+        assert_eq!(output.data["endpoint"], "wss://a");
+        assert_eq!(output.data["registry"], url.as_str());
+        assert!(output.summary.contains(&format!("Registry: {}", url)), "{}", output.summary);
+
+        // Cached answers remember where they came from
+        let output = server.handle_lookup(&args(json!({ "did": ALICE }))).await.unwrap();
+        assert_eq!(output.data["registry"], url.as_str());
+
+        let err = server.lookup_impl(BOB, true).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn registries_can_be_switched_at_runtime() {
+        let app = Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .route("/lookup/:did", get(|| async {
+                Json(json!({ "did": ALICE, "endpoint": "wss://a", "status": "online", "expires_at": 4102444800i64 }))
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let (old, _) = fake_registry(Script::default()).await;
+        let server = server(old.clone());
+
+        // Mistakes are caught before anything is sent
+        let err = server.switch_registry_impl("reach.example.com").await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        assert!(err.message.contains("did you mean https://reach.example.com?"), "{}", err.message);
+        for bad in ["ftp://reach.example.com", "https://reach.example.com/v1", "https://"] {
+            let err = server.switch_registry_impl(bad).await.unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidParams, "{}", bad);
+        }
+
+        // A registry that doesn't answer isn't switched to
+        let err = server.switch_registry_impl(&unreachable_registry().await).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NetworkError);
+        assert_eq!(server.registry().registry_url(), old);
+
+        let output = server.handle_switch_registry(&args(json!({ "url": format!("{}/", url) }))).await.unwrap();
+        assert_eq!(output.data["registry"], url.as_str());
+        assert_eq!(output.data["previous"], json!([old]));
+        assert_eq!(server.handle_whoami().await.unwrap().data["registry"], url.as_str());
+        let (_, registry) = server.lookup_impl(ALICE, true).await.unwrap();
+        assert_eq!(registry, url);
+    }
+
+    #[tokio::test]
+    async fn status_comes_from_the_session() {
+        let registered = Arc::new(AtomicBool::new(false));
+        let app = Router::new()
+            .route("/hello", post(|Json(hello): Json<Hello>| async move {
+                Json(Challenge::new("did:key:registry".to_string(), hello.did))
+            }))
+            .route("/proof", post(|| async {
+                Json(ProofAccepted {
+                    type_: "ProofAccepted".to_string(),
+                    version: "1.0".to_string(),
+                    session_id: "session-1".to_string(),
+                    counter_proof: CounterProof {
+                        challenge_hash: String::new(),
+                        responder_did: "did:key:registry".to_string(),
+                        signing_key: String::new(),
+                        signature: String::new(),
+                    },
+                    session_expires_at: (chrono::Utc::now().timestamp() + 300) * 1000,
+                })
+            }))
+            .route("/me", get(|State(registered): State<Arc<AtomicBool>>, headers: HeaderMap| async move {
+                assert_eq!(headers["authorization"], "Bearer session-1");
+                if !registered.load(Ordering::SeqCst) {
+                    return (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" })));
+                }
+                (StatusCode::OK, Json(json!({ "did": "did:key:me", "endpoint": "wss://me", "status": "online", "expires_at": 4102444800i64, "ttl_remaining_secs": 3900 })))
+            }))
+            .with_state(registered.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let server = server(url);
+
+        let output = server.handle_status().await.unwrap();
+        assert_eq!(output.data["registered"], false);
+
+        registered.store(true, Ordering::SeqCst);
+        let output = server.handle_status().await.unwrap();
+        assert_eq!(output.data["registered"], true);
+        assert_eq!(output.data["endpoint"], "wss://me");
+        assert_eq!(output.data["ttl_remaining_secs"], 3900);
+        assert!(output.summary.contains("Expires in: 1h 5m"), "{}", output.summary);
+    }
+
+    #[tokio::test]
+    async fn cbor_lookups_are_decoded() {
+        let app = Router::new().route("/lookup/:did", get(|headers: HeaderMap| async move {
+            assert_eq!(headers["accept"], "application/cbor");
+            let entry = json!({ "did": ALICE, "endpoint": "coap://sensor:5683", "status": "online", "expires_at": 4102444800i64 });
+            let mut body = Vec::new();
+            ciborium::into_writer(&entry, &mut body).unwrap();
+            ([(axum::http::header::CONTENT_TYPE, "application/cbor")], body)
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (lookup, _) = server(url).with_cbor(true).lookup_impl(ALICE, true).await.unwrap();
+        assert_eq!(lookup.endpoint, "coap://sensor:5683");
+        assert_eq!(lookup.expires_at, 4102444800);
+    }
+
+    #[tokio::test]
+    async fn versioned_registries_are_called_under_their_prefix() {
+        let versions = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/version", get(|State(versions): State<Arc<AtomicUsize>>| async move {
+                versions.fetch_add(1, Ordering::SeqCst);
+                Json(json!({ "version": "0.2.0", "api_versions": ["v1"] }))
+            }))
+            .route("/v1/lookup/:did", get(|| async {
+                Json(json!({ "did": ALICE, "endpoint": "wss://a", "status": "online", "expires_at": 4102444800i64 }))
+            }))
+            .with_state(versions.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let server = server(url);
+        for _ in 0..2 {
+            let (lookup, _) = server.lookup_impl(ALICE, true).await.unwrap();
+            assert_eq!(lookup.endpoint, "wss://a");
+        }
+        assert_eq!(versions.load(Ordering::SeqCst), 1, "the prefix is probed once");
+    }
+
+    #[tokio::test]
+    async fn registry_health_renders_each_component() {
+        let app = Router::new()
+            .route("/version", get(|| async { Json(json!({ "version": "0.2.0", "api_versions": ["v1"] })) }))
+            .route("/v1/health", get(|| async { "ok" }))
+            .route("/v1/health/ready", get(|| async {
+                let body = json!({
+                    "status": "degraded",
+                    "version": "0.2.0",
+                    "components": {
+                        "storage": { "status": "ok", "latency_ms": 3 },
+                        "sync": { "status": "down", "detail": "No round finished in 95s", "last_run_secs": 95 },
+                    },
+                });
+                (StatusCode::SERVICE_UNAVAILABLE, Json(body))
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let output = server(url).handle_registry_health().await.unwrap();
+        assert!(output.summary.starts_with("✗ Registry"), "{}", output.summary);
+        assert!(output.summary.contains("Version: 0.2.0 (API v1)"));
+        assert!(output.summary.contains("storage: ok, 3ms"));
+        assert!(output.summary.contains("sync: down, last ran 95s ago (No round finished in 95s)"));
+        assert_eq!(output.data["reachable"], true);
+        assert_eq!(output.data["status"], "degraded");
+        assert_eq!(output.data["api_versions"], json!(["v1"]));
+        assert_eq!(output.data["components"]["sync"]["status"], "down");
+
+        // Registries from before readiness checks are only reported as up
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let output = server(url).handle_registry_health().await.unwrap();
+        assert!(output.summary.starts_with("✓ Registry"), "{}", output.summary);
+        assert!(output.summary.contains("Readiness: not reported"));
+        assert_eq!(output.data["version"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn handle_resolutions_are_cached() {
+        let resolves = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/resolve", get(|State(resolves): State<Arc<AtomicUsize>>, Query(params): Query<HashMap<String, String>>| async move {
+                resolves.fetch_add(1, Ordering::SeqCst);
+                if params["handle"] != "alice@example.com" {
+                    return (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" })));
+                }
+                let agent = json!({ "did": ALICE, "endpoint": "wss://alice", "expires_at": 4102444800i64, "handle": "alice@example.com" });
+                (StatusCode::OK, Json(json!({ "handle": "alice@example.com", "did": ALICE, "agent": agent })))
+            }))
+            .with_state(resolves.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let server = server(url);
+
+        let lookup = server.resolve_impl("Alice@Example.com", false).await.unwrap();
+        assert_eq!(lookup.did, ALICE);
+        assert_eq!(lookup.endpoint, "wss://alice");
+        let cached = server.resolve_impl("alice@example.com", false).await.unwrap();
+        assert_eq!(cached.did, ALICE);
+        assert_eq!(resolves.load(Ordering::SeqCst), 1);
+        // The same entry answers DID lookups
+        assert!(server.cache.lock().await.lookup(ALICE).is_some());
+
+        let err = server.resolve_impl("bob@example.com", false).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NotFound);
+        assert_eq!(err.message, "No agent registered for handle bob@example.com");
+    }
+
+    #[tokio::test]
+    async fn lookups_accept_contact_nicknames() {
+        let app = Router::new().route("/lookup/:did", get(|axum::extract::Path(did): axum::extract::Path<String>| async move {
+            if did != ALICE {
+                return (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" })));
+            }
+            (StatusCode::OK, Json(json!({ "did": ALICE, "endpoint": "wss://alice", "expires_at": 4102444800i64 })))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let server = server(url);
+
+        let err = server.handle_contact_add(&args(json!({ "did": BOB, "verify": true }))).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NotFound);
+        assert!(server.contacts.list().unwrap().is_empty(), "unverified contacts aren't saved");
+
+        let added = server.handle_contact_add(&args(json!({ "did": ALICE, "nickname": "alice", "verify": true }))).await.unwrap();
+        assert_eq!(added.data["endpoint"], "wss://alice");
+
+        let output = server.handle_lookup(&args(json!({ "did": "Alice" }))).await.unwrap();
+        assert_eq!(output.data["did"], ALICE);
+        assert_eq!(output.data["contact"], "alice");
+
+        let err = server.handle_lookup(&args(json!({ "did": "carol" }))).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+    }
+
+    #[tokio::test]
+    async fn expired_lookups_say_when_they_expired() {
+        let app = Router::new().route("/lookup/:did", get(|axum::extract::Path(did): axum::extract::Path<String>| async move {
+            if did != ALICE {
+                return (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" })));
+            }
+            (StatusCode::GONE, Json(json!({ "error": "Registration expired", "code": "expired", "expires_at": 1700000000 })))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let server = server(url);
+
+        let err = server.lookup_impl(ALICE, true).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::Expired);
+        assert_eq!(err.fields["expires_at"], 1700000000);
+
+        let err = server.lookup_impl(BOB, true).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::NotFound);
+        assert!(!err.fields.contains_key("expires_at"));
+    }
+
+    #[tokio::test]
+    async fn malformed_input_is_refused_with_a_hint() {
+        let server = server(unreachable_registry().await);
+
+        let bare = ALICE.strip_prefix("did:key:").unwrap();
+        let err = server.handle_lookup(&args(json!({ "did": bare }))).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        assert_eq!(err.fields["suggestion"], ALICE);
+
+        let err = server.handle_lookup(&args(json!({ "did": "did:key:z6Mk" }))).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams, "refused before the registry is tried");
+        assert!(err.message.contains("not a valid did:key DID"), "{}", err);
+
+        let err = server.handle_register(&args(json!({ "endpoint": "agent.example:8080" }))).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        assert_eq!(err.fields["suggestion"], "wss://agent.example:8080");
+        assert_eq!(err.fields["endpoint"], "agent.example:8080");
+    }
+
+    /// MCP client that counts resource list change notifications
+    #[derive(Clone)]
+    struct Listener {
+        peer: Option<Peer<rmcp::service::RoleClient>>,
+        changes: tokio::sync::mpsc::UnboundedSender<()>,
+    }
+
+    impl rmcp::handler::client::ClientHandler for Listener {
+        async fn on_resource_list_changed(&self) {
+            let _ = self.changes.send(());
+        }
+
+        fn get_peer(&self) -> Option<Peer<rmcp::service::RoleClient>> {
+            self.peer.clone()
+        }
+
+        fn set_peer(&mut self, peer: Peer<rmcp::service::RoleClient>) {
+            self.peer = Some(peer);
+        }
+    }
+
+    #[tokio::test]
+    async fn resources_are_served_to_mcp_clients() {
+        let app = Router::new().route("/agents", get(|Query(page): Query<HashMap<String, usize>>| async move {
+            let (offset, limit) = (page["offset"], page["limit"]);
+            let agents: Vec<Value> = (offset..150.min(offset + limit))
+                .map(|i| json!({ "did": format!("did:key:{}", i), "endpoint": format!("wss://{}", i), "expires_at": 4102444800i64 }))
+                .collect();
+            Json(json!({ "agents": agents, "total": 150 }))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+        let server = server(url);
+        tokio::spawn(async move { server.serve(server_io).await.unwrap().waiting().await });
+        let (changes, mut changed) = tokio::sync::mpsc::unbounded_channel();
+        let client = Listener { peer: None, changes }.serve(client_io).await.unwrap();
+
+        let read = |uri: &str| {
+            let request = client.read_resource(ReadResourceRequestParam { uri: uri.to_string() });
+            async move {
+                let result = request.await.unwrap();
+                match &result.contents[0] {
+                    rmcp::model::ResourceContents::TextResourceContents { mime_type, text, .. } => {
+                        assert_eq!(mime_type.as_deref(), Some("application/json"));
+                        serde_json::from_str::<Value>(text).unwrap()
+                    }
+                    other => panic!("unexpected contents {:?}", other),
+                }
+            }
+        };
+
+        let uris = |resources: Vec<rmcp::model::Resource>| resources.into_iter().map(|r| r.raw.uri).collect::<Vec<_>>();
+        assert_eq!(uris(client.list_all_resources().await.unwrap()), ["reach://self", "reach://registry"]);
+
+        let contact_uri = resources::contact_uri(ALICE);
+        let arguments = json!({ "did": ALICE, "nickname": "alice" }).as_object().cloned();
+        client.call_tool(CallToolRequestParam { name: "reach_contact_add".into(), arguments }).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), changed.recv()).await.expect("list change notified");
+        assert_eq!(
+            uris(client.list_all_resources().await.unwrap()),
+            ["reach://self", contact_uri.as_str(), "reach://registry"]
+        );
+        assert_eq!(read(contact_uri.as_str()).await["nickname"], "alice");
+
+        let first = read("reach://registry").await;
+        assert_eq!((first["total"].as_u64(), first["agents"].as_array().unwrap().len()), (Some(150), 100));
+        assert_eq!(first["next"], "reach://registry?page=2");
+        let second = read("reach://registry?page=2").await;
+        assert_eq!(second["agents"].as_array().unwrap().len(), 50);
+        assert_eq!(second["next"], Value::Null);
+        let past_the_end = format!("reach://registry?page={}", usize::MAX);
+        assert!(client.read_resource(ReadResourceRequestParam { uri: past_the_end }).await.is_err());
+
+        assert!(client.read_resource(ReadResourceRequestParam { uri: "reach://nope".to_string() }).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tools_behave_the_same_over_http() {
+        let app = Router::new().route("/lookup/:did", get(|axum::extract::Path(did): axum::extract::Path<String>| async move {
+            if did != ALICE {
+                return (StatusCode::NOT_FOUND, Json(json!({ "error": "Agent not found" })));
+            }
+            (StatusCode::OK, Json(json!({ "did": ALICE, "endpoint": "wss://alice", "expires_at": 4102444800i64 })))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+        let stdio_server = server(url.clone());
+        tokio::spawn(async move { stdio_server.serve(server_io).await.unwrap().waiting().await });
+        let stdio_client = ().serve(client_io).await.unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sse_url = format!("http://{}{}", listener.local_addr().unwrap(), http::SSE_PATH);
+        let router = http::router(server(url), Some("secret".to_string()), http::SessionLimits::default());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let unauthenticated = reqwest::get(&sse_url).await.unwrap();
+        assert_eq!(unauthenticated.status().as_u16(), 401);
+
+        let mut headers = reqwest_sse::header::HeaderMap::new();
+        headers.insert(reqwest_sse::header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let http_client = reqwest_sse::Client::builder().default_headers(headers).build().unwrap();
+        let transport = rmcp::transport::SseTransport::start_with_client(sse_url.as_str(), http_client).await.unwrap();
+        let http_client = ().serve(transport).await.unwrap();
+
+        let tool_names = |tools: Vec<Tool>| tools.into_iter().map(|t| t.name).collect::<Vec<_>>();
+        assert_eq!(
+            tool_names(http_client.list_all_tools().await.unwrap()),
+            tool_names(stdio_client.list_all_tools().await.unwrap())
+        );
+
+        // Structured results, less the request IDs that differ per call
+        async fn call(client: &Peer<rmcp::service::RoleClient>, name: &str, arguments: Value) -> (Option<bool>, Value) {
+            let params = CallToolRequestParam { name: name.to_string().into(), arguments: arguments.as_object().cloned() };
+            let result = client.call_tool(params).await.unwrap();
+            let mut data: Value = serde_json::from_str(&result.content[1].as_text().unwrap().text).unwrap();
+            data.as_object_mut().unwrap().remove("request_id");
+            (result.is_error, data)
+        }
+        for (name, arguments) in [
+            ("reach_lookup", json!({ "did": ALICE })),
+            ("reach_lookup", json!({ "did": BOB })),
+            ("reach_contact_add", json!({ "did": ALICE, "nickname": "alice" })),
+            ("reach_contact_list", json!({})),
+            ("reach_lookup", json!({ "did": "alice" })),
+        ] {
+            let over_http = call(&http_client, name, arguments.clone()).await;
+            let over_stdio = call(&stdio_client, name, arguments).await;
+            assert_eq!(over_http, over_stdio, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn http_sessions_are_capped_and_expire_when_idle() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sse_url = format!("http://{}{}", listener.local_addr().unwrap(), http::SSE_PATH);
+        let limits = http::SessionLimits { max_sessions: 1, idle_timeout: Duration::from_millis(300) };
+        let router = http::router(server(unreachable_registry().await), None, limits);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut first = reqwest::get(&sse_url).await.unwrap();
+        assert_eq!(first.status().as_u16(), 200);
+        let endpoint = String::from_utf8(first.chunk().await.unwrap().unwrap().to_vec()).unwrap();
+        assert!(endpoint.contains("sessionId="), "{}", endpoint);
+        assert_eq!(reqwest::get(&sse_url).await.unwrap().status().as_u16(), 503);
+
+        // An idle session is closed, which ends its stream and frees its place
+        tokio::time::timeout(Duration::from_secs(5), first.bytes()).await.expect("idle stream ends").unwrap();
+        let mut second = None;
+        for _ in 0..50 {
+            let response = reqwest::get(&sse_url).await.unwrap();
+            if response.status().as_u16() == 200 {
+                second = Some(response);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(second.is_some(), "a new session opens once the idle one is gone");
+    }
+
+    #[tokio::test]
+    async fn tools_answer_in_json_when_asked() {
+        let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+        let mcp_server = server(unreachable_registry().await);
+        tokio::spawn(async move { mcp_server.serve(server_io).await.unwrap().waiting().await });
+        let client = ().serve(client_io).await.unwrap();
+
+        for tool in client.list_all_tools().await.unwrap() {
+            assert!(tool.input_schema["properties"].get("format").is_some(), "{} takes a format", tool.name);
+        }
+
+        let call = |name: &str, arguments: Value| {
+            let params = CallToolRequestParam { name: name.to_string().into(), arguments: arguments.as_object().cloned() };
+            let client = &client;
+            async move { client.call_tool(params).await.unwrap() }
+        };
+
+        let text = call("reach_whoami", json!({})).await;
+        assert_eq!(text.content.len(), 2);
+        assert!(text.content[0].as_text().unwrap().text.starts_with("Your DID: "));
+
+        let json = call("reach_whoami", json!({ "format": "json" })).await;
+        assert_eq!(json.content.len(), 1, "the object alone");
+        let object: Value = serde_json::from_str(&json.content[0].as_text().unwrap().text).unwrap();
+        assert_eq!(object["ok"], true);
+        assert_eq!(object["did"].as_str().map(|did| did.starts_with("did:key:")), Some(true));
+
+        let failed = call("reach_lookup", json!({ "did": ALICE, "format": "json" })).await;
+        assert_eq!((failed.is_error, failed.content.len()), (Some(true), 1));
+        let object: Value = serde_json::from_str(&failed.content[0].as_text().unwrap().text).unwrap();
+        assert_eq!((object["ok"].as_bool(), object["code"].as_str()), (Some(false), Some("network_error")));
+
+        let refused = call("reach_whoami", json!({ "format": "yaml" })).await;
+        assert_eq!(refused.is_error, Some(true));
+        assert!(refused.content[0].as_text().unwrap().text.contains("format must be"));
+    }
+
+    #[tokio::test]
+    async fn recent_requests_are_shown_only_when_debugging() {
+        let (url, _) = fake_registry(Script::default()).await;
+        let quiet = server(url.clone());
+        let off = quiet.handle_debug_last_request(&Args::new()).await.unwrap();
+        assert_eq!(off.data, json!({ "recording": false, "exchanges": [] }));
+        assert!(off.summary.contains("REACH_DEBUG=1"));
+
+        let server = server(url.clone()).with_debug(true);
+        let request = RegisterRequest::new("wss://agent.example");
+        agent_reach_client::with_request_id("req-debug".to_string(), server.register_impl(&request)).await.unwrap();
+
+        let last = server.handle_debug_last_request(&Args::new()).await.unwrap();
+        let exchanges = last.data["exchanges"].as_array().unwrap();
+        assert_eq!(exchanges.len(), 1);
+        let register = &exchanges[0];
+        assert_eq!(register["method"], "POST");
+        assert_eq!(register["url"], format!("{}/register", url));
+        assert_eq!(register["status"], 200);
+        assert_eq!(register["request_headers"]["authorization"], "[redacted]");
+        assert_eq!(register["request_headers"]["x-request-id"], "req-debug");
+        assert!(last.summary.contains(&format!("POST {}/register → 200", url)));
+
+        let all = server.handle_debug_last_request(&args(json!({ "count": 100 }))).await.unwrap();
+        let exchanges = all.data["exchanges"].as_array().unwrap();
+        assert!(exchanges.len() > 1 && exchanges.len() <= DEBUG_EXCHANGES);
+        let recorded = serde_json::to_string(&all.data).unwrap();
+        assert!(!recorded.contains("session-1") && !recorded.contains("session-2"), "no session IDs: {}", recorded);
+    }
+
+    #[test]
+    fn generate_identity_accepts_one_from_the_environment() {
+        std::env::set_var("REACH_AUTO_GENERATE_IDENTITY", "1");
+        let cli = Cli::try_parse_from(["agent-reach-mcp"]);
+        std::env::remove_var("REACH_AUTO_GENERATE_IDENTITY");
+        assert!(cli.unwrap().generate_identity);
+        assert!(!Cli::try_parse_from(["agent-reach-mcp"]).unwrap().generate_identity);
+    }
+
+    #[test]
+    fn cbor_accepts_one_from_the_environment() {
+        std::env::set_var("REACH_CBOR", "1");
+        let cli = Cli::try_parse_from(["agent-reach-mcp"]);
+        std::env::remove_var("REACH_CBOR");
+        assert!(cli.unwrap().cbor);
+        assert!(!Cli::try_parse_from(["agent-reach-mcp"]).unwrap().cbor);
+    }
+
+    #[test]
+    fn debug_accepts_one_from_the_environment() {
+        std::env::set_var("REACH_DEBUG", "1");
+        let cli = Cli::try_parse_from(["agent-reach-mcp"]);
+        std::env::remove_var("REACH_DEBUG");
+        assert!(cli.unwrap().debug);
+        assert!(!Cli::try_parse_from(["agent-reach-mcp"]).unwrap().debug);
+    }
+
+    #[test]
+    fn profiles_that_leave_the_identity_directory_are_refused() {
+        assert!(Cli::try_parse_from(["agent-reach-mcp", "--profile", "../elsewhere"]).is_err());
+        let cli = Cli::try_parse_from(["agent-reach-mcp", "--profile", "work"]).unwrap();
+        assert_eq!(cli.profile.as_deref(), Some("work"));
+    }
+}
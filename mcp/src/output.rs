@@ -0,0 +1,173 @@
+//! Rendering tool results
+//!
+//! Every tool takes a `format` argument. `text`, the default, gives a
+//! summary line for people followed by the JSON object; `json` gives only
+//! the compact JSON object, for agent frameworks that parse results. Tools
+//! build a [`ToolOutput`] or a [`ToolError`] and leave the rendering here,
+//! so new tools get both formats without doing anything.
+
+use std::sync::Arc;
+
+use rmcp::model::{CallToolResult, Content, Tool};
+use serde_json::{json, Map, Value};
+
+use crate::error::ToolError;
+
+/// Successful tool result: a human-readable summary plus structured fields
+#[derive(Debug)]
+pub struct ToolOutput {
+    pub summary: String,
+    pub data: Value,
+}
+
+impl ToolOutput {
+    pub fn new(summary: String, data: Value) -> Self {
+        Self { summary, data }
+    }
+
+    /// Structured form: `{"ok": true, ...data}`
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("ok".to_string(), Value::Bool(true));
+        if let Value::Object(data) = &self.data {
+            object.extend(data.clone());
+        }
+        Value::Object(object)
+    }
+}
+
+/// How a tool result is rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Summary for people, then the JSON object
+    #[default]
+    Text,
+    /// The JSON object alone
+    Json,
+}
+
+impl Format {
+    /// The `format` argument, `text` when absent
+    pub fn from_args(args: &Map<String, Value>) -> Result<Self, ToolError> {
+        match args.get("format") {
+            None | Some(Value::Null) => Ok(Self::Text),
+            Some(Value::String(format)) if format == "text" => Ok(Self::Text),
+            Some(Value::String(format)) if format == "json" => Ok(Self::Json),
+            Some(other) => Err(ToolError::invalid_params(format!("format must be \"text\" or \"json\", not {}", other))),
+        }
+    }
+
+    /// Render a tool's result; failures quote `request_id` so they can be
+    /// found in the registry's logs
+    pub fn render(self, result: Result<ToolOutput, ToolError>, request_id: &str) -> Result<CallToolResult, rmcp::Error> {
+        let (summary, structured, is_error) = match result {
+            Ok(output) => (output.summary.clone(), output.to_json(), false),
+            Err(e) => {
+                let e = e.with_field("request_id", request_id);
+                (format!("✗ {} (request id: {})", e, request_id), e.to_json(), true)
+            }
+        };
+
+        let content = match self {
+            Self::Text => vec![Content::text(summary), Content::json(structured)?],
+            Self::Json => vec![Content::json(structured)?],
+        };
+        Ok(CallToolResult { content, is_error: Some(is_error) })
+    }
+}
+
+/// Add the `format` argument to each tool's input schema
+pub fn add_format_arg(tools: &mut [Tool]) {
+    for tool in tools {
+        let schema = Arc::make_mut(&mut tool.input_schema);
+        let properties = schema.entry("properties").or_insert_with(|| json!({}));
+        if let Value::Object(properties) = properties {
+            properties.insert(
+                "format".to_string(),
+                json!({
+                    "type": "string",
+                    "enum": ["text", "json"],
+                    "description": "text (default) for a summary followed by JSON, json for the compact JSON object alone"
+                }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    fn texts(result: &CallToolResult) -> Vec<&str> {
+        result.content.iter().map(|c| c.as_text().expect("text content").text.as_str()).collect()
+    }
+
+    fn args(format: Value) -> Map<String, Value> {
+        json!({ "format": format }).as_object().cloned().unwrap()
+    }
+
+    fn registered() -> ToolOutput {
+        ToolOutput::new(
+            "✓ Registered\n  DID: did:key:z6Mk\n  Endpoint: wss://a".to_string(),
+            json!({ "did": "did:key:z6Mk", "endpoint": "wss://a", "expires_at": 1700000000 }),
+        )
+    }
+
+    #[test]
+    fn results_render_in_both_formats() {
+        let text = Format::Text.render(Ok(registered()), "req-1").unwrap();
+        assert_eq!(
+            texts(&text),
+            [
+                "✓ Registered\n  DID: did:key:z6Mk\n  Endpoint: wss://a",
+                r#"{"did":"did:key:z6Mk","endpoint":"wss://a","expires_at":1700000000,"ok":true}"#,
+            ]
+        );
+        assert_eq!(text.is_error, Some(false));
+
+        let json = Format::Json.render(Ok(registered()), "req-1").unwrap();
+        assert_eq!(texts(&json), [r#"{"did":"did:key:z6Mk","endpoint":"wss://a","expires_at":1700000000,"ok":true}"#]);
+    }
+
+    #[test]
+    fn errors_render_in_both_formats() {
+        let error = || ToolError::new(ErrorCode::NotFound, "Agent not found").with_field("did", "did:key:z6Mk");
+
+        let text = Format::Text.render(Err(error()), "req-2").unwrap();
+        assert_eq!(
+            texts(&text),
+            [
+                "✗ Agent not found (request id: req-2)",
+                r#"{"code":"not_found","did":"did:key:z6Mk","message":"Agent not found","ok":false,"request_id":"req-2"}"#,
+            ]
+        );
+        assert_eq!(text.is_error, Some(true));
+
+        let json = Format::Json.render(Err(error()), "req-2").unwrap();
+        assert_eq!(
+            texts(&json),
+            [r#"{"code":"not_found","did":"did:key:z6Mk","message":"Agent not found","ok":false,"request_id":"req-2"}"#]
+        );
+        assert_eq!(json.is_error, Some(true));
+    }
+
+    #[test]
+    fn format_defaults_to_text() {
+        assert_eq!(Format::from_args(&Map::new()).unwrap(), Format::Text);
+        assert_eq!(Format::from_args(&args(json!("json"))).unwrap(), Format::Json);
+        assert_eq!(Format::from_args(&args(json!("yaml"))).unwrap_err().code, ErrorCode::InvalidParams);
+    }
+
+    #[test]
+    fn every_tool_takes_a_format() {
+        let mut tools = vec![
+            Tool::new("a", "", Arc::new(json!({ "type": "object", "properties": {} }).as_object().cloned().unwrap())),
+            Tool::new("b", "", Arc::new(json!({ "type": "object" }).as_object().cloned().unwrap())),
+        ];
+        add_format_arg(&mut tools);
+        for tool in tools {
+            assert_eq!(tool.input_schema["properties"]["format"]["enum"], json!(["text", "json"]));
+        }
+    }
+}
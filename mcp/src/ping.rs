@@ -0,0 +1,210 @@
+//! Liveness probes for registered endpoints
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use reqwest::{redirect, StatusCode, Url};
+
+/// Default probe timeout
+pub const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+
+/// Upper bound on the per-call timeout
+pub const MAX_TIMEOUT_MS: u64 = 30_000;
+
+/// Env var that allows probing private and loopback addresses
+pub const ALLOW_PRIVATE_ENV: &str = "REACH_PING_ALLOW_PRIVATE";
+
+/// Result of a successful probe
+pub struct PingOutcome {
+    pub latency: Duration,
+    pub detail: String,
+}
+
+/// Whether private/loopback probing has been enabled via env
+pub fn allow_private_from_env() -> bool {
+    std::env::var(ALLOW_PRIVATE_ENV)
+        .map(|v| matches!(v.as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Probe an endpoint with a protocol-appropriate liveness check
+///
+/// http(s) endpoints get a HEAD request (falling back to GET if HEAD is not
+/// allowed); ws(s) endpoints get a WebSocket upgrade handshake.
+pub async fn ping(endpoint: &str, timeout: Duration, allow_private: bool) -> Result<PingOutcome, String> {
+    let url = Url::parse(endpoint)
+        .map_err(|e| format!("Invalid endpoint URL '{}': {}", endpoint, e))?;
+
+    let websocket = match url.scheme() {
+        "http" | "https" => false,
+        "ws" | "wss" => true,
+        other => return Err(format!(
+            "Cannot ping '{}' endpoints (only http, https, ws and wss are supported)", other
+        )),
+    };
+
+    let host = url.host_str()
+        .ok_or_else(|| format!("Endpoint has no host: {}", endpoint))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = url.port_or_known_default()
+        .ok_or_else(|| format!("Endpoint has no port: {}", endpoint))?;
+
+    let addr = resolve(&host, port, timeout, allow_private).await?;
+
+    // Pin the resolved address so the request can't be re-resolved elsewhere
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(redirect::Policy::none())
+        .resolve(&host, addr)
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+
+    if websocket {
+        probe_websocket(&client, url).await
+    } else {
+        probe_http(&client, url).await
+    }
+}
+
+/// Resolve a host and enforce the private-address policy
+async fn resolve(host: &str, port: u16, timeout: Duration, allow_private: bool) -> Result<SocketAddr, String> {
+    let addrs: Vec<SocketAddr> = tokio::time::timeout(timeout, tokio::net::lookup_host((host, port)))
+        .await
+        .map_err(|_| format!("Timed out resolving {}", host))?
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .collect();
+
+    if !allow_private {
+        if let Some(addr) = addrs.iter().find(|addr| agent_reach_addr::is_private(addr.ip())) {
+            return Err(format!(
+                "Refusing to probe private or loopback address {} (set {}=1 to allow)",
+                addr.ip(), ALLOW_PRIVATE_ENV
+            ));
+        }
+    }
+
+    addrs.into_iter().next()
+        .ok_or_else(|| format!("No addresses found for {}", host))
+}
+
+async fn probe_http(client: &reqwest::Client, url: Url) -> Result<PingOutcome, String> {
+    let start = Instant::now();
+    let mut resp = client.head(url.clone()).send().await.map_err(describe_error)?;
+
+    if matches!(resp.status(), StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED) {
+        resp = client.get(url).send().await.map_err(describe_error)?;
+    }
+    let latency = start.elapsed();
+
+    Ok(PingOutcome {
+        latency,
+        detail: format!("HTTP {}", resp.status()),
+    })
+}
+
+async fn probe_websocket(client: &reqwest::Client, mut url: Url) -> Result<PingOutcome, String> {
+    let http_scheme = if url.scheme() == "wss" { "https" } else { "http" };
+    url.set_scheme(http_scheme)
+        .map_err(|_| "Failed to convert WebSocket URL".to_string())?;
+
+    let key: [u8; 16] = rand::random();
+
+    let start = Instant::now();
+    let resp = client.get(url)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", BASE64.encode(key))
+        .send()
+        .await
+        .map_err(describe_error)?;
+    let latency = start.elapsed();
+
+    if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(format!("WebSocket upgrade rejected: HTTP {}", resp.status()));
+    }
+
+    Ok(PingOutcome {
+        latency,
+        detail: "WebSocket upgrade accepted".to_string(),
+    })
+}
+
+fn describe_error(e: reqwest::Error) -> String {
+    if e.is_timeout() {
+        "Timed out waiting for a response".to_string()
+    } else if e.is_connect() {
+        format!("Connection failed: {}", e)
+    } else {
+        format!("Request failed: {}", e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get, Router};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn serve(app: Router) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    #[tokio::test]
+    async fn unsupported_and_private_endpoints_are_refused() {
+        let err = ping("grpc://agent.example:443", TIMEOUT, false).await.err().unwrap();
+        assert!(err.contains("Cannot ping 'grpc' endpoints"), "{}", err);
+
+        let err = ping("not a url", TIMEOUT, false).await.err().unwrap();
+        assert!(err.starts_with("Invalid endpoint URL"), "{}", err);
+
+        let addr = serve(Router::new().route("/", get(|| async { "ok" }))).await;
+        let err = ping(&format!("http://{}/", addr), TIMEOUT, false).await.err().unwrap();
+        assert!(err.contains("Refusing to probe private or loopback address 127.0.0.1"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn http_endpoints_fall_back_to_get_when_head_is_refused() {
+        // HEAD is answered by GET routes, so refuse it explicitly
+        let app = Router::new().route(
+            "/",
+            get(|method: axum::http::Method| async move {
+                if method == axum::http::Method::HEAD {
+                    StatusCode::METHOD_NOT_ALLOWED
+                } else {
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let addr = serve(app).await;
+
+        let outcome = ping(&format!("http://{}/", addr), TIMEOUT, true).await.unwrap();
+        assert_eq!(outcome.detail, "HTTP 204 No Content");
+    }
+
+    #[tokio::test]
+    async fn websocket_endpoints_need_an_upgrade() {
+        let addr = serve(Router::new().route("/", get(|| async { "not a websocket" }))).await;
+
+        let err = ping(&format!("ws://{}/", addr), TIMEOUT, true).await.err().unwrap();
+        assert_eq!(err, "WebSocket upgrade rejected: HTTP 200 OK");
+    }
+
+    #[tokio::test]
+    async fn unreachable_endpoints_report_the_connection_failure() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let err = ping(&format!("http://{}/", addr), TIMEOUT, true).await.err().unwrap();
+        assert!(err.starts_with("Connection failed"), "{}", err);
+    }
+}
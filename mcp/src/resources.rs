@@ -0,0 +1,98 @@
+//! MCP resources: the agent's own registration, saved contacts and the
+//! registry's listing
+//!
+//! URIs use the `reach://` scheme. Each page of the registry listing asks the
+//! registry for just that page.
+
+use rmcp::model::{AnnotateAble, RawResource, Resource, ResourceContents};
+use serde_json::Value;
+
+/// This agent's registration status
+pub const SELF_URI: &str = "reach://self";
+
+/// Prefix of saved contacts' URIs, followed by the DID
+pub const CONTACT_PREFIX: &str = "reach://contacts/";
+
+/// First page of the registry listing; later pages add `?page=N`
+pub const REGISTRY_URI: &str = "reach://registry";
+
+/// Agents per page of the registry listing
+pub const PAGE_SIZE: usize = 100;
+
+/// Media type of every resource
+const JSON: &str = "application/json";
+
+/// A parsed resource URI
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResourceUri {
+    Me,
+    Contact(String),
+    /// Registry listing page, counting from 1
+    Registry(usize),
+}
+
+impl ResourceUri {
+    pub fn parse(uri: &str) -> Option<Self> {
+        if uri == SELF_URI {
+            return Some(Self::Me);
+        }
+        if let Some(did) = uri.strip_prefix(CONTACT_PREFIX) {
+            let did = urlencoding::decode(did).ok()?;
+            return (!did.is_empty()).then(|| Self::Contact(did.into_owned()));
+        }
+        let rest = uri.strip_prefix(REGISTRY_URI)?;
+        if rest.is_empty() {
+            return Some(Self::Registry(1));
+        }
+        let page = rest.strip_prefix("?page=")?.parse().ok()?;
+        (page > 0).then_some(Self::Registry(page))
+    }
+}
+
+/// URI of a saved contact
+pub fn contact_uri(did: &str) -> String {
+    format!("{}{}", CONTACT_PREFIX, urlencoding::encode(did))
+}
+
+/// URI of a registry listing page
+pub fn registry_page_uri(page: usize) -> String {
+    match page {
+        1 => REGISTRY_URI.to_string(),
+        _ => format!("{}?page={}", REGISTRY_URI, page),
+    }
+}
+
+/// A resource entry for `resources/list`
+pub fn resource(uri: String, name: impl Into<String>, description: impl Into<String>) -> Resource {
+    let mut raw = RawResource::new(uri, name);
+    raw.description = Some(description.into());
+    raw.mime_type = Some(JSON.to_string());
+    raw.no_annotation()
+}
+
+/// Pretty-printed JSON contents of a resource
+pub fn json_contents(uri: &str, value: &Value) -> ResourceContents {
+    ResourceContents::TextResourceContents {
+        uri: uri.to_string(),
+        mime_type: Some(JSON.to_string()),
+        text: serde_json::to_string_pretty(value).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uris_round_trip() {
+        assert_eq!(ResourceUri::parse(SELF_URI), Some(ResourceUri::Me));
+        let uri = contact_uri("did:key:z6Mk/x");
+        assert_eq!(ResourceUri::parse(&uri), Some(ResourceUri::Contact("did:key:z6Mk/x".to_string())));
+        assert_eq!(ResourceUri::parse(&registry_page_uri(1)), Some(ResourceUri::Registry(1)));
+        assert_eq!(ResourceUri::parse(&registry_page_uri(3)), Some(ResourceUri::Registry(3)));
+
+        for bad in ["reach://registry?page=0", "reach://registry/x", "reach://contacts/", "reach://other"] {
+            assert_eq!(ResourceUri::parse(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Lookup throughput against SQLite, with and without the in-process cache
+//!
+//! `cargo bench -p agent-reach-server --features sqlite --bench lookup_cache`
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use agent_reach_server::backend::RegistryBackend;
+use agent_reach_server::lookup_cache::CachedRegistry;
+use agent_reach_server::sqlite::SqliteRegistry;
+use agent_reach_server::types::RegistryEntry;
+
+/// Registered DIDs looked up in turn
+const AGENTS: usize = 1_000;
+
+fn entry(did: &str, now: i64) -> RegistryEntry {
+    RegistryEntry::new(did, &format!("wss://{}.agents.example", did), now, now + 3600)
+}
+
+fn lookups(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join(format!("agent-reach-bench-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let registered: Vec<String> = (0..AGENTS).map(|i| format!("did:key:z6Mkbench{}", i)).collect();
+    let missing: Vec<String> = (0..AGENTS).map(|i| format!("did:key:z6Mkmissing{}", i)).collect();
+    let sqlite: Arc<dyn RegistryBackend> = runtime.block_on(async {
+        let sqlite = SqliteRegistry::open(&dir.join("reach.db"), 0).await.unwrap();
+        let now = chrono::Utc::now().timestamp();
+        for did in &registered {
+            sqlite.register(entry(did, now)).await.unwrap();
+        }
+        Arc::new(sqlite)
+    });
+    let cached: Arc<dyn RegistryBackend> = Arc::new(CachedRegistry::new(sqlite.clone(), Duration::from_secs(5)));
+
+    let mut group = c.benchmark_group("lookup");
+    for (name, backend) in [("sqlite", &sqlite), ("cached", &cached)] {
+        for (outcome, dids) in [("found", &registered), ("missing", &missing)] {
+            let mut next = 0;
+            group.bench_function(format!("{}/{}", name, outcome), |b| {
+                b.to_async(&runtime).iter(|| {
+                    next = (next + 1) % dids.len();
+                    backend.lookup(&dids[next])
+                })
+            });
+        }
+    }
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
@@ -0,0 +1,111 @@
+//! Mixed read/write throughput of the sharded in-memory registry, against
+//! the single `RwLock<HashMap>` it replaced
+//!
+//! Each thread does 95 lookups for every 5 registrations, on DIDs spread
+//! over the whole registry.
+//!
+//! `cargo bench -p agent-reach-server --bench registry`
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use agent_reach_server::backend::Precondition;
+use agent_reach_server::registry::Registry;
+use agent_reach_server::types::RegistryEntry;
+
+/// Registered DIDs the workload picks from
+const AGENTS: usize = 10_000;
+
+/// Operations out of every 100 that are registrations
+const WRITES_PER_100: u64 = 5;
+
+/// The registry before sharding: every access takes the one lock
+#[derive(Default)]
+struct SingleLock(RwLock<HashMap<String, RegistryEntry>>);
+
+trait Store: Send + Sync {
+    fn register(&self, entry: RegistryEntry);
+    fn lookup(&self, did: &str) -> Option<RegistryEntry>;
+}
+
+impl Store for SingleLock {
+    fn register(&self, entry: RegistryEntry) {
+        self.0.write().unwrap().insert(entry.did.clone(), entry);
+    }
+
+    fn lookup(&self, did: &str) -> Option<RegistryEntry> {
+        self.0.read().unwrap().get(did).cloned()
+    }
+}
+
+impl Store for Registry {
+    fn register(&self, entry: RegistryEntry) {
+        Registry::register_when(self, entry, Precondition::Always);
+    }
+
+    fn lookup(&self, did: &str) -> Option<RegistryEntry> {
+        Registry::lookup(self, did)
+    }
+}
+
+fn entry(did: &str, now: i64) -> RegistryEntry {
+    RegistryEntry::new(did, &format!("wss://{}.agents.example", did), now, now + 3600)
+}
+
+/// Run `operations` across `threads` threads and time the slowest
+fn mixed(store: &Arc<dyn Store>, dids: &Arc<Vec<String>>, threads: usize, operations: u64) -> Duration {
+    let now = chrono::Utc::now().timestamp();
+    let per_thread = operations.div_ceil(threads as u64);
+    let start = Instant::now();
+    let workers: Vec<_> = (0..threads)
+        .map(|t| {
+            let store = store.clone();
+            let dids = dids.clone();
+            thread::spawn(move || {
+                // Each thread walks the DIDs with its own stride so threads
+                // rarely touch the same entry at once
+                let stride = 7919 + 2 * t;
+                for n in 0..per_thread {
+                    let did = &dids[(n as usize * stride + t) % dids.len()];
+                    if n % 100 < WRITES_PER_100 {
+                        store.register(entry(did, now));
+                    } else {
+                        criterion::black_box(store.lookup(did));
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn read_mostly(c: &mut Criterion) {
+    let now = chrono::Utc::now().timestamp();
+    let dids: Arc<Vec<String>> = Arc::new((0..AGENTS).map(|i| format!("did:key:z6Mkbench{}", i)).collect());
+    let single: Arc<dyn Store> = Arc::new(SingleLock::default());
+    let sharded: Arc<dyn Store> = Arc::new(Registry::new());
+    for did in dids.iter() {
+        single.register(entry(did, now));
+        sharded.register(entry(did, now));
+    }
+
+    let mut group = c.benchmark_group("read_mostly");
+    for threads in [1, 4, 8] {
+        for (name, store) in [("single_lock", &single), ("sharded", &sharded)] {
+            group.bench_with_input(BenchmarkId::new(name, threads), &threads, |b, &threads| {
+                b.iter_custom(|operations| mixed(store, &dids, threads, operations))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read_mostly);
+criterion_main!(benches);
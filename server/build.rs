@@ -0,0 +1,15 @@
+//! Generates the gRPC bindings from `proto/reach.proto` when the `grpc`
+//! feature is on, so they always match the .proto. protoc comes from
+//! `protoc-bin-vendored`, so nothing needs installing.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/reach.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::configure()
+            .compile_protos(&["proto/reach.proto"], &["proto"])
+            .expect("failed to generate gRPC bindings");
+    }
+}
@@ -0,0 +1,22 @@
+-- Current registrations, one row per DID
+CREATE TABLE IF NOT EXISTS agents (
+    did TEXT PRIMARY KEY,
+    endpoint TEXT NOT NULL,
+    protocols TEXT[] NOT NULL DEFAULT '{}',
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    registered_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS agents_expires_at_idx ON agents (expires_at);
+
+-- Past registrations, recorded whenever a DID's endpoint changes
+CREATE TABLE IF NOT EXISTS agent_history (
+    id BIGSERIAL PRIMARY KEY,
+    did TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    registered_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS agent_history_did_idx ON agent_history (did, id DESC);
@@ -0,0 +1,3 @@
+-- When each agent last completed a handshake; existing rows start at registration
+ALTER TABLE agents ADD COLUMN last_seen BIGINT NOT NULL DEFAULT 0;
+UPDATE agents SET last_seen = registered_at;
@@ -0,0 +1,2 @@
+-- JSON array of {uri, weight} for agents registered at several endpoints
+ALTER TABLE agents ADD COLUMN endpoints TEXT;
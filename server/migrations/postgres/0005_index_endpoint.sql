@@ -0,0 +1,2 @@
+-- Lets registrations find other DIDs claiming the same endpoint
+CREATE INDEX agents_endpoint ON agents (endpoint);
@@ -0,0 +1,2 @@
+-- Bumped on every registration, for If-Match updates
+ALTER TABLE agents ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
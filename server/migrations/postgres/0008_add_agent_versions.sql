@@ -0,0 +1,8 @@
+-- Last version issued per DID, kept after its agent row is deleted so a
+-- re-registration never reuses a version
+CREATE TABLE IF NOT EXISTS agent_versions (
+    did TEXT PRIMARY KEY,
+    version BIGINT NOT NULL
+);
+
+INSERT INTO agent_versions (did, version) SELECT did, version FROM agents;
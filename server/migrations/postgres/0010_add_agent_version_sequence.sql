@@ -0,0 +1,8 @@
+-- Versions come from one registry-wide sequence, so they never repeat for a
+-- DID without a per-DID record outliving its agent row
+CREATE SEQUENCE IF NOT EXISTS agent_version_seq;
+
+SELECT setval('agent_version_seq', GREATEST(COALESCE(MAX(version), 0), 1), COALESCE(MAX(version), 0) > 0)
+FROM (SELECT version FROM agent_versions UNION ALL SELECT version FROM agents) issued;
+
+DROP TABLE agent_versions;
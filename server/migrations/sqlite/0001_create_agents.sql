@@ -0,0 +1,23 @@
+-- Current registrations, one row per DID
+CREATE TABLE IF NOT EXISTS agents (
+    did TEXT PRIMARY KEY NOT NULL,
+    endpoint TEXT NOT NULL,
+    -- JSON arrays
+    protocols TEXT NOT NULL DEFAULT '[]',
+    tags TEXT NOT NULL DEFAULT '[]',
+    registered_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS agents_expires_at_idx ON agents (expires_at);
+
+-- Past registrations, recorded whenever a DID's endpoint changes
+CREATE TABLE IF NOT EXISTS agent_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    did TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    registered_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS agent_history_did_idx ON agent_history (did, id DESC);
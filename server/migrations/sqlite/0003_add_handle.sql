@@ -0,0 +1,3 @@
+-- Optional human-readable name@domain handle, unique among agents
+ALTER TABLE agents ADD COLUMN handle TEXT;
+CREATE UNIQUE INDEX agents_handle ON agents (handle);
@@ -0,0 +1,2 @@
+-- Controller DID that made a delegated registration
+ALTER TABLE agent_history ADD COLUMN registered_by TEXT;
@@ -0,0 +1,9 @@
+-- When each subject last revoked a delegate's certificates; a row can go
+-- once every certificate it covers has expired
+CREATE TABLE IF NOT EXISTS delegation_revocations (
+    subject TEXT NOT NULL,
+    delegate TEXT NOT NULL,
+    revoked_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    PRIMARY KEY (subject, delegate)
+);
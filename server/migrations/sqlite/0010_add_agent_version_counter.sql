@@ -0,0 +1,12 @@
+-- Versions come from one registry-wide counter, so they never repeat for a
+-- DID without a per-DID record outliving its agent row
+CREATE TABLE IF NOT EXISTS agent_version_counter (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    version INTEGER NOT NULL
+);
+
+INSERT INTO agent_version_counter (id, version)
+SELECT 1, COALESCE(MAX(version), 0)
+FROM (SELECT version FROM agent_versions UNION ALL SELECT version FROM agents);
+
+DROP TABLE agent_versions;
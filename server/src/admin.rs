@@ -0,0 +1,516 @@
+//! Operator endpoints, guarded by the admin token
+//!
+//! The routes are only mounted when `--admin-token` is configured.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::ReachError;
+use crate::handlers::{AppState, SESSION_TTL_SECS};
+use crate::snapshot::{ExportParams, ImportParams, ImportReport, Snapshot};
+
+/// Check the `Authorization: Bearer <admin token>` header
+pub(crate) fn require_admin(headers: &HeaderMap, state: &AppState) -> Result<(), ReachError> {
+    let expected = state.admin_token.as_deref().ok_or(ReachError::AdminForbidden)?;
+    let provided = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(ReachError::AdminForbidden)?;
+
+    if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(ReachError::AdminForbidden)
+    }
+}
+
+/// Compare without short-circuiting, so timing doesn't leak the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// GET /admin/export
+///
+/// Snapshot of every live registration. With `include_expired=true`,
+/// expired entries not yet purged are included, for rebuilding a backend
+/// exactly with `mode=restore`.
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    tag = "admin",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "Registry snapshot", body = Snapshot),
+        (status = 403, description = "Missing or wrong admin token", body = crate::openapi::ErrorResponse),
+    ),
+    security(("admin" = []))
+)]
+pub async fn export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ExportParams>,
+) -> Result<Json<Snapshot>, ReachError> {
+    require_admin(&headers, &state)?;
+
+    let snapshot = Snapshot::export(state.registry.as_ref(), params.include_expired).await?;
+    info!(
+        agents = snapshot.agents.len(),
+        include_expired = params.include_expired,
+        "Exported registry snapshot"
+    );
+
+    Ok(Json(snapshot))
+}
+
+/// POST /admin/import
+///
+/// Load a snapshot. Every entry is validated before anything is written;
+/// invalid entries are reported and left out.
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    tag = "admin",
+    params(ImportParams),
+    request_body = Snapshot,
+    responses(
+        (status = 200, description = "Import summary", body = ImportReport),
+        (status = 400, description = "Unsupported snapshot version", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "Missing or wrong admin token", body = crate::openapi::ErrorResponse),
+    ),
+    security(("admin" = []))
+)]
+pub async fn import(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ImportParams>,
+    Json(snapshot): Json<Snapshot>,
+) -> Result<Json<ImportReport>, ReachError> {
+    require_admin(&headers, &state)?;
+
+    let report = snapshot.import(state.registry.as_ref(), params.mode).await?;
+    info!(
+        mode = ?params.mode,
+        added = report.added,
+        skipped = report.skipped,
+        rejected = report.rejected,
+        "Imported registry snapshot"
+    );
+
+    Ok(Json(report))
+}
+
+/// Characters of a session ID shown by `GET /admin/sessions`: the
+/// UUIDv7's timestamp and first random bits, enough to tell sessions apart
+/// without handing out a usable bearer token
+const SESSION_ID_PREFIX_LEN: usize = 18;
+
+/// A session as listed by `GET /admin/sessions`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SessionSummary {
+    /// Start of the session ID; `DELETE /admin/sessions/{id}` accepts it
+    #[schema(example = "019c4bf5-bd75-7be1")]
+    pub session_id_prefix: String,
+    pub did: String,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+    pub age_secs: i64,
+}
+
+/// GET /admin/sessions
+///
+/// Every unexpired session, oldest first, with its ID truncated.
+#[utoipa::path(
+    get,
+    path = "/admin/sessions",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Active sessions", body = Vec<SessionSummary>),
+        (status = 403, description = "Missing or wrong admin token", body = crate::openapi::ErrorResponse),
+    ),
+    security(("admin" = []))
+)]
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SessionSummary>>, ReachError> {
+    require_admin(&headers, &state)?;
+
+    let now = chrono::Utc::now().timestamp();
+    let mut sessions: Vec<SessionSummary> = state
+        .handshake
+        .store
+        .sessions()
+        .await?
+        .into_iter()
+        .filter(|(_, session)| now - session.created_at <= SESSION_TTL_SECS)
+        .map(|(id, session)| SessionSummary {
+            session_id_prefix: id.chars().take(SESSION_ID_PREFIX_LEN).collect(),
+            did: session.did,
+            created_at: session.created_at,
+            age_secs: now - session.created_at,
+        })
+        .collect();
+    sessions.sort_by(|a, b| (a.created_at, &a.session_id_prefix).cmp(&(b.created_at, &b.session_id_prefix)));
+
+    Ok(Json(sessions))
+}
+
+/// DELETE /admin/sessions/{id}
+///
+/// End a session, given its full ID or the prefix `GET /admin/sessions`
+/// shows. A prefix shared by several sessions revokes none of them.
+#[utoipa::path(
+    delete,
+    path = "/admin/sessions/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Session ID or listed prefix")),
+    responses(
+        (status = 204, description = "Session ended"),
+        (status = 400, description = "The prefix matches several sessions", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "Missing or wrong admin token", body = crate::openapi::ErrorResponse),
+        (status = 404, description = "No session has this ID or prefix", body = crate::openapi::ErrorResponse),
+    ),
+    security(("admin" = []))
+)]
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ReachError> {
+    require_admin(&headers, &state)?;
+
+    let store = &state.handshake.store;
+    let session_id = match store.session(&id).await? {
+        Some(_) => id,
+        None if id.len() >= SESSION_ID_PREFIX_LEN => {
+            let mut matches = store.sessions().await?.into_iter().filter(|(session_id, _)| session_id.starts_with(&id));
+            let (session_id, _) = matches.next().ok_or(ReachError::SessionNotFound)?;
+            if matches.next().is_some() {
+                return Err(ReachError::InvalidRequest(format!("{} matches several sessions", id)));
+            }
+            session_id
+        }
+        None => return Err(ReachError::SessionNotFound),
+    };
+
+    if !store.remove_session(&session_id).await? {
+        return Err(ReachError::SessionNotFound);
+    }
+    info!(session = %&session_id[..SESSION_ID_PREFIX_LEN.min(session_id.len())], "Revoked session");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Query parameters for `DELETE /admin/agents/{did}`
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EvictParams {
+    /// Also bar the DID from registering for this many seconds; at least 1
+    pub ban_secs: Option<u64>,
+    /// Also bar the DID from registering until the ban is lifted
+    #[serde(default)]
+    pub ban: bool,
+}
+
+/// Response to `DELETE /admin/agents/{did}`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EvictResponse {
+    pub did: String,
+    /// Whether a registration was removed
+    pub evicted: bool,
+    /// Whether the DID is now barred from registering
+    pub banned: bool,
+    /// When the ban ends (Unix timestamp, seconds); absent for permanent bans
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banned_until: Option<i64>,
+}
+
+/// DELETE /admin/agents/{did}
+///
+/// Remove a registration, optionally banning the DID from registering
+/// again: for `ban_secs` seconds, or until lifted with `ban=true`. Bans are
+/// held in memory by this server, so a restart lifts them.
+#[utoipa::path(
+    delete,
+    path = "/admin/agents/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "DID to evict"), EvictParams),
+    responses(
+        (status = 200, description = "Registration removed if there was one", body = EvictResponse),
+        (status = 400, description = "Both ban_secs and ban were given, or ban_secs is 0", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "Missing or wrong admin token", body = crate::openapi::ErrorResponse),
+    ),
+    security(("admin" = []))
+)]
+pub async fn evict(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(did): Path<String>,
+    Query(params): Query<EvictParams>,
+) -> Result<Json<EvictResponse>, ReachError> {
+    require_admin(&headers, &state)?;
+
+    let until = match (params.ban_secs, params.ban) {
+        (Some(_), true) => return Err(ReachError::InvalidRequest("give ban_secs or ban, not both".into())),
+        (Some(0), false) => return Err(ReachError::InvalidRequest("ban_secs must be at least 1".into())),
+        (Some(secs), false) => {
+            let secs = i64::try_from(secs).map_err(|_| ReachError::InvalidRequest("ban_secs is too large".into()))?;
+            Some(Some(chrono::Utc::now().timestamp().saturating_add(secs)))
+        }
+        (None, true) => Some(None),
+        (None, false) => None,
+    };
+    // Ban first, so the DID can't register again in between
+    if let Some(until) = until {
+        state.denylist.ban(&did, until);
+    }
+    let evicted = state.registry.deregister(&did).await?;
+    info!(did = %did, evicted, banned = until.is_some(), "Evicted agent");
+
+    Ok(Json(EvictResponse { did, evicted, banned: until.is_some(), banned_until: until.flatten() }))
+}
+
+/// DELETE /admin/bans/{did}
+///
+/// Let a banned DID register again before its ban runs out.
+#[utoipa::path(
+    delete,
+    path = "/admin/bans/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "Banned DID")),
+    responses(
+        (status = 204, description = "Ban lifted"),
+        (status = 403, description = "Missing or wrong admin token", body = crate::openapi::ErrorResponse),
+        (status = 404, description = "The DID isn't banned", body = crate::openapi::ErrorResponse),
+    ),
+    security(("admin" = []))
+)]
+pub async fn lift_ban(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(did): Path<String>,
+) -> Result<StatusCode, ReachError> {
+    require_admin(&headers, &state)?;
+
+    if !state.denylist.lift(&did) {
+        return Err(ReachError::NotFound);
+    }
+    info!(did = %did, "Lifted ban");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::header;
+    use axum::response::IntoResponse;
+
+    use super::*;
+    use crate::handlers::AuthenticatedSession;
+    use crate::snapshot::ImportMode;
+    use crate::types::RegistryEntry;
+
+    fn admin_state() -> (AppState, HeaderMap) {
+        let state = AppState {
+            admin_token: Some(Arc::from("admin-secret")),
+            ..AppState::for_tests()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer admin-secret".parse().unwrap());
+        (state, headers)
+    }
+
+    fn entry(expires_in: i64) -> RegistryEntry {
+        let now = chrono::Utc::now().timestamp();
+        let did = agent_id::RootKey::generate().did().to_string();
+        RegistryEntry::new(&did, &format!("wss://agent-{}", expires_in), now - 600, now + expires_in)
+    }
+
+    async fn export_all(state: &AppState, headers: &HeaderMap) -> Snapshot {
+        let params = ExportParams { include_expired: true };
+        let Json(exported) = export(State(state.clone()), headers.clone(), Query(params)).await.unwrap();
+        exported
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trips_through_clear() {
+        let (state, headers) = admin_state();
+        for entry in [entry(3600), entry(60), entry(-30)] {
+            state.registry.register(entry).await.unwrap();
+        }
+
+        let Json(live) = export(State(state.clone()), headers.clone(), Query(ExportParams::default())).await.unwrap();
+        assert_eq!(live.agents.len(), 2);
+        let exported = export_all(&state, &headers).await;
+        assert_eq!(exported.agents.len(), 3, "expired entries are included");
+
+        for entry in &exported.agents {
+            state.registry.deregister(&entry.did).await.unwrap();
+        }
+        assert!(state.registry.all_entries().await.unwrap().is_empty());
+
+        let restore = ImportParams { mode: ImportMode::Restore };
+        let snapshot = Snapshot { agents: exported.agents.clone(), ..exported };
+        let Json(report) = import(State(state.clone()), headers.clone(), Query(restore), Json(snapshot))
+            .await
+            .unwrap();
+        assert_eq!((report.added, report.skipped), (3, 0));
+
+        // Reloaded entries get versions past the ones they were removed at
+        let mut reimported = export_all(&state, &headers).await.agents;
+        for (reloaded, original) in reimported.iter_mut().zip(&exported.agents) {
+            assert!(reloaded.version > original.version);
+            reloaded.version = original.version;
+        }
+        assert_eq!(reimported, exported.agents);
+    }
+
+    #[tokio::test]
+    async fn restore_reports_invalid_entries() {
+        let (state, headers) = admin_state();
+        let mut bad = entry(3600);
+        bad.did = "not-a-did".to_string();
+        let snapshot = Snapshot { version: crate::snapshot::SNAPSHOT_VERSION, exported_at: 0, agents: vec![entry(3600), bad] };
+
+        let restore = ImportParams { mode: ImportMode::Restore };
+        let Json(report) = import(State(state.clone()), headers, Query(restore), Json(snapshot)).await.unwrap();
+
+        assert_eq!((report.added, report.rejected), (1, 1));
+        assert_eq!(report.errors[0].did, "not-a-did");
+        assert_eq!(state.registry.all_entries().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn wrong_token_is_rejected() {
+        let (state, _) = admin_state();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer guess".parse().unwrap());
+
+        assert!(matches!(
+            export(State(state), headers, Query(ExportParams::default())).await,
+            Err(ReachError::AdminForbidden)
+        ));
+    }
+
+    async fn add_session(state: &AppState, id: &str, age_secs: i64) {
+        let session = AuthenticatedSession {
+            did: format!("did:key:{}", id),
+            created_at: chrono::Utc::now().timestamp() - age_secs,
+            scope: Default::default(),
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session(id.to_string(), session).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sessions_are_listed_truncated() {
+        let (state, headers) = admin_state();
+        add_session(&state, "019c4bf5-bd75-7be1-9c92-2224cd8fc319", 30).await;
+        add_session(&state, "019c4bf6-0000-7000-8000-000000000000", 10).await;
+        add_session(&state, "019c4bf4-0000-7000-8000-000000000000", SESSION_TTL_SECS + 1).await;
+
+        let Json(sessions) = list_sessions(State(state), headers).await.unwrap();
+
+        let prefixes: Vec<_> = sessions.iter().map(|s| s.session_id_prefix.as_str()).collect();
+        assert_eq!(prefixes, ["019c4bf5-bd75-7be1", "019c4bf6-0000-7000"], "expired sessions are left out");
+        assert_eq!(sessions[0].did, "did:key:019c4bf5-bd75-7be1-9c92-2224cd8fc319");
+        assert_eq!(sessions[0].age_secs, 30);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_revoked_by_id_or_prefix() {
+        let (state, headers) = admin_state();
+        let full = "019c4bf5-bd75-7be1-9c92-2224cd8fc319";
+        add_session(&state, full, 0).await;
+        add_session(&state, "019c4bf6-0000-7000-8000-000000000001", 0).await;
+        add_session(&state, "019c4bf6-0000-7000-8000-000000000002", 0).await;
+
+        let status = revoke_session(State(state.clone()), headers.clone(), Path(full.to_string())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(state.handshake.store.session(full).await.unwrap().is_none());
+        assert!(matches!(
+            revoke_session(State(state.clone()), headers.clone(), Path(full.to_string())).await,
+            Err(ReachError::SessionNotFound)
+        ));
+
+        assert!(matches!(
+            revoke_session(State(state.clone()), headers.clone(), Path("019c4bf6-0000-7000".to_string())).await,
+            Err(ReachError::InvalidRequest(_))
+        ));
+        revoke_session(State(state.clone()), headers.clone(), Path("019c4bf6-0000-7000-8000-000000000001".to_string()))
+            .await
+            .unwrap();
+        revoke_session(State(state.clone()), headers, Path("019c4bf6-0000-7000".to_string())).await.unwrap();
+        assert!(state.handshake.store.sessions().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn session_admin_needs_the_token() {
+        let (state, _) = admin_state();
+        add_session(&state, "019c4bf5-bd75-7be1-9c92-2224cd8fc319", 0).await;
+
+        let status = list_sessions(State(state.clone()), HeaderMap::new()).await.unwrap_err().into_response().status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let result = revoke_session(State(state.clone()), HeaderMap::new(), Path("019c4bf5-bd75-7be1".to_string())).await;
+        assert!(matches!(result, Err(ReachError::AdminForbidden)));
+        assert_eq!(state.handshake.store.sessions().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn evicted_agents_wait_out_their_ban() {
+        let (state, headers) = admin_state();
+        let id = "019c4bf5-bd75-7be1-9c92-2224cd8fc319";
+        let did = format!("did:key:{}", id);
+        add_session(&state, id, 0).await;
+        let mut session = HeaderMap::new();
+        session.insert(header::AUTHORIZATION, format!("Bearer {}", id).parse().unwrap());
+        let register = || {
+            let request = serde_json::from_value(serde_json::json!({ "endpoint": "wss://agent.example" })).unwrap();
+            crate::handlers::register(State(state.clone()), session.clone(), Query(Default::default()), Json(request))
+        };
+        assert!(register().await.unwrap().ok);
+
+        let params = EvictParams { ban_secs: Some(0), ban: false };
+        let err = evict(State(state.clone()), headers.clone(), Path(did.clone()), Query(params)).await.unwrap_err();
+        assert!(matches!(err, ReachError::InvalidRequest(_)));
+        assert!(state.registry.lookup(&did).await.unwrap().is_some(), "a rejected eviction removes nothing");
+
+        let params = EvictParams { ban_secs: Some(600), ban: false };
+        let Json(evicted) = evict(State(state.clone()), headers.clone(), Path(did.clone()), Query(params)).await.unwrap();
+        assert!(evicted.evicted && evicted.banned);
+        assert!(state.registry.lookup(&did).await.unwrap().is_none());
+
+        let response = register().await.unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((599..=600).contains(&retry_after));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "banned");
+        assert_eq!(body["retry_after_secs"], retry_after);
+
+        // Once the ban is up the DID registers again
+        state.denylist.ban(&did, Some(chrono::Utc::now().timestamp()));
+        assert!(register().await.unwrap().ok);
+
+        let params = EvictParams { ban_secs: None, ban: true };
+        let Json(evicted) = evict(State(state.clone()), headers.clone(), Path(did.clone()), Query(params)).await.unwrap();
+        assert_eq!(evicted.banned_until, None);
+        assert!(matches!(register().await, Err(ReachError::Forbidden(None))));
+        assert_eq!(lift_ban(State(state.clone()), headers.clone(), Path(did.clone())).await.unwrap(), StatusCode::NO_CONTENT);
+        assert!(register().await.unwrap().ok);
+        assert!(matches!(lift_ban(State(state), headers, Path(did)).await, Err(ReachError::NotFound)));
+    }
+}
@@ -0,0 +1,61 @@
+//! API versioning
+//!
+//! The HTTP API is served under `/v1`. The same routes also answer without
+//! the prefix as deprecated aliases for clients written before it existed;
+//! their responses carry a `Deprecation` header and a `Link` to the
+//! versioned path. `GET /version` is unversioned so clients can find out
+//! which prefixes a server understands before picking one.
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Path prefix of the current API version
+pub const PREFIX: &str = "/v1";
+
+/// API versions this server serves, oldest first
+pub const SUPPORTED: &[&str] = &["v1"];
+
+/// Server and API versions, for clients negotiating a prefix
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VersionResponse {
+    /// Server release
+    #[schema(example = "0.1.0")]
+    pub version: String,
+    /// Each is served under `/<version>`
+    #[schema(example = json!(["v1"]))]
+    pub api_versions: Vec<String>,
+}
+
+/// GET /version
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "lookup",
+    responses((status = 200, description = "Server and supported API versions", body = VersionResponse))
+)]
+pub async fn version() -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        api_versions: SUPPORTED.iter().map(|v| v.to_string()).collect(),
+    })
+}
+
+/// Mark a response from an unprefixed alias as deprecated, pointing at the
+/// versioned route that replaces it
+pub async fn deprecated(request: Request, next: Next) -> Response {
+    let successor = format!("<{}{}>; rel=\"successor-version\"", PREFIX, request.uri().path());
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.append(header::LINK, link);
+    }
+    response
+}
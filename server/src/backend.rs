@@ -0,0 +1,513 @@
+use async_trait::async_trait;
+
+use crate::error::ReachError;
+use crate::handlers::AuthenticatedSession;
+use crate::types::{PendingChallenge, RegistryEntry, StatusCounts};
+
+/// Condition for [`RegistryBackend::register_when`] to store an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    Always,
+    /// The DID has no live entry
+    Absent,
+    /// The DID has a stored entry, live or expired, with this version
+    Version(u64),
+}
+
+/// Storage for registry entries
+///
+/// Implementations must be safe to share across request handlers. Storage
+/// failures are reported as `ReachError::Internal`.
+#[async_trait]
+pub trait RegistryBackend: Send + Sync {
+    /// Register or update an agent's endpoint if `precondition` holds,
+    /// returning the version the entry was stored with, or `None` if it
+    /// didn't hold. The check and write must be atomic.
+    ///
+    /// The entry's `version` is ignored: the stored one comes from a counter
+    /// shared by every DID, so it is higher than any version the DID had
+    /// before, even one removed since. Fails with
+    /// `ReachError::HandleTaken` if the entry's handle belongs to another
+    /// DID's live entry; an expired holder gives it up.
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError>;
+
+    /// [`register_when`](Self::register_when), also returning the entries
+    /// of other DIDs the backend evicted to make room. Backends that evict
+    /// on their own should override this and have `register_when` call it.
+    async fn register_evicting(
+        &self,
+        entry: RegistryEntry,
+        precondition: Precondition,
+    ) -> Result<(Option<u64>, Vec<RegistryEntry>), ReachError> {
+        Ok((self.register_when(entry, precondition).await?, Vec::new()))
+    }
+
+    /// Register or update an agent's endpoint
+    async fn register(&self, entry: RegistryEntry) -> Result<(), ReachError> {
+        self.register_when(entry, Precondition::Always).await.map(|_| ())
+    }
+
+    /// Register only if the DID has no live (non-expired) entry, returning
+    /// whether the entry was stored
+    async fn register_if_absent(&self, entry: RegistryEntry) -> Result<bool, ReachError> {
+        Ok(self.register_when(entry, Precondition::Absent).await?.is_some())
+    }
+
+    /// Look up an agent by DID (expired entries are still returned)
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError>;
+
+    /// Look up the entry registered with a normalized handle (expired
+    /// entries are still returned)
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError>;
+
+    /// Some DID other than `did` with a live entry at `endpoint`, if any.
+    /// Backends that can find one without loading every entry should
+    /// override this.
+    async fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Result<Option<String>, ReachError> {
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .find(|entry| entry.endpoint == endpoint && entry.did != did)
+            .map(|entry| entry.did))
+    }
+
+    /// Cheap pre-check before `lookup`: `false` means the DID was certainly
+    /// never registered. Backends without such an index always say `true`.
+    fn might_contain(&self, _did: &str) -> bool {
+        true
+    }
+
+    /// Set a DID's `last_seen`, returning whether it has an entry
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError>;
+
+    /// Remove an agent's registration, returning whether it existed
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError>;
+
+    /// Remove expired entries and certificate revocations, returning the
+    /// entries removed as they were stored
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError>;
+
+    /// Number of stored entries, if known without a round trip
+    fn size_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// Stored entries by status as of `now`: expired, idle (live but last
+    /// seen before `idle_before`) or online. Backends that can count without
+    /// loading every entry should override this.
+    async fn status_counts(&self, now: i64, idle_before: i64) -> Result<StatusCounts, ReachError> {
+        let mut counts = StatusCounts::default();
+        for entry in self.all_entries().await? {
+            counts.add(&entry, now, idle_before);
+        }
+        Ok(counts)
+    }
+
+    /// Number of live entries as of `now`. Backends that can count them
+    /// without loading every entry should override this.
+    async fn live_count(&self, now: i64) -> Result<usize, ReachError> {
+        let counts = self.status_counts(now, now).await?;
+        Ok(counts.online + counts.idle)
+    }
+
+    /// The live entry that expires soonest after `now`. Backends that can
+    /// find it without loading every entry should override this.
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|entry| entry.expires_at > now)
+            .min_by_key(|entry| entry.expires_at))
+    }
+
+    /// Fail as registering a new entry for `did` would, if the registry
+    /// limits what it admits. Dry runs use it to report what a real
+    /// registration would hit.
+    async fn check_admission(&self, _did: &str, _now: i64) -> Result<(), ReachError> {
+        Ok(())
+    }
+
+    /// Check that the storage answers, for readiness probes; storage held
+    /// in this process always does
+    async fn ping(&self) -> Result<(), ReachError> {
+        Ok(())
+    }
+
+    /// All non-expired entries
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError>;
+
+    /// Every stored entry, including expired ones not yet purged
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError>;
+
+    /// Past registrations for a DID, newest first
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError>;
+
+    /// Deny `delegate` the delegation certificates `subject` issued up to
+    /// `revoked_at`, remembering it until `expires_at`. A later revocation
+    /// of the same pair replaces an earlier one.
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError>;
+
+    /// When `subject` last revoked `delegate`'s certificates, unless that
+    /// revocation expired by `now`
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError>;
+}
+
+/// Storage for in-flight handshakes and authenticated sessions
+///
+/// Kept separate from the registry so several server replicas can share
+/// sessions: a challenge issued by one replica can be answered at another.
+#[async_trait]
+pub trait HandshakeBackend: Send + Sync {
+    /// Store a pending challenge under its hash
+    async fn put_challenge(&self, hash: String, challenge: PendingChallenge) -> Result<(), ReachError>;
+
+    /// Remove and return a pending challenge, so each can be answered once
+    async fn take_challenge(&self, hash: &str) -> Result<Option<PendingChallenge>, ReachError>;
+
+    /// Store an authenticated session
+    async fn put_session(&self, session_id: String, session: AuthenticatedSession) -> Result<(), ReachError>;
+
+    /// Look up a session (expired sessions may still be returned)
+    async fn session(&self, session_id: &str) -> Result<Option<AuthenticatedSession>, ReachError>;
+
+    /// Every stored session with its ID (expired sessions may be included)
+    async fn sessions(&self) -> Result<Vec<(String, AuthenticatedSession)>, ReachError>;
+
+    /// Remove a session, returning whether it existed
+    async fn remove_session(&self, session_id: &str) -> Result<bool, ReachError>;
+
+    /// Remove `did`'s oldest sessions so at most `keep` remain, returning
+    /// how many were removed
+    async fn trim_sessions(&self, did: &str, keep: usize) -> Result<usize, ReachError>;
+
+    /// Remove sessions that expired before `now`, returning how many; stores
+    /// that expire sessions on their own have nothing to do
+    async fn purge_expired_sessions(&self, _now: i64) -> Result<usize, ReachError> {
+        Ok(0)
+    }
+
+    /// Unexpired sessions and pending challenges as of `now`, if the store
+    /// can count them without a round trip
+    fn counts(&self, _now: i64) -> Option<HandshakeCounts> {
+        None
+    }
+}
+
+/// Handshake activity reported by [`HandshakeBackend::counts`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeCounts {
+    pub active_sessions: usize,
+    pub pending_challenges: usize,
+}
+
+/// Other registries consulted when a lookup misses locally
+#[async_trait]
+pub trait PeerLookup: Send + Sync {
+    /// The first live entry a peer has for `did`, with the peer's base URL.
+    /// `hops` counts the registries that already forwarded this lookup.
+    /// Peer failures are not errors: the lookup just misses.
+    async fn lookup(&self, did: &str, hops: u32) -> Option<(String, RegistryEntry)>;
+}
+
+/// Resolves DIDs whose keys aren't part of the DID itself (did:web)
+#[async_trait]
+pub trait DidResolver: Send + Sync {
+    /// Ed25519 keys the DID's document lists for authentication. Fails
+    /// with `ReachError::DidResolution` when the document can't be fetched
+    /// or has no usable key.
+    async fn keys(&self, did: &str) -> Result<Vec<ed25519_dalek::VerifyingKey>, ReachError>;
+}
+
+/// Behaviour every backend must satisfy
+#[cfg(test)]
+pub mod harness {
+    use super::*;
+    use crate::types::Endpoint;
+
+    fn new_did() -> String {
+        format!("did:key:test-{}", uuid::Uuid::new_v4())
+    }
+
+    fn with_handle(entry: RegistryEntry, handle: &str) -> RegistryEntry {
+        RegistryEntry { handle: Some(handle.to_string()), ..entry }
+    }
+
+    /// Run every check against a backend
+    pub async fn run(backend: &dyn RegistryBackend) {
+        register_then_lookup(backend).await;
+        register_overwrites(backend).await;
+        register_if_absent_respects_live_entry(backend).await;
+        versions_guard_updates(backend).await;
+        touch_updates_last_seen(backend).await;
+        status_counts_split_by_status(backend).await;
+        handles_resolve_and_stay_unique(backend).await;
+        endpoints_round_trip(backend).await;
+        endpoint_claimants_are_other_live_dids(backend).await;
+        expired_entries_give_up_handles(backend).await;
+        deregister_removes(backend).await;
+        list_skips_expired(backend).await;
+        all_entries_includes_expired(backend).await;
+        purge_removes_expired(backend).await;
+        live_entries_are_counted_and_ordered(backend).await;
+        revocations_last_until_they_expire(backend).await;
+        history_tracks_endpoint_changes(backend).await;
+        history_is_dropped_after_retention(backend).await;
+    }
+
+    async fn register_then_lookup(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        backend.register(RegistryEntry::for_test(&did, "wss://one", 3600)).await.unwrap();
+
+        let found = backend.lookup(&did).await.unwrap().expect("registered entry");
+        assert_eq!(found.endpoint, "wss://one");
+        assert!(backend.lookup("did:key:missing").await.unwrap().is_none());
+    }
+
+    async fn register_overwrites(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        backend.register(RegistryEntry::for_test(&did, "wss://one", 3600)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&did, "wss://two", 3600)).await.unwrap();
+
+        let found = backend.lookup(&did).await.unwrap().unwrap();
+        assert_eq!(found.endpoint, "wss://two");
+    }
+
+    async fn register_if_absent_respects_live_entry(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        assert!(backend.register_if_absent(RegistryEntry::for_test(&did, "wss://one", 3600)).await.unwrap());
+        assert!(!backend.register_if_absent(RegistryEntry::for_test(&did, "wss://two", 3600)).await.unwrap());
+        assert_eq!(backend.lookup(&did).await.unwrap().unwrap().endpoint, "wss://one");
+
+        // An expired entry doesn't block a conditional registration
+        let expired = new_did();
+        backend.register(RegistryEntry::for_test(&expired, "wss://old", -10)).await.unwrap();
+        assert!(backend.register_if_absent(RegistryEntry::for_test(&expired, "wss://new", 3600)).await.unwrap());
+        assert_eq!(backend.lookup(&expired).await.unwrap().unwrap().endpoint, "wss://new");
+    }
+
+    async fn versions_guard_updates(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        let first = backend.register_when(RegistryEntry::for_test(&did, "wss://one", 3600), Precondition::Always).await.unwrap().unwrap();
+        assert_eq!(backend.lookup(&did).await.unwrap().unwrap().version, first);
+
+        let second = backend.register_when(RegistryEntry::for_test(&did, "wss://two", 3600), Precondition::Version(first)).await.unwrap().unwrap();
+        assert!(second > first);
+        let stale = backend.register_when(RegistryEntry::for_test(&did, "wss://three", 3600), Precondition::Version(first)).await.unwrap();
+        assert_eq!(stale, None);
+        let found = backend.lookup(&did).await.unwrap().unwrap();
+        assert_eq!((found.endpoint.as_str(), found.version), ("wss://two", second));
+
+        // Versions carry on past a removal, so a copy read before it can't
+        // match the re-registration
+        assert!(backend.deregister(&did).await.unwrap());
+        let again = backend.register_when(RegistryEntry::for_test(&did, "wss://one", 3600), Precondition::Always).await.unwrap().unwrap();
+        assert!(again > second);
+        let replaced = backend.register_when(RegistryEntry::for_test(&did, "wss://two", 3600), Precondition::Version(second)).await.unwrap();
+        assert_eq!(replaced, None);
+
+        let expired = new_did();
+        let old = backend.register_when(RegistryEntry::for_test(&expired, "wss://old", -10), Precondition::Always).await.unwrap().unwrap();
+        backend.purge_expired().await.unwrap();
+        let renewed = backend.register_when(RegistryEntry::for_test(&expired, "wss://new", 3600), Precondition::Always).await.unwrap().unwrap();
+        assert!(renewed > old);
+
+        // A version can't match a DID with nothing stored
+        let missing = backend.register_when(RegistryEntry::for_test(&new_did(), "wss://one", 3600), Precondition::Version(0)).await.unwrap();
+        assert_eq!(missing, None);
+    }
+
+    async fn touch_updates_last_seen(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        let registered = RegistryEntry::for_test(&did, "wss://one", 3600);
+        backend.register(registered.clone()).await.unwrap();
+
+        assert!(backend.touch(&did, registered.last_seen + 60).await.unwrap());
+        assert!(!backend.touch("did:key:missing", registered.last_seen).await.unwrap());
+
+        let found = backend.lookup(&did).await.unwrap().unwrap();
+        assert_eq!(found.last_seen, registered.last_seen + 60);
+        assert_eq!(found.registered_at, registered.registered_at);
+    }
+
+    async fn status_counts_split_by_status(backend: &dyn RegistryBackend) {
+        let now = chrono::Utc::now().timestamp();
+        let idle_before = now - 3600;
+        let before = backend.status_counts(now, idle_before).await.unwrap();
+
+        backend.register(RegistryEntry::for_test(&new_did(), "wss://online", 3600)).await.unwrap();
+        let idle = RegistryEntry { last_seen: now - 7200, ..RegistryEntry::for_test(&new_did(), "wss://idle", 3600) };
+        backend.register(idle).await.unwrap();
+        backend.register(RegistryEntry::for_test(&new_did(), "wss://expired", -10)).await.unwrap();
+
+        let after = backend.status_counts(now, idle_before).await.unwrap();
+        assert_eq!(after.online - before.online, 1);
+        assert_eq!(after.idle - before.idle, 1);
+        assert_eq!(after.expired - before.expired, 1);
+    }
+
+    async fn endpoint_claimants_are_other_live_dids(backend: &dyn RegistryBackend) {
+        let endpoint = format!("wss://{}", uuid::Uuid::new_v4());
+        let (owner, other) = (new_did(), new_did());
+        backend.register(RegistryEntry::for_test(&owner, &endpoint, 3600)).await.unwrap();
+
+        assert_eq!(backend.endpoint_claimant(&endpoint, &other).await.unwrap(), Some(owner.clone()));
+        assert_eq!(backend.endpoint_claimant(&endpoint, &owner).await.unwrap(), None);
+
+        backend.register(RegistryEntry::for_test(&owner, &endpoint, -10)).await.unwrap();
+        assert_eq!(backend.endpoint_claimant(&endpoint, &other).await.unwrap(), None);
+    }
+
+    async fn handles_resolve_and_stay_unique(backend: &dyn RegistryBackend) {
+        let handle = format!("{}@example.com", uuid::Uuid::new_v4());
+        let (owner, other) = (new_did(), new_did());
+        backend.register(with_handle(RegistryEntry::for_test(&owner, "wss://owner", 3600), &handle)).await.unwrap();
+
+        let found = backend.resolve_handle(&handle).await.unwrap().expect("registered handle");
+        assert_eq!(found.did, owner);
+        assert_eq!(backend.lookup(&owner).await.unwrap().unwrap().handle.as_deref(), Some(handle.as_str()));
+
+        // Another DID can't take it, conditionally or not
+        let claim = with_handle(RegistryEntry::for_test(&other, "wss://other", 3600), &handle);
+        assert!(matches!(backend.register(claim.clone()).await, Err(ReachError::HandleTaken)));
+        assert!(matches!(backend.register_if_absent(claim.clone()).await, Err(ReachError::HandleTaken)));
+        assert!(backend.lookup(&other).await.unwrap().is_none());
+
+        // The owner re-registering without it releases it
+        backend.register(RegistryEntry::for_test(&owner, "wss://owner", 3600)).await.unwrap();
+        assert!(backend.resolve_handle(&handle).await.unwrap().is_none());
+        backend.register(claim).await.unwrap();
+        assert_eq!(backend.resolve_handle(&handle).await.unwrap().unwrap().did, other);
+    }
+
+    async fn endpoints_round_trip(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        let endpoints = vec![
+            Endpoint { uri: "wss://one".to_string(), weight: Some(2), region: Some("eu-west".to_string()), registered_at: None },
+            Endpoint { uri: "wss://two".to_string(), weight: None, region: None, registered_at: None },
+        ];
+        let several = RegistryEntry { endpoints: endpoints.clone(), ..RegistryEntry::for_test(&did, "wss://one", 3600) };
+        backend.register(several).await.unwrap();
+        assert_eq!(backend.lookup(&did).await.unwrap().unwrap().endpoints, endpoints);
+
+        backend.register(RegistryEntry::for_test(&did, "wss://one", 3600)).await.unwrap();
+        assert!(backend.lookup(&did).await.unwrap().unwrap().endpoints.is_empty());
+    }
+
+    async fn expired_entries_give_up_handles(backend: &dyn RegistryBackend) {
+        let handle = format!("{}@example.com", uuid::Uuid::new_v4());
+        let (expired, next) = (new_did(), new_did());
+        backend.register(with_handle(RegistryEntry::for_test(&expired, "wss://old", -10), &handle)).await.unwrap();
+        assert_eq!(backend.resolve_handle(&handle).await.unwrap().unwrap().did, expired);
+
+        backend.register(with_handle(RegistryEntry::for_test(&next, "wss://new", 3600), &handle)).await.unwrap();
+        assert_eq!(backend.resolve_handle(&handle).await.unwrap().unwrap().did, next);
+        assert!(backend.lookup(&expired).await.unwrap().unwrap().handle.is_none());
+    }
+
+    async fn deregister_removes(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        backend.register(RegistryEntry::for_test(&did, "wss://one", 3600)).await.unwrap();
+
+        assert!(backend.deregister(&did).await.unwrap());
+        assert!(!backend.deregister(&did).await.unwrap());
+        assert!(backend.lookup(&did).await.unwrap().is_none());
+    }
+
+    async fn list_skips_expired(backend: &dyn RegistryBackend) {
+        let live = new_did();
+        let expired = new_did();
+        backend.register(RegistryEntry::for_test(&live, "wss://live", 3600)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&expired, "wss://expired", -10)).await.unwrap();
+
+        let listed = backend.list().await.unwrap();
+        assert!(listed.iter().any(|e| e.did == live));
+        assert!(!listed.iter().any(|e| e.did == expired));
+    }
+
+    async fn all_entries_includes_expired(backend: &dyn RegistryBackend) {
+        let live = new_did();
+        let expired = new_did();
+        backend.register(RegistryEntry::for_test(&live, "wss://live", 3600)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&expired, "wss://expired", -10)).await.unwrap();
+
+        let all = backend.all_entries().await.unwrap();
+        assert!(all.iter().any(|e| e.did == live));
+        assert!(all.iter().any(|e| e.did == expired));
+    }
+
+    async fn purge_removes_expired(backend: &dyn RegistryBackend) {
+        let live = new_did();
+        let expired = new_did();
+        backend.register(RegistryEntry::for_test(&live, "wss://live", 3600)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&expired, "wss://expired", -10)).await.unwrap();
+
+        let purged = backend.purge_expired().await.unwrap();
+        let purged_entry = |did: &str| purged.iter().find(|entry| entry.did == did);
+        assert_eq!(purged_entry(&expired).expect("purged").endpoint, "wss://expired");
+        assert!(purged_entry(&live).is_none());
+        assert!(backend.lookup(&live).await.unwrap().is_some());
+        assert!(backend.lookup(&expired).await.unwrap().is_none());
+    }
+
+    async fn live_entries_are_counted_and_ordered(backend: &dyn RegistryBackend) {
+        let now = chrono::Utc::now().timestamp();
+        let before = backend.live_count(now).await.unwrap();
+        let soonest = new_did();
+        backend.register(RegistryEntry::for_test(&soonest, "wss://soonest", 2)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&new_did(), "wss://later", 3600)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&new_did(), "wss://expired", -10)).await.unwrap();
+
+        assert_eq!(backend.live_count(now).await.unwrap() - before, 2);
+        let found = backend.soonest_expiring(now).await.unwrap().unwrap();
+        assert_eq!(found.did, soonest);
+    }
+
+    async fn revocations_last_until_they_expire(backend: &dyn RegistryBackend) {
+        let now = chrono::Utc::now().timestamp();
+        let (subject, delegate) = (new_did(), new_did());
+        assert_eq!(backend.certificates_revoked_at(&subject, &delegate, now).await.unwrap(), None);
+
+        backend.revoke_certificates(&subject, &delegate, now - 10, now + 60).await.unwrap();
+        backend.revoke_certificates(&subject, &delegate, now, now + 70).await.unwrap();
+        assert_eq!(backend.certificates_revoked_at(&subject, &delegate, now).await.unwrap(), Some(now));
+        assert_eq!(backend.certificates_revoked_at(&delegate, &subject, now).await.unwrap(), None);
+        assert_eq!(backend.certificates_revoked_at(&subject, &delegate, now + 70).await.unwrap(), None);
+
+        // Purging drops revocations that have expired
+        let lapsed = new_did();
+        backend.revoke_certificates(&lapsed, &delegate, now - 20, now - 1).await.unwrap();
+        backend.purge_expired().await.unwrap();
+        assert_eq!(backend.certificates_revoked_at(&lapsed, &delegate, now - 2).await.unwrap(), None);
+        assert_eq!(backend.certificates_revoked_at(&subject, &delegate, now).await.unwrap(), Some(now));
+    }
+
+    async fn history_tracks_endpoint_changes(backend: &dyn RegistryBackend) {
+        let did = new_did();
+        backend.register(RegistryEntry::for_test(&did, "wss://one", 3600)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&did, "wss://two", 3600)).await.unwrap();
+        backend.deregister(&did).await.unwrap();
+
+        let history = backend.history(&did).await.unwrap();
+        let endpoints: Vec<_> = history.iter().map(|e| e.endpoint.as_str()).collect();
+        assert_eq!(endpoints, ["wss://two", "wss://one"]);
+    }
+
+    async fn history_is_dropped_after_retention(backend: &dyn RegistryBackend) {
+        let lapsed = new_did();
+        let recent = new_did();
+        let refreshed = new_did();
+        let retention = crate::registry::HISTORY_RETENTION_SECS;
+        backend.register(RegistryEntry::for_test(&lapsed, "wss://one", -retention - 10)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&recent, "wss://one", -10)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&refreshed, "wss://one", -retention - 10)).await.unwrap();
+        backend.register(RegistryEntry::for_test(&refreshed, "wss://one", 3600)).await.unwrap();
+        backend.deregister(&refreshed).await.unwrap();
+
+        backend.purge_expired().await.unwrap();
+        assert!(backend.history(&lapsed).await.unwrap().is_empty());
+        assert_eq!(backend.history(&recent).await.unwrap().len(), 1);
+        assert_eq!(backend.history(&refreshed).await.unwrap().len(), 1);
+    }
+}
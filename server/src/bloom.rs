@@ -0,0 +1,83 @@
+//! Lock-free Bloom filter over registered DIDs
+//!
+//! Answers "definitely never registered" without touching the registry's
+//! locks. Entries are never removed, so deregistered DIDs remain possible
+//! matches and simply fall through to a real lookup.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Filter size in bits (128 KiB); about 1% false positives at 100k DIDs
+const BITS: usize = 1 << 20;
+
+/// Bit positions set per DID
+const HASHES: u64 = 7;
+
+pub struct BloomFilter {
+    words: Box<[AtomicU64]>,
+}
+
+impl BloomFilter {
+    pub fn new() -> Self {
+        Self {
+            words: (0..BITS / 64).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn insert(&self, did: &str) {
+        for bit in positions(did) {
+            self.words[bit / 64].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// `false` means the DID was never inserted
+    pub fn might_contain(&self, did: &str) -> bool {
+        positions(did).all(|bit| self.words[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bit positions for a DID, by double hashing one 64-bit hash
+fn positions(did: &str) -> impl Iterator<Item = usize> {
+    let mut hasher = DefaultHasher::new();
+    did.hash(&mut hasher);
+    let hash = hasher.finish();
+    let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % BITS as u64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserted_dids_always_match() {
+        let filter = BloomFilter::new();
+        for i in 0..1000 {
+            filter.insert(&format!("did:key:present-{}", i));
+        }
+
+        for i in 0..1000 {
+            assert!(filter.might_contain(&format!("did:key:present-{}", i)));
+        }
+    }
+
+    #[test]
+    fn absent_dids_rarely_match() {
+        let filter = BloomFilter::new();
+        for i in 0..10_000 {
+            filter.insert(&format!("did:key:present-{}", i));
+        }
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.might_contain(&format!("did:key:absent-{}", i)))
+            .count();
+        assert!(false_positives < 10, "{} false positives", false_positives);
+    }
+}
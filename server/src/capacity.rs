@@ -0,0 +1,326 @@
+//! Global cap on live registrations
+//!
+//! With `--capacity`, registering a DID that has no live entry is refused
+//! with 503 once the registry holds that many live entries, so a flood of
+//! generated keys can't hold more than that many registrations at once. The
+//! history of DIDs that are gone is kept on top of that for
+//! [`HISTORY_RETENTION_SECS`](crate::registry::HISTORY_RETENTION_SECS).
+//! Refreshing an entry that is still live always succeeds. With `--capacity-policy evict-soonest` the
+//! entry closest to expiring is dropped to make room instead. Admission is
+//! serialized within this process; servers sharing storage each enforce the
+//! cap on their own writes, so they can overshoot it together.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::backend::{Precondition, RegistryBackend};
+use crate::error::ReachError;
+use crate::types::{RegistryEntry, StatusCounts};
+
+/// Share of the capacity, in percent, at which a warning is logged
+pub const DEFAULT_HIGH_WATER_PERCENT: u8 = 90;
+
+/// What to do with a new registration when the registry is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CapacityPolicy {
+    /// Refuse it with 503
+    #[default]
+    Reject,
+    /// Deregister the live entry that expires soonest
+    EvictSoonest,
+}
+
+/// Enforces a capacity on the backend it wraps
+pub struct CappedRegistry {
+    inner: Arc<dyn RegistryBackend>,
+    capacity: usize,
+    policy: CapacityPolicy,
+    /// Live entries at which to warn
+    high_water: usize,
+    /// Whether the warning was logged since the count was last below the mark
+    warned: AtomicBool,
+    admission: tokio::sync::Mutex<()>,
+}
+
+impl CappedRegistry {
+    pub fn new(inner: Arc<dyn RegistryBackend>, capacity: usize, policy: CapacityPolicy, high_water_percent: u8) -> Self {
+        let high_water = capacity * usize::from(high_water_percent.min(100)) / 100;
+        Self {
+            inner,
+            capacity,
+            policy,
+            high_water: high_water.max(1),
+            warned: AtomicBool::new(false),
+            admission: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Deregister the live entry closest to expiring, returning whether
+    /// one was removed
+    async fn evict_soonest(&self, now: i64) -> Result<bool, ReachError> {
+        let Some(victim) = self.inner.soonest_expiring(now).await? else {
+            return Ok(false);
+        };
+        tracing::info!(did = %victim.did, "Evicting registration at capacity");
+        self.inner.deregister(&victim.did).await
+    }
+
+    /// Whether storing `entry` over `stored` would fail anyway, so nothing
+    /// should be evicted for it
+    async fn would_fail(
+        &self,
+        entry: &RegistryEntry,
+        stored: Option<&RegistryEntry>,
+        precondition: Precondition,
+        now: i64,
+    ) -> Result<bool, ReachError> {
+        if let Precondition::Version(version) = precondition {
+            if stored.is_none_or(|stored| stored.version != version) {
+                return Ok(true);
+            }
+        }
+        let Some(handle) = &entry.handle else {
+            return Ok(false);
+        };
+        let holder = self.inner.resolve_handle(handle).await?;
+        if holder.is_some_and(|holder| holder.did != entry.did && holder.expires_at > now) {
+            return Err(ReachError::HandleTaken);
+        }
+        Ok(false)
+    }
+
+    /// Log once each time the live count reaches the high-water mark
+    fn check_high_water(&self, live: usize) {
+        if live < self.high_water {
+            self.warned.store(false, Ordering::Relaxed);
+        } else if !self.warned.swap(true, Ordering::Relaxed) {
+            tracing::warn!(live, capacity = self.capacity, "Registry is nearly full");
+        }
+    }
+}
+
+#[async_trait]
+impl RegistryBackend for CappedRegistry {
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        let now = chrono::Utc::now().timestamp();
+        let live_entry = |stored: &Option<RegistryEntry>| stored.as_ref().is_some_and(|existing| existing.expires_at > now);
+        // Refreshing a live entry takes no room, so it doesn't wait on
+        // admissions of new DIDs
+        if live_entry(&self.inner.lookup(&entry.did).await?) {
+            return self.inner.register_when(entry, precondition).await;
+        }
+
+        let _admission = self.admission.lock().await;
+        let stored = self.inner.lookup(&entry.did).await?;
+        if live_entry(&stored) {
+            return self.inner.register_when(entry, precondition).await;
+        }
+        if self.would_fail(&entry, stored.as_ref(), precondition, now).await? {
+            return Ok(None);
+        }
+
+        let mut live = self.inner.live_count(now).await?;
+        if live >= self.capacity {
+            let evicted = match self.policy {
+                CapacityPolicy::Reject => false,
+                CapacityPolicy::EvictSoonest => self.evict_soonest(now).await?,
+            };
+            if !evicted {
+                self.check_high_water(live);
+                return Err(ReachError::RegistryFull);
+            }
+            live -= 1;
+        }
+        let stored = self.inner.register_when(entry, precondition).await?;
+        if stored.is_some() {
+            live += 1;
+        }
+        self.check_high_water(live);
+        Ok(stored)
+    }
+
+    async fn check_admission(&self, did: &str, now: i64) -> Result<(), ReachError> {
+        if self.inner.lookup(did).await?.is_some_and(|existing| existing.expires_at > now) {
+            return self.inner.check_admission(did, now).await;
+        }
+        if self.inner.live_count(now).await? >= self.capacity {
+            let evictable = match self.policy {
+                CapacityPolicy::Reject => false,
+                CapacityPolicy::EvictSoonest => self.inner.soonest_expiring(now).await?.is_some(),
+            };
+            if !evictable {
+                return Err(ReachError::RegistryFull);
+            }
+        }
+        self.inner.check_admission(did, now).await
+    }
+
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.lookup(did).await
+    }
+
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.resolve_handle(handle).await
+    }
+
+    async fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Result<Option<String>, ReachError> {
+        self.inner.endpoint_claimant(endpoint, did).await
+    }
+
+    fn might_contain(&self, did: &str) -> bool {
+        self.inner.might_contain(did)
+    }
+
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+        self.inner.touch(did, last_seen).await
+    }
+
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+        self.inner.deregister(did).await
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.purge_expired().await
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+
+    async fn ping(&self) -> Result<(), ReachError> {
+        self.inner.ping().await
+    }
+
+    async fn status_counts(&self, now: i64, idle_before: i64) -> Result<StatusCounts, ReachError> {
+        self.inner.status_counts(now, idle_before).await
+    }
+
+    async fn live_count(&self, now: i64) -> Result<usize, ReachError> {
+        self.inner.live_count(now).await
+    }
+
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.soonest_expiring(now).await
+    }
+
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.list().await
+    }
+
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.all_entries().await
+    }
+
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.history(did).await
+    }
+
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+        self.inner.revoke_certificates(subject, delegate, revoked_at, expires_at).await
+    }
+
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+        self.inner.certificates_revoked_at(subject, delegate, now).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+
+    fn entry(did: &str, expires_in: i64) -> RegistryEntry {
+        RegistryEntry::for_test(did, "wss://agent", expires_in)
+    }
+
+    fn capped(policy: CapacityPolicy) -> CappedRegistry {
+        CappedRegistry::new(Arc::new(Registry::new()), 2, policy, DEFAULT_HIGH_WATER_PERCENT)
+    }
+
+    #[tokio::test]
+    async fn full_registry_rejects_new_dids_but_not_refreshes() {
+        let registry = capped(CapacityPolicy::Reject);
+        registry.register(entry("did:key:a", 3600)).await.unwrap();
+        registry.register(entry("did:key:b", 3600)).await.unwrap();
+
+        let err = registry.register(entry("did:key:c", 3600)).await.unwrap_err();
+        assert!(matches!(err, ReachError::RegistryFull));
+        assert!(registry.lookup("did:key:c").await.unwrap().is_none());
+
+        registry.register(entry("did:key:a", 7200)).await.expect("refresh of a live entry");
+
+        // Expired entries don't count against the capacity
+        registry.register(entry("did:key:b", -10)).await.unwrap();
+        registry.register(entry("did:key:c", 3600)).await.expect("room left by the expired entry");
+    }
+
+    #[tokio::test]
+    async fn refreshes_skip_the_admission_lock() {
+        let registry = capped(CapacityPolicy::Reject);
+        registry.register(entry("did:key:a", 3600)).await.unwrap();
+
+        let _admitting = registry.admission.lock().await;
+        let refresh = registry.register(entry("did:key:a", 7200));
+        tokio::time::timeout(std::time::Duration::from_secs(1), refresh)
+            .await
+            .expect("refresh waited on admission")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn eviction_drops_the_soonest_to_expire() {
+        let registry = capped(CapacityPolicy::EvictSoonest);
+        registry.register(entry("did:key:soon", 60)).await.unwrap();
+        registry.register(entry("did:key:late", 3600)).await.unwrap();
+
+        registry.register(entry("did:key:new", 600)).await.unwrap();
+        assert!(registry.lookup("did:key:soon").await.unwrap().is_none());
+        assert!(registry.lookup("did:key:late").await.unwrap().is_some());
+        assert!(registry.lookup("did:key:new").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn nothing_is_evicted_for_a_write_that_would_fail() {
+        let registry = capped(CapacityPolicy::EvictSoonest);
+        let holder = RegistryEntry { handle: Some("alice@example.com".to_string()), ..entry("did:key:holder", 3600) };
+        registry.register(holder).await.unwrap();
+        registry.register(entry("did:key:soon", 60)).await.unwrap();
+
+        let stale = registry.register_when(entry("did:key:new", 600), Precondition::Version(1)).await.unwrap();
+        assert_eq!(stale, None);
+        let taken = RegistryEntry { handle: Some("alice@example.com".to_string()), ..entry("did:key:new", 600) };
+        let err = registry.register(taken).await.unwrap_err();
+        assert!(matches!(err, ReachError::HandleTaken));
+
+        assert!(registry.lookup("did:key:soon").await.unwrap().is_some());
+        assert!(registry.lookup("did:key:new").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn admission_check_matches_registration() {
+        let registry = capped(CapacityPolicy::Reject);
+        let now = chrono::Utc::now().timestamp();
+        registry.register(entry("did:key:a", 3600)).await.unwrap();
+        registry.check_admission("did:key:b", now).await.expect("room for one more");
+        registry.register(entry("did:key:b", 3600)).await.unwrap();
+
+        let err = registry.check_admission("did:key:c", now).await.unwrap_err();
+        assert!(matches!(err, ReachError::RegistryFull));
+        registry.check_admission("did:key:a", now).await.expect("refresh of a live entry");
+
+        let evicting = capped(CapacityPolicy::EvictSoonest);
+        evicting.register(entry("did:key:a", 3600)).await.unwrap();
+        evicting.register(entry("did:key:b", 3600)).await.unwrap();
+        evicting.check_admission("did:key:c", now).await.expect("an entry can be evicted");
+        assert_eq!(evicting.live_count(now).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn full_registry_with_nothing_to_evict_rejects() {
+        let registry = CappedRegistry::new(Arc::new(Registry::new()), 0, CapacityPolicy::EvictSoonest, DEFAULT_HIGH_WATER_PERCENT);
+        let err = registry.register(entry("did:key:a", 3600)).await.unwrap_err();
+        assert!(matches!(err, ReachError::RegistryFull));
+    }
+}
@@ -0,0 +1,608 @@
+//! Feed of registry changes for mirrors and indexes
+//!
+//! Every registration, refresh, deregistration and purge of an expired entry
+//! made through this server gets the next sequence number, and
+//! `GET /changes?since=` replays them in order. The log is a bounded ring
+//! buffer in memory: a consumer that falls further behind than it retains
+//! is told to resync from `/agents`. Numbering starts again when the server
+//! restarts, so every response carries the log's epoch, and a cursor from
+//! another epoch is told to resync too. Replicas sharing storage each
+//! number their own writes, so a consumer should follow a single replica.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::backend::{Precondition, RegistryBackend};
+use crate::error::ReachError;
+use crate::handlers::AppState;
+use crate::types::{RegistryEntry, StatusCounts};
+
+/// Changes retained by default
+pub const DEFAULT_RETAINED: usize = 10_000;
+
+/// Changes returned when the request doesn't set `limit`
+const DEFAULT_LIMIT: usize = 100;
+
+/// Most changes returned by one request
+const MAX_LIMIT: usize = 1000;
+
+/// Changes queued for one DID's watcher before it is told it fell behind
+const DID_WATCH_CAPACITY: usize = 64;
+
+/// What happened to a DID's registration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// A DID without a live entry registered
+    Register,
+    /// A DID with a live entry registered again
+    Refresh,
+    Deregister,
+    /// The sweeper removed an expired entry
+    Expire,
+}
+
+/// One numbered change
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Change {
+    /// Position in the feed, increasing by one per change
+    pub seq: u64,
+    pub kind: ChangeKind,
+    pub did: String,
+    /// Unix timestamp (seconds) the change was recorded
+    pub at: i64,
+    /// The stored entry for `register` and `refresh`; the last one stored
+    /// for `deregister` and `expire`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<RegistryEntry>,
+}
+
+/// The retained tail of the change feed
+pub struct ChangeLog {
+    /// Names this run of the log; sequence numbers restart with a new one
+    epoch: String,
+    retained: usize,
+    state: Mutex<LogState>,
+    /// Sequence number of the newest change, for watchers waiting on more
+    notify: tokio::sync::watch::Sender<u64>,
+    /// Channels to each watched DID's watchers, so a change only reaches
+    /// those following its DID
+    by_did: Mutex<HashMap<String, tokio::sync::broadcast::Sender<Change>>>,
+}
+
+struct LogState {
+    /// Sequence number of the newest change (0 before the first)
+    latest: u64,
+    changes: VecDeque<Change>,
+}
+
+impl ChangeLog {
+    /// Keep the `retained` most recent changes
+    pub fn new(retained: usize) -> Self {
+        Self {
+            epoch: uuid::Uuid::new_v4().simple().to_string(),
+            retained: retained.max(1),
+            state: Mutex::new(LogState {
+                latest: 0,
+                changes: VecDeque::new(),
+            }),
+            notify: tokio::sync::watch::channel(0).0,
+            by_did: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn record(&self, kind: ChangeKind, did: String, entry: Option<RegistryEntry>) {
+        let at = chrono::Utc::now().timestamp();
+        let mut state = self.state.lock();
+        state.latest += 1;
+        let seq = state.latest;
+        if state.changes.len() == self.retained {
+            state.changes.pop_front();
+        }
+        let change = Change { seq, kind, did, at, entry };
+        if let Some(watchers) = self.by_did.lock().get(&change.did) {
+            let _ = watchers.send(change.clone());
+        }
+        state.changes.push_back(change);
+        self.notify.send_replace(seq);
+    }
+
+    /// Receives the newest sequence number after every change
+    #[cfg(feature = "grpc")]
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<u64> {
+        self.notify.subscribe()
+    }
+
+    /// Receive each change to `did` recorded from now on
+    pub fn watch_did(self: &Arc<Self>, did: &str) -> DidChanges {
+        let receiver = self
+            .by_did
+            .lock()
+            .entry(did.to_string())
+            .or_insert_with(|| tokio::sync::broadcast::channel(DID_WATCH_CAPACITY).0)
+            .subscribe();
+        DidChanges { log: self.clone(), did: did.to_string(), receiver }
+    }
+
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+
+    /// Whether a cursor a client kept was numbered by this log. Starting
+    /// from 0 needs no epoch.
+    pub fn numbered(&self, epoch: Option<&str>, since: u64) -> bool {
+        since == 0 || epoch == Some(self.epoch.as_str())
+    }
+
+    /// [`since`](Self::since) for a cursor a client kept: `None` as well if
+    /// the cursor is from another epoch
+    pub fn resume(&self, epoch: Option<&str>, since: u64, limit: usize) -> (Option<Vec<Change>>, u64) {
+        if !self.numbered(epoch, since) {
+            return (None, self.state.lock().latest);
+        }
+        self.since(since, limit)
+    }
+
+    /// Up to `limit` changes after `since`, oldest first, or `None` if some
+    /// of them are no longer retained (or `since` is ahead of the log)
+    pub fn since(&self, since: u64, limit: usize) -> (Option<Vec<Change>>, u64) {
+        let state = self.state.lock();
+        let oldest = state.changes.front().map_or(state.latest + 1, |change| change.seq);
+        if since > state.latest || since + 1 < oldest {
+            return (None, state.latest);
+        }
+
+        let skip = (since + 1 - oldest) as usize;
+        let changes = state.changes.iter().skip(skip).take(limit).cloned().collect();
+        (Some(changes), state.latest)
+    }
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETAINED)
+    }
+}
+
+/// Changes to one DID as they are recorded, from
+/// [`ChangeLog::watch_did`]
+pub struct DidChanges {
+    log: Arc<ChangeLog>,
+    did: String,
+    receiver: tokio::sync::broadcast::Receiver<Change>,
+}
+
+impl DidChanges {
+    /// The next change, or `None` if this watcher fell behind and missed
+    /// some; it then carries on from the next change recorded
+    pub async fn recv(&mut self) -> Option<Change> {
+        use tokio::sync::broadcast::error::RecvError;
+
+        match self.receiver.recv().await {
+            Ok(change) => Some(change),
+            Err(RecvError::Lagged(_)) => {
+                self.receiver = self.receiver.resubscribe();
+                None
+            }
+            // The sender outlives every receiver; see `drop`
+            Err(RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for DidChanges {
+    fn drop(&mut self) {
+        // Subscribing holds the same lock, so no watcher joins in between
+        let mut by_did = self.log.by_did.lock();
+        if by_did.get(&self.did).is_some_and(|watchers| watchers.receiver_count() <= 1) {
+            by_did.remove(&self.did);
+        }
+    }
+}
+
+/// Number of write locks DIDs are spread over
+const WRITE_STRIPES: usize = 16;
+
+/// Records every write made through it in a [`ChangeLog`]
+///
+/// Writes to one DID are serialized, so the feed orders a DID's changes the
+/// way storage applied them; writes to DIDs on different stripes go ahead
+/// together. A purge holds every stripe. Lookups and `touch` pass straight
+/// through.
+pub struct RecordingRegistry {
+    inner: Arc<dyn RegistryBackend>,
+    log: Arc<ChangeLog>,
+    writes: Box<[tokio::sync::Mutex<()>]>,
+}
+
+impl RecordingRegistry {
+    pub fn new(inner: Arc<dyn RegistryBackend>, log: Arc<ChangeLog>) -> Self {
+        Self {
+            inner,
+            log,
+            writes: (0..WRITE_STRIPES).map(|_| tokio::sync::Mutex::new(())).collect(),
+        }
+    }
+
+    /// Hold off other writes to `did` until the guard is dropped
+    async fn write(&self, did: &str) -> tokio::sync::MutexGuard<'_, ()> {
+        self.writes[crate::registry::shard_of(did, self.writes.len())].lock().await
+    }
+
+    /// `Refresh` if the DID has a live entry, `Register` otherwise
+    async fn kind_of_registration(&self, did: &str) -> Result<ChangeKind, ReachError> {
+        let now = chrono::Utc::now().timestamp();
+        Ok(match self.inner.lookup(did).await? {
+            Some(existing) if existing.expires_at > now => ChangeKind::Refresh,
+            _ => ChangeKind::Register,
+        })
+    }
+}
+
+#[async_trait]
+impl RegistryBackend for RecordingRegistry {
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        Ok(self.register_evicting(entry, precondition).await?.0)
+    }
+
+    async fn register_evicting(
+        &self,
+        mut entry: RegistryEntry,
+        precondition: Precondition,
+    ) -> Result<(Option<u64>, Vec<RegistryEntry>), ReachError> {
+        let _write = self.write(&entry.did).await;
+        let kind = match precondition {
+            Precondition::Absent => ChangeKind::Register,
+            _ => self.kind_of_registration(&entry.did).await?,
+        };
+        let (stored, evicted) = self.inner.register_evicting(entry.clone(), precondition).await?;
+        // Entries evicted to make room leave the feed like any other removal
+        let now = chrono::Utc::now().timestamp();
+        for gone in &evicted {
+            let kind = if gone.expires_at <= now { ChangeKind::Expire } else { ChangeKind::Deregister };
+            self.log.record(kind, gone.did.clone(), Some(gone.clone()));
+        }
+        if let Some(version) = stored {
+            entry.version = version;
+            self.log.record(kind, entry.did.clone(), Some(entry));
+        }
+        Ok((stored, evicted))
+    }
+
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.lookup(did).await
+    }
+
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.resolve_handle(handle).await
+    }
+
+    async fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Result<Option<String>, ReachError> {
+        self.inner.endpoint_claimant(endpoint, did).await
+    }
+
+    fn might_contain(&self, did: &str) -> bool {
+        self.inner.might_contain(did)
+    }
+
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+        self.inner.touch(did, last_seen).await
+    }
+
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+        let _write = self.write(did).await;
+        let last = self.inner.lookup(did).await?;
+        let removed = self.inner.deregister(did).await?;
+        if removed {
+            self.log.record(ChangeKind::Deregister, did.to_string(), last);
+        }
+        Ok(removed)
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let mut writes = Vec::with_capacity(self.writes.len());
+        for stripe in self.writes.iter() {
+            writes.push(stripe.lock().await);
+        }
+        let removed = self.inner.purge_expired().await?;
+        for entry in &removed {
+            self.log.record(ChangeKind::Expire, entry.did.clone(), Some(entry.clone()));
+        }
+        Ok(removed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+
+    async fn ping(&self) -> Result<(), ReachError> {
+        self.inner.ping().await
+    }
+
+    async fn status_counts(&self, now: i64, idle_before: i64) -> Result<StatusCounts, ReachError> {
+        self.inner.status_counts(now, idle_before).await
+    }
+
+    async fn check_admission(&self, did: &str, now: i64) -> Result<(), ReachError> {
+        self.inner.check_admission(did, now).await
+    }
+
+    async fn live_count(&self, now: i64) -> Result<usize, ReachError> {
+        self.inner.live_count(now).await
+    }
+
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.soonest_expiring(now).await
+    }
+
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.list().await
+    }
+
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.all_entries().await
+    }
+
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.history(did).await
+    }
+
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+        self.inner.revoke_certificates(subject, delegate, revoked_at, expires_at).await
+    }
+
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+        self.inner.certificates_revoked_at(subject, delegate, now).await
+    }
+}
+
+/// Query parameters for `GET /changes`
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangesParams {
+    /// Return changes after this sequence number (0 for the start)
+    #[serde(default)]
+    pub since: u64,
+    /// The `epoch` of the response `since` came from; required unless
+    /// `since` is 0
+    pub epoch: Option<String>,
+    /// Most changes to return (default 100, max 1000)
+    pub limit: Option<usize>,
+}
+
+/// Changes after the requested sequence number
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChangesResponse {
+    /// Oldest first; empty when `resync` is set
+    pub changes: Vec<Change>,
+    /// Sequence number of the newest change; pass the last `seq` you
+    /// processed as `since` to continue
+    pub latest: u64,
+    /// This run of the feed; pass it as `epoch` along with `since`
+    pub epoch: String,
+    /// Changes after `since` are no longer retained, or were numbered in
+    /// another epoch: reload `/agents`, then follow the feed from `latest`
+    pub resync: bool,
+}
+
+/// GET /changes
+///
+/// Registrations, refreshes, deregistrations and expiries after `since`, in
+/// order. No authentication required.
+#[utoipa::path(
+    get,
+    path = "/changes",
+    tag = "lookup",
+    params(ChangesParams),
+    responses((status = 200, description = "Changes after `since`", body = ChangesResponse))
+)]
+pub async fn changes(State(state): State<AppState>, Query(params): Query<ChangesParams>) -> Json<ChangesResponse> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let (changes, latest) = state.changes.resume(params.epoch.as_deref(), params.since, limit);
+    Json(ChangesResponse {
+        resync: changes.is_none(),
+        changes: changes.unwrap_or_default(),
+        latest,
+        epoch: state.changes.epoch().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+
+    fn entry(did: &str, expires_in: i64) -> RegistryEntry {
+        RegistryEntry::for_test(did, "wss://agent", expires_in)
+    }
+
+    fn kinds(changes: &[Change]) -> Vec<(u64, ChangeKind, &str)> {
+        changes.iter().map(|c| (c.seq, c.kind, c.did.as_str())).collect()
+    }
+
+    #[tokio::test]
+    async fn backend_harness() {
+        let registry = RecordingRegistry::new(Arc::new(Registry::new()), Arc::new(ChangeLog::default()));
+        crate::backend::harness::run(&registry).await;
+    }
+
+    #[tokio::test]
+    async fn watchers_get_only_their_did() {
+        let log = Arc::new(ChangeLog::new(1000));
+        let mut a = log.watch_did("did:key:a");
+        let mut also_a = log.watch_did("did:key:a");
+        let mut b = log.watch_did("did:key:b");
+
+        log.record(ChangeKind::Register, "did:key:a".to_string(), None);
+        log.record(ChangeKind::Register, "did:key:c".to_string(), None);
+        log.record(ChangeKind::Deregister, "did:key:a".to_string(), None);
+        for watcher in [&mut a, &mut also_a] {
+            assert_eq!(watcher.recv().await.unwrap().seq, 1);
+            assert_eq!(watcher.recv().await.unwrap().seq, 3);
+        }
+        assert!(b.receiver.is_empty());
+
+        // A watcher that falls behind is told so, then carries on
+        for _ in 0..=DID_WATCH_CAPACITY {
+            log.record(ChangeKind::Refresh, "did:key:b".to_string(), None);
+        }
+        assert!(b.recv().await.is_none());
+        log.record(ChangeKind::Deregister, "did:key:b".to_string(), None);
+        assert_eq!(b.recv().await.unwrap().kind, ChangeKind::Deregister);
+
+        // A DID's channel goes with its last watcher
+        drop((a, b));
+        assert_eq!(log.by_did.lock().len(), 1);
+        drop(also_a);
+        assert!(log.by_did.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn writes_are_numbered_in_order() {
+        let log = Arc::new(ChangeLog::default());
+        let registry = RecordingRegistry::new(Arc::new(Registry::new()), log.clone());
+
+        registry.register(entry("did:key:a", 3600)).await.unwrap();
+        registry.register(entry("did:key:a", 3600)).await.unwrap();
+        registry.register(entry("did:key:b", -10)).await.unwrap();
+        registry.deregister("did:key:a").await.unwrap();
+        assert!(!registry.deregister("did:key:a").await.unwrap());
+        registry.purge_expired().await.unwrap();
+
+        let (changes, latest) = log.since(0, 100);
+        assert_eq!(
+            kinds(&changes.unwrap()),
+            [
+                (1, ChangeKind::Register, "did:key:a"),
+                (2, ChangeKind::Refresh, "did:key:a"),
+                (3, ChangeKind::Register, "did:key:b"),
+                (4, ChangeKind::Deregister, "did:key:a"),
+                (5, ChangeKind::Expire, "did:key:b"),
+            ]
+        );
+        assert_eq!(latest, 5);
+
+        let (page, _) = log.since(2, 2);
+        assert_eq!(page.unwrap().iter().map(|c| c.seq).collect::<Vec<_>>(), [3, 4]);
+    }
+
+    #[tokio::test]
+    async fn evictions_are_recorded() {
+        let log = Arc::new(ChangeLog::default());
+        let registry = RecordingRegistry::new(Arc::new(Registry::new().with_capacity(Some(2))), log.clone());
+
+        registry.register(entry("did:key:a", 3600)).await.unwrap();
+        registry.register(entry("did:key:b", -10)).await.unwrap();
+        registry.register(entry("did:key:c", 3600)).await.unwrap();
+        registry.register(entry("did:key:d", 3600)).await.unwrap();
+
+        let changes = log.since(2, 10).0.unwrap();
+        assert_eq!(
+            kinds(&changes),
+            [
+                (3, ChangeKind::Expire, "did:key:b"),
+                (4, ChangeKind::Register, "did:key:c"),
+                (5, ChangeKind::Deregister, "did:key:a"),
+                (6, ChangeKind::Register, "did:key:d"),
+            ]
+        );
+        assert!(changes.iter().filter(|c| c.kind != ChangeKind::Register).all(|c| c.entry.is_some()));
+    }
+
+    #[tokio::test]
+    async fn removals_carry_the_last_entry() {
+        let log = Arc::new(ChangeLog::default());
+        let registry = RecordingRegistry::new(Arc::new(Registry::new()), log.clone());
+
+        registry.register(entry("did:key:a", 3600)).await.unwrap();
+        registry.register(entry("did:key:b", -10)).await.unwrap();
+        registry.deregister("did:key:a").await.unwrap();
+        registry.purge_expired().await.unwrap();
+
+        let changes = log.since(2, 10).0.unwrap();
+        assert_eq!(kinds(&changes), [(3, ChangeKind::Deregister, "did:key:a"), (4, ChangeKind::Expire, "did:key:b")]);
+        for change in &changes {
+            let last = change.entry.as_ref().expect("removals carry the entry");
+            assert_eq!(last.did, change.did);
+            assert_eq!(last.endpoint, "wss://agent");
+        }
+    }
+
+    #[test]
+    fn change_schema() {
+        let mut last = entry("did:key:a", 0);
+        last.registered_at = 100;
+        last.expires_at = 200;
+        last.last_seen = 150;
+        last.version = 3;
+        let change = Change {
+            seq: 7,
+            kind: ChangeKind::Expire,
+            did: "did:key:a".to_string(),
+            at: 201,
+            entry: Some(last),
+        };
+        assert_eq!(
+            serde_json::to_value(&change).unwrap(),
+            serde_json::json!({
+                "seq": 7,
+                "kind": "expire",
+                "did": "did:key:a",
+                "at": 201,
+                "entry": {
+                    "did": "did:key:a",
+                    "endpoint": "wss://agent",
+                    "registered_at": 100,
+                    "expires_at": 200,
+                    "last_seen": 150,
+                    "version": 3
+                }
+            })
+        );
+
+        let change = Change { entry: None, kind: ChangeKind::Deregister, ..change };
+        assert_eq!(
+            serde_json::to_value(&change).unwrap(),
+            serde_json::json!({"seq": 7, "kind": "deregister", "did": "did:key:a", "at": 201})
+        );
+    }
+
+    #[test]
+    fn compacted_history_needs_a_resync() {
+        let log = ChangeLog::new(2);
+        for did in ["did:key:a", "did:key:b", "did:key:c"] {
+            log.record(ChangeKind::Deregister, did.to_string(), None);
+        }
+
+        assert!(log.since(0, 10).0.is_none(), "change 1 was dropped");
+        assert_eq!(log.since(1, 10).0.unwrap().len(), 2);
+        assert!(log.since(3, 10).0.unwrap().is_empty());
+        assert!(log.since(7, 10).0.is_none(), "since is from before a restart");
+    }
+
+    #[test]
+    fn cursors_from_another_epoch_need_a_resync() {
+        let earlier = ChangeLog::default();
+        let log = ChangeLog::default();
+        assert_ne!(earlier.epoch(), log.epoch());
+        for did in ["did:key:a", "did:key:b"] {
+            log.record(ChangeKind::Deregister, did.to_string(), None);
+        }
+
+        assert_eq!(log.resume(None, 0, 10).0.unwrap().len(), 2);
+        assert_eq!(log.resume(Some(log.epoch()), 1, 10).0.unwrap().len(), 1);
+        let (changes, latest) = log.resume(Some(earlier.epoch()), 1, 10);
+        assert!(changes.is_none());
+        assert_eq!(latest, 2);
+        assert!(log.resume(None, 1, 10).0.is_none(), "a cursor without its epoch");
+    }
+}
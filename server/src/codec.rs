@@ -0,0 +1,213 @@
+//! CBOR and MessagePack bodies for constrained agents
+//!
+//! Routes wrapped in [`negotiate`] accept `Content-Type: application/cbor`
+//! or `application/msgpack` request bodies and answer an `Accept` that
+//! prefers either with that encoding. Handlers only ever see and produce
+//! JSON: bodies are transcoded here through the serde data model, so every
+//! encoding deserializes to the same types. Any other `Accept` gets JSON,
+//! and errors stay JSON so they keep their `request_id`.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ReachError;
+
+/// Media type of CBOR bodies
+pub const CBOR: &str = "application/cbor";
+
+/// Media type of MessagePack bodies
+pub const MSGPACK: &str = "application/msgpack";
+
+/// MessagePack's unregistered media type, still sent by many libraries
+const X_MSGPACK: &str = "application/x-msgpack";
+
+/// Largest body transcoded in either direction
+const MAX_BODY: usize = 1024 * 1024;
+
+/// A body encoding routes can speak besides JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Cbor,
+    MsgPack,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Cbor => "CBOR",
+            Format::MsgPack => "MessagePack",
+        }
+    }
+
+    fn media_type(self) -> &'static str {
+        match self {
+            Format::Cbor => CBOR,
+            Format::MsgPack => MSGPACK,
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        if media_type.eq_ignore_ascii_case(CBOR) {
+            Some(Format::Cbor)
+        } else if media_type.eq_ignore_ascii_case(MSGPACK) || media_type.eq_ignore_ascii_case(X_MSGPACK) {
+            Some(Format::MsgPack)
+        } else {
+            None
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Result<serde_json::Value, String> {
+        match self {
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+            Format::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn encode(self, value: &serde_json::Value) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+                Ok(bytes)
+            }
+            Format::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Decode CBOR and MessagePack request bodies and encode successful
+/// responses in whichever of them the client prefers
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let respond_in = preferred(request.headers());
+    let request = match content_format(request.headers()) {
+        Some(format) => match to_json(request, format).await {
+            Ok(request) => request,
+            Err(e) => return e.into_response(),
+        },
+        None => request,
+    };
+
+    let mut response = next.run(request).await;
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+    let Some(format) = respond_in else {
+        return response;
+    };
+    if !response.status().is_success() || !has_type(response.headers(), "application/json") {
+        return response;
+    }
+    from_json(response, format).await
+}
+
+/// The encoding `Accept` ranks highest if it's at least as high as JSON;
+/// a missing or unrecognised `Accept` means JSON. CBOR wins a tie with
+/// MessagePack.
+fn preferred(headers: &HeaderMap) -> Option<Format> {
+    let (mut cbor, mut msgpack, mut json) = (0.0f32, 0.0f32, 0.0f32);
+    let ranges = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','));
+    for range in ranges {
+        let mut params = range.split(';');
+        let media = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match media.as_str() {
+            "application/json" | "application/*" | "*/*" => json = json.max(q),
+            media => match Format::from_media_type(media) {
+                Some(Format::Cbor) => cbor = cbor.max(q),
+                Some(Format::MsgPack) => msgpack = msgpack.max(q),
+                None => {}
+            },
+        }
+    }
+    let (format, q) = if msgpack > cbor { (Format::MsgPack, msgpack) } else { (Format::Cbor, cbor) };
+    (q > 0.0 && q >= json).then_some(format)
+}
+
+/// The binary encoding named by `Content-Type`, if any
+fn content_format(headers: &HeaderMap) -> Option<Format> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .and_then(|v| Format::from_media_type(v.trim()))
+}
+
+/// Whether the `Content-Type` is `media_type`, ignoring parameters
+fn has_type(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case(media_type))
+}
+
+async fn to_json(request: Request, format: Format) -> Result<Request, ReachError> {
+    let (mut parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_BODY)
+        .await
+        .map_err(|_| ReachError::InvalidRequest(format!("{} body is unreadable or too large", format.name())))?;
+    let value = format
+        .decode(&bytes)
+        .map_err(|e| ReachError::InvalidRequest(format!("Invalid {} body: {}", format.name(), e)))?;
+    let json = serde_json::to_vec(&value).map_err(|e| ReachError::Internal(e.to_string()))?;
+
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Request::from_parts(parts, Body::from(json)))
+}
+
+async fn from_json(response: Response, format: Format) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_BODY).await else {
+        return ReachError::Internal("Unreadable response body".into()).into_response();
+    };
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let encoded = match format.encode(&value) {
+        Ok(encoded) => encoded,
+        Err(e) => return ReachError::Internal(format!("{} encoding failed: {}", format.name(), e)).into_response(),
+    };
+
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.media_type()));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn binary_only_when_preferred() {
+        assert_eq!(preferred(&accept("application/cbor")), Some(Format::Cbor));
+        assert_eq!(preferred(&accept("application/json;q=0.5, application/cbor")), Some(Format::Cbor));
+        assert_eq!(preferred(&accept("Application/CBOR, */*")), Some(Format::Cbor));
+        assert_eq!(preferred(&accept("application/msgpack")), Some(Format::MsgPack));
+        assert_eq!(preferred(&accept("application/x-msgpack, application/json")), Some(Format::MsgPack));
+        assert_eq!(preferred(&accept("application/cbor;q=0.5, application/msgpack")), Some(Format::MsgPack));
+        assert_eq!(preferred(&accept("application/msgpack, application/cbor")), Some(Format::Cbor));
+
+        assert_eq!(preferred(&HeaderMap::new()), None);
+        assert_eq!(preferred(&accept("application/json")), None);
+        assert_eq!(preferred(&accept("application/cbor;q=0.2, application/json")), None);
+        assert_eq!(preferred(&accept("application/msgpack;q=0")), None);
+        assert_eq!(preferred(&accept("text/html, application/x-unknown")), None);
+    }
+}
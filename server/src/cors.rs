@@ -0,0 +1,115 @@
+//! Cross-origin access for browser-based agents and dashboards
+//!
+//! Public read endpoints are open to any origin unless an allowlist is
+//! configured. Authenticated write endpoints only accept cross-origin
+//! requests when explicitly enabled, and the admin API never does. Every
+//! route answers preflight requests, so disallowed origins get a clean
+//! refusal rather than a 405.
+
+use std::time::Duration;
+
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::REQUEST_ID_HEADER;
+
+/// How long browsers may cache a preflight response
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// Which origins may make cross-origin requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Origins {
+    /// Nothing configured: reads from anywhere, no cross-origin writes
+    Default,
+    /// Only the listed origins
+    List(Vec<HeaderValue>),
+    /// Any origin (`*`), for local development
+    Any,
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    origins: Origins,
+    allow_writes: bool,
+}
+
+impl CorsConfig {
+    /// Build from the configured origins (`*` for any) and whether
+    /// cross-origin writes are allowed
+    pub fn new(origins: &[String], allow_writes: bool) -> anyhow::Result<Self> {
+        let origins = if origins.is_empty() {
+            Origins::Default
+        } else if origins.iter().any(|o| o == "*") {
+            tracing::warn!("CORS allows any origin; use an explicit origin list outside local development");
+            Origins::Any
+        } else {
+            let list = origins
+                .iter()
+                .map(|o| {
+                    HeaderValue::from_str(o.trim_end_matches('/'))
+                        .map_err(|_| anyhow::anyhow!("Invalid CORS origin: {}", o))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            Origins::List(list)
+        };
+
+        Ok(Self { origins, allow_writes })
+    }
+
+    /// Layer for the public read endpoints
+    pub fn reads(&self) -> CorsLayer {
+        // Lookups are revalidated with ETags, which browsers hide from
+        // cross-origin scripts unless exposed
+        let layer = base()
+            .allow_methods([Method::GET, Method::HEAD])
+            .allow_headers([header::IF_NONE_MATCH, HeaderName::from_static(REQUEST_ID_HEADER)])
+            .expose_headers([header::ETAG, HeaderName::from_static(REQUEST_ID_HEADER)]);
+        match &self.origins {
+            Origins::Default | Origins::Any => layer.allow_origin(Any),
+            Origins::List(list) => layer.allow_origin(list.clone()),
+        }
+    }
+
+    /// Layer for the handshake, registration and session endpoints
+    pub fn writes(&self) -> CorsLayer {
+        if !self.allow_writes {
+            return closed();
+        }
+
+        let layer = base()
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_MATCH,
+                header::IF_NONE_MATCH,
+                HeaderName::from_static(REQUEST_ID_HEADER),
+                HeaderName::from_static(crate::idempotency::IDEMPOTENCY_KEY_HEADER),
+            ]);
+        match &self.origins {
+            Origins::Default => closed(),
+            Origins::Any => layer.allow_origin(Any),
+            Origins::List(list) => layer.allow_origin(list.clone()),
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: Origins::Default,
+            allow_writes: false,
+        }
+    }
+}
+
+/// Answers preflight requests without allowing any origin
+pub fn closed() -> CorsLayer {
+    CorsLayer::new().allow_origin(AllowOrigin::list([]))
+}
+
+fn base() -> CorsLayer {
+    CorsLayer::new()
+        .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER)])
+        .max_age(PREFLIGHT_MAX_AGE)
+}
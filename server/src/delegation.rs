@@ -0,0 +1,386 @@
+//! Registration on behalf of another DID
+//!
+//! A controller agent authenticates with its own session and registers an
+//! endpoint for a sub-agent. Both keys sign the same canonical message: the
+//! sub-agent consents to the registration, and the controller asserts
+//! authority over the sub-agent. The message is UTF-8, lines joined by `\n`
+//! with no trailing newline:
+//!
+//! ```text
+//! agent-reach-delegation/v1
+//! delegator:<session DID>
+//! subject:<sub-agent DID>
+//! endpoint:<endpoint>
+//! ttl:<ttl>
+//! issued_at:<unix seconds>
+//! ```
+//!
+//! A worker agent can instead hand its controller a standing
+//! [`DelegationCertificate`], signed by the worker alone, which the
+//! controller presents with each `/register` or `/deregister` until it
+//! expires or the worker revokes it. Certificates are valid for at most 30
+//! days. Its message has the same form:
+//!
+//! ```text
+//! agent-reach-delegation-certificate/v1
+//! subject:<worker DID>
+//! delegate:<controller DID>
+//! operations:<comma-separated, e.g. register,deregister>
+//! issued_at:<unix seconds>
+//! expires_at:<unix seconds>
+//! ```
+//!
+//! Signatures are base64 (standard alphabet) Ed25519 signatures by the keys
+//! behind each `did:key`.
+
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use parking_lot::RwLock;
+
+use crate::error::ReachError;
+use crate::types::{DelegatedOperation, DelegationCertificate, RegisterRequest, RegistrationDelegation};
+
+/// First line of every delegation message
+const MESSAGE_VERSION: &str = "agent-reach-delegation/v1";
+
+/// First line of every certificate message
+const CERTIFICATE_VERSION: &str = "agent-reach-delegation-certificate/v1";
+
+/// How far `issued_at` may be from the server clock (seconds)
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Longest a certificate may be valid for (seconds)
+pub const MAX_CERTIFICATE_LIFETIME_SECS: i64 = 30 * 24 * 3600;
+
+/// The canonical message both parties sign
+pub fn message(delegator: &str, subject: &str, endpoint: &str, ttl: u64, issued_at: i64) -> String {
+    format!(
+        "{}\ndelegator:{}\nsubject:{}\nendpoint:{}\nttl:{}\nissued_at:{}",
+        MESSAGE_VERSION, delegator, subject, endpoint, ttl, issued_at
+    )
+}
+
+/// Check a delegation presented by `delegator`'s session, returning the DID
+/// the entry should be stored under
+pub fn verify(
+    delegator: &str,
+    req: &RegisterRequest,
+    delegation: &RegistrationDelegation,
+    now: i64,
+) -> Result<String, ReachError> {
+    if (now - delegation.issued_at).abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(ReachError::InvalidDelegation("issued_at is too far from server time".into()));
+    }
+
+    let message = message(delegator, &delegation.did, &req.endpoint, req.ttl, delegation.issued_at);
+    check_signature(&delegation.did, &message, &delegation.subject_signature)
+        .map_err(|reason| ReachError::InvalidDelegation(format!("subject signature: {}", reason)))?;
+    check_signature(delegator, &message, &delegation.delegator_signature)
+        .map_err(|reason| ReachError::InvalidDelegation(format!("delegator signature: {}", reason)))?;
+
+    Ok(delegation.did.clone())
+}
+
+/// The canonical message a worker signs for a certificate
+pub fn certificate_message(
+    subject: &str,
+    delegate: &str,
+    operations: &[DelegatedOperation],
+    issued_at: i64,
+    expires_at: i64,
+) -> String {
+    let operations: Vec<_> = operations.iter().map(|op| op.as_str()).collect();
+    format!(
+        "{}\nsubject:{}\ndelegate:{}\noperations:{}\nissued_at:{}\nexpires_at:{}",
+        CERTIFICATE_VERSION,
+        subject,
+        delegate,
+        operations.join(","),
+        issued_at,
+        expires_at
+    )
+}
+
+/// Check that `certificate` lets `delegate` perform `operation` for `did`,
+/// given when the subject last revoked the delegate's certificates
+pub fn verify_certificate(
+    delegate: &str,
+    did: &str,
+    operation: DelegatedOperation,
+    certificate: &DelegationCertificate,
+    revoked_at: Option<i64>,
+    now: i64,
+) -> Result<(), ReachError> {
+    if certificate.subject != did {
+        return Err(ReachError::InvalidDelegation("certificate is for another DID".into()));
+    }
+    if certificate.delegate != delegate {
+        return Err(ReachError::InvalidDelegation("certificate names another delegate".into()));
+    }
+    if !certificate.operations.contains(&operation) {
+        return Err(ReachError::InvalidDelegation(format!("certificate does not allow {}", operation.as_str())));
+    }
+    if certificate.issued_at - now > MAX_CLOCK_SKEW_SECS {
+        return Err(ReachError::InvalidDelegation("issued_at is in the future".into()));
+    }
+    if certificate.expires_at - certificate.issued_at > MAX_CERTIFICATE_LIFETIME_SECS {
+        return Err(ReachError::InvalidDelegation("certificate is valid for more than 30 days".into()));
+    }
+    if certificate.expires_at <= now {
+        return Err(ReachError::InvalidDelegation("certificate expired".into()));
+    }
+    if revoked_at.is_some_and(|revoked_at| revokes(revoked_at, certificate)) {
+        return Err(ReachError::InvalidDelegation("certificate revoked".into()));
+    }
+
+    let message = certificate_message(
+        &certificate.subject,
+        &certificate.delegate,
+        &certificate.operations,
+        certificate.issued_at,
+        certificate.expires_at,
+    );
+    check_signature(&certificate.subject, &message, &certificate.signature)
+        .map_err(|reason| ReachError::InvalidDelegation(format!("certificate signature: {}", reason)))
+}
+
+/// Whether a revocation made at `revoked_at` covers `certificate`
+///
+/// A certificate may be dated up to the allowed clock skew ahead, so one
+/// signed before the revocation can carry an `issued_at` after it. Every
+/// `issued_at` up to that far past the revocation is covered; a fresh
+/// certificate has to be dated later.
+fn revokes(revoked_at: i64, certificate: &DelegationCertificate) -> bool {
+    certificate.issued_at <= revoked_at + MAX_CLOCK_SKEW_SECS
+}
+
+/// When a revocation made at `revoked_at` can be forgotten: every
+/// certificate it covers has expired by then
+pub fn revocation_expires_at(revoked_at: i64) -> i64 {
+    revoked_at + MAX_CLOCK_SKEW_SECS + MAX_CERTIFICATE_LIFETIME_SECS
+}
+
+/// Revocations held in memory, for the in-memory registry
+///
+/// Maps (subject, delegate) to when the pair was last revoked and when
+/// that revocation expires.
+#[derive(Default)]
+pub struct Revocations {
+    revoked: RwLock<HashMap<(String, String), (i64, i64)>>,
+}
+
+impl Revocations {
+    /// Deny `delegate` the certificates `subject` issued up to `revoked_at`,
+    /// until `expires_at`
+    pub fn revoke(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) {
+        let mut revoked = self.revoked.write();
+        let (at, until) = revoked.entry((subject.to_string(), delegate.to_string())).or_insert((revoked_at, expires_at));
+        *at = (*at).max(revoked_at);
+        *until = (*until).max(expires_at);
+    }
+
+    /// When `subject` last revoked `delegate`, unless that has expired
+    pub fn revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Option<i64> {
+        self.revoked
+            .read()
+            .get(&(subject.to_string(), delegate.to_string()))
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(revoked_at, _)| *revoked_at)
+    }
+
+    /// Forget revocations that expired by `now`
+    pub fn purge_expired(&self, now: i64) {
+        self.revoked.write().retain(|_, (_, expires_at)| *expires_at > now);
+    }
+}
+
+fn check_signature(did: &str, message: &str, signature: &str) -> Result<(), &'static str> {
+    let did: agent_id::Did = did.parse().map_err(|_| "invalid DID")?;
+    let public_key = did.public_key().map_err(|_| "invalid DID")?;
+    let bytes: [u8; 64] = STANDARD
+        .decode(signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("missing or malformed")?;
+    let signature = ed25519_dalek::Signature::from_bytes(&bytes);
+    agent_id::core::keys::verify(&public_key, message.as_bytes(), &signature).map_err(|_| "does not verify")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use agent_id::RootKey;
+
+    use super::*;
+
+    /// A delegation from `delegator` for `subject` to register `req`
+    pub fn sign(delegator: &RootKey, subject: &RootKey, req: &RegisterRequest) -> RegistrationDelegation {
+        let issued_at = chrono::Utc::now().timestamp();
+        let subject_did = subject.did().to_string();
+        let message = message(&delegator.did().to_string(), &subject_did, &req.endpoint, req.ttl, issued_at);
+        RegistrationDelegation {
+            did: subject_did,
+            issued_at,
+            subject_signature: STANDARD.encode(subject.sign(message.as_bytes()).to_bytes()),
+            delegator_signature: STANDARD.encode(delegator.sign(message.as_bytes()).to_bytes()),
+        }
+    }
+
+    /// A certificate from `subject` letting `delegate` register for it
+    pub fn certify(subject: &RootKey, delegate: &RootKey, issued_at: i64) -> DelegationCertificate {
+        let (subject_did, delegate_did) = (subject.did().to_string(), delegate.did().to_string());
+        let operations = vec![DelegatedOperation::Register, DelegatedOperation::Deregister];
+        let expires_at = issued_at + 3600;
+        let message = certificate_message(&subject_did, &delegate_did, &operations, issued_at, expires_at);
+        DelegationCertificate {
+            subject: subject_did,
+            delegate: delegate_did,
+            operations,
+            issued_at,
+            expires_at,
+            signature: STANDARD.encode(subject.sign(message.as_bytes()).to_bytes()),
+        }
+    }
+
+    fn request() -> RegisterRequest {
+        RegisterRequest {
+            endpoint: "wss://sub-agent:8080".to_string(),
+            ttl: 3600,
+            delegation: None,
+            did: None,
+            certificate: None,
+            handle: None,
+            weight: None,
+            endpoints: Vec::new(),
+            region: None,
+        }
+    }
+
+    #[test]
+    fn valid_delegation_names_the_subject() {
+        let (controller, sub_agent) = (RootKey::generate(), RootKey::generate());
+        let delegation = sign(&controller, &sub_agent, &request());
+        let now = chrono::Utc::now().timestamp();
+
+        let did = verify(&controller.did().to_string(), &request(), &delegation, now).unwrap();
+
+        assert_eq!(did, sub_agent.did().to_string());
+    }
+
+    #[test]
+    fn wrong_delegator_is_rejected() {
+        let (controller, sub_agent, other) = (RootKey::generate(), RootKey::generate(), RootKey::generate());
+        let delegation = sign(&controller, &sub_agent, &request());
+        let now = chrono::Utc::now().timestamp();
+
+        let err = verify(&other.did().to_string(), &request(), &delegation, now).unwrap_err();
+
+        assert!(matches!(err, ReachError::InvalidDelegation(_)));
+    }
+
+    #[test]
+    fn missing_subject_signature_is_rejected() {
+        let (controller, sub_agent) = (RootKey::generate(), RootKey::generate());
+        let mut delegation = sign(&controller, &sub_agent, &request());
+        delegation.subject_signature.clear();
+        let now = chrono::Utc::now().timestamp();
+
+        let err = verify(&controller.did().to_string(), &request(), &delegation, now).unwrap_err();
+
+        assert!(err.to_string().contains("subject signature"), "{}", err);
+    }
+
+    #[test]
+    fn altered_endpoint_is_rejected() {
+        let (controller, sub_agent) = (RootKey::generate(), RootKey::generate());
+        let delegation = sign(&controller, &sub_agent, &request());
+        let mut altered = request();
+        altered.endpoint = "wss://attacker:8080".to_string();
+        let now = chrono::Utc::now().timestamp();
+
+        assert!(verify(&controller.did().to_string(), &altered, &delegation, now).is_err());
+    }
+
+    #[test]
+    fn certificate_allows_its_delegate() {
+        let (controller, worker) = (RootKey::generate(), RootKey::generate());
+        let now = chrono::Utc::now().timestamp();
+        let certificate = certify(&worker, &controller, now);
+        let (controller, worker) = (controller.did().to_string(), worker.did().to_string());
+
+        assert!(verify_certificate(&controller, &worker, DelegatedOperation::Register, &certificate, None, now).is_ok());
+        assert!(verify_certificate(&worker, &worker, DelegatedOperation::Register, &certificate, None, now).is_err());
+        assert!(verify_certificate(&controller, &worker, DelegatedOperation::Register, &certificate, None, now + 3600).is_err());
+    }
+
+    #[test]
+    fn certificate_operations_are_signed() {
+        let (controller, worker) = (RootKey::generate(), RootKey::generate());
+        let now = chrono::Utc::now().timestamp();
+        let mut certificate = certify(&worker, &controller, now);
+        certificate.operations = vec![DelegatedOperation::Register];
+        let (controller, worker) = (controller.did().to_string(), worker.did().to_string());
+
+        let err = verify_certificate(&controller, &worker, DelegatedOperation::Register, &certificate, None, now)
+            .unwrap_err();
+
+        assert!(err.to_string().contains("certificate signature"), "{}", err);
+        certificate.operations.clear();
+        let err = verify_certificate(&controller, &worker, DelegatedOperation::Deregister, &certificate, None, now)
+            .unwrap_err();
+        assert!(err.to_string().contains("does not allow deregister"), "{}", err);
+    }
+
+    #[test]
+    fn revocation_covers_earlier_certificates_only() {
+        let (controller, worker) = (RootKey::generate(), RootKey::generate());
+        let now = chrono::Utc::now().timestamp();
+        let old = certify(&worker, &controller, now - 10);
+        // Dated ahead, as the clock skew allows, but signed before the revocation
+        let ahead = certify(&worker, &controller, now + MAX_CLOCK_SKEW_SECS);
+        let new = certify(&worker, &controller, now + MAX_CLOCK_SKEW_SECS + 1);
+        let (controller, worker) = (controller.did().to_string(), worker.did().to_string());
+
+        for certificate in [&old, &ahead] {
+            let err = verify_certificate(&controller, &worker, DelegatedOperation::Register, certificate, Some(now), now)
+                .unwrap_err();
+            assert!(err.to_string().contains("revoked"), "{}", err);
+        }
+        let later = now + 2;
+        assert!(verify_certificate(&controller, &worker, DelegatedOperation::Register, &new, Some(now), later).is_ok());
+    }
+
+    #[test]
+    fn long_lived_certificates_are_rejected() {
+        let (controller, worker) = (RootKey::generate(), RootKey::generate());
+        let now = chrono::Utc::now().timestamp();
+        let (subject, delegate) = (worker.did().to_string(), controller.did().to_string());
+        let operations = vec![DelegatedOperation::Register];
+        let expires_at = now + MAX_CERTIFICATE_LIFETIME_SECS + 1;
+        let message = certificate_message(&subject, &delegate, &operations, now, expires_at);
+        let certificate = DelegationCertificate {
+            subject: subject.clone(),
+            delegate: delegate.clone(),
+            operations,
+            issued_at: now,
+            expires_at,
+            signature: STANDARD.encode(worker.sign(message.as_bytes()).to_bytes()),
+        };
+
+        let err = verify_certificate(&delegate, &subject, DelegatedOperation::Register, &certificate, None, now).unwrap_err();
+        assert!(err.to_string().contains("more than 30 days"), "{}", err);
+    }
+
+    #[test]
+    fn revocations_expire() {
+        let revocations = Revocations::default();
+        revocations.revoke("did:key:worker", "did:key:controller", 100, 200);
+        revocations.revoke("did:key:worker", "did:key:controller", 50, 150);
+
+        assert_eq!(revocations.revoked_at("did:key:worker", "did:key:controller", 199), Some(100));
+        assert_eq!(revocations.revoked_at("did:key:worker", "did:key:other", 0), None);
+        assert_eq!(revocations.revoked_at("did:key:worker", "did:key:controller", 200), None);
+        revocations.purge_expired(200);
+        assert!(revocations.revoked.read().is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+//! DIDs barred from registering, for good or for a while
+//!
+//! Bans are set with `DELETE /admin/agents/{did}` and held in memory, so
+//! they last until the registry restarts and bind only the server that set
+//! them, not others sharing its storage. A temporary ban needs no one to
+//! lift it: once its time is up the DID can register again.
+
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+
+use crate::error::ReachError;
+
+#[derive(Default)]
+pub struct Denylist {
+    /// DID to the Unix time its ban ends, `None` for a permanent ban
+    banned: RwLock<HashMap<String, Option<i64>>>,
+}
+
+impl Denylist {
+    /// Bar `did` from registering until `until`, or for good with `None`
+    pub fn ban(&self, did: &str, until: Option<i64>) {
+        self.banned.write().insert(did.to_string(), until);
+    }
+
+    /// Lift `did`'s ban, saying whether it had one
+    pub fn lift(&self, did: &str) -> bool {
+        self.banned.write().remove(did).is_some()
+    }
+
+    /// Refuse `did` while it's banned, forgetting bans that have run out
+    pub fn check(&self, did: &str, now: i64) -> Result<(), ReachError> {
+        let Some(&until) = self.banned.read().get(did) else {
+            return Ok(());
+        };
+        match until {
+            None => Err(ReachError::Forbidden(None)),
+            Some(until) if until > now => Err(ReachError::Forbidden(Some((until - now) as u64))),
+            Some(_) => {
+                let mut banned = self.banned.write();
+                // Unless it was banned again meanwhile
+                if banned.get(did).is_some_and(|until| until.is_some_and(|until| until <= now)) {
+                    banned.remove(did);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temporary_bans_run_out() {
+        let denylist = Denylist::default();
+        denylist.ban("did:key:a", Some(1_000));
+
+        assert!(matches!(denylist.check("did:key:a", 900), Err(ReachError::Forbidden(Some(100)))));
+        assert!(denylist.check("did:key:b", 900).is_ok());
+
+        assert!(denylist.check("did:key:a", 1_000).is_ok());
+        assert!(denylist.banned.read().is_empty(), "expired bans are forgotten");
+    }
+
+    #[test]
+    fn permanent_bans_last_until_lifted() {
+        let denylist = Denylist::default();
+        denylist.ban("did:key:a", None);
+
+        assert!(matches!(denylist.check("did:key:a", i64::MAX), Err(ReachError::Forbidden(None))));
+        assert!(denylist.lift("did:key:a"));
+        assert!(!denylist.lift("did:key:a"));
+        assert!(denylist.check("did:key:a", 0).is_ok());
+    }
+}
@@ -0,0 +1,85 @@
+//! DID parsing
+//!
+//! Agents authenticate as `did:key` DIDs, or as `did:web` DIDs when a
+//! resolver is configured. Errors tell a client whether the DID's method
+//! isn't one this registry supports or the DID itself is malformed, and name
+//! the method either way.
+
+use crate::error::ReachError;
+
+/// A DID an agent can authenticate as
+#[derive(Debug)]
+pub enum AgentDid {
+    Key(agent_id::Did),
+    /// Keys come from the DID document, fetched during the handshake
+    Web,
+}
+
+/// Split a DID into its method and method-specific id, checking only the
+/// generic `did:<method>:<id>` syntax
+pub fn split(did: &str) -> Result<(&str, &str), ReachError> {
+    let rest = did
+        .strip_prefix("did:")
+        .ok_or_else(|| ReachError::InvalidDid("expected did:<method>:<id>".into()))?;
+    let (method, id) = rest
+        .split_once(':')
+        .ok_or_else(|| ReachError::InvalidDid("expected did:<method>:<id>".into()))?;
+    if method.is_empty() || !method.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()) {
+        return Err(ReachError::InvalidDid(format!(
+            "method {:?} must be lowercase letters and digits",
+            method
+        )));
+    }
+    if id.is_empty() {
+        return Err(ReachError::InvalidDid(format!("did:{} DID has no method-specific id", method)));
+    }
+    Ok((method, id))
+}
+
+/// Parse a DID an agent wants to authenticate as; `did:web` is only
+/// supported when `did_web` is set
+pub fn parse(did: &str, did_web: bool) -> Result<AgentDid, ReachError> {
+    match split(did)? {
+        ("key", _) => did
+            .parse()
+            .map(AgentDid::Key)
+            .map_err(|_| ReachError::InvalidDid("malformed did:key DID".into())),
+        ("web", _) if did_web => Ok(AgentDid::Web),
+        (method, _) => Err(ReachError::UnsupportedDidMethod(method.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_dids_parse() {
+        let did = agent_id::RootKey::generate().did().to_string();
+        assert!(matches!(parse(&did, false), Ok(AgentDid::Key(_))));
+    }
+
+    #[test]
+    fn unknown_methods_are_named() {
+        let err = parse("did:foo:abc", true).unwrap_err();
+        assert!(matches!(&err, ReachError::UnsupportedDidMethod(method) if method == "foo"));
+        assert!(err.to_string().contains("foo"), "{}", err);
+
+        // did:web without a resolver is as unsupported as any other method
+        assert!(matches!(parse("did:web:example.com", false), Err(ReachError::UnsupportedDidMethod(_))));
+        assert!(matches!(parse("did:web:example.com", true), Ok(AgentDid::Web)));
+    }
+
+    #[test]
+    fn malformed_dids_are_not_unsupported() {
+        let did = agent_id::RootKey::generate().did().to_string();
+        let truncated = &did[..did.len() / 2];
+        let err = parse(truncated, false).unwrap_err();
+        assert!(matches!(err, ReachError::InvalidDid(_)));
+        assert!(err.to_string().contains("did:key"), "{}", err);
+
+        for bad in ["did:key:", "did:key", "key:z6Mk", "did:Key:z6Mk", "did::z6Mk"] {
+            assert!(matches!(parse(bad, false), Err(ReachError::InvalidDid(_))), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! DID documents for registered agents
+//!
+//! `GET /did/:did` renders a registration as a minimal DID document, for
+//! tooling that resolves DIDs rather than reading the lookup JSON: the
+//! did:key's Ed25519 public key as the verification method and one service
+//! per registered endpoint, typed by its URI scheme.
+
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::error::ReachError;
+use crate::handlers::{live_entry, AppState};
+use crate::types::RegistryEntry;
+
+/// Media type of a DID document in plain JSON
+pub const DID_JSON: &str = "application/did+json";
+
+/// Minimal DID document for a registered agent
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    #[schema(example = "did:key:z6Mk...")]
+    pub id: String,
+    /// The did:key's public key; empty for other DID methods
+    pub verification_method: Vec<VerificationMethod>,
+    pub authentication: Vec<String>,
+    pub assertion_method: Vec<String>,
+    /// Registered endpoints, `endpoint` first
+    pub service: Vec<Service>,
+}
+
+/// Public key that signs for the DID
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMethod {
+    #[schema(example = "did:key:z6Mk...#root")]
+    pub id: String,
+    #[serde(rename = "type")]
+    #[schema(example = "Ed25519VerificationKey2020")]
+    pub type_: String,
+    pub controller: String,
+    pub public_key_multibase: String,
+}
+
+/// One registered endpoint
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Service {
+    #[schema(example = "did:key:z6Mk...#endpoint-1")]
+    pub id: String,
+    /// `WebSocketEndpoint`, `HttpEndpoint` or `AgentEndpoint`, by URI scheme
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub service_endpoint: String,
+}
+
+impl From<RegistryEntry> for DidDocument {
+    fn from(entry: RegistryEntry) -> Self {
+        let did = entry.did;
+        let key_id = format!("{}#root", did);
+        let verification_method: Vec<_> = did
+            .parse::<agent_id::Did>()
+            .ok()
+            .map(|parsed| VerificationMethod {
+                id: key_id.clone(),
+                type_: "Ed25519VerificationKey2020".to_string(),
+                controller: did.clone(),
+                public_key_multibase: parsed.key_id(),
+            })
+            .into_iter()
+            .collect();
+        let key_refs: Vec<String> = verification_method.iter().map(|method| method.id.clone()).collect();
+
+        let uris: Vec<String> = if entry.endpoints.is_empty() {
+            vec![entry.endpoint]
+        } else {
+            entry.endpoints.into_iter().map(|endpoint| endpoint.uri).collect()
+        };
+        let service = uris
+            .into_iter()
+            .enumerate()
+            .map(|(i, uri)| Service {
+                id: format!("{}#endpoint-{}", did, i + 1),
+                type_: service_type(&uri).to_string(),
+                service_endpoint: uri,
+            })
+            .collect();
+
+        Self {
+            context: vec![
+                "https://www.w3.org/ns/did/v1".to_string(),
+                "https://w3id.org/security/suites/ed25519-2020/v1".to_string(),
+            ],
+            id: did,
+            verification_method,
+            authentication: key_refs.clone(),
+            assertion_method: key_refs,
+            service,
+        }
+    }
+}
+
+/// Service type for an endpoint URI
+fn service_type(uri: &str) -> &'static str {
+    let scheme = uri.split_once("://").map_or("", |(scheme, _)| scheme);
+    match scheme.to_ascii_lowercase().as_str() {
+        "ws" | "wss" => "WebSocketEndpoint",
+        "http" | "https" => "HttpEndpoint",
+        _ => "AgentEndpoint",
+    }
+}
+
+/// GET /did/:did
+///
+/// The agent's registration as a DID document. No authentication required.
+#[utoipa::path(
+    get,
+    path = "/did/{did}",
+    tag = "lookup",
+    params(("did" = String, Path, description = "DID to resolve (URL-encoded)")),
+    responses(
+        (status = 200, description = "DID document", body = DidDocument, content_type = "application/did+json"),
+        (status = 404, description = "Agent not registered", body = crate::openapi::ErrorResponse),
+        (status = 410, description = "Registration expired", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn did_document(State(state): State<AppState>, Path(did): Path<String>) -> Result<Response, ReachError> {
+    let entry = live_entry(&state, did).await?;
+    Ok(([(header::CONTENT_TYPE, DID_JSON)], Json(DidDocument::from(entry))).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use agent_id::RootKey;
+    use axum::body::to_bytes;
+
+    use super::*;
+    use crate::types::Endpoint;
+
+    fn state() -> AppState {
+        AppState::for_tests()
+    }
+
+    fn endpoint(uri: &str) -> Endpoint {
+        Endpoint { uri: uri.to_string(), weight: None, region: None, registered_at: None }
+    }
+
+    #[tokio::test]
+    async fn registered_did_renders_as_a_document() {
+        let state = state();
+        let key = RootKey::generate();
+        let did = key.did().to_string();
+        state
+            .registry
+            .register(RegistryEntry {
+                endpoints: vec![endpoint("wss://agent.example"), endpoint("https://agent.example/inbox")],
+                ..RegistryEntry::for_test(&did, "wss://agent.example", 3600)
+            })
+            .await
+            .unwrap();
+
+        let response = did_document(State(state), Path(did.clone())).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], DID_JSON);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(document["id"], did.as_str());
+        assert_eq!(document["verificationMethod"][0]["publicKeyMultibase"], key.did().key_id());
+        assert_eq!(document["authentication"][0], format!("{}#root", did));
+        assert_eq!(document["service"][0]["type"], "WebSocketEndpoint");
+        assert_eq!(document["service"][1]["type"], "HttpEndpoint");
+        assert_eq!(document["service"][1]["serviceEndpoint"], "https://agent.example/inbox");
+    }
+
+    #[tokio::test]
+    async fn unknown_did_is_not_found() {
+        let err = did_document(State(state()), Path("did:key:z6Mkabsent".to_string())).await.unwrap_err();
+        assert!(matches!(err, ReachError::NotFound));
+    }
+}
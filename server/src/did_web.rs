@@ -0,0 +1,308 @@
+//! did:web identities
+//!
+//! With `--did-web`, agents can run the handshake as a `did:web` DID. Its
+//! document is fetched over HTTPS when the agent says hello, and the proof
+//! must be signed by one of the Ed25519 keys the document lists under
+//! `authentication`. Keys are cached for five minutes. Plain http is never
+//! used, redirects aren't followed and hosts that resolve to loopback,
+//! private or link-local addresses are refused, so a DID can't point the
+//! registry at its own network.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::VerifyingKey;
+use parking_lot::RwLock;
+use reqwest::{header, redirect, Url};
+use serde::Deserialize;
+
+use crate::backend::DidResolver;
+use crate::did_document::DID_JSON;
+use crate::error::ReachError;
+
+/// How long resolved keys are reused
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Most DIDs kept in the cache
+const MAX_CACHED: usize = 10_000;
+
+/// Largest DID document accepted
+const MAX_DOCUMENT_BYTES: usize = 64 * 1024;
+
+/// Fetches did:web documents over HTTPS
+pub struct Resolver {
+    timeout: Duration,
+    /// Keys by DID, with when they were fetched
+    cache: RwLock<HashMap<String, (Instant, Vec<VerifyingKey>)>>,
+}
+
+impl Resolver {
+    /// Resolve DIDs, waiting up to `timeout` for each document
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            cache: RwLock::default(),
+        }
+    }
+
+    fn cached(&self, did: &str, now: Instant) -> Option<Vec<VerifyingKey>> {
+        let cache = self.cache.read();
+        cache
+            .get(did)
+            .filter(|(fetched, _)| now.duration_since(*fetched) < CACHE_TTL)
+            .map(|(_, keys)| keys.clone())
+    }
+
+    fn remember(&self, did: &str, keys: &[VerifyingKey], now: Instant) {
+        let mut cache = self.cache.write();
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, (fetched, _)| now.duration_since(*fetched) < CACHE_TTL);
+            if cache.len() >= MAX_CACHED {
+                cache.clear();
+            }
+        }
+        cache.insert(did.to_string(), (now, keys.to_vec()));
+    }
+
+    async fn fetch(&self, did: &str) -> Result<Vec<VerifyingKey>, String> {
+        let url = document_url(did)?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("{} has no host", did))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addr = resolve(&host, port, self.timeout).await?;
+
+        // Pin the checked address so the request can't be re-resolved elsewhere
+        let client = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .redirect(redirect::Policy::none())
+            .https_only(true)
+            .resolve(&host, addr)
+            .user_agent(concat!("agent-reach-server/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+        let mut response = client
+            .get(url.clone())
+            .header(header::ACCEPT, format!("{}, application/json", DID_JSON))
+            .send()
+            .await
+            .map_err(|e| format!("Fetching {} failed: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("{} answered HTTP {}", url, response.status()));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| format!("Reading {} failed: {}", url, e))? {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_DOCUMENT_BYTES {
+                return Err(format!("{} is larger than {} bytes", url, MAX_DOCUMENT_BYTES));
+            }
+        }
+        let document: Document =
+            serde_json::from_slice(&body).map_err(|e| format!("{} is not a DID document: {}", url, e))?;
+        document.authentication_keys(did)
+    }
+}
+
+#[async_trait]
+impl DidResolver for Resolver {
+    async fn keys(&self, did: &str) -> Result<Vec<VerifyingKey>, ReachError> {
+        if let Some(keys) = self.cached(did, Instant::now()) {
+            return Ok(keys);
+        }
+        let keys = self.fetch(did).await.map_err(ReachError::DidResolution)?;
+        self.remember(did, &keys, Instant::now());
+        Ok(keys)
+    }
+}
+
+/// Where a did:web DID's document lives: `did:web:example.com` is
+/// `https://example.com/.well-known/did.json`, and each further
+/// colon-separated segment is a path segment. A port is written `%3A`.
+pub fn document_url(did: &str) -> Result<Url, String> {
+    let invalid = || format!("{} is not a valid did:web DID", did);
+    let id = did.strip_prefix("did:web:").ok_or_else(invalid)?;
+    let mut segments = id
+        .split(':')
+        .map(|segment| urlencoding::decode(segment).map(|s| s.into_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+
+    let host = segments.remove(0);
+    if host.is_empty() || host.contains(['/', '\\', '@', '?', '#']) {
+        return Err(invalid());
+    }
+    if segments
+        .iter()
+        .any(|s| s.is_empty() || s == "." || s == ".." || s.contains(['/', '\\', '?', '#']))
+    {
+        return Err(invalid());
+    }
+    let path = if segments.is_empty() {
+        ".well-known".to_string()
+    } else {
+        segments.join("/")
+    };
+    Url::parse(&format!("https://{}/{}/did.json", host, path)).map_err(|_| invalid())
+}
+
+/// Resolve a host, refusing it if any address isn't public
+async fn resolve(host: &str, port: u16, timeout: Duration) -> Result<SocketAddr, String> {
+    let addrs: Vec<SocketAddr> = tokio::time::timeout(timeout, tokio::net::lookup_host((host, port)))
+        .await
+        .map_err(|_| format!("Timed out resolving {}", host))?
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .collect();
+
+    if let Some(addr) = addrs.iter().find(|addr| agent_reach_addr::is_private(addr.ip())) {
+        return Err(format!("Refusing to fetch from private or loopback address {}", addr.ip()));
+    }
+    addrs
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("No addresses found for {}", host))
+}
+
+/// The parts of a DID document used for authentication
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    id: String,
+    #[serde(default)]
+    verification_method: Vec<Method>,
+    #[serde(default)]
+    authentication: Vec<MethodRef>,
+}
+
+/// An `authentication` entry: a verification method's ID or the method itself
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MethodRef {
+    Id(String),
+    Embedded(Method),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Method {
+    id: String,
+    public_key_multibase: Option<String>,
+    public_key_jwk: Option<Jwk>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Jwk {
+    kty: String,
+    crv: String,
+    x: String,
+}
+
+impl Document {
+    /// Ed25519 keys of the methods listed under `authentication`
+    fn authentication_keys(&self, did: &str) -> Result<Vec<VerifyingKey>, String> {
+        if self.id != did {
+            return Err(format!("Document is for {}, not {}", self.id, did));
+        }
+        // Method IDs may be relative to the DID
+        let absolute = |id: &str| if id.starts_with('#') { format!("{}{}", did, id) } else { id.to_string() };
+
+        let keys: Vec<VerifyingKey> = self
+            .authentication
+            .iter()
+            .filter_map(|method| match method {
+                MethodRef::Embedded(method) => Some(method),
+                MethodRef::Id(id) => {
+                    let id = absolute(id);
+                    self.verification_method.iter().find(|method| absolute(&method.id) == id)
+                }
+            })
+            .filter_map(Method::ed25519_key)
+            .collect();
+        if keys.is_empty() {
+            return Err(format!("Document for {} has no Ed25519 authentication key", did));
+        }
+        Ok(keys)
+    }
+}
+
+impl Method {
+    fn ed25519_key(&self) -> Option<VerifyingKey> {
+        if let Some(multibase) = &self.public_key_multibase {
+            // An Ed25519 multikey is encoded exactly like a did:key
+            let did: agent_id::Did = format!("did:key:{}", multibase).parse().ok()?;
+            return did.public_key().ok();
+        }
+        let jwk = self.public_key_jwk.as_ref()?;
+        if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+            return None;
+        }
+        let bytes: [u8; 32] = URL_SAFE_NO_PAD.decode(&jwk.x).ok()?.try_into().ok()?;
+        VerifyingKey::from_bytes(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use agent_id::RootKey;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn dids_map_to_https_urls() {
+        let url = |did| document_url(did).map(|url| url.to_string());
+        assert_eq!(url("did:web:example.com").unwrap(), "https://example.com/.well-known/did.json");
+        assert_eq!(url("did:web:example.com:agents:alice").unwrap(), "https://example.com/agents/alice/did.json");
+        assert_eq!(url("did:web:example.com%3A8443").unwrap(), "https://example.com:8443/.well-known/did.json");
+
+        for bad in ["did:key:z6Mk", "did:web:", "did:web:user@example.com", "did:web:example.com:..", "did:web:a::b"] {
+            assert!(url(bad).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn private_hosts_are_refused() {
+        let resolver = Resolver::new(Duration::from_secs(2));
+        for did in ["did:web:localhost", "did:web:127.0.0.1%3A8443", "did:web:10.1.2.3", "did:web:169.254.169.254"] {
+            let err = resolver.keys(did).await.unwrap_err();
+            assert!(matches!(&err, ReachError::DidResolution(m) if m.contains("Refusing")), "{}: {}", did, err);
+        }
+    }
+
+    #[test]
+    fn authentication_keys_are_extracted() {
+        let did = "did:web:example.com";
+        let multikey = RootKey::generate();
+        let jwk_key = RootKey::generate();
+        let unlisted = RootKey::generate();
+        let document: Document = serde_json::from_value(json!({
+            "id": did,
+            "verificationMethod": [
+                { "id": "#multikey", "type": "Multikey", "publicKeyMultibase": multikey.did().key_id() },
+                { "id": "did:web:example.com#unlisted", "type": "Multikey", "publicKeyMultibase": unlisted.did().key_id() },
+                { "id": "did:web:example.com#rsa", "type": "JsonWebKey2020", "publicKeyJwk": { "kty": "RSA", "n": "AQAB", "e": "AQAB" } },
+            ],
+            "authentication": [
+                "did:web:example.com#multikey",
+                "#rsa",
+                {
+                    "id": "#jwk",
+                    "type": "JsonWebKey2020",
+                    "publicKeyJwk": { "kty": "OKP", "crv": "Ed25519", "x": URL_SAFE_NO_PAD.encode(jwk_key.verifying_key().as_bytes()) },
+                },
+            ],
+        }))
+        .unwrap();
+
+        let keys = document.authentication_keys(did).unwrap();
+        assert_eq!(keys, vec![multikey.verifying_key(), jwk_key.verifying_key()]);
+        assert!(document.authentication_keys("did:web:other.example").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Agents reachable at several endpoints
+//!
+//! A registration can list further endpoints alongside `endpoint`, each with
+//! a weight and optionally the region it serves. Lookups return them all, or
+//! a single one: with `?region=` the endpoint serving that region (any
+//! endpoint if none does), and with `?pick=weighted` one chosen at random in
+//! proportion to its weight, so clients share load without implementing the
+//! selection themselves.
+
+use rand::Rng;
+
+use crate::error::ReachError;
+use crate::types::{Endpoint, LookupResponse, RegisterRequest};
+
+/// Most endpoints one registration may carry, `endpoint` included
+pub const MAX_ENDPOINTS: usize = 16;
+
+/// Weight of an endpoint registered without one
+const DEFAULT_WEIGHT: u32 = 1;
+
+/// Longest accepted region label
+const MAX_REGION_LEN: usize = 64;
+
+/// Every endpoint a registration asks for, `endpoint` first, or none if it
+/// only has a plain `endpoint`
+///
+/// Regions must be listed in `regions`, unless it is empty.
+pub fn from_request(req: &RegisterRequest, regions: &[String]) -> Result<Vec<Endpoint>, ReachError> {
+    if req.endpoints.is_empty() && req.weight.is_none() && req.region.is_none() {
+        return Ok(Vec::new());
+    }
+    if req.delegation.is_some() && !req.endpoints.is_empty() {
+        // The delegation signatures only cover `endpoint`
+        return Err(ReachError::InvalidRequest(
+            "endpoints can't be combined with a delegation".to_string(),
+        ));
+    }
+    if req.endpoints.len() + 1 > MAX_ENDPOINTS {
+        return Err(ReachError::InvalidRequest(format!(
+            "at most {} endpoints can be registered",
+            MAX_ENDPOINTS
+        )));
+    }
+
+    let primary = Endpoint {
+        uri: req.endpoint.clone(),
+        weight: req.weight,
+        region: req.region.clone(),
+        registered_at: None,
+    };
+    let mut endpoints: Vec<Endpoint> = std::iter::once(primary).chain(req.endpoints.iter().cloned()).collect();
+    if endpoints.iter().any(|e| e.weight == Some(0)) {
+        return Err(ReachError::InvalidRequest("endpoint weights must be at least 1".to_string()));
+    }
+    for endpoint in &mut endpoints {
+        if let Some(region) = endpoint.region.take() {
+            endpoint.region = Some(check_region(&region, regions)?);
+        }
+    }
+    Ok(endpoints)
+}
+
+/// Validate a region label against the allowlist, returning it trimmed
+fn check_region(region: &str, regions: &[String]) -> Result<String, ReachError> {
+    let region = region.trim();
+    let well_formed = !region.is_empty()
+        && region.len() <= MAX_REGION_LEN
+        && region.chars().all(|c| c.is_ascii_alphanumeric() || "-_".contains(c));
+    if !well_formed {
+        return Err(ReachError::InvalidRequest(format!("Invalid region {:?}", region)));
+    }
+    if !regions.is_empty() && !regions.iter().any(|r| r.eq_ignore_ascii_case(region)) {
+        return Err(ReachError::InvalidRequest(format!(
+            "Unknown region {:?}; expected one of {}",
+            region,
+            regions.join(", ")
+        )));
+    }
+    Ok(region.to_string())
+}
+
+/// Date each endpoint: one the agent's live `previous` registration
+/// already listed keeps its date, the rest joined `now`
+pub fn stamp(endpoints: &mut [Endpoint], previous: Option<&[Endpoint]>, now: i64) {
+    for endpoint in endpoints {
+        endpoint.registered_at = previous
+            .and_then(|previous| previous.iter().find(|p| p.uri == endpoint.uri))
+            .and_then(|p| p.registered_at)
+            .or(Some(now));
+    }
+}
+
+/// Order endpoints most recently added first, keeping registration order
+/// among those added together, and make the first one `endpoint`
+pub fn sort_by_freshness(response: &mut LookupResponse) {
+    response.endpoints.sort_by_key(|e| std::cmp::Reverse(e.registered_at.unwrap_or(0)));
+    if let Some(first) = response.endpoints.first() {
+        response.endpoint = first.uri.clone();
+    }
+}
+
+/// Narrow a lookup to a single endpoint: one serving `region` if any does,
+/// otherwise any endpoint, chosen by weight when `weighted` and first
+/// otherwise
+pub fn select(response: &mut LookupResponse, region: Option<&str>, weighted: bool) {
+    let endpoints = std::mem::take(&mut response.endpoints);
+    let in_region: Vec<&Endpoint> = match region {
+        Some(region) => endpoints
+            .iter()
+            .filter(|e| e.region.as_deref().is_some_and(|r| r.eq_ignore_ascii_case(region)))
+            .collect(),
+        None => Vec::new(),
+    };
+    let candidates = if in_region.is_empty() {
+        endpoints.iter().collect()
+    } else {
+        in_region
+    };
+
+    let chosen = if weighted {
+        pick_weighted(&candidates)
+    } else {
+        candidates.first().copied()
+    };
+    if let Some(chosen) = chosen {
+        response.endpoint = chosen.uri.clone();
+        response.region = chosen.region.clone();
+    }
+}
+
+/// Choose one endpoint at random, in proportion to the weights
+pub fn pick_weighted<'a>(endpoints: &[&'a Endpoint]) -> Option<&'a Endpoint> {
+    let weight = |e: &Endpoint| u64::from(e.weight.unwrap_or(DEFAULT_WEIGHT));
+    let total: u64 = endpoints.iter().map(|e| weight(e)).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut roll = rand::thread_rng().gen_range(0..total);
+    endpoints.iter().copied().find(|e| match roll.checked_sub(weight(e)) {
+        Some(rest) => {
+            roll = rest;
+            false
+        }
+        None => true,
+    })
+}
+
+/// Encode an endpoint list for a storage column or field, `None` when empty
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "redis"))]
+pub fn to_json(endpoints: &[Endpoint]) -> Result<Option<String>, ReachError> {
+    if endpoints.is_empty() {
+        return Ok(None);
+    }
+    serde_json::to_string(endpoints)
+        .map(Some)
+        .map_err(|e| ReachError::Internal(e.to_string()))
+}
+
+/// Decode an endpoint list written by [`to_json`]
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "redis"))]
+pub fn from_json(json: Option<&str>) -> Result<Vec<Endpoint>, ReachError> {
+    json.map(serde_json::from_str)
+        .transpose()
+        .map(Option::unwrap_or_default)
+        .map_err(|e| ReachError::Internal(format!("Invalid stored endpoints: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(uri: &str, weight: Option<u32>) -> Endpoint {
+        Endpoint {
+            uri: uri.to_string(),
+            weight,
+            region: None,
+            registered_at: None,
+        }
+    }
+
+    fn in_region(uri: &str, region: &str) -> Endpoint {
+        Endpoint {
+            region: Some(region.to_string()),
+            ..endpoint(uri, None)
+        }
+    }
+
+    fn request(endpoints: Vec<Endpoint>) -> RegisterRequest {
+        serde_json::from_value(serde_json::json!({
+            "endpoint": "wss://primary",
+            "weight": 2,
+            "endpoints": endpoints,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn request_lists_primary_first() {
+        let endpoints = from_request(&request(vec![endpoint("wss://second", None)]), &[]).unwrap();
+        assert_eq!(endpoints, [endpoint("wss://primary", Some(2)), endpoint("wss://second", None)]);
+
+        let plain: RegisterRequest = serde_json::from_value(serde_json::json!({"endpoint": "wss://primary"})).unwrap();
+        assert!(from_request(&plain, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_endpoint_lists_are_rejected() {
+        let zero = request(vec![endpoint("wss://second", Some(0))]);
+        assert!(matches!(from_request(&zero, &[]), Err(ReachError::InvalidRequest(_))));
+
+        let many = request((0..MAX_ENDPOINTS).map(|i| endpoint(&format!("wss://{}", i), None)).collect());
+        assert!(matches!(from_request(&many, &[]), Err(ReachError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn regions_are_checked_against_the_allowlist() {
+        let allowed = ["us-east".to_string(), "eu-west".to_string()];
+
+        let known = request(vec![in_region("wss://second", " eu-west ")]);
+        assert_eq!(from_request(&known, &allowed).unwrap()[1], in_region("wss://second", "eu-west"));
+
+        let unknown = request(vec![in_region("wss://second", "ap-south")]);
+        assert!(matches!(from_request(&unknown, &allowed), Err(ReachError::InvalidRequest(_))));
+        assert!(from_request(&unknown, &[]).is_ok());
+
+        let malformed = request(vec![in_region("wss://second", "eu west")]);
+        assert!(matches!(from_request(&malformed, &[]), Err(ReachError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn weighted_picks_follow_weights() {
+        let endpoints = [endpoint("wss://light", Some(1)), endpoint("wss://heavy", Some(3))];
+        let endpoints: Vec<&Endpoint> = endpoints.iter().collect();
+
+        let picks = 20_000;
+        let heavy = (0..picks)
+            .filter(|_| pick_weighted(&endpoints).unwrap().uri == "wss://heavy")
+            .count();
+
+        // Expect 75%; the bounds are over 10 standard deviations wide
+        let share = heavy as f64 / picks as f64;
+        assert!((0.72..0.78).contains(&share), "heavy endpoint got {:.3} of picks", share);
+    }
+
+    #[test]
+    fn unweighted_endpoints_count_once() {
+        let only = endpoint("wss://only", None);
+        assert_eq!(pick_weighted(&[&only]).unwrap().uri, "wss://only");
+        assert!(pick_weighted(&[]).is_none());
+    }
+
+    #[test]
+    fn stamps_keep_the_date_an_endpoint_joined() {
+        let mut previous = vec![endpoint("wss://one", None)];
+        stamp(&mut previous, None, 100);
+        assert_eq!(previous[0].registered_at, Some(100));
+
+        let mut current = vec![endpoint("wss://one", None), endpoint("wss://two", None)];
+        stamp(&mut current, Some(&previous), 200);
+        assert_eq!(current.iter().map(|e| e.registered_at).collect::<Vec<_>>(), vec![Some(100), Some(200)]);
+    }
+}
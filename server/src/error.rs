@@ -1,14 +1,25 @@
 use axum::{
-    http::StatusCode,
+    body::Body,
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
 
+use crate::REQUEST_ID_HEADER;
+
+/// Largest error body rewritten by [`attach_request_id`]
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ReachError {
-    #[error("Invalid DID format")]
-    InvalidDid,
+    #[error("Invalid DID: {0}")]
+    InvalidDid(String),
+
+    #[error("Unsupported DID method: did:{0}")]
+    UnsupportedDidMethod(String),
 
     #[error("Invalid signature")]
     InvalidSignature,
@@ -19,8 +30,31 @@ pub enum ReachError {
     #[error("Agent not found")]
     NotFound,
 
+    #[error("Session not found")]
+    SessionNotFound,
+
+    /// The DID registered once, but its registration's TTL has passed; holds
+    /// the registration's `expires_at`
     #[error("Registration expired")]
-    Expired,
+    Expired(i64),
+
+    #[error("Agent already has a live registration")]
+    Conflict,
+
+    #[error("Handle is registered to another DID")]
+    HandleTaken,
+
+    #[error("Endpoint is registered to another DID")]
+    EndpointTaken,
+
+    #[error("Registration version does not match If-Match")]
+    VersionMismatch,
+
+    #[error("A request with this Idempotency-Key is still in progress")]
+    IdempotencyInProgress,
+
+    #[error("Idempotency-Key was already used for a different request")]
+    IdempotencyKeyReused,
 
     #[error("Unauthorized - valid session required")]
     Unauthorized,
@@ -28,9 +62,45 @@ pub enum ReachError {
     #[error("Session expired")]
     SessionExpired,
 
+    #[error("Forbidden - valid admin token required")]
+    AdminForbidden,
+
+    #[error("Session scope does not allow this request")]
+    InsufficientScope,
+
+    #[error("No protocol in common; the registry speaks {}", .0.join(", "))]
+    UnsupportedProtocol(Vec<String>),
+
+    #[error("Registration needs a session that agreed on one of {}", .0.join(", "))]
+    ProtocolNotAllowed(Vec<String>),
+
+    #[error("DID is banned from registering{}", .0.map(|secs| format!(" for another {} seconds", secs)).unwrap_or_default())]
+    Forbidden(Option<u64>),
+
+    #[error("Invalid delegation: {0}")]
+    InvalidDelegation(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Request body exceeds {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("Registry is full; try again later")]
+    RegistryFull,
+
+    #[error("Too many open watches; try again later")]
+    TooManyWatchers,
+
+    #[error("Too many failed proofs; retry in {0} seconds")]
+    TooManyFailedProofs(u64),
+
     #[error("Handshake error: {0}")]
     HandshakeError(String),
 
+    #[error("DID resolution failed: {0}")]
+    DidResolution(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
@@ -38,21 +108,215 @@ pub enum ReachError {
 impl IntoResponse for ReachError {
     fn into_response(self) -> Response {
         let (status, message) = match &self {
-            ReachError::InvalidDid => (StatusCode::BAD_REQUEST, self.to_string()),
+            ReachError::InvalidDid(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            ReachError::UnsupportedDidMethod(_) => (StatusCode::BAD_REQUEST, self.to_string()),
             ReachError::InvalidSignature => (StatusCode::UNAUTHORIZED, self.to_string()),
             ReachError::InvalidChallenge => (StatusCode::BAD_REQUEST, self.to_string()),
             ReachError::NotFound => (StatusCode::NOT_FOUND, self.to_string()),
-            ReachError::Expired => (StatusCode::GONE, self.to_string()),
+            ReachError::SessionNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            ReachError::Expired(_) => (StatusCode::GONE, self.to_string()),
+            ReachError::Conflict => (StatusCode::CONFLICT, self.to_string()),
+            ReachError::HandleTaken => (StatusCode::CONFLICT, self.to_string()),
+            ReachError::EndpointTaken => (StatusCode::CONFLICT, self.to_string()),
+            ReachError::VersionMismatch => (StatusCode::CONFLICT, self.to_string()),
+            ReachError::IdempotencyInProgress => (StatusCode::CONFLICT, self.to_string()),
+            ReachError::IdempotencyKeyReused => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()),
             ReachError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
             ReachError::SessionExpired => (StatusCode::UNAUTHORIZED, self.to_string()),
+            ReachError::AdminForbidden => (StatusCode::FORBIDDEN, self.to_string()),
+            ReachError::InsufficientScope => (StatusCode::FORBIDDEN, self.to_string()),
+            ReachError::UnsupportedProtocol(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            ReachError::ProtocolNotAllowed(_) => (StatusCode::FORBIDDEN, self.to_string()),
+            ReachError::Forbidden(_) => (StatusCode::FORBIDDEN, self.to_string()),
+            ReachError::InvalidDelegation(_) => (StatusCode::FORBIDDEN, self.to_string()),
+            ReachError::InvalidRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            ReachError::PayloadTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()),
+            ReachError::RegistryFull => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            ReachError::TooManyWatchers => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            ReachError::TooManyFailedProofs(_) => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
             ReachError::HandshakeError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            ReachError::DidResolution(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
             ReachError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal error".into()),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": message
+        });
+        // Tell clients that a new handshake, not a retry, gets them back in
+        if let ReachError::SessionExpired = self {
+            body["code"] = "session_expired".into();
+            body["action"] = "reauthenticate".into();
+        }
+        // Unlike a 404, the DID did register; say how stale the entry is
+        if let ReachError::Expired(expires_at) = self {
+            body["code"] = "expired".into();
+            body["expires_at"] = expires_at.into();
+        }
+        if let ReachError::InvalidDid(_) = self {
+            body["code"] = "invalid_did".into();
+        }
+        if let ReachError::UnsupportedDidMethod(method) = &self {
+            body["code"] = "unsupported_did_method".into();
+            body["method"] = method.as_str().into();
+        }
+        if let ReachError::InsufficientScope = self {
+            body["code"] = "insufficient_scope".into();
+        }
+        if let ReachError::UnsupportedProtocol(supported) = &self {
+            body["code"] = "unsupported_protocol".into();
+            body["supported"] = supported.clone().into();
+        }
+        if let ReachError::ProtocolNotAllowed(allowed) = &self {
+            body["code"] = "protocol_not_allowed".into();
+            body["allowed"] = allowed.clone().into();
+        }
+        if let ReachError::PayloadTooLarge(limit) = self {
+            body["code"] = "payload_too_large".into();
+            body["limit"] = limit.into();
+        }
+        if let ReachError::RegistryFull = self {
+            body["code"] = "registry_full".into();
+        }
+        if let ReachError::TooManyWatchers = self {
+            body["code"] = "too_many_watchers".into();
+        }
+        if let ReachError::Forbidden(remaining) = self {
+            body["code"] = "banned".into();
+            if let Some(remaining) = remaining {
+                body["retry_after_secs"] = remaining.into();
+                return (status, [(header::RETRY_AFTER, remaining.to_string())], Json(body)).into_response();
+            }
+        }
+        if let ReachError::TooManyFailedProofs(retry_after) = self {
+            body["code"] = "too_many_failed_proofs".into();
+            return (status, [(header::RETRY_AFTER, retry_after.to_string())], Json(body)).into_response();
+        }
+
+        (status, Json(body)).into_response()
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<ReachError> for tonic::Status {
+    fn from(error: ReachError) -> Self {
+        use tonic::Code;
+
+        let code = match &error {
+            ReachError::InvalidDid(_)
+            | ReachError::UnsupportedDidMethod(_)
+            | ReachError::InvalidChallenge
+            | ReachError::InvalidRequest(_)
+            | ReachError::UnsupportedProtocol(_)
+            | ReachError::HandshakeError(_) => Code::InvalidArgument,
+            ReachError::PayloadTooLarge(_)
+            | ReachError::RegistryFull
+            | ReachError::TooManyWatchers
+            | ReachError::TooManyFailedProofs(_) => Code::ResourceExhausted,
+            ReachError::InvalidSignature
+            | ReachError::Unauthorized
+            | ReachError::SessionExpired => Code::Unauthenticated,
+            ReachError::NotFound | ReachError::SessionNotFound | ReachError::Expired(_) => Code::NotFound,
+            ReachError::Conflict | ReachError::HandleTaken | ReachError::EndpointTaken => Code::AlreadyExists,
+            ReachError::VersionMismatch | ReachError::IdempotencyInProgress => Code::Aborted,
+            ReachError::IdempotencyKeyReused => Code::FailedPrecondition,
+            ReachError::InsufficientScope
+            | ReachError::AdminForbidden
+            | ReachError::Forbidden(_)
+            | ReachError::InvalidDelegation(_)
+            | ReachError::ProtocolNotAllowed(_) => Code::PermissionDenied,
+            ReachError::DidResolution(_) => Code::Unavailable,
+            ReachError::Internal(_) => return tonic::Status::internal("Internal error"),
+        };
+        tonic::Status::new(code, error.to_string())
+    }
+}
+
+/// Add the request ID to JSON error bodies, so a client can quote it when
+/// reporting a failure
+pub async fn attach_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    let response = next.run(request).await;
+
+    let is_error = response.status().is_client_error() || response.status().is_server_error();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    let Some(request_id) = request_id.filter(|_| is_error && is_json) else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY).await else {
+        return ReachError::Internal("Unreadable error body".into()).into_response();
+    };
+    let Ok(mut object) = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    object.insert("request_id".into(), request_id.into());
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = serde_json::to_vec(&object).unwrap_or_else(|_| bytes.to_vec());
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(error: ReachError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_ask_for_a_new_handshake() {
+        let (status, json) = body(ReachError::SessionExpired).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json, json!({
+            "error": "Session expired",
+            "code": "session_expired",
+            "action": "reauthenticate",
+        }));
+
+        let (status, json) = body(ReachError::Unauthorized).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json, json!({ "error": "Unauthorized - valid session required" }));
+    }
+
+    #[tokio::test]
+    async fn expired_registrations_say_when() {
+        let (status, json) = body(ReachError::Expired(1700000000)).await;
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(json, json!({
+            "error": "Registration expired",
+            "code": "expired",
+            "expires_at": 1700000000,
+        }));
+
+        let (status, json) = body(ReachError::NotFound).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json, json!({ "error": "Agent not found" }));
+    }
+
+    #[tokio::test]
+    async fn did_errors_say_which_kind() {
+        let (status, json) = body(ReachError::UnsupportedDidMethod("foo".into())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json, json!({
+            "error": "Unsupported DID method: did:foo",
+            "code": "unsupported_did_method",
+            "method": "foo",
         }));
 
-        (status, body).into_response()
+        let (status, json) = body(ReachError::InvalidDid("malformed did:key DID".into())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["code"], "invalid_did");
     }
 }
@@ -0,0 +1,332 @@
+//! Forwarding lookup misses to peer registries
+//!
+//! With `--peers`, a lookup for a DID this registry doesn't have is sent to
+//! every peer at once and the first live entry found is returned, marked
+//! with the peer it came from. Forwarded lookups carry `X-Reach-Forwarded`
+//! with the number of registries crossed so far and are not forwarded again
+//! past `--peer-max-hops`, so registries that peer with each other can't
+//! loop. A peer that fails several lookups in a row is skipped for a while,
+//! then sent a single lookup to see whether it recovered.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use tokio::task::JoinSet;
+
+use crate::api_version;
+use crate::backend::PeerLookup;
+use crate::handlers::FORWARDED_HEADER;
+use crate::types::RegistryEntry;
+
+/// Consecutive failures that stop lookups going to a peer
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// How long a failing peer is skipped before it is tried again
+const OPEN_FOR: Duration = Duration::from_secs(30);
+
+/// Most entries kept when caching peer lookups
+const MAX_CACHED: usize = 10_000;
+
+/// Peer registries asked over HTTP
+pub struct Federation {
+    client: reqwest::Client,
+    peers: Vec<Arc<Peer>>,
+    max_hops: u32,
+    /// Entries found at peers, with the peer, kept until they expire
+    cache: Option<RwLock<HashMap<String, (String, RegistryEntry)>>>,
+}
+
+struct Peer {
+    /// Base URL, without a trailing slash
+    url: String,
+    breaker: Mutex<Breaker>,
+}
+
+/// Circuit breaker for one peer
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    /// Skip the peer until then, once `failures` reaches the threshold
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    /// Whether a lookup may go to the peer at `now`. After the open period
+    /// one lookup goes through and the breaker opens again until it answers.
+    fn admit(&mut self, now: Instant) -> bool {
+        match self.open_until {
+            None => true,
+            Some(until) if now >= until => {
+                self.open_until = Some(now + OPEN_FOR);
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn succeeded(&mut self) {
+        *self = Breaker::default();
+    }
+
+    fn failed(&mut self, now: Instant) {
+        self.failures += 1;
+        if self.failures >= FAILURE_THRESHOLD {
+            self.open_until = Some(now + OPEN_FOR);
+        }
+    }
+}
+
+impl Federation {
+    /// Ask `peers` (base URLs), waiting up to `timeout` for each
+    pub fn new(peers: &[String], timeout: Duration, max_hops: u32, cache: bool) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(concat!("agent-reach-server/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let peers = peers
+            .iter()
+            .map(|url| url.trim().trim_end_matches('/'))
+            .filter(|url| !url.is_empty())
+            .map(|url| {
+                Arc::new(Peer {
+                    url: url.to_string(),
+                    breaker: Mutex::default(),
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            client,
+            peers,
+            max_hops,
+            cache: cache.then(RwLock::default),
+        })
+    }
+
+    fn cached(&self, did: &str, now: i64) -> Option<(String, RegistryEntry)> {
+        let cache = self.cache.as_ref()?.read();
+        cache.get(did).filter(|(_, entry)| entry.expires_at > now).cloned()
+    }
+
+    fn remember(&self, source: &str, entry: &RegistryEntry, now: i64) {
+        let Some(cache) = &self.cache else { return };
+        let mut cache = cache.write();
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, (_, entry)| entry.expires_at > now);
+            if cache.len() >= MAX_CACHED {
+                cache.clear();
+            }
+        }
+        cache.insert(entry.did.clone(), (source.to_string(), entry.clone()));
+    }
+}
+
+impl Peer {
+    /// The peer's entry for `did`, recording the outcome in the breaker
+    async fn lookup(&self, client: &reqwest::Client, did: &str, hops: u32) -> Option<RegistryEntry> {
+        let outcome = self.fetch(client, did, hops).await;
+        let mut breaker = self.breaker.lock();
+        match outcome {
+            Ok(entry) => {
+                breaker.succeeded();
+                entry
+            }
+            Err(e) => {
+                breaker.failed(Instant::now());
+                tracing::warn!(peer = %self.url, error = %e, "Peer lookup failed");
+                None
+            }
+        }
+    }
+
+    /// Client errors (unknown, expired or malformed DID) are misses; anything
+    /// else that isn't an entry counts against the peer
+    async fn fetch(&self, client: &reqwest::Client, did: &str, hops: u32) -> Result<Option<RegistryEntry>, reqwest::Error> {
+        let response = client
+            .get(format!("{}{}/lookup/{}", self.url, api_version::PREFIX, urlencoding::encode(did)))
+            .header(FORWARDED_HEADER, hops.to_string())
+            .send()
+            .await?;
+        if response.status().is_client_error() {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.json().await?))
+    }
+}
+
+#[async_trait]
+impl PeerLookup for Federation {
+    async fn lookup(&self, did: &str, hops: u32) -> Option<(String, RegistryEntry)> {
+        if hops >= self.max_hops {
+            return None;
+        }
+        let now = chrono::Utc::now().timestamp();
+        if let Some(found) = self.cached(did, now) {
+            return Some(found);
+        }
+
+        let mut lookups = JoinSet::new();
+        let started = Instant::now();
+        for peer in &self.peers {
+            if !peer.breaker.lock().admit(started) {
+                continue;
+            }
+            let (client, peer, did) = (self.client.clone(), peer.clone(), did.to_string());
+            lookups.spawn(async move {
+                let entry = peer.lookup(&client, &did, hops + 1).await;
+                (peer, entry)
+            });
+        }
+
+        // Dropping the set cancels lookups still in flight
+        while let Some(joined) = lookups.join_next().await {
+            let Ok((peer, Some(entry))) = joined else { continue };
+            if entry.did == did && entry.expires_at > now {
+                self.remember(&peer.url, &entry, now);
+                return Some((peer.url.clone(), entry));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::{Path, Query, State},
+        http::{HeaderMap, HeaderValue},
+    };
+
+    use super::*;
+    use crate::handlers::AppState;
+
+    fn state() -> AppState {
+        AppState::for_tests()
+    }
+
+    fn entry(did: &str) -> RegistryEntry {
+        RegistryEntry::for_test(did, "wss://peer.example/agent", 3600)
+    }
+
+    /// Serve `state` on a local port, returning its base URL
+    async fn serve(state: AppState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = crate::app(state, &crate::cors::CorsConfig::default(), crate::limits::DEFAULT_MAX_BODY_BYTES);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    fn federation(peers: &[String], cache: bool) -> Federation {
+        Federation::new(peers, Duration::from_secs(2), 2, cache).unwrap()
+    }
+
+    #[tokio::test]
+    async fn lookups_missing_locally_are_answered_by_a_peer() {
+        let peer = state();
+        peer.registry.register(entry("did:key:remote")).await.unwrap();
+        let peer_url = serve(peer).await;
+
+        let mut local = state();
+        local.peers = Some(Arc::new(federation(&[format!("{}/", peer_url)], false)));
+
+        let response = crate::handlers::lookup(
+            State(local.clone()),
+            Path("did:key:remote".to_string()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["endpoint"], "wss://peer.example/agent");
+        assert_eq!(json["source"], peer_url.as_str());
+
+        let missing = crate::handlers::lookup(
+            State(local),
+            Path("did:key:nowhere".to_string()),
+            Query(Default::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(missing.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn forwarded_lookups_stop_at_the_hop_limit() {
+        let peer = state();
+        peer.registry.register(entry("did:key:remote")).await.unwrap();
+        let federation = federation(&[serve(peer).await], false);
+
+        assert!(federation.lookup("did:key:remote", 1).await.is_some());
+        assert!(federation.lookup("did:key:remote", 2).await.is_none());
+
+        let mut local = state();
+        local.peers = Some(Arc::new(federation));
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_HEADER, HeaderValue::from_static("2"));
+        let response = crate::handlers::lookup(
+            State(local),
+            Path("did:key:remote".to_string()),
+            Query(Default::default()),
+            headers,
+        )
+        .await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn cached_entries_outlive_the_peer() {
+        let peer = state();
+        peer.registry.register(entry("did:key:remote")).await.unwrap();
+        let peer_url = serve(peer.clone()).await;
+        let federation = federation(std::slice::from_ref(&peer_url), true);
+
+        assert!(federation.lookup("did:key:remote", 0).await.is_some());
+        peer.registry.deregister("did:key:remote").await.unwrap();
+        let (source, cached) = federation.lookup("did:key:remote", 0).await.unwrap();
+        assert_eq!(source, peer_url);
+        assert_eq!(cached.endpoint, "wss://peer.example/agent");
+    }
+
+    #[tokio::test]
+    async fn failing_peers_are_skipped() {
+        // Answers every request with a server error
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = hits.clone();
+        let app = axum::Router::new().fallback(move || {
+            counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async { (axum::http::StatusCode::BAD_GATEWAY, Body::empty()) }
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let federation = federation(&[url], false);
+        for _ in 0..FAILURE_THRESHOLD + 2 {
+            assert!(federation.lookup("did:key:remote", 0).await.is_none());
+        }
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), FAILURE_THRESHOLD as usize);
+    }
+
+    #[test]
+    fn open_breakers_let_one_probe_through() {
+        let now = Instant::now();
+        let mut breaker = Breaker::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            assert!(breaker.admit(now));
+            breaker.failed(now);
+        }
+        assert!(!breaker.admit(now + OPEN_FOR / 2));
+
+        let later = now + OPEN_FOR;
+        assert!(breaker.admit(later));
+        assert!(!breaker.admit(later), "only one probe at a time");
+        breaker.succeeded();
+        assert!(breaker.admit(later));
+    }
+}
@@ -0,0 +1,540 @@
+//! gRPC interface (the `grpc` feature)
+//!
+//! Serves the operations in `proto/reach.proto` on a separate listener,
+//! sharing [`AppState`] with the HTTP API. Each RPC runs the matching HTTP
+//! handler, so validation, sessions and errors behave the same on both.
+//! Register and Deregister read the session from `authorization` metadata,
+//! exactly as the HTTP handlers read the header.
+
+use std::future::Future;
+
+use agent_id::core::delegation::Delegation;
+use agent_id_handshake::messages::CounterChallenge;
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    Json,
+};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+use crate::changes::{Change, ChangeKind, ChangeLog};
+use crate::error::ReachError;
+use crate::handlers::{self, AppState};
+use crate::types::{self, AgentStatus, Challenge, Hello, Proof, ProofAccepted, RegisterParams};
+use crate::REQUEST_ID_HEADER;
+
+/// Most DIDs in one BatchLookup
+const MAX_BATCH_LOOKUP: usize = 100;
+
+/// Changes read from the log at a time by Watch
+const WATCH_BATCH: usize = 100;
+
+pub mod pb {
+    tonic::include_proto!("agent_reach.v1");
+}
+
+use pb::reach_server::{Reach, ReachServer};
+
+/// Serve gRPC on `listener` until `shutdown` resolves
+pub async fn serve(
+    state: AppState,
+    listener: TcpListener,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    tonic::transport::Server::builder()
+        .trace_fn(request_span)
+        .add_service(ReachServer::new(ReachService { state }))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await?;
+    Ok(())
+}
+
+/// Span for each call, with the same fields as the HTTP request span
+fn request_span(request: &axum::http::Request<()>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    tracing::info_span!(
+        "grpc",
+        method = %request.uri().path(),
+        otel.name = request.uri().path().trim_start_matches('/'),
+        otel.kind = "server",
+        request_id,
+        did = tracing::field::Empty,
+        endpoint = tracing::field::Empty,
+        session_age = tracing::field::Empty,
+        registry_size = tracing::field::Empty,
+    )
+}
+
+struct ReachService {
+    state: AppState,
+}
+
+#[tonic::async_trait]
+impl Reach for ReachService {
+    async fn hello(&self, request: Request<pb::HelloRequest>) -> Result<Response<pb::Challenge>, Status> {
+        let hello = request.into_inner().into();
+        let Json(challenge) = handlers::hello(State(self.state.clone()), Json(hello)).await?;
+        Ok(Response::new(challenge.try_into()?))
+    }
+
+    async fn proof(&self, request: Request<pb::ProofRequest>) -> Result<Response<pb::ProofAccepted>, Status> {
+        let client = request.remote_addr().map(ConnectInfo);
+        let proof = request.into_inner().try_into()?;
+        let Json(accepted) = handlers::proof(State(self.state.clone()), client, Json(proof)).await?;
+        Ok(Response::new(accepted.into()))
+    }
+
+    async fn register(&self, request: Request<pb::RegisterRequest>) -> Result<Response<pb::RegisterResponse>, Status> {
+        let (metadata, _, req) = request.into_parts();
+        let params = RegisterParams { if_absent: req.if_absent, dry_run: false };
+        let req = types::RegisterRequest {
+            endpoint: req.endpoint,
+            ttl: if req.ttl == 0 { types::default_ttl() } else { req.ttl },
+            delegation: req.delegation.map(|d| types::RegistrationDelegation {
+                did: d.did,
+                issued_at: d.issued_at,
+                subject_signature: d.subject_signature,
+                delegator_signature: d.delegator_signature,
+            }),
+            handle: req.handle,
+            weight: req.weight,
+            endpoints: req.endpoints.into_iter().map(Into::into).collect(),
+            region: req.region,
+            did: None,
+            certificate: None,
+        };
+
+        let Json(response) =
+            handlers::register(State(self.state.clone()), metadata.into_headers(), Query(params), Json(req)).await?;
+        Ok(Response::new(pb::RegisterResponse {
+            ok: response.ok,
+            did: response.did,
+            expires_at: response.expires_at,
+            ttl: response.ttl,
+            version: response.version,
+        }))
+    }
+
+    async fn deregister(
+        &self,
+        request: Request<pb::DeregisterRequest>,
+    ) -> Result<Response<pb::DeregisterResponse>, Status> {
+        let headers = request.into_parts().0.into_headers();
+        let Json(response) = handlers::deregister(State(self.state.clone()), headers, Default::default()).await?;
+        Ok(Response::new(pb::DeregisterResponse { ok: response.ok }))
+    }
+
+    async fn lookup(&self, request: Request<pb::LookupRequest>) -> Result<Response<pb::LookupResponse>, Status> {
+        let req = request.into_inner();
+        let mut entry = types::LookupResponse::from(handlers::live_entry(&self.state, req.did).await?);
+        if let Some(region) = req.region.as_deref() {
+            crate::endpoints::select(&mut entry, Some(region), false);
+        }
+        Ok(Response::new(entry.into()))
+    }
+
+    async fn batch_lookup(
+        &self,
+        request: Request<pb::BatchLookupRequest>,
+    ) -> Result<Response<pb::BatchLookupResponse>, Status> {
+        let req = request.into_inner();
+        if req.dids.len() > MAX_BATCH_LOOKUP {
+            return Err(ReachError::InvalidRequest(format!("at most {} DIDs per request", MAX_BATCH_LOOKUP)).into());
+        }
+
+        let mut response = pb::BatchLookupResponse::default();
+        for did in req.dids {
+            match self.state.registry.lookup(&did).await? {
+                Some(entry) if entry.status() == AgentStatus::Online => {
+                    let mut entry = types::LookupResponse::from(entry);
+                    if let Some(region) = req.region.as_deref() {
+                        crate::endpoints::select(&mut entry, Some(region), false);
+                    }
+                    response.found.push(entry.into());
+                }
+                _ => response.missing.push(did),
+            }
+        }
+        Ok(Response::new(response))
+    }
+
+    type WatchStream = ReceiverStream<Result<pb::ChangeEvent, Status>>;
+
+    async fn watch(&self, request: Request<pb::WatchRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let log = self.state.changes.clone();
+        let mut notify = log.subscribe();
+        let request = request.into_inner();
+        let cursor = match request.since {
+            Some(since) if !log.numbered(request.epoch.as_deref(), since) => {
+                return Err(Status::out_of_range("The cursor is from another epoch; resync with Lookup"));
+            }
+            Some(since) => since,
+            None => *notify.borrow_and_update(),
+        };
+        let (tx, rx) = mpsc::channel(WATCH_BATCH);
+        tokio::spawn(async move { watch(&log, notify, cursor, tx).await });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Send changes after `cursor` to `tx` as they are recorded, until the
+/// caller goes away or falls out of the retained log
+async fn watch(
+    log: &ChangeLog,
+    mut notify: tokio::sync::watch::Receiver<u64>,
+    mut cursor: u64,
+    tx: mpsc::Sender<Result<pb::ChangeEvent, Status>>,
+) {
+    loop {
+        let (changes, _) = log.since(cursor, WATCH_BATCH);
+        let Some(changes) = changes else {
+            let status = Status::out_of_range("Changes are no longer retained; resync with Lookup");
+            let _ = tx.send(Err(status)).await;
+            return;
+        };
+        if changes.is_empty() {
+            tokio::select! {
+                changed = notify.changed() => if changed.is_err() { return },
+                _ = tx.closed() => return,
+            }
+            continue;
+        }
+        for change in changes {
+            cursor = change.seq;
+            let event = pb::ChangeEvent { epoch: log.epoch().to_string(), ..change.into() };
+            if tx.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl From<types::LookupResponse> for pb::LookupResponse {
+    fn from(entry: types::LookupResponse) -> Self {
+        Self {
+            did: entry.did,
+            endpoint: entry.endpoint,
+            status: match entry.status {
+                AgentStatus::Online => "online",
+                AgentStatus::Expired => "expired",
+            }
+            .to_string(),
+            registered_at: entry.registered_at,
+            expires_at: entry.expires_at,
+            last_seen: entry.last_seen,
+            handle: entry.handle,
+            endpoints: entry.endpoints.into_iter().map(Into::into).collect(),
+            region: entry.region,
+            version: entry.version,
+            ttl_remaining_secs: entry.ttl_remaining_secs,
+        }
+    }
+}
+
+impl From<Change> for pb::ChangeEvent {
+    fn from(change: Change) -> Self {
+        Self {
+            seq: change.seq,
+            kind: match change.kind {
+                ChangeKind::Register => "register",
+                ChangeKind::Refresh => "refresh",
+                ChangeKind::Deregister => "deregister",
+                ChangeKind::Expire => "expire",
+            }
+            .to_string(),
+            did: change.did,
+            at: change.at,
+            entry: change.entry.map(|entry| types::LookupResponse::from(entry).into()),
+            epoch: String::new(),
+        }
+    }
+}
+
+// ============================================================================
+// Handshake message mapping
+// ============================================================================
+
+fn delegation_to_json(delegation: Option<Delegation>) -> Result<Option<String>, Status> {
+    delegation
+        .map(|d| serde_json::to_string(&d))
+        .transpose()
+        .map_err(|e| Status::internal(e.to_string()))
+}
+
+fn delegation_from_json(json: Option<String>) -> Result<Option<Delegation>, Status> {
+    json.map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|e| Status::invalid_argument(format!("Invalid delegation_json: {}", e)))
+}
+
+impl From<pb::Endpoint> for types::Endpoint {
+    fn from(endpoint: pb::Endpoint) -> Self {
+        Self { uri: endpoint.uri, weight: endpoint.weight, region: endpoint.region, registered_at: None }
+    }
+}
+
+impl From<types::Endpoint> for pb::Endpoint {
+    fn from(endpoint: types::Endpoint) -> Self {
+        Self { uri: endpoint.uri, weight: endpoint.weight, region: endpoint.region }
+    }
+}
+
+impl From<pb::HelloRequest> for Hello {
+    fn from(hello: pb::HelloRequest) -> Self {
+        Self {
+            type_: "Hello".to_string(),
+            version: hello.version,
+            did: hello.did,
+            protocols: hello.protocols,
+            timestamp: hello.timestamp,
+            capabilities: (!hello.capabilities.is_empty()).then_some(hello.capabilities),
+        }
+    }
+}
+
+impl TryFrom<Challenge> for pb::Challenge {
+    type Error = Status;
+
+    fn try_from(challenge: Challenge) -> Result<Self, Status> {
+        Ok(Self {
+            version: challenge.version,
+            nonce: challenge.nonce,
+            timestamp: challenge.timestamp,
+            audience: challenge.audience,
+            issuer: challenge.issuer,
+            domain: challenge.domain,
+            session_pubkey: challenge.session_pubkey,
+            delegation_json: delegation_to_json(challenge.delegation)?,
+        })
+    }
+}
+
+impl TryFrom<pb::ProofRequest> for Proof {
+    type Error = Status;
+
+    fn try_from(proof: pb::ProofRequest) -> Result<Self, Status> {
+        Ok(Self {
+            type_: "Proof".to_string(),
+            version: proof.version,
+            challenge_hash: proof.challenge_hash,
+            responder_did: proof.responder_did,
+            signing_key: proof.signing_key,
+            signature: proof.signature,
+            delegation: delegation_from_json(proof.delegation_json)?,
+            counter_challenge: proof.counter_challenge.map(|counter| CounterChallenge {
+                nonce: counter.nonce,
+                timestamp: counter.timestamp,
+                audience: counter.audience,
+            }),
+        })
+    }
+}
+
+impl From<ProofAccepted> for pb::ProofAccepted {
+    fn from(accepted: ProofAccepted) -> Self {
+        let counter_proof = accepted.counter_proof;
+        Self {
+            version: accepted.version,
+            session_id: accepted.session_id,
+            counter_proof: Some(pb::CounterProof {
+                challenge_hash: counter_proof.challenge_hash,
+                responder_did: counter_proof.responder_did,
+                signing_key: counter_proof.signing_key,
+                signature: counter_proof.signature,
+            }),
+            session_expires_at: accepted.session_expires_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use agent_id::RootKey;
+    use tonic::{transport::Channel, Code};
+
+    use super::*;
+    use crate::changes::RecordingRegistry;
+    use crate::registry::Registry;
+    use pb::reach_client::ReachClient;
+
+    /// Serve gRPC on an ephemeral port, returning a connected client
+    async fn client() -> ReachClient<Channel> {
+        let changes = Arc::new(ChangeLog::default());
+        let state = AppState {
+            registry: Arc::new(RecordingRegistry::new(Arc::new(Registry::new()), changes.clone())),
+            changes,
+            ..AppState::for_tests()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(state, listener, std::future::pending()));
+        ReachClient::connect(format!("http://{}", addr)).await.unwrap()
+    }
+
+    /// Rebuild the handshake crate's challenge, as a client signs it
+    fn challenge(challenge: pb::Challenge) -> Challenge {
+        Challenge {
+            type_: "Challenge".to_string(),
+            version: challenge.version,
+            nonce: challenge.nonce,
+            timestamp: challenge.timestamp,
+            audience: challenge.audience,
+            issuer: challenge.issuer,
+            domain: challenge.domain,
+            session_pubkey: challenge.session_pubkey,
+            delegation: None,
+        }
+    }
+
+    fn proof_request(proof: Proof) -> pb::ProofRequest {
+        pb::ProofRequest {
+            version: proof.version,
+            challenge_hash: proof.challenge_hash,
+            responder_did: proof.responder_did,
+            signing_key: proof.signing_key,
+            signature: proof.signature,
+            delegation_json: None,
+            counter_challenge: proof.counter_challenge.map(|counter| pb::CounterChallenge {
+                nonce: counter.nonce,
+                timestamp: counter.timestamp,
+                audience: counter.audience,
+            }),
+        }
+    }
+
+    /// Handshake as `key` and return the session ID
+    async fn handshake(client: &mut ReachClient<Channel>, key: &RootKey) -> String {
+        let hello = Hello::new(key.did().to_string());
+        let issued = client
+            .hello(pb::HelloRequest {
+                version: hello.version,
+                did: hello.did,
+                protocols: hello.protocols,
+                timestamp: hello.timestamp,
+                capabilities: Vec::new(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let challenge = challenge(issued);
+        let proof = agent_id_handshake::protocol::sign_proof(&challenge, &key.did(), key, Some(challenge.issuer.clone()))
+            .unwrap();
+
+        client.proof(proof_request(proof)).await.unwrap().into_inner().session_id
+    }
+
+    /// Handshake as `key` and register `endpoint`
+    async fn register(client: &mut ReachClient<Channel>, key: &RootKey, endpoint: &str) -> pb::RegisterResponse {
+        let session_id = handshake(client, key).await;
+        let mut request = Request::new(pb::RegisterRequest {
+            endpoint: endpoint.to_string(),
+            ..Default::default()
+        });
+        request.metadata_mut().insert("authorization", format!("Bearer {}", session_id).parse().unwrap());
+        client.register(request).await.unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn register_and_lookup_over_grpc() {
+        let mut client = client().await;
+        let key = RootKey::generate();
+        let registered = register(&mut client, &key, "wss://grpc-agent:8080").await;
+        assert!(registered.ok);
+        assert_eq!(registered.did, key.did().to_string());
+
+        let found = client
+            .lookup(pb::LookupRequest { did: key.did().to_string(), region: None })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(found.endpoint, "wss://grpc-agent:8080");
+        assert_eq!(found.status, "online");
+    }
+
+    #[tokio::test]
+    async fn errors_map_to_grpc_status_codes() {
+        let mut client = client().await;
+
+        let missing = client
+            .lookup(pb::LookupRequest { did: RootKey::generate().did().to_string(), region: None })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+
+        let anonymous = client.deregister(pb::DeregisterRequest {}).await.unwrap_err();
+        assert_eq!(anonymous.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn batch_lookup_splits_found_and_missing() {
+        let mut client = client().await;
+        let key = RootKey::generate();
+        register(&mut client, &key, "wss://batch-agent:8080").await;
+        let unknown = RootKey::generate().did().to_string();
+
+        let batch = client
+            .batch_lookup(pb::BatchLookupRequest {
+                dids: vec![key.did().to_string(), unknown.clone()],
+                region: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(batch.found.len(), 1);
+        assert_eq!(batch.found[0].endpoint, "wss://batch-agent:8080");
+        assert_eq!(batch.missing, [unknown]);
+
+        let too_many = client
+            .batch_lookup(pb::BatchLookupRequest {
+                dids: vec![key.did().to_string(); MAX_BATCH_LOOKUP + 1],
+                region: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(too_many.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn watch_streams_changes_as_they_happen() {
+        let mut client = client().await;
+        let earlier = RootKey::generate();
+        register(&mut client, &earlier, "wss://earlier:8080").await;
+
+        let mut live = client.watch(pb::WatchRequest::default()).await.unwrap().into_inner();
+        let key = RootKey::generate();
+        register(&mut client, &key, "wss://watched:8080").await;
+
+        let event = live.message().await.unwrap().unwrap();
+        assert_eq!(event.kind, "register");
+        assert_eq!(event.did, key.did().to_string());
+        assert_eq!(event.entry.unwrap().endpoint, "wss://watched:8080");
+
+        // Replaying from the start includes what came before
+        let from = |since| pb::WatchRequest { since: Some(since), epoch: None };
+        let mut replay = client.watch(from(0)).await.unwrap().into_inner();
+        assert_eq!(replay.message().await.unwrap().unwrap().did, earlier.did().to_string());
+        assert_eq!(replay.message().await.unwrap().unwrap().seq, event.seq);
+
+        let resume = |since, epoch: &str| pb::WatchRequest { since: Some(since), epoch: Some(epoch.to_string()) };
+        let mut resumed = client.watch(resume(event.seq - 1, &event.epoch)).await.unwrap().into_inner();
+        assert_eq!(resumed.message().await.unwrap().unwrap().seq, event.seq);
+
+        let mut ahead = client.watch(resume(event.seq + 10, &event.epoch)).await.unwrap().into_inner();
+        assert_eq!(ahead.message().await.unwrap_err().code(), Code::OutOfRange);
+
+        // A cursor from before a restart isn't followed from the wrong place
+        for stale in [from(event.seq), resume(event.seq, "earlier-run")] {
+            assert_eq!(client.watch(stale).await.unwrap_err().code(), Code::OutOfRange);
+        }
+    }
+}
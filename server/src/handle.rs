@@ -0,0 +1,77 @@
+//! Human-readable handles
+//!
+//! An agent may register a `name@domain` handle alongside its DID, so people
+//! can find it without typing a DID. Handles are compared case-insensitively
+//! and stored lowercase. The registry only checks the syntax: it does not
+//! verify that the registrant controls the domain.
+
+use crate::error::ReachError;
+
+/// Longest accepted handle, as for an email address
+const MAX_HANDLE_LEN: usize = 254;
+
+/// Longest accepted name part
+const MAX_NAME_LEN: usize = 64;
+
+/// Longest accepted domain label
+const MAX_LABEL_LEN: usize = 63;
+
+/// Validate a handle, returning its normalized (lowercase) form
+pub fn normalize(handle: &str) -> Result<String, ReachError> {
+    let handle = handle.trim().to_ascii_lowercase();
+    let invalid = || ReachError::InvalidRequest(format!("Invalid handle {:?}: expected name@domain", handle));
+
+    if handle.len() > MAX_HANDLE_LEN {
+        return Err(invalid());
+    }
+    let (name, domain) = handle.split_once('@').ok_or_else(invalid)?;
+
+    let name_ok = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "._+-".contains(c));
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_ok = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    if !name_ok || !domain_ok {
+        return Err(invalid());
+    }
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_are_lowercased() {
+        assert_eq!(normalize("Alice@Example.COM").unwrap(), "alice@example.com");
+        assert_eq!(normalize("bot.v2+eu@agents.example.org").unwrap(), "bot.v2+eu@agents.example.org");
+    }
+
+    #[test]
+    fn malformed_handles_are_rejected() {
+        for handle in [
+            "alice",
+            "@example.com",
+            "alice@",
+            "alice@localhost",
+            "alice@example..com",
+            "alice@-example.com",
+            "alice@bob@example.com",
+            "al ice@example.com",
+            "alice@exa_mple.com",
+        ] {
+            assert!(
+                matches!(normalize(handle), Err(ReachError::InvalidRequest(_))),
+                "{handle} should be rejected"
+            );
+        }
+    }
+}
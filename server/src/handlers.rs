@@ -1,61 +1,301 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
-    http::HeaderMap,
+    body::Bytes,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use async_trait::async_trait;
 use parking_lot::RwLock;
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, info_span, warn, Span};
 
 use agent_id::RootKey;
-use agent_id_handshake::{
-    messages::{Hello, Proof, ProofAccepted},
-    protocol::Verifier,
-    Challenge,
-};
+use agent_id_handshake::protocol::Verifier;
 
+use crate::api_version;
+use crate::backend::{DidResolver, HandshakeBackend, HandshakeCounts, PeerLookup, Precondition, RegistryBackend};
+use crate::did::AgentDid;
+use crate::idempotency::Begin;
 use crate::error::ReachError;
-use crate::registry::Registry;
+use crate::ttl::TtlPolicy;
 use crate::types::*;
 
+/// How long an authenticated session stays valid (seconds)
+pub const SESSION_TTL_SECS: i64 = 300;
+
+/// Sessions a DID may hold by default; a new one ends the oldest
+pub const DEFAULT_MAX_SESSIONS_PER_DID: usize = 5;
+
 /// Shared state for handshake sessions
 pub struct HandshakeState {
     /// agent-reach's own identity
     pub key: RootKey,
-    /// Pending challenges (challenge_hash -> (challenge, verifier))
-    pub pending_challenges: RwLock<HashMap<String, (Challenge, Verifier)>>,
-    /// Authenticated sessions (session_id -> did)
-    pub sessions: RwLock<HashMap<String, AuthenticatedSession>>,
+    /// Pending challenges and authenticated sessions
+    pub store: Arc<dyn HandshakeBackend>,
+    /// Sessions a DID may hold at once
+    pub max_sessions_per_did: usize,
+    /// Resolver for did:web agents; without one only did:key can connect
+    pub did_web: Option<Arc<dyn DidResolver>>,
+    /// Failed proofs per client, for locking out repeat offenders
+    pub lockout: crate::lockout::ProofLockout,
+    /// Length of the nonces challenges carry
+    pub nonce: crate::nonce::NoncePolicy,
+    /// Registry protocols spoken, and which may register
+    pub protocols: crate::protocol::ProtocolPolicy,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AuthenticatedSession {
     pub did: String,
     pub created_at: i64,
+    /// Sessions stored before scopes existed could write
+    #[serde(default)]
+    pub scope: Scope,
+    /// Registry protocols agreed on in the handshake; none for sessions
+    /// stored before negotiation
+    #[serde(default)]
+    pub protocols: Vec<String>,
 }
 
 impl HandshakeState {
+    /// Handshake state kept in memory
     pub fn new() -> Self {
+        Self::with_store(Arc::new(MemoryHandshakeStore::default()))
+    }
+
+    pub fn with_store(store: Arc<dyn HandshakeBackend>) -> Self {
         // Generate identity for agent-reach service
         // TODO: Load from file for persistence
         let key = RootKey::generate();
         info!(did = %key.did(), "agent-reach identity generated");
-        
+
         Self {
             key,
-            pending_challenges: RwLock::new(HashMap::new()),
-            sessions: RwLock::new(HashMap::new()),
+            store,
+            max_sessions_per_did: DEFAULT_MAX_SESSIONS_PER_DID,
+            did_web: None,
+            lockout: Default::default(),
+            nonce: Default::default(),
+            protocols: Default::default(),
+        }
+    }
+}
+
+impl Default for HandshakeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// In-memory handshake store
+#[derive(Default)]
+pub struct MemoryHandshakeStore {
+    /// Pending challenges (challenge_hash -> challenge)
+    pending_challenges: RwLock<HashMap<String, PendingChallenge>>,
+    /// Authenticated sessions
+    sessions: RwLock<Sessions>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    /// session_id -> session
+    by_id: HashMap<String, AuthenticatedSession>,
+    /// did -> session IDs, oldest first
+    by_did: HashMap<String, VecDeque<String>>,
+}
+
+#[async_trait]
+impl HandshakeBackend for MemoryHandshakeStore {
+    async fn put_challenge(&self, hash: String, challenge: PendingChallenge) -> Result<(), ReachError> {
+        self.pending_challenges.write().insert(hash, challenge);
+        Ok(())
+    }
+
+    async fn take_challenge(&self, hash: &str) -> Result<Option<PendingChallenge>, ReachError> {
+        Ok(self.pending_challenges.write().remove(hash))
+    }
+
+    async fn put_session(&self, session_id: String, session: AuthenticatedSession) -> Result<(), ReachError> {
+        let mut sessions = self.sessions.write();
+        sessions.by_did.entry(session.did.clone()).or_default().push_back(session_id.clone());
+        sessions.by_id.insert(session_id, session);
+        Ok(())
+    }
+
+    async fn session(&self, session_id: &str) -> Result<Option<AuthenticatedSession>, ReachError> {
+        Ok(self.sessions.read().by_id.get(session_id).cloned())
+    }
+
+    async fn sessions(&self) -> Result<Vec<(String, AuthenticatedSession)>, ReachError> {
+        let sessions = self.sessions.read();
+        Ok(sessions.by_id.iter().map(|(id, session)| (id.clone(), session.clone())).collect())
+    }
+
+    async fn remove_session(&self, session_id: &str) -> Result<bool, ReachError> {
+        let mut sessions = self.sessions.write();
+        let Some(session) = sessions.by_id.remove(session_id) else {
+            return Ok(false);
+        };
+        if let Some(ids) = sessions.by_did.get_mut(&session.did) {
+            ids.retain(|id| id != session_id);
+            if ids.is_empty() {
+                sessions.by_did.remove(&session.did);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn trim_sessions(&self, did: &str, keep: usize) -> Result<usize, ReachError> {
+        let mut sessions = self.sessions.write();
+        let Some(ids) = sessions.by_did.get_mut(did) else {
+            return Ok(0);
+        };
+        let excess = ids.len().saturating_sub(keep);
+        let evicted: Vec<String> = ids.drain(..excess).collect();
+        for id in &evicted {
+            sessions.by_id.remove(id);
         }
+        Ok(evicted.len())
+    }
+
+    async fn purge_expired_sessions(&self, now: i64) -> Result<usize, ReachError> {
+        let mut sessions = self.sessions.write();
+        let Sessions { by_id, by_did } = &mut *sessions;
+        let before = by_id.len();
+        by_id.retain(|_, session| now - session.created_at <= SESSION_TTL_SECS);
+        by_did.retain(|_, ids| {
+            ids.retain(|id| by_id.contains_key(id));
+            !ids.is_empty()
+        });
+        Ok(before - by_id.len())
+    }
+
+    fn counts(&self, now: i64) -> Option<HandshakeCounts> {
+        let active_sessions = self
+            .sessions
+            .read()
+            .by_id
+            .values()
+            .filter(|session| now - session.created_at <= SESSION_TTL_SECS)
+            .count();
+        Some(HandshakeCounts {
+            active_sessions,
+            pending_challenges: self.pending_challenges.read().len(),
+        })
     }
 }
 
 /// App state combining registry and handshake state
 #[derive(Clone)]
 pub struct AppState {
-    pub registry: Registry,
+    pub registry: Arc<dyn RegistryBackend>,
     pub handshake: Arc<HandshakeState>,
+    /// Bearer token for the `/admin` endpoints; they are disabled when unset
+    pub admin_token: Option<Arc<str>>,
+    /// Bounds applied to requested registration TTLs
+    pub ttl: TtlPolicy,
+    /// Regions endpoints may be tagged with; any region when empty
+    pub regions: Arc<[String]>,
+    /// Reject registering an endpoint another live DID has registered
+    pub unique_endpoints: bool,
+    /// Counters behind `GET /stats`
+    pub stats: Arc<crate::stats::Stats>,
+    /// Recent writes, for `GET /changes`
+    pub changes: Arc<crate::changes::ChangeLog>,
+    /// Open `GET /watch` sockets, capped by `--max-watchers`
+    pub watchers: Arc<crate::watch::Watchers>,
+    /// Responses to replay for retried registrations
+    pub idempotency: Arc<crate::idempotency::IdempotencyCache>,
+    /// DIDs barred from registering, by `DELETE /admin/agents/{did}`
+    pub denylist: Arc<crate::denylist::Denylist>,
+    /// Registries asked about DIDs not registered here
+    pub peers: Option<Arc<dyn PeerLookup>>,
+    /// Versions writes for replication, with `--sync-peers`
+    pub replica: Option<Arc<crate::sync::Replica>>,
+    /// Where clients reach this registry, with `--public-url`
+    pub public_url: Option<Arc<crate::webfinger::PublicUrl>>,
+    /// Background tasks' last rounds, for `GET /health/ready`
+    pub heartbeats: Arc<crate::health::Heartbeats>,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// An empty in-memory registry with every option off; tests override
+    /// what they need with `AppState { .., ..AppState::for_tests() }`
+    pub(crate) fn for_tests() -> Self {
+        Self {
+            registry: Arc::new(crate::registry::Registry::new()),
+            handshake: Arc::new(HandshakeState::new()),
+            admin_token: None,
+            ttl: TtlPolicy::default(),
+            regions: Default::default(),
+            unique_endpoints: false,
+            stats: Default::default(),
+            changes: Default::default(),
+            watchers: Default::default(),
+            idempotency: Default::default(),
+            denylist: Default::default(),
+            peers: None,
+            replica: None,
+            public_url: None,
+            heartbeats: Default::default(),
+        }
+    }
+}
+
+/// GET /health
+/// 
+/// Returns `ok` if the server is running.
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses((status = 200, description = "Server is running", body = String, example = json!("ok")))
+)]
+pub async fn health() -> &'static str {
+    "ok"
+}
+
+/// How long clients may cache the discovery document
+const DISCOVERY_CACHE_CONTROL: &str = "public, max-age=300";
+
+/// GET /.well-known/agent-reach
+///
+/// Registry identity, limits and endpoint paths, for clients configuring
+/// themselves against an unfamiliar registry.
+#[utoipa::path(
+    get,
+    path = "/.well-known/agent-reach",
+    tag = "lookup",
+    responses((status = 200, description = "Registry metadata", body = DiscoveryDocument))
+)]
+pub async fn discovery(State(state): State<AppState>) -> impl IntoResponse {
+    let document = DiscoveryDocument {
+        registry_did: state.handshake.key.did().to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        supported_protocols: crate::protocol::HANDSHAKE
+            .iter()
+            .map(|p| p.to_string())
+            .chain(state.handshake.protocols.supported().iter().cloned())
+            .collect(),
+        min_ttl: state.ttl.min,
+        max_ttl: state.ttl.max,
+        session_ttl: SESSION_TTL_SECS,
+        endpoints: DiscoveryEndpoints {
+            hello: format!("{}/hello", api_version::PREFIX),
+            proof: format!("{}/proof", api_version::PREFIX),
+            register: format!("{}/register", api_version::PREFIX),
+            lookup: format!("{}/lookup/{{did}}", api_version::PREFIX),
+            resolve: format!("{}/resolve?handle={{handle}}", api_version::PREFIX),
+            watch: format!("{}/watch/{{did}}", api_version::PREFIX),
+        },
+    };
+    ([(header::CACHE_CONTROL, DISCOVERY_CACHE_CONTROL)], Json(document))
 }
 
 // ============================================================================
@@ -65,177 +305,2037 @@ pub struct AppState {
 /// POST /hello
 /// 
 /// First step of handshake. Returns a challenge.
+///
+/// A `scope:read` capability asks for a session that can't change the
+/// registry; sessions are `scope:write` otherwise.
+#[utoipa::path(
+    post,
+    path = "/hello",
+    tag = "handshake",
+    request_body = crate::openapi::Hello,
+    responses(
+        (status = 200, description = "Challenge to sign", body = crate::openapi::Challenge),
+        (status = 400, description = "Invalid DID, Hello message or requested scope", body = crate::openapi::ErrorResponse),
+        (status = 413, description = "Request body too large", body = crate::openapi::ErrorResponse),
+    )
+)]
 pub async fn hello(
     State(state): State<AppState>,
     Json(hello): Json<Hello>,
 ) -> Result<Json<Challenge>, ReachError> {
+    crate::limits::check_did(&hello.did)?;
+    record_did(&hello.did);
     info!(did = %hello.did, "Received Hello");
+    let scope = requested_scope(&hello)?;
+    let protocols = state.handshake.protocols.negotiate(&hello.protocols)?;
 
-    // Parse and validate DID
-    let did: agent_id::Did = hello.did.parse()
-        .map_err(|_| ReachError::InvalidDid)?;
+    // Parse and validate DID; a did:web document must resolve before we
+    // hand out a challenge, which is then issued by our own DID
+    let verifier = match crate::did::parse(&hello.did, state.handshake.did_web.is_some())? {
+        AgentDid::Web => {
+            did_web_keys(&state, &hello.did).await?;
+            Verifier::new(state.handshake.key.did())
+        }
+        AgentDid::Key(did) => Verifier::new(did),
+    };
 
-    // Create verifier and generate challenge
-    let verifier = Verifier::new(did);
-    let challenge = verifier.handle_hello(&hello)
+    // Generate challenge
+    let mut challenge = info_span!("generate_challenge")
+        .in_scope(|| verifier.handle_hello(&hello))
         .map_err(|e| ReachError::HandshakeError(e.to_string()))?;
+    state.handshake.nonce.apply(&mut challenge);
 
-    // Store challenge and verifier for verification
+    // Store challenge for verification
     let challenge_hash = agent_id_handshake::protocol::hash_challenge(&challenge)
         .map_err(|e| ReachError::Internal(e.to_string()))?;
-    
-    state.handshake.pending_challenges.write()
-        .insert(challenge_hash, (challenge.clone(), verifier));
 
-    info!(did = %hello.did, "Sent Challenge");
+    info!(did = %hello.did, ?scope, ?protocols, "Sent Challenge");
+    let pending = PendingChallenge { challenge: challenge.clone(), scope, protocols };
+    state.handshake.store.put_challenge(challenge_hash, pending).await?;
 
     Ok(Json(challenge))
 }
 
+/// The scope a Hello asks for through its `scope:` capabilities
+fn requested_scope(hello: &Hello) -> Result<Scope, ReachError> {
+    let mut requested = hello
+        .capabilities
+        .iter()
+        .flatten()
+        .filter(|capability| capability.starts_with("scope:"))
+        .map(|capability| {
+            [Scope::Read, Scope::Write]
+                .into_iter()
+                .find(|scope| scope.capability() == capability)
+                .ok_or_else(|| ReachError::InvalidRequest(format!("Unknown scope capability {}", capability)))
+        });
+    let scope = requested.next().transpose()?.unwrap_or_default();
+    if requested.next().is_some() {
+        return Err(ReachError::InvalidRequest("Hello may ask for one scope only".into()));
+    }
+    Ok(scope)
+}
+
 /// POST /proof
 /// 
 /// Second step of handshake. Verifies proof, returns ProofAccepted with session.
+#[utoipa::path(
+    post,
+    path = "/proof",
+    tag = "handshake",
+    request_body = crate::openapi::Proof,
+    responses(
+        (status = 200, description = "Session established", body = crate::openapi::ProofAccepted),
+        (status = 400, description = "Unknown challenge or invalid proof", body = crate::openapi::ErrorResponse),
+        (status = 413, description = "Request body too large", body = crate::openapi::ErrorResponse),
+        (status = 429, description = "Too many failed proofs from this client for this DID", body = crate::openapi::ErrorResponse),
+    )
+)]
 pub async fn proof(
     State(state): State<AppState>,
+    connect: Option<ConnectInfo<SocketAddr>>,
     Json(proof): Json<Proof>,
 ) -> Result<Json<ProofAccepted>, ReachError> {
+    let ip = connect.map(|ConnectInfo(addr)| addr.ip());
+    accept_proof(&state, ProofFrom::Client(ip), proof).await.map(Json)
+}
+
+/// Who a proof came from
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ProofFrom {
+    /// A client, at this address when known
+    Client(Option<IpAddr>),
+    /// `POST /admin/selftest`, whose handshakes leave the handshake
+    /// metrics, the session log and `last_seen` alone
+    Selftest,
+}
+
+/// Verify `proof` against its pending challenge and store the session it
+/// opens
+pub(crate) async fn accept_proof(state: &AppState, from: ProofFrom, proof: Proof) -> Result<ProofAccepted, ReachError> {
+    let (ip, client) = match from {
+        ProofFrom::Client(ip) => (ip, true),
+        ProofFrom::Selftest => (None, false),
+    };
+    crate::limits::check_did(&proof.responder_did)?;
+    record_did(&proof.responder_did);
     info!(did = %proof.responder_did, "Received Proof");
 
-    // Get the pending challenge and verifier
-    let (challenge, verifier) = state.handshake.pending_challenges.write()
-        .remove(&proof.challenge_hash)
+    // Get the pending challenge and rebuild its verifier
+    let PendingChallenge { challenge, scope, protocols } = state.handshake.store.take_challenge(&proof.challenge_hash).await?
         .ok_or(ReachError::InvalidChallenge)?;
 
-    // Verify the proof
-    verifier.verify_proof(&proof, &challenge)
-        .map_err(|e| ReachError::HandshakeError(e.to_string()))?;
+    // Failures count against the DID the challenge was issued to, not the
+    // one the client claims, so changing the claim doesn't escape a lockout
+    let now = chrono::Utc::now().timestamp();
+    state.handshake.lockout.check(ip, &challenge.audience, now)?;
+    state.handshake.nonce.check_challenge(&challenge)?;
+    if let Some(counter) = &proof.counter_challenge {
+        crate::nonce::check_agent_nonce(&counter.nonce)?;
+    }
+    let verified = match crate::did::parse(&challenge.audience, state.handshake.did_web.is_some())? {
+        AgentDid::Web => {
+            let keys = did_web_keys(state, &challenge.audience).await?;
+            info_span!("verify_proof")
+                .in_scope(|| verify_did_web_proof(&proof, &challenge, &keys))
+                .map(|_| Verifier::new(state.handshake.key.did()))
+        }
+        AgentDid::Key(did) => {
+            let verifier = Verifier::new(did);
+
+            // Verify the proof
+            info_span!("verify_proof")
+                .in_scope(|| verifier.verify_proof(&proof, &challenge))
+                .map_err(|e| ReachError::HandshakeError(e.to_string()))
+                .map(|_| verifier)
+        }
+    };
+    let verifier = match verified {
+        Ok(verifier) => verifier,
+        Err(e) => {
+            let locked_out = state.handshake.lockout.record_failure(ip, &challenge.audience, now);
+            if locked_out {
+                warn!(did = %challenge.audience, ip = ?ip, "Locked out after repeated failed proofs");
+            }
+            #[cfg(feature = "otel")]
+            if client {
+                crate::telemetry::record_failed_proof(locked_out);
+            }
+            return Err(e);
+        }
+    };
+    state.handshake.lockout.record_success(ip, &challenge.audience);
 
     info!(did = %proof.responder_did, "Proof verified");
 
     // Accept proof and generate counter-proof (mutual auth)
-    let accepted = verifier.accept_proof(&proof, &state.handshake.key)
+    let mut accepted = verifier.accept_proof(&proof, &state.handshake.key)
         .map_err(|e| ReachError::HandshakeError(e.to_string()))?;
 
     // Store authenticated session
+    let session_created_at = chrono::Utc::now().timestamp();
     let session = AuthenticatedSession {
         did: proof.responder_did.clone(),
-        created_at: chrono::Utc::now().timestamp(),
+        created_at: session_created_at,
+        scope,
+        protocols,
     };
-    state.handshake.sessions.write()
-        .insert(accepted.session_id.clone(), session);
+    // Report our own session lifetime rather than the handshake crate's default
+    accepted.session_expires_at = (session.created_at + SESSION_TTL_SECS) * 1000;
+    state.handshake.store.put_session(accepted.session_id.clone(), session).await?;
+    let evicted = state.handshake.store
+        .trim_sessions(&proof.responder_did, state.handshake.max_sessions_per_did)
+        .await?;
+    if evicted > 0 {
+        info!(did = %proof.responder_did, evicted, "Ended oldest sessions over the per-DID limit");
+    }
+    if !client {
+        return Ok(accepted);
+    }
+    #[cfg(feature = "otel")]
+    crate::telemetry::record_handshake(challenge.timestamp);
+
+    info!(did = %proof.responder_did, session = %accepted.session_id, ?scope, "Session created");
 
-    info!(did = %proof.responder_did, session = %accepted.session_id, "Session created");
+    // A completed handshake shows a registered agent is still alive; the
+    // session is already issued, so a storage error here isn't fatal
+    if let Err(e) = state.registry.touch(&proof.responder_did, session_created_at).await {
+        warn!(did = %proof.responder_did, error = %e, "Failed to update last_seen");
+    }
 
-    Ok(Json(accepted))
+    Ok(accepted)
+}
+
+/// Keys of a did:web DID; unsupported when did:web isn't enabled
+async fn did_web_keys(state: &AppState, did: &str) -> Result<Vec<ed25519_dalek::VerifyingKey>, ReachError> {
+    let resolver = state.handshake.did_web.as_ref()
+        .ok_or_else(|| ReachError::UnsupportedDidMethod("web".into()))?;
+    resolver.keys(did).await
+}
+
+/// Check a did:web agent's proof: it answers our challenge for that DID, is
+/// signed by one of the DID's keys and carries a fresh counter-challenge
+/// addressed to us. did:key proofs are checked by the handshake crate,
+/// which can only take keys from the DID itself.
+fn verify_did_web_proof(
+    proof: &Proof,
+    challenge: &Challenge,
+    keys: &[ed25519_dalek::VerifyingKey],
+) -> Result<(), ReachError> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use agent_id_handshake::protocol::{hash_challenge, DEFAULT_TIMESTAMP_TOLERANCE_MS};
+
+    let expected_hash = hash_challenge(challenge).map_err(|e| ReachError::Internal(e.to_string()))?;
+    if proof.challenge_hash != expected_hash {
+        return Err(ReachError::InvalidChallenge);
+    }
+    if proof.responder_did != challenge.audience {
+        return Err(ReachError::HandshakeError(format!(
+            "Proof is from {} but the challenge was for {}", proof.responder_did, challenge.audience
+        )));
+    }
+    if let Some(counter) = &proof.counter_challenge {
+        if (chrono::Utc::now().timestamp_millis() - counter.timestamp).abs() > DEFAULT_TIMESTAMP_TOLERANCE_MS {
+            return Err(ReachError::HandshakeError("Counter-challenge timestamp out of range".into()));
+        }
+        if counter.audience != challenge.issuer {
+            return Err(ReachError::HandshakeError(format!(
+                "Counter-challenge is for {}, not {}", counter.audience, challenge.issuer
+            )));
+        }
+    }
+
+    let signature = STANDARD.decode(&proof.signature).ok()
+        .and_then(|bytes| ed25519_dalek::Signature::from_slice(&bytes).ok())
+        .ok_or(ReachError::InvalidSignature)?;
+    if !keys.iter().any(|key| key.verify_strict(proof.challenge_hash.as_bytes(), &signature).is_ok()) {
+        return Err(ReachError::InvalidSignature);
+    }
+    Ok(())
 }
 
 // ============================================================================
 // Registration Endpoints (require authenticated session)
 // ============================================================================
 
-/// Extract session from Authorization header
-fn get_session(headers: &HeaderMap, state: &AppState) -> Result<AuthenticatedSession, ReachError> {
-    let auth = headers
-        .get("authorization")
-        .and_then(|v| v.to_str().ok())
-        .ok_or(ReachError::Unauthorized)?;
+/// Tag the current request span with the DID it concerns
+fn record_did(did: &str) {
+    Span::current().record("did", did);
+}
 
-    let session_id = auth
-        .strip_prefix("Bearer ")
-        .ok_or(ReachError::Unauthorized)?;
+/// Tag the current request span with the registry size, for backends that
+/// can report it cheaply
+fn record_registry_size(registry: &dyn RegistryBackend) {
+    if let Some(size) = registry.size_hint() {
+        Span::current().record("registry_size", size);
+    }
+}
 
-    let sessions = state.handshake.sessions.read();
-    let session = sessions
-        .get(session_id)
-        .cloned()
+/// Extract session from Authorization header
+async fn get_session(headers: &HeaderMap, state: &AppState) -> Result<AuthenticatedSession, ReachError> {
+    let session_id = session_id(headers)?;
+    let session = state.handshake.store.session(session_id).await?
         .ok_or(ReachError::Unauthorized)?;
+    record_did(&session.did);
 
-    // Check session age (expire after 5 minutes)
+    // Check session age
     let now = chrono::Utc::now().timestamp();
-    if now - session.created_at > 300 {
+    Span::current().record("session_age", now - session.created_at);
+    if now - session.created_at > SESSION_TTL_SECS {
         return Err(ReachError::SessionExpired);
     }
 
     Ok(session)
 }
 
+/// Fail with 403 unless the session may change the registry
+fn require_write(session: &AuthenticatedSession) -> Result<(), ReachError> {
+    match session.scope {
+        Scope::Write => Ok(()),
+        Scope::Read => Err(ReachError::InsufficientScope),
+    }
+}
+
+/// Session ID from the `Authorization: Bearer` header
+fn session_id(headers: &HeaderMap) -> Result<&str, ReachError> {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+        .ok_or(ReachError::Unauthorized)
+}
+
+/// Whether the request asks to register only if no live entry exists,
+/// via `?if_absent=true` or `If-None-Match: *`
+fn wants_if_absent(headers: &HeaderMap, params: &RegisterParams) -> bool {
+    params.if_absent
+        || headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.trim() == "*")
+}
+
+/// What must hold for a registration to be stored: `If-Match` with the
+/// stored entry's version, or no live entry
+fn precondition(headers: &HeaderMap, params: &RegisterParams) -> Result<Precondition, ReachError> {
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(|v| v.trim().trim_matches('"').parse::<u64>().ok())
+                .ok_or_else(|| ReachError::InvalidRequest("If-Match must be a registration version".into()))
+        })
+        .transpose()?;
+
+    match (if_match, wants_if_absent(headers, params)) {
+        (Some(_), true) => Err(ReachError::InvalidRequest(
+            "If-Match can't be combined with if_absent or If-None-Match".into(),
+        )),
+        (Some(version), false) => Ok(Precondition::Version(version)),
+        (None, true) => Ok(Precondition::Absent),
+        (None, false) => Ok(Precondition::Always),
+    }
+}
+
 /// POST /register
 /// 
-/// Register endpoint for authenticated agent.
+/// Register endpoint for authenticated agent. With `?if_absent=true` (or
+/// `If-None-Match: *`), fails with 409 if the DID already has a live entry.
+/// With `If-Match: <version>`, fails with 409 unless the DID's stored entry
+/// has that version.
+/// With a `delegation`, the entry is stored under the sub-agent's DID. A
+/// `handle` already held by another DID's live entry fails with 409, as does
+/// an `endpoint` held by one when the server enforces unique endpoints.
+/// A retry carrying the `Idempotency-Key` of a registration that succeeded
+/// gets that registration's response back instead of registering again.
+/// With `?dry_run=true`, everything is checked as for a real registration
+/// and the response says what would be stored, but nothing is.
+#[utoipa::path(
+    post,
+    path = "/register",
+    tag = "registration",
+    params(
+        RegisterParams,
+        ("If-None-Match" = Option<String>, Header, description = "`*` to register only if absent"),
+        ("If-Match" = Option<u64>, Header, description = "Register only if the stored entry has this version"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Client-chosen key; retries with it replay the first successful response"),
+    ),
+    request_body = RegisterRequest,
+    responses(
+        (status = 200, description = "Registered", body = RegisterResponse),
+        (status = 401, description = "Missing, unknown or expired session", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "Delegation signatures do not verify, or the session is read-only", body = crate::openapi::ErrorResponse),
+        (status = 409, description = "Conditional registration and a live entry exists or the version doesn't match, the handle (or, with unique endpoints enforced, the endpoint) belongs to another DID, or a request with the same idempotency key is still running", body = crate::openapi::ErrorResponse),
+        (status = 413, description = "Request body too large", body = crate::openapi::ErrorResponse),
+        (status = 422, description = "Idempotency key already used for a different request", body = crate::openapi::ErrorResponse),
+        (status = 503, description = "The registry is at capacity and the DID has no live entry", body = crate::openapi::ErrorResponse),
+    ),
+    security(("session" = []))
+)]
 pub async fn register(
     State(state): State<AppState>,
     headers: HeaderMap,
+    Query(params): Query<RegisterParams>,
     Json(req): Json<RegisterRequest>,
 ) -> Result<Json<RegisterResponse>, ReachError> {
     // Verify session
-    let session = get_session(&headers, &state)?;
-
-    info!(did = %session.did, endpoint = %req.endpoint, "Registering endpoint");
+    let session = get_session(&headers, &state).await?;
+    require_write(&session)?;
+    state.handshake.protocols.check_register(&session.protocols)?;
+    crate::limits::check_register(&req)?;
 
-    // Calculate expiration
     let now = chrono::Utc::now().timestamp();
-    let expires_at = now + req.ttl as i64;
+    let precondition = precondition(&headers, &params)?;
+
+    // A retry under the same idempotency key gets the first response again;
+    // dry runs neither claim nor replay keys
+    let claim = match crate::idempotency::key(&headers)? {
+        Some(_) if params.dry_run => None,
+        Some(key) => {
+            let fingerprint = crate::idempotency::fingerprint(&req, precondition);
+            match state.idempotency.begin(&session.did, key, fingerprint, now)? {
+                Begin::Replay(response) => {
+                    info!(did = %response.did, "Replayed registration for a repeated idempotency key");
+                    return Ok(Json(response));
+                }
+                Begin::Claimed(claim) => Some(claim),
+            }
+        }
+        None => None,
+    };
+
+    let response = store_registration(&state, &session, req, precondition, params.dry_run, now).await?;
+    if let Some(claim) = claim {
+        claim.complete(&response);
+    }
+    Ok(Json(response))
+}
+
+/// The body of [`register`] once the session is known
+async fn store_registration(
+    state: &AppState,
+    session: &AuthenticatedSession,
+    req: RegisterRequest,
+    precondition: Precondition,
+    dry_run: bool,
+    now: i64,
+) -> Result<RegisterResponse, ReachError> {
+    // A delegation stores the entry under the sub-agent's DID
+    let (did, registered_by) = match (&req.delegation, &req.certificate) {
+        (Some(_), Some(_)) => {
+            return Err(ReachError::InvalidRequest("delegation and certificate can't be combined".into()))
+        }
+        (Some(delegation), None) => {
+            let did = crate::delegation::verify(&session.did, &req, delegation, now)?;
+            if req.did.as_ref().is_some_and(|requested| *requested != did) {
+                return Err(ReachError::InvalidRequest("did does not match the delegation".into()));
+            }
+            record_did(&did);
+            info!(did = %did, delegator = %session.did, "Delegated registration");
+            (did, Some(session.did.clone()))
+        }
+        (None, certificate) => acting_for(
+            state,
+            session,
+            req.did.as_deref(),
+            certificate.as_ref(),
+            DelegatedOperation::Register,
+            now,
+        )
+        .await?,
+    };
+    state.denylist.check(&did, now)?;
+
+    let ttl = state.ttl.apply(req.ttl)?;
+    let handle = req.handle.as_deref().map(crate::handle::normalize).transpose()?;
+    let mut adjustments = Vec::new();
+    if ttl != req.ttl {
+        adjustments.push(format!("ttl clamped from {} to {}", req.ttl, ttl));
+    }
+    if let Some(normalized) = handle.as_ref().filter(|h| req.handle.as_deref() != Some(h.as_str())) {
+        adjustments.push(format!("handle normalized to {}", normalized));
+    }
+    let mut endpoints = crate::endpoints::from_request(&req, &state.regions)?;
+    Span::current().record("endpoint", req.endpoint.as_str());
+    info!(did = %did, endpoint = %req.endpoint, ttl, handle, extra_endpoints = req.endpoints.len(), "Registering endpoint");
+
+    let expires_at = crate::ttl::expires_at(now, ttl);
+
+    // Best effort: two DIDs registering the same endpoint at once can both
+    // get through
+    if state.unique_endpoints {
+        if let Some(claimant) = state.registry.endpoint_claimant(&req.endpoint, &did).await? {
+            info!(did = %did, claimant = %claimant, "Endpoint already registered to another DID");
+            return Err(ReachError::EndpointTaken);
+        }
+    }
+
+    if dry_run {
+        check_dry_run(state, &did, handle.as_deref(), precondition, now).await?;
+        info!(did = %did, "Dry-run registration passed");
+        return Ok(RegisterResponse {
+            ok: true,
+            did,
+            expires_at,
+            ttl,
+            version: 0,
+            handle,
+            adjustments,
+            dry_run: true,
+        });
+    }
+
+    if !endpoints.is_empty() {
+        let previous = state.registry.lookup(&did).await?.filter(|entry| entry.expires_at > now);
+        crate::endpoints::stamp(&mut endpoints, previous.as_ref().map(|entry| entry.endpoints.as_slice()), now);
+    }
 
     // Store in registry
     let entry = RegistryEntry {
-        did: session.did.clone(),
+        did: did.clone(),
         endpoint: req.endpoint,
         registered_at: now,
         expires_at,
+        last_seen: now,
+        handle: handle.clone(),
+        endpoints,
+        version: 0,
+        registered_by,
+    };
+    let Some(version) = state.registry.register_when(entry, precondition).await? else {
+        info!(did = %did, ?precondition, "Conditional registration rejected");
+        return Err(match precondition {
+            Precondition::Version(_) => ReachError::VersionMismatch,
+            _ => ReachError::Conflict,
+        });
     };
-    state.registry.register(entry);
 
-    info!(did = %session.did, "Agent registered");
+    state.stats.record_registration(now);
+    record_registry_size(state.registry.as_ref());
+    info!(did = %did, "Agent registered");
 
-    Ok(Json(RegisterResponse {
+    Ok(RegisterResponse {
         ok: true,
-        did: session.did,
+        did,
         expires_at,
-    }))
+        ttl,
+        version,
+        handle,
+        adjustments,
+        dry_run: false,
+    })
 }
 
+/// Fail as storing the registration would, without storing it
+async fn check_dry_run(
+    state: &AppState,
+    did: &str,
+    handle: Option<&str>,
+    precondition: Precondition,
+    now: i64,
+) -> Result<(), ReachError> {
+    if let Some(handle) = handle {
+        let holder = state.registry.resolve_handle(handle).await?;
+        if holder.is_some_and(|holder| holder.did != did && holder.expires_at > now) {
+            return Err(ReachError::HandleTaken);
+        }
+    }
+
+    let stored = state.registry.lookup(did).await?;
+    match precondition {
+        Precondition::Absent if stored.as_ref().is_some_and(|entry| entry.expires_at > now) => {
+            return Err(ReachError::Conflict)
+        }
+        Precondition::Version(version) if stored.as_ref().map(|entry| entry.version) != Some(version) => {
+            return Err(ReachError::VersionMismatch)
+        }
+        Precondition::Always | Precondition::Absent | Precondition::Version(_) => {}
+    }
+    state.registry.check_admission(did, now).await
+}
+
+/// The DID a request acts on, and the session DID when that is a delegate
+/// holding a certificate for it
+async fn acting_for(
+    state: &AppState,
+    session: &AuthenticatedSession,
+    did: Option<&str>,
+    certificate: Option<&DelegationCertificate>,
+    operation: DelegatedOperation,
+    now: i64,
+) -> Result<(String, Option<String>), ReachError> {
+    let Some(certificate) = certificate else {
+        return match did {
+            Some(did) if did != session.did => Err(ReachError::InvalidDelegation(
+                "acting for another DID needs a certificate".into(),
+            )),
+            _ => Ok((session.did.clone(), None)),
+        };
+    };
+
+    let did = did.unwrap_or(&certificate.subject);
+    let revoked_at = state
+        .registry
+        .certificates_revoked_at(&certificate.subject, &certificate.delegate, now)
+        .await?;
+    crate::delegation::verify_certificate(&session.did, did, operation, certificate, revoked_at, now)?;
+    record_did(did);
+    info!(did = %did, delegate = %session.did, operation = operation.as_str(), "Acting under a delegation certificate");
+    Ok((did.to_string(), Some(session.did.clone())))
+}
+
+/// Longest time a lookup may be cached, whatever the entry's remaining TTL
+const LOOKUP_MAX_AGE_SECS: i64 = 300;
+
+/// Registries crossed by a lookup forwarded from a peer
+pub const FORWARDED_HEADER: &str = "x-reach-forwarded";
+
 /// GET /lookup/:did
 /// 
 /// Look up an agent by DID. No authentication required.
+///
+/// Live entries carry an `ETag` and a `Cache-Control` max-age bounded by the
+/// time left until expiry; `If-None-Match` with a current tag gets a 304.
+/// Errors are `no-store` so proxies don't hold on to negative results.
+///
+/// With `?pick=weighted`, an agent registered with several endpoints gets one
+/// chosen at random by weight in `endpoint`, and `endpoints` is left out.
+/// Such responses are `no-store`, so each client draws its own.
+///
+/// With `?region=`, `endpoint` is the one serving that region, or any
+/// endpoint if none does, and `region` says which region it serves. Combined
+/// with `pick=weighted`, the weighted pick is made among the region's
+/// endpoints.
+///
+/// With `?sort=freshness`, `endpoints` are ordered by when each joined the
+/// registration, newest first, and `endpoint` is the first of them.
+///
+/// When the server has peers, a DID not registered here is looked up at
+/// them and the entry returned with the peer in `source`.
+#[utoipa::path(
+    get,
+    path = "/lookup/{did}",
+    tag = "lookup",
+    params(
+        ("did" = String, Path, description = "DID to look up (URL-encoded)"),
+        LookupParams,
+        ("If-None-Match" = Option<String>, Header, description = "ETag from an earlier lookup"),
+        ("X-Reach-Forwarded" = Option<u32>, Header, description = "Registries this lookup was already forwarded through"),
+    ),
+    responses(
+        (status = 200, description = "Agent found", body = LookupResponse),
+        (status = 304, description = "Entry unchanged since the given ETag"),
+        (status = 404, description = "Agent never registered, or its expired entry was swept", body = crate::openapi::ErrorResponse),
+        (status = 410, description = "Registration expired; `expires_at` says when", body = crate::openapi::ErrorResponse),
+    )
+)]
 pub async fn lookup(
     State(state): State<AppState>,
     Path(did): Path<String>,
-) -> Result<Json<LookupResponse>, ReachError> {
+    Query(params): Query<LookupParams>,
+    headers: HeaderMap,
+) -> Response {
+    let (entry, source) = match live_entry(&state, did.clone()).await {
+        Ok(entry) => (entry, None),
+        Err(ReachError::NotFound) => match peer_entry(&state, &did, &headers).await {
+            Some((source, entry)) => (entry, Some(source)),
+            None => return ([(header::CACHE_CONTROL, "no-store")], ReachError::NotFound).into_response(),
+        },
+        Err(e) => return ([(header::CACHE_CONTROL, "no-store")], e).into_response(),
+    };
+
+    let weighted = params.pick == Some(Pick::Weighted);
+    if weighted {
+        let mut response = LookupResponse::from(entry);
+        response.source = source;
+        crate::endpoints::select(&mut response, params.region.as_deref(), true);
+        return ([(header::CACHE_CONTROL, "no-store")], Json(response)).into_response();
+    }
+
+    let max_age = (entry.expires_at - chrono::Utc::now().timestamp()).clamp(0, LOOKUP_MAX_AGE_SECS);
+    let mut response = LookupResponse::from(entry);
+    response.source = source;
+    if params.sort == Some(Sort::Freshness) {
+        crate::endpoints::sort_by_freshness(&mut response);
+    }
+    if let Some(region) = params.region.as_deref() {
+        crate::endpoints::select(&mut response, Some(region), false);
+    }
+
+    let etag = lookup_etag(&response);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, format!("public, max-age={}", max_age)),
+    ];
+    if matches_etag(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (cache_headers, Json(response)).into_response()
+}
+
+/// Resolve a (URL-encoded) DID to its registration, failing if it is
+/// missing or expired
+pub async fn live_entry(state: &AppState, did: String) -> Result<RegistryEntry, ReachError> {
     // URL decode the DID
     let did = urlencoding::decode(&did)
-        .map_err(|_| ReachError::InvalidDid)?
+        .map_err(|_| ReachError::InvalidDid("not valid percent-encoding".into()))?
         .into_owned();
+    crate::limits::check_did(&did)?;
+    crate::did::split(&did)?;
+    record_did(&did);
+
+    // Most lookups on a public registry are for DIDs that never registered
+    if !state.registry.might_contain(&did) {
+        return Err(ReachError::NotFound);
+    }
+    let entry = state.registry.lookup(&did).await?.ok_or(ReachError::NotFound)?;
+    Span::current().record("endpoint", entry.endpoint.as_str());
+
+    if entry.status() == AgentStatus::Expired {
+        return Err(ReachError::Expired(entry.expires_at));
+    }
+
+    Ok(entry)
+}
+
+/// Ask peer registries about a (URL-encoded) DID not registered here
+async fn peer_entry(state: &AppState, did: &str, headers: &HeaderMap) -> Option<(String, RegistryEntry)> {
+    let peers = state.peers.as_ref()?;
+    let did = urlencoding::decode(did).ok()?;
+    // A header we can't read is treated as past any hop limit
+    let hops = headers.get(FORWARDED_HEADER).map_or(0, |hops| {
+        hops.to_str().ok().and_then(|hops| hops.trim().parse().ok()).unwrap_or(u32::MAX)
+    });
+    peers.lookup(&did, hops).await
+}
+
+/// Strong validator for a lookup answer: a digest of its JSON body, so it
+/// changes whenever anything the client would see does. The countdown in
+/// `ttl_remaining_secs` is left out, since it follows from `expires_at` and
+/// would otherwise change the tag every second.
+fn lookup_etag(response: &LookupResponse) -> String {
+    // Serializing a struct with string keys can't fail
+    let mut body = serde_json::to_value(response).unwrap_or_default();
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("ttl_remaining_secs");
+    }
+    let digest = Sha256::digest(body.to_string());
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Whether `If-None-Match` lists `etag` (or `*`), using weak comparison
+fn matches_etag(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
 
-    let entry = state.registry.lookup(&did).ok_or(ReachError::NotFound)?;
+/// GET /resolve?handle=
+/// 
+/// Resolve a `name@domain` handle to the DID registered with it. No
+/// authentication required.
+#[utoipa::path(
+    get,
+    path = "/resolve",
+    tag = "lookup",
+    params(ResolveParams),
+    responses(
+        (status = 200, description = "Handle resolved", body = ResolveResponse),
+        (status = 400, description = "Malformed handle", body = crate::openapi::ErrorResponse),
+        (status = 404, description = "No registration has this handle", body = crate::openapi::ErrorResponse),
+        (status = 410, description = "Registration expired", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn resolve(
+    State(state): State<AppState>,
+    Query(params): Query<ResolveParams>,
+) -> Result<Json<ResolveResponse>, ReachError> {
+    let handle = crate::handle::normalize(&params.handle)?;
 
-    let status = entry.status();
-    if status == AgentStatus::Expired {
-        return Err(ReachError::Expired);
+    let entry = state.registry.resolve_handle(&handle).await?.ok_or(ReachError::NotFound)?;
+    record_did(&entry.did);
+    Span::current().record("endpoint", entry.endpoint.as_str());
+    if entry.status() == AgentStatus::Expired {
+        return Err(ReachError::Expired(entry.expires_at));
     }
 
-    Ok(Json(LookupResponse {
-        did: entry.did,
-        endpoint: entry.endpoint,
-        status,
-        registered_at: entry.registered_at,
-        expires_at: entry.expires_at,
+    Ok(Json(ResolveResponse {
+        handle,
+        did: entry.did.clone(),
+        agent: entry.into(),
+    }))
+}
+
+/// Most registrations one `GET /agents` page returns
+const MAX_AGENTS_LIMIT: usize = 1000;
+
+/// GET /agents
+/// 
+/// List live registrations in DID order, a page at a time with `offset` and
+/// `limit` or all at once. No authentication required.
+#[utoipa::path(
+    get,
+    path = "/agents",
+    tag = "lookup",
+    params(AgentsParams),
+    responses((status = 200, description = "Live registrations", body = AgentsResponse))
+)]
+pub async fn agents(
+    State(state): State<AppState>,
+    Query(params): Query<AgentsParams>,
+) -> Result<Json<AgentsResponse>, ReachError> {
+    let mut agents = state.registry.list().await?;
+    let total = agents.len();
+    Span::current().record("registry_size", total);
+    agents.sort_unstable_by(|a, b| a.did.cmp(&b.did));
+
+    let limit = params.limit.map_or(usize::MAX, |limit| limit.clamp(1, MAX_AGENTS_LIMIT));
+    Ok(Json(AgentsResponse {
+        agents: agents.into_iter().skip(params.offset).take(limit).map(Into::into).collect(),
+        total,
+    }))
+}
+
+/// GET /agents/:did/history
+/// 
+/// Past registrations for a DID, newest first. No authentication required.
+#[utoipa::path(
+    get,
+    path = "/agents/{did}/history",
+    tag = "lookup",
+    params(("did" = String, Path, description = "DID (URL-encoded)")),
+    responses(
+        (status = 200, description = "Past registrations, newest first", body = HistoryResponse),
+        (status = 404, description = "No history for this DID", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn history(
+    State(state): State<AppState>,
+    Path(did): Path<String>,
+) -> Result<Json<HistoryResponse>, ReachError> {
+    let did = urlencoding::decode(&did)
+        .map_err(|_| ReachError::InvalidDid("not valid percent-encoding".into()))?
+        .into_owned();
+    crate::did::split(&did)?;
+    record_did(&did);
+
+    let history = state.registry.history(&did).await?;
+    if history.is_empty() {
+        return Err(ReachError::NotFound);
+    }
+
+    Ok(Json(HistoryResponse {
+        did,
+        history: history
+            .into_iter()
+            .map(|entry| HistoryEntry {
+                endpoint: entry.endpoint,
+                registered_at: entry.registered_at,
+                expires_at: entry.expires_at,
+                registered_by: entry.registered_by,
+            })
+            .collect(),
     }))
 }
 
 /// POST /deregister
 /// 
-/// Remove registration. Requires authenticated session.
+/// Remove registration. Requires authenticated session. An optional body
+/// with a `did` and a `certificate` from it removes that DID's entry instead.
+#[utoipa::path(
+    post,
+    path = "/deregister",
+    tag = "registration",
+    request_body(content = Option<DeregisterRequest>, description = "Deregister another DID under its certificate"),
+    responses(
+        (status = 200, description = "`ok` is false if there was no registration", body = DeregisterResponse),
+        (status = 400, description = "Malformed body", body = crate::openapi::ErrorResponse),
+        (status = 401, description = "Missing, unknown or expired session", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "The session is read-only, or the certificate doesn't allow deregistering the DID", body = crate::openapi::ErrorResponse),
+    ),
+    security(("session" = []))
+)]
 pub async fn deregister(
     State(state): State<AppState>,
     headers: HeaderMap,
+    body: Bytes,
 ) -> Result<Json<DeregisterResponse>, ReachError> {
-    let session = get_session(&headers, &state)?;
+    let session = get_session(&headers, &state).await?;
+    require_write(&session)?;
+
+    // An empty body deregisters the session's own DID; anything else must
+    // parse, so a mistyped request can't fall back to that
+    let req: DeregisterRequest = if body.is_empty() {
+        DeregisterRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| ReachError::InvalidRequest(e.to_string()))?
+    };
+    let now = chrono::Utc::now().timestamp();
+    let (did, _) = acting_for(
+        &state,
+        &session,
+        req.did.as_deref(),
+        req.certificate.as_ref(),
+        DelegatedOperation::Deregister,
+        now,
+    )
+    .await?;
 
-    let existed = state.registry.deregister(&session.did);
+    let existed = state.registry.deregister(&did).await?;
     
     if existed {
-        info!(did = %session.did, "Agent deregistered");
+        info!(did = %did, "Agent deregistered");
     }
 
     Ok(Json(DeregisterResponse { ok: existed }))
 }
+
+/// POST /delegations/revoke
+///
+/// Stop `delegate` from using the delegation certificates the caller has
+/// issued to it so far. Registrations it already made stay until they
+/// expire or are deregistered.
+#[utoipa::path(
+    post,
+    path = "/delegations/revoke",
+    tag = "registration",
+    request_body = RevokeDelegationRequest,
+    responses(
+        (status = 204, description = "Certificates revoked"),
+        (status = 401, description = "Missing, unknown or expired session", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "The session is read-only", body = crate::openapi::ErrorResponse),
+    ),
+    security(("session" = []))
+)]
+pub async fn revoke_delegation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<RevokeDelegationRequest>,
+) -> Result<StatusCode, ReachError> {
+    let session = get_session(&headers, &state).await?;
+    require_write(&session)?;
+
+    let now = chrono::Utc::now().timestamp();
+    let expires_at = crate::delegation::revocation_expires_at(now);
+    state.registry.revoke_certificates(&session.did, &req.delegate, now, expires_at).await?;
+    info!(did = %session.did, delegate = %req.delegate, "Delegation certificates revoked");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /logout
+///
+/// End the caller's session now rather than when it expires. The
+/// registration, if any, is left alone.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "handshake",
+    responses(
+        (status = 204, description = "Session ended"),
+        (status = 401, description = "Missing, unknown or expired session", body = crate::openapi::ErrorResponse),
+    ),
+    security(("session" = []))
+)]
+pub async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Result<StatusCode, ReachError> {
+    let session = get_session(&headers, &state).await?;
+    state.handshake.store.remove_session(session_id(&headers)?).await?;
+    info!(did = %session.did, "Session ended");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /session
+///
+/// The caller's session and how long it has left, so clients can
+/// re-authenticate before it lapses.
+#[utoipa::path(
+    get,
+    path = "/session",
+    tag = "handshake",
+    responses(
+        (status = 200, description = "Session is valid", body = SessionResponse),
+        (status = 401, description = "Missing, unknown or expired session", body = crate::openapi::ErrorResponse),
+    ),
+    security(("session" = []))
+)]
+pub async fn session(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<SessionResponse>, ReachError> {
+    let session = get_session(&headers, &state).await?;
+
+    let expires_at = session.created_at + SESSION_TTL_SECS;
+    Ok(Json(SessionResponse {
+        remaining_secs: (expires_at - chrono::Utc::now().timestamp()).max(0),
+        did: session.did,
+        scope: session.scope,
+        protocols: session.protocols,
+        created_at: session.created_at,
+        expires_at,
+    }))
+}
+
+/// GET /me
+///
+/// The caller's own registration, found by the session's DID, so an agent
+/// can check its status without the public lookup.
+#[utoipa::path(
+    get,
+    path = "/me",
+    tag = "registration",
+    responses(
+        (status = 200, description = "The caller's registration", body = LookupResponse),
+        (status = 401, description = "Missing, unknown or expired session", body = crate::openapi::ErrorResponse),
+        (status = 404, description = "The caller isn't registered", body = crate::openapi::ErrorResponse),
+        (status = 410, description = "The caller's registration has expired", body = crate::openapi::ErrorResponse),
+    ),
+    security(("session" = []))
+)]
+pub async fn me(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<LookupResponse>, ReachError> {
+    let session = get_session(&headers, &state).await?;
+
+    let entry = state.registry.lookup(&session.did).await?
+        .ok_or(ReachError::NotFound)?;
+    if entry.expires_at <= chrono::Utc::now().timestamp() {
+        return Err(ReachError::Expired(entry.expires_at));
+    }
+
+    Ok(Json(entry.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+
+
+    /// App state with an authenticated session, returning the auth headers
+    async fn authenticated_state(did: &str) -> (AppState, HeaderMap) {
+        let state = AppState::for_tests();
+        let session = AuthenticatedSession {
+            did: did.to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            scope: Scope::Write,
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session("test-session".to_string(), session).await.unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer test-session".parse().unwrap());
+        (state, headers)
+    }
+
+    fn register_request(endpoint: &str) -> Json<RegisterRequest> {
+        Json(RegisterRequest {
+            endpoint: endpoint.to_string(),
+            ttl: 3600,
+            delegation: None,
+            did: None,
+            certificate: None,
+            handle: None,
+            weight: None,
+            endpoints: Vec::new(),
+            region: None,
+        })
+    }
+
+    fn register_with_handle(endpoint: &str, handle: &str) -> Json<RegisterRequest> {
+        let Json(req) = register_request(endpoint);
+        Json(RegisterRequest { handle: Some(handle.to_string()), ..req })
+    }
+
+    fn resolve_query(handle: &str) -> Query<ResolveParams> {
+        Query(ResolveParams { handle: handle.to_string() })
+    }
+
+    #[tokio::test]
+    async fn conditional_register_conflicts_with_live_entry() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let params = || Query(RegisterParams { if_absent: true, ..Default::default() });
+
+        let Json(first) = register(State(state.clone()), headers.clone(), params(), register_request("wss://one"))
+            .await
+            .expect("first conditional register succeeds");
+        assert!(first.ok);
+
+        let err = register(State(state.clone()), headers, params(), register_request("wss://two"))
+            .await
+            .expect_err("second conditional register conflicts");
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+
+        let entry = state.registry.lookup("did:key:a").await.unwrap().unwrap();
+        assert_eq!(entry.endpoint, "wss://one");
+    }
+
+    #[tokio::test]
+    async fn retries_with_an_idempotency_key_are_replayed() {
+        let (state, mut headers) = authenticated_state("did:key:a").await;
+        headers.insert("idempotency-key", "attempt-1".parse().unwrap());
+        let params = || Query(RegisterParams { if_absent: true, ..Default::default() });
+
+        let Json(first) = register(State(state.clone()), headers.clone(), params(), register_request("wss://one"))
+            .await
+            .unwrap();
+        // Without the key this would conflict with the entry just stored
+        let Json(retried) = register(State(state.clone()), headers.clone(), params(), register_request("wss://one"))
+            .await
+            .expect("retry is replayed");
+        assert_eq!(retried, first);
+
+        let err = register(State(state.clone()), headers, params(), register_request("wss://two"))
+            .await
+            .expect_err("key reused for another request");
+        assert_eq!(err.into_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(state.registry.lookup("did:key:a").await.unwrap().unwrap().endpoint, "wss://one");
+    }
+
+    #[tokio::test]
+    async fn replayed_retries_leave_the_entry_and_change_feed_alone() {
+        let (mut state, mut headers) = authenticated_state("did:key:a").await;
+        state.registry = Arc::new(crate::changes::RecordingRegistry::new(state.registry.clone(), state.changes.clone()));
+        headers.insert("idempotency-key", "attempt-1".parse().unwrap());
+
+        let _ = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), register_request("wss://one"))
+            .await
+            .unwrap();
+        let first = state.registry.lookup("did:key:a").await.unwrap().unwrap();
+        let _ = register(State(state.clone()), headers, Query(RegisterParams::default()), register_request("wss://one"))
+            .await
+            .expect("retry is replayed");
+
+        let entry = state.registry.lookup("did:key:a").await.unwrap().unwrap();
+        assert_eq!((entry.registered_at, entry.version), (first.registered_at, first.version));
+        let (changes, _) = state.changes.since(0, 10);
+        assert_eq!(changes.unwrap().len(), 1, "one registration event");
+    }
+
+    #[tokio::test]
+    async fn if_match_guards_against_stale_versions() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let register_if_match = |version: &str, endpoint| {
+            let mut headers = headers.clone();
+            headers.insert(header::IF_MATCH, version.parse().unwrap());
+            register(State(state.clone()), headers, Query(RegisterParams::default()), register_request(endpoint))
+        };
+
+        let params = Query(RegisterParams::default());
+        let Json(first) = register(State(state.clone()), headers.clone(), params, register_request("wss://one"))
+            .await
+            .unwrap();
+        assert_eq!(first.version, 1);
+
+        let Json(updated) = register_if_match("\"1\"", "wss://two").await.expect("version matches");
+        assert_eq!(updated.version, 2);
+
+        let err = register_if_match("1", "wss://three").await.expect_err("version 1 is stale");
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+        let entry = state.registry.lookup("did:key:a").await.unwrap().unwrap();
+        assert_eq!((entry.endpoint.as_str(), entry.version), ("wss://two", 2));
+    }
+
+    #[tokio::test]
+    async fn register_clamps_ttl_to_bounds() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let register_with = |ttl| {
+            let Json(mut req) = register_request("wss://one");
+            req.ttl = ttl;
+            register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), Json(req))
+        };
+
+        let Json(longest) = register_with(u64::MAX).await.unwrap();
+        assert_eq!(longest.ttl, crate::ttl::DEFAULT_MAX_TTL_SECS);
+        let entry = state.registry.lookup("did:key:a").await.unwrap().unwrap();
+        assert_eq!(entry.expires_at - entry.registered_at, crate::ttl::DEFAULT_MAX_TTL_SECS as i64);
+
+        let Json(shortest) = register_with(0).await.unwrap();
+        assert_eq!(shortest.ttl, crate::ttl::DEFAULT_MIN_TTL_SECS);
+    }
+
+    #[tokio::test]
+    async fn register_rejects_out_of_bounds_ttl() {
+        let (mut state, headers) = authenticated_state("did:key:a").await;
+        state.ttl = TtlPolicy::new(60, 3600, crate::ttl::TtlMode::Reject).unwrap();
+        let Json(mut req) = register_request("wss://one");
+        req.ttl = 3601;
+
+        let err = register(State(state.clone()), headers, Query(RegisterParams::default()), Json(req))
+            .await
+            .expect_err("TTL above the maximum is rejected");
+
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        assert!(state.registry.lookup("did:key:a").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn registered_handle_resolves_to_did() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let req = register_with_handle("wss://one", "Alice@Example.com");
+        let Json(registered) =
+            register(State(state.clone()), headers, Query(RegisterParams::default()), req).await.unwrap();
+        assert!(registered.ok);
+
+        let Json(resolved) = resolve(State(state.clone()), resolve_query("alice@EXAMPLE.com")).await.unwrap();
+        assert_eq!(resolved.handle, "alice@example.com");
+        assert_eq!(resolved.did, "did:key:a");
+        assert_eq!(resolved.agent.endpoint, "wss://one");
+        assert_eq!(resolved.agent.handle.as_deref(), Some("alice@example.com"));
+
+        let missing = resolve(State(state.clone()), resolve_query("bob@example.com")).await.unwrap_err();
+        assert_eq!(missing.into_response().status(), StatusCode::NOT_FOUND);
+        let malformed = resolve(State(state), resolve_query("alice")).await.unwrap_err();
+        assert_eq!(malformed.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn handle_held_by_another_did_conflicts() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let req = register_with_handle("wss://a", "alice@example.com");
+        let Json(registered) =
+            register(State(state.clone()), headers, Query(RegisterParams::default()), req).await.unwrap();
+        assert!(registered.ok);
+
+        let session = AuthenticatedSession {
+            did: "did:key:b".to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            scope: Scope::Write,
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session("other-session".to_string(), session).await.unwrap();
+        let mut other = HeaderMap::new();
+        other.insert(header::AUTHORIZATION, "Bearer other-session".parse().unwrap());
+
+        let req = register_with_handle("wss://b", "ALICE@example.com");
+        let err = register(State(state.clone()), other, Query(RegisterParams::default()), req)
+            .await
+            .expect_err("handle belongs to did:key:a");
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+
+        assert!(state.registry.lookup("did:key:b").await.unwrap().is_none());
+        let Json(resolved) = resolve(State(state), resolve_query("alice@example.com")).await.unwrap();
+        assert_eq!(resolved.did, "did:key:a");
+    }
+
+    #[tokio::test]
+    async fn session_reports_remaining_lifetime() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+
+        let Json(session) = session(State(state), headers).await.unwrap();
+        assert_eq!(session.did, "did:key:a");
+        assert_eq!(session.expires_at, session.created_at + SESSION_TTL_SECS);
+        assert!((SESSION_TTL_SECS - 5..=SESSION_TTL_SECS).contains(&session.remaining_secs));
+    }
+
+    #[tokio::test]
+    async fn me_returns_the_callers_registration() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+
+        let err = me(State(state.clone()), headers.clone()).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+
+        let _ = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), register_request("wss://one"))
+            .await
+            .unwrap();
+        let Json(entry) = me(State(state.clone()), headers).await.unwrap();
+        assert_eq!(entry.did, "did:key:a");
+        assert_eq!(entry.endpoint, "wss://one");
+        assert_eq!(entry.status, AgentStatus::Online);
+
+        let err = me(State(state), HeaderMap::new()).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn expired_session_is_unauthorized() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let stale = AuthenticatedSession {
+            did: "did:key:a".to_string(),
+            created_at: chrono::Utc::now().timestamp() - SESSION_TTL_SECS - 1,
+            scope: Scope::Write,
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session("test-session".to_string(), stale).await.unwrap();
+
+        let err = session(State(state.clone()), headers).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+        let err = session(State(state), HeaderMap::new()).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn logged_out_session_is_unauthorized() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+
+        let status = logout(State(state.clone()), headers.clone()).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let err = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), register_request("wss://a"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+        let err = logout(State(state), headers).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn unique_endpoints_reject_another_dids_endpoint() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let state = AppState { unique_endpoints: true, ..state };
+        let Json(registered) =
+            register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), register_request("wss://shared"))
+                .await
+                .unwrap();
+        assert!(registered.ok);
+
+        // The owner can register the same endpoint again
+        let Json(again) =
+            register(State(state.clone()), headers, Query(RegisterParams::default()), register_request("wss://shared"))
+                .await
+                .unwrap();
+        assert!(again.ok);
+
+        let session = AuthenticatedSession {
+            did: "did:key:b".to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            scope: Scope::Write,
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session("other-session".to_string(), session).await.unwrap();
+        let mut other = HeaderMap::new();
+        other.insert(header::AUTHORIZATION, "Bearer other-session".parse().unwrap());
+
+        let result = register(State(state.clone()), other, Query(RegisterParams::default()), register_request("wss://shared")).await;
+        assert!(matches!(result, Err(ReachError::EndpointTaken)));
+        assert!(state.registry.lookup("did:key:b").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn weighted_lookup_returns_one_endpoint() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let Json(req) = register_request("wss://one");
+        let req = RegisterRequest {
+            weight: Some(3),
+            endpoints: vec![Endpoint { uri: "wss://two".to_string(), weight: None, region: None, registered_at: None }],
+            ..req
+        };
+        let Json(registered) =
+            register(State(state.clone()), headers, Query(RegisterParams::default()), Json(req)).await.unwrap();
+        assert!(registered.ok);
+
+        let all = lookup_did(&state, "did:key:a", HeaderMap::new()).await;
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(all.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["endpoint"], "wss://one");
+        let registered_at = &body["endpoints"][0]["registered_at"];
+        assert!(registered_at.is_i64());
+        assert_eq!(
+            body["endpoints"],
+            serde_json::json!([
+                {"uri": "wss://one", "weight": 3, "registered_at": registered_at},
+                {"uri": "wss://two", "registered_at": registered_at},
+            ])
+        );
+
+        let params = Query(LookupParams { pick: Some(Pick::Weighted), region: None, sort: None });
+        let picked = lookup(State(state), Path("did:key:a".into()), params, HeaderMap::new()).await;
+        assert_eq!(picked.headers()[header::CACHE_CONTROL], "no-store");
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(picked.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert!(["wss://one", "wss://two"].contains(&body["endpoint"].as_str().unwrap()));
+        assert!(body.get("endpoints").is_none());
+    }
+
+    #[tokio::test]
+    async fn freshness_sort_puts_the_newest_endpoint_first() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let with_endpoints = |uris: &[&str]| {
+            let Json(req) = register_request("wss://one");
+            let endpoints = uris.iter()
+                .map(|uri| Endpoint { uri: uri.to_string(), weight: None, region: None, registered_at: None })
+                .collect();
+            Json(RegisterRequest { weight: Some(1), endpoints, ..req })
+        };
+        let _ = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), with_endpoints(&[]))
+            .await
+            .unwrap();
+        // Backdate the first endpoint, as if registered a while ago
+        let mut entry = state.registry.lookup("did:key:a").await.unwrap().unwrap();
+        entry.endpoints[0].registered_at = Some(entry.registered_at - 100);
+        state.registry.register(entry).await.unwrap();
+
+        let _ = register(State(state.clone()), headers, Query(RegisterParams::default()), with_endpoints(&["wss://two"]))
+            .await
+            .unwrap();
+
+        let sorted = |sort| {
+            let params = Query(LookupParams { pick: None, region: None, sort });
+            let state = state.clone();
+            async move {
+                let response = lookup(State(state), Path("did:key:a".into()), params, HeaderMap::new()).await;
+                let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+                let uris: Vec<String> = body["endpoints"].as_array().unwrap().iter().map(|e| e["uri"].as_str().unwrap().to_string()).collect();
+                (body["endpoint"].as_str().unwrap().to_string(), uris)
+            }
+        };
+
+        assert_eq!(sorted(None).await, ("wss://one".to_string(), vec!["wss://one".to_string(), "wss://two".to_string()]));
+        assert_eq!(
+            sorted(Some(Sort::Freshness)).await,
+            ("wss://two".to_string(), vec!["wss://two".to_string(), "wss://one".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn remaining_ttl_counts_down_between_lookups() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let _ = register(State(state.clone()), headers, Query(RegisterParams::default()), register_request("wss://one"))
+            .await
+            .unwrap();
+
+        let remaining = || {
+            let params = Query(LookupParams { pick: None, region: None, sort: None });
+            let state = state.clone();
+            async move {
+                let response = lookup(State(state), Path("did:key:a".into()), params, HeaderMap::new()).await;
+                let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+                body["ttl_remaining_secs"].as_i64().unwrap()
+            }
+        };
+
+        let first = remaining().await;
+        assert!(first > 0 && first <= crate::types::default_ttl() as i64);
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert!(remaining().await < first);
+
+        // Entries already due report nothing left rather than a negative TTL
+        let mut entry = state.registry.lookup("did:key:a").await.unwrap().unwrap();
+        entry.expires_at = chrono::Utc::now().timestamp() - 5;
+        assert_eq!(LookupResponse::from(entry).ttl_remaining_secs, 0);
+    }
+
+    #[tokio::test]
+    async fn region_lookup_prefers_matching_endpoint() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let state = AppState { regions: Arc::from(["us-east".to_string(), "eu-west".to_string()]), ..state };
+        let Json(req) = register_request("wss://us");
+        let req = RegisterRequest {
+            region: Some("us-east".to_string()),
+            endpoints: vec![Endpoint { uri: "wss://eu".to_string(), weight: None, region: Some("eu-west".to_string()), registered_at: None }],
+            ..req
+        };
+        let Json(registered) =
+            register(State(state.clone()), headers, Query(RegisterParams::default()), Json(req)).await.unwrap();
+        assert!(registered.ok);
+
+        let in_region = |region: &str| {
+            let params = Query(LookupParams { pick: None, region: Some(region.to_string()), sort: None });
+            lookup(State(state.clone()), Path("did:key:a".into()), params, HeaderMap::new())
+        };
+
+        let exact = in_region("eu-west").await;
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(exact.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["endpoint"], "wss://eu");
+        assert_eq!(body["region"], "eu-west");
+        assert!(body.get("endpoints").is_none());
+
+        let fallback = in_region("ap-south").await;
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(fallback.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["endpoint"], "wss://us");
+        assert_eq!(body["region"], "us-east");
+    }
+
+    #[tokio::test]
+    async fn regions_outside_the_allowlist_are_rejected() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let state = AppState { regions: Arc::from(["us-east".to_string()]), ..state };
+        let Json(req) = register_request("wss://one");
+        let req = RegisterRequest { region: Some("mars-1".to_string()), ..req };
+
+        let result = register(State(state), headers, Query(RegisterParams::default()), Json(req)).await;
+        assert!(matches!(result, Err(ReachError::InvalidRequest(_))));
+    }
+
+    async fn lookup_did(state: &AppState, did: &str, headers: HeaderMap) -> Response {
+        lookup(State(state.clone()), Path(did.into()), Query(LookupParams::default()), headers).await
+    }
+
+    fn registered(did: &str, expires_in: i64) -> RegistryEntry {
+        let now = chrono::Utc::now().timestamp();
+        RegistryEntry::new(did, "wss://cached", now - 60, now + expires_in)
+    }
+
+    #[tokio::test]
+    async fn agents_are_paged_in_did_order() {
+        let state = AppState::for_tests();
+        for did in ["did:key:c", "did:key:a", "did:key:b"] {
+            state.registry.register(registered(did, 3600)).await.unwrap();
+        }
+        state.registry.register(registered("did:key:expired", -10)).await.unwrap();
+        let page = |offset, limit| {
+            let state = state.clone();
+            async move {
+                let Json(page) = agents(State(state), Query(AgentsParams { offset, limit })).await.unwrap();
+                (page.agents.into_iter().map(|agent| agent.did).collect::<Vec<_>>(), page.total)
+            }
+        };
+
+        assert_eq!(page(0, None).await, (vec!["did:key:a".to_string(), "did:key:b".into(), "did:key:c".into()], 3));
+        assert_eq!(page(1, Some(1)).await, (vec!["did:key:b".to_string()], 3));
+        assert_eq!(page(2, Some(5)).await, (vec!["did:key:c".to_string()], 3));
+        assert_eq!(page(usize::MAX, Some(5)).await, (Vec::new(), 3));
+        assert_eq!(page(0, Some(0)).await.0.len(), 1, "limits are at least 1");
+    }
+
+    #[tokio::test]
+    async fn lookup_revalidates_with_etag() {
+        let (state, _) = authenticated_state("did:key:a").await;
+        state.registry.register(registered("did:key:a", 3600)).await.unwrap();
+
+        let first = lookup_did(&state, "did:key:a", HeaderMap::new()).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers()[header::CACHE_CONTROL], format!("public, max-age={}", LOOKUP_MAX_AGE_SECS));
+        let etag = first.headers()[header::ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let revalidated = lookup_did(&state, "did:key:a", headers.clone()).await;
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(revalidated.headers()[header::ETAG], etag);
+
+        // The tag holds as the TTL counts down
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let later = lookup_did(&state, "did:key:a", headers.clone()).await;
+        assert_eq!(later.status(), StatusCode::NOT_MODIFIED);
+
+        // Moving endpoint invalidates the tag
+        let mut moved = registered("did:key:a", 3600);
+        moved.endpoint = "wss://moved".to_string();
+        state.registry.register(moved.clone()).await.unwrap();
+        let changed = lookup_did(&state, "did:key:a", headers).await;
+        assert_eq!(changed.status(), StatusCode::OK);
+        assert_ne!(changed.headers()[header::ETAG], etag);
+
+        // So does any other change in the same second, such as a new handle
+        let etag = changed.headers()[header::ETAG].clone();
+        moved.handle = Some("alice@example.com".to_string());
+        state.registry.register(moved).await.unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let claimed = lookup_did(&state, "did:key:a", headers).await;
+        assert_eq!(claimed.status(), StatusCode::OK);
+        assert_ne!(claimed.headers()[header::ETAG], etag);
+    }
+
+    #[tokio::test]
+    async fn lookup_etags_follow_the_representation() {
+        let (state, _) = authenticated_state("did:key:a").await;
+        let mut entry = registered("did:key:a", 3600);
+        entry.endpoints = ["wss://eu", "wss://us"]
+            .iter()
+            .zip(["eu-west", "us-east"])
+            .map(|(uri, region)| Endpoint { uri: uri.to_string(), weight: None, region: Some(region.to_string()), registered_at: None })
+            .collect();
+        state.registry.register(entry).await.unwrap();
+
+        let all = lookup(State(state.clone()), Path("did:key:a".to_string()), Query(LookupParams::default()), HeaderMap::new()).await;
+        let params = LookupParams { region: Some("eu-west".to_string()), ..Default::default() };
+        let eu = lookup(State(state), Path("did:key:a".to_string()), Query(params), HeaderMap::new()).await;
+        assert_ne!(all.headers()[header::ETAG], eu.headers()[header::ETAG]);
+    }
+
+    #[tokio::test]
+    async fn lookup_max_age_stops_at_expiry() {
+        let (state, _) = authenticated_state("did:key:a").await;
+        state.registry.register(registered("did:key:a", 30)).await.unwrap();
+
+        let response = lookup_did(&state, "did:key:a", HeaderMap::new()).await;
+
+        let max_age: i64 = response.headers()[header::CACHE_CONTROL]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("public, max-age=")
+            .parse()
+            .unwrap();
+        assert!((29..=30).contains(&max_age), "{}", max_age);
+    }
+
+    #[tokio::test]
+    async fn lookup_includes_the_public_key() {
+        let (state, _) = authenticated_state("did:key:a").await;
+        let key = RootKey::generate();
+        let did = key.did().to_string();
+        state.registry.register(registered(&did, 3600)).await.unwrap();
+        state.registry.register(registered("did:key:a", 3600)).await.unwrap();
+
+        let response = lookup_did(&state, &did, HeaderMap::new()).await;
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["key_type"], "Ed25519");
+        assert_eq!(body["public_key"]["multibase"], key.did().key_id());
+        use base64::Engine;
+        let raw = base64::engine::general_purpose::STANDARD.decode(body["public_key"]["base64"].as_str().unwrap()).unwrap();
+        assert_eq!(raw, key.did().public_key().unwrap().to_bytes());
+
+        // A DID that doesn't decode still looks up, without a key
+        let response = lookup_did(&state, "did:key:a", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert!(body.get("public_key").is_none() && body.get("key_type").is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_are_not_stored() {
+        let (state, _) = authenticated_state("did:key:a").await;
+        state.registry.register(registered("did:key:expired", -10)).await.unwrap();
+
+        for (did, status) in [("did:key:missing", StatusCode::NOT_FOUND), ("did:key:expired", StatusCode::GONE)] {
+            let response = lookup_did(&state, did, HeaderMap::new()).await;
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+            assert!(response.headers().get(header::ETAG).is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_lookups_are_gone_rather_than_missing() {
+        let (state, _) = authenticated_state("did:key:a").await;
+        let entry = registered("did:key:expired", -90);
+        let expires_at = entry.expires_at;
+        state.registry.register(entry).await.unwrap();
+
+        let response = lookup_did(&state, "did:key:expired", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["code"], "expired");
+        assert_eq!(body["expires_at"], expires_at);
+
+        let response = lookup_did(&state, "did:key:missing", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "error": "Agent not found" }));
+    }
+
+    /// Complete a hello/proof handshake as `key`
+    async fn handshake(state: &AppState, key: &RootKey) -> ProofAccepted {
+        let hello = Hello::new(key.did().to_string());
+        let Json(challenge) = super::hello(State(state.clone()), Json(hello)).await.unwrap();
+        let proof = agent_id_handshake::protocol::sign_proof(&challenge, &key.did(), key, Some(challenge.issuer.clone()))
+            .unwrap();
+        let Json(accepted) = super::proof(State(state.clone()), None, Json(proof)).await.unwrap();
+        accepted
+    }
+
+    #[tokio::test]
+    async fn repeated_failed_proofs_are_locked_out() {
+        let mut handshake_state = HandshakeState::new();
+        handshake_state.lockout = crate::lockout::ProofLockout::new(2, 60, 60);
+        let state = AppState {
+            handshake: Arc::new(handshake_state),
+            ..AppState::for_tests()
+        };
+        let (key, impostor) = (RootKey::generate(), RootKey::generate());
+        let client = Some(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 40000))));
+
+        async fn prove(
+            state: &AppState,
+            client: Option<ConnectInfo<SocketAddr>>,
+            did: &str,
+            signer: &RootKey,
+        ) -> Result<Json<ProofAccepted>, ReachError> {
+            let Json(challenge) = super::hello(State(state.clone()), Json(Hello::new(did.to_string()))).await?;
+            let mut proof = agent_id_handshake::protocol::sign_proof(
+                &challenge, &signer.did(), signer, Some(challenge.issuer.clone()),
+            ).unwrap();
+            proof.responder_did = did.to_string();
+            super::proof(State(state.clone()), client, Json(proof)).await
+        }
+
+        let did = key.did().to_string();
+        assert!(prove(&state, client, &did, &impostor).await.is_err());
+        assert!(prove(&state, client, &did, &impostor).await.is_err());
+        let err = prove(&state, client, &did, &key).await.expect_err("locked out, even with the right key");
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        // Other clients can still authenticate as the DID
+        assert!(prove(&state, None, &did, &key).await.is_ok());
+
+        // Claiming a different DID in each proof doesn't spread the
+        // failures out: they count against the challenge's DID
+        let other = RootKey::generate().did().to_string();
+        let elsewhere = Some(ConnectInfo(SocketAddr::from(([203, 0, 113, 8], 40000))));
+        for claimed in ["did:key:z6MkClaimedOne", "did:key:z6MkClaimedTwo"] {
+            let Json(challenge) = super::hello(State(state.clone()), Json(Hello::new(other.clone()))).await.unwrap();
+            let mut proof = agent_id_handshake::protocol::sign_proof(
+                &challenge, &impostor.did(), &impostor, Some(challenge.issuer.clone()),
+            ).unwrap();
+            proof.responder_did = claimed.to_string();
+            assert!(super::proof(State(state.clone()), elsewhere, Json(proof)).await.is_err());
+        }
+        let err = state.handshake.lockout.check(elsewhere.map(|ConnectInfo(addr)| addr.ip()), &other, chrono::Utc::now().timestamp());
+        assert!(matches!(err, Err(ReachError::TooManyFailedProofs(_))));
+    }
+
+    #[tokio::test]
+    async fn challenges_carry_the_configured_nonce_length() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let mut handshake_state = HandshakeState::new();
+        handshake_state.nonce = crate::nonce::NoncePolicy::new(Some(48)).unwrap();
+        let state = AppState {
+            handshake: Arc::new(handshake_state),
+            ..AppState::for_tests()
+        };
+        let key = RootKey::generate();
+
+        let Json(challenge) = super::hello(State(state.clone()), Json(Hello::new(key.did().to_string()))).await.unwrap();
+        assert_eq!(STANDARD.decode(&challenge.nonce).unwrap().len(), 48);
+
+        // The replaced nonce is the one the proof is checked against
+        let proof = agent_id_handshake::protocol::sign_proof(&challenge, &key.did(), &key, Some(challenge.issuer.clone()))
+            .unwrap();
+        assert!(super::proof(State(state.clone()), None, Json(proof)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn read_sessions_cannot_change_the_registry() {
+        let (state, _) = authenticated_state("did:key:unused").await;
+        let key = RootKey::generate();
+        let did = key.did().to_string();
+
+        let mut hello = Hello::new(did.clone());
+        hello.capabilities = Some(vec!["scope:read".to_string()]);
+        let Json(challenge) = super::hello(State(state.clone()), Json(hello)).await.unwrap();
+        let proof = agent_id_handshake::protocol::sign_proof(&challenge, &key.did(), &key, Some(challenge.issuer.clone()))
+            .unwrap();
+        let Json(accepted) = super::proof(State(state.clone()), None, Json(proof)).await.unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", accepted.session_id).parse().unwrap());
+
+        let Json(session) = super::session(State(state.clone()), headers.clone()).await.unwrap();
+        assert_eq!(session.scope, Scope::Read);
+
+        let err = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), register_request("wss://agent"))
+            .await
+            .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["code"], "insufficient_scope");
+        assert!(matches!(deregister(State(state.clone()), headers.clone(), Bytes::new()).await, Err(ReachError::InsufficientScope)));
+        assert!(matches!(me(State(state.clone()), headers).await, Err(ReachError::NotFound)));
+
+        let mut unknown = Hello::new(did);
+        unknown.capabilities = Some(vec!["scope:admin".to_string()]);
+        assert!(matches!(super::hello(State(state), Json(unknown)).await, Err(ReachError::InvalidRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn negotiated_protocols_reach_the_session() {
+        let (state, _) = authenticated_state("did:key:unused").await;
+        let mut handshake = HandshakeState::new();
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        handshake.protocols =
+            crate::protocol::ProtocolPolicy::new(strings(&["reach/2", "reach/1"]), strings(&["reach/2"])).unwrap();
+        let state = AppState { handshake: Arc::new(handshake), ..state };
+        let key = RootKey::generate();
+
+        let mut hello = Hello::new(key.did().to_string());
+        hello.protocols = strings(&["aip/1.0", "reach/1"]);
+        let Json(challenge) = super::hello(State(state.clone()), Json(hello)).await.unwrap();
+        let proof = agent_id_handshake::protocol::sign_proof(&challenge, &key.did(), &key, Some(challenge.issuer.clone()))
+            .unwrap();
+        let Json(accepted) = super::proof(State(state.clone()), None, Json(proof)).await.unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", accepted.session_id).parse().unwrap());
+
+        let Json(session) = super::session(State(state.clone()), headers.clone()).await.unwrap();
+        assert_eq!(session.protocols, ["reach/1"]);
+        let err = register(State(state.clone()), headers, Query(RegisterParams::default()), register_request("wss://agent"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::FORBIDDEN);
+
+        let mut disjoint = Hello::new(key.did().to_string());
+        disjoint.protocols = strings(&["reach/9"]);
+        let response = super::hello(State(state), Json(disjoint)).await.unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["code"], "unsupported_protocol");
+        assert_eq!(body["supported"], serde_json::json!(["reach/2", "reach/1"]));
+    }
+
+    #[tokio::test]
+    async fn handshake_refreshes_last_seen() {
+        let state = AppState::for_tests();
+        let key = RootKey::generate();
+        let accepted = handshake(&state, &key).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", accepted.session_id).parse().unwrap());
+        let Json(response) = register(State(state.clone()), headers, Query(RegisterParams::default()), register_request("wss://agent"))
+            .await
+            .unwrap();
+        assert!(response.ok);
+        let did = key.did().to_string();
+        let registered = state.registry.lookup(&did).await.unwrap().unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        handshake(&state, &key).await;
+
+        let seen = state.registry.lookup(&did).await.unwrap().unwrap();
+        assert!(seen.last_seen > registered.last_seen);
+        assert_eq!(seen.registered_at, registered.registered_at);
+    }
+
+    /// Resolves one did:web DID to a fixed key
+    struct StaticResolver(String, ed25519_dalek::VerifyingKey);
+
+    #[async_trait]
+    impl DidResolver for StaticResolver {
+        async fn keys(&self, did: &str) -> Result<Vec<ed25519_dalek::VerifyingKey>, ReachError> {
+            if did == self.0 {
+                Ok(vec![self.1])
+            } else {
+                Err(ReachError::DidResolution(format!("{} not found", did)))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn did_web_agents_prove_with_their_document_keys() {
+        let did = "did:web:agents.example:alice";
+        let (key, impostor) = (RootKey::generate(), RootKey::generate());
+        let mut handshake_state = HandshakeState::new();
+        handshake_state.did_web = Some(Arc::new(StaticResolver(did.to_string(), key.verifying_key())));
+        let state = AppState {
+            handshake: Arc::new(handshake_state),
+            ..AppState::for_tests()
+        };
+
+        async fn prove(state: &AppState, did: &str, signer: &RootKey) -> Result<Json<ProofAccepted>, ReachError> {
+            let Json(challenge) = super::hello(State(state.clone()), Json(Hello::new(did.to_string()))).await?;
+            let mut proof = agent_id_handshake::protocol::sign_proof(
+                &challenge, &signer.did(), signer, Some(challenge.issuer.clone()),
+            ).unwrap();
+            proof.responder_did = did.to_string();
+            super::proof(State(state.clone()), None, Json(proof)).await
+        }
+
+        let Json(accepted) = prove(&state, did, &key).await.unwrap();
+        let session = state.handshake.store.session(&accepted.session_id).await.unwrap().unwrap();
+        assert_eq!(session.did, did);
+        assert!(matches!(prove(&state, did, &impostor).await.unwrap_err(), ReachError::InvalidSignature));
+
+        let unresolvable = Hello::new("did:web:elsewhere.example".to_string());
+        let err = super::hello(State(state.clone()), Json(unresolvable)).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_GATEWAY);
+
+        // Without a resolver did:web isn't a DID we accept
+        let (plain, _) = authenticated_state("did:key:other").await;
+        let err = super::hello(State(plain), Json(Hello::new(did.to_string()))).await.unwrap_err();
+        assert!(matches!(err, ReachError::UnsupportedDidMethod(method) if method == "web"));
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_expired_sessions() {
+        let store = MemoryHandshakeStore::default();
+        let now = chrono::Utc::now().timestamp();
+        let session = |created_at| AuthenticatedSession { did: "did:key:a".to_string(), created_at, scope: Scope::Write, protocols: Vec::new() };
+        store.put_session("expired".to_string(), session(now - SESSION_TTL_SECS - 1)).await.unwrap();
+        store.put_session("live".to_string(), session(now)).await.unwrap();
+
+        assert_eq!(store.purge_expired_sessions(now).await.unwrap(), 1);
+        assert!(store.session("expired").await.unwrap().is_none());
+        assert!(store.session("live").await.unwrap().is_some());
+        assert_eq!(store.sessions.read().by_did["did:key:a"], ["live"]);
+    }
+
+    #[tokio::test]
+    async fn new_sessions_end_the_oldest_over_the_limit() {
+        let (state, _) = authenticated_state("did:key:other").await;
+        let key = RootKey::generate();
+        let cap = state.handshake.max_sessions_per_did;
+
+        let mut sessions = Vec::new();
+        for _ in 0..=cap {
+            sessions.push(handshake(&state, &key).await.session_id);
+        }
+
+        let bearer = |session_id: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, format!("Bearer {}", session_id).parse().unwrap());
+            headers
+        };
+        let err = session(State(state.clone()), bearer(&sessions[0])).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+        for session_id in &sessions[1..] {
+            assert!(session(State(state.clone()), bearer(session_id)).await.is_ok());
+        }
+        // Other DIDs' sessions are untouched
+        assert!(state.handshake.store.session("test-session").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn delegated_register_stores_under_sub_agent() {
+        let (controller, sub_agent) = (RootKey::generate(), RootKey::generate());
+        let (state, headers) = authenticated_state(&controller.did().to_string()).await;
+        let Json(mut req) = register_request("wss://sub-agent:8080");
+        req.delegation = Some(crate::delegation::tests::sign(&controller, &sub_agent, &req));
+
+        let Json(response) = register(State(state.clone()), headers, Query(RegisterParams::default()), Json(req))
+            .await
+            .unwrap();
+
+        let sub_did = sub_agent.did().to_string();
+        assert_eq!(response.did, sub_did);
+        assert_eq!(state.registry.lookup(&sub_did).await.unwrap().unwrap().endpoint, "wss://sub-agent:8080");
+        assert!(state.registry.lookup(&controller.did().to_string()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delegation_from_another_session_is_forbidden() {
+        let (controller, sub_agent) = (RootKey::generate(), RootKey::generate());
+        let (state, headers) = authenticated_state("did:key:someone-else").await;
+        let Json(mut req) = register_request("wss://sub-agent:8080");
+        req.delegation = Some(crate::delegation::tests::sign(&controller, &sub_agent, &req));
+
+        let err = register(State(state.clone()), headers, Query(RegisterParams::default()), Json(req))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.into_response().status(), StatusCode::FORBIDDEN);
+        assert!(state.registry.lookup(&sub_agent.did().to_string()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn certificate_register_is_recorded_in_history() {
+        let (controller, worker) = (RootKey::generate(), RootKey::generate());
+        let (state, headers) = authenticated_state(&controller.did().to_string()).await;
+        let worker_did = worker.did().to_string();
+        let Json(mut req) = register_request("wss://worker:8080");
+        req.did = Some(worker_did.clone());
+        req.certificate = Some(crate::delegation::tests::certify(&worker, &controller, chrono::Utc::now().timestamp()));
+
+        let Json(response) = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), Json(req))
+            .await
+            .unwrap();
+
+        assert_eq!(response.did, worker_did);
+        let Json(past) = history(State(state.clone()), Path(worker_did.clone())).await.unwrap();
+        assert_eq!(past.history[0].registered_by, Some(controller.did().to_string()));
+
+        let body = serde_json::to_vec(&serde_json::json!({
+            "did": worker_did,
+            "certificate": crate::delegation::tests::certify(&worker, &controller, chrono::Utc::now().timestamp()),
+        }))
+        .unwrap();
+        let Json(removed) = deregister(State(state.clone()), headers, Bytes::from(body)).await.unwrap();
+        assert!(removed.ok);
+        assert!(state.registry.lookup(&worker_did).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn revoked_certificate_is_forbidden() {
+        let (controller, worker) = (RootKey::generate(), RootKey::generate());
+        let (state, controller_headers) = authenticated_state(&controller.did().to_string()).await;
+        let certificate = crate::delegation::tests::certify(&worker, &controller, chrono::Utc::now().timestamp() - 1);
+        let session = AuthenticatedSession {
+            did: worker.did().to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            scope: Scope::Write,
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session("worker-session".to_string(), session).await.unwrap();
+        let mut worker_headers = HeaderMap::new();
+        worker_headers.insert(header::AUTHORIZATION, "Bearer worker-session".parse().unwrap());
+
+        let revoke = RevokeDelegationRequest { delegate: controller.did().to_string() };
+        let status = revoke_delegation(State(state.clone()), worker_headers, Json(revoke)).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let Json(mut req) = register_request("wss://worker:8080");
+        req.certificate = Some(certificate);
+        let err = register(State(state.clone()), controller_headers, Query(RegisterParams::default()), Json(req))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("revoked"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn another_did_needs_a_certificate() {
+        let (state, headers) = authenticated_state("did:key:controller").await;
+        let Json(mut req) = register_request("wss://worker:8080");
+        req.did = Some("did:key:worker".to_string());
+
+        let err = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), Json(req))
+            .await
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::FORBIDDEN);
+
+        let err = deregister(State(state), headers, Bytes::from_static(b"{\"did\": 1}")).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn dry_run_stores_nothing() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let Json(req) = register_with_handle("wss://one", " Alice@Example.com ");
+        let req = RegisterRequest { ttl: u64::MAX, ..req };
+        let params = Query(RegisterParams { dry_run: true, ..Default::default() });
+
+        let Json(response) = register(State(state.clone()), headers, params, Json(req)).await.unwrap();
+
+        assert!(response.dry_run);
+        assert_eq!(response.ttl, crate::ttl::DEFAULT_MAX_TTL_SECS);
+        assert_eq!(response.handle.as_deref(), Some("alice@example.com"));
+        assert_eq!(response.adjustments.len(), 2, "{:?}", response.adjustments);
+        assert!(state.registry.lookup("did:key:a").await.unwrap().is_none());
+        assert!(state.registry.history("did:key:a").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dry_run_checks_preconditions() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let _ = register(State(state.clone()), headers.clone(), Query(RegisterParams::default()), register_request("wss://one"))
+            .await
+            .unwrap();
+        let params = Query(RegisterParams { if_absent: true, dry_run: true });
+
+        let err = register(State(state), headers, params, register_request("wss://two")).await.unwrap_err();
+
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn dry_run_checks_capacity() {
+        let (state, headers) = authenticated_state("did:key:a").await;
+        let capped = crate::capacity::CappedRegistry::new(
+            state.registry.clone(),
+            0,
+            crate::capacity::CapacityPolicy::Reject,
+            crate::capacity::DEFAULT_HIGH_WATER_PERCENT,
+        );
+        let state = AppState { registry: Arc::new(capped), ..state };
+        let params = Query(RegisterParams { dry_run: true, ..Default::default() });
+
+        let err = register(State(state), headers, params, register_request("wss://one")).await.unwrap_err();
+
+        assert_eq!(err.into_response().status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn if_none_match_star_is_conditional() {
+        let (state, mut headers) = authenticated_state("did:key:a").await;
+        headers.insert(header::IF_NONE_MATCH, "*".parse().unwrap());
+        let params = || Query(RegisterParams::default());
+
+        let Json(first) = register(State(state.clone()), headers.clone(), params(), register_request("wss://one"))
+            .await
+            .unwrap();
+        assert!(first.ok);
+        let err = register(State(state), headers, params(), register_request("wss://two"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,216 @@
+//! Readiness checks for `GET /health/ready`
+//!
+//! `GET /health` only says the process answers. Readiness also asks the
+//! storage backend to answer within a deadline and checks that every
+//! background task finished a round recently, so a load balancer can stop
+//! routing to a registry that can't serve and its operator can see which
+//! part failed.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::handlers::AppState;
+
+/// How long the storage backend gets to answer
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Rounds a background task may miss before it counts as stalled
+const MISSED_ROUNDS: u32 = 3;
+
+/// When each background task last finished a round
+#[derive(Default)]
+pub struct Heartbeats {
+    tasks: Mutex<BTreeMap<&'static str, Heartbeat>>,
+}
+
+struct Heartbeat {
+    /// How often the task runs
+    interval: Duration,
+    last: Instant,
+}
+
+impl Heartbeats {
+    /// Record that `task`, which runs every `interval`, finished a round
+    pub fn beat(&self, task: &'static str, interval: Duration) {
+        self.tasks.lock().insert(task, Heartbeat { interval, last: Instant::now() });
+    }
+
+    /// Each task's health, down once it has missed a few rounds
+    fn check(&self) -> Vec<(&'static str, ComponentHealth)> {
+        self.tasks
+            .lock()
+            .iter()
+            .map(|(task, beat)| {
+                let elapsed = beat.last.elapsed();
+                let mut health = if elapsed > beat.interval * MISSED_ROUNDS {
+                    ComponentHealth::down(format!("No round finished in {}s", elapsed.as_secs()))
+                } else {
+                    ComponentHealth::ok()
+                };
+                health.last_run_secs = Some(elapsed.as_secs());
+                (*task, health)
+            })
+            .collect()
+    }
+}
+
+/// Overall readiness
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Readiness {
+    Ok,
+    /// Some component is down
+    Degraded,
+}
+
+/// State of one component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Ok,
+    Down,
+}
+
+/// Result of checking one component
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ComponentHealth {
+    pub status: ComponentStatus,
+    /// What went wrong, when down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// How long the storage backend took to answer (milliseconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Seconds since a background task last finished a round
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run_secs: Option<u64>,
+}
+
+impl ComponentHealth {
+    fn ok() -> Self {
+        Self { status: ComponentStatus::Ok, detail: None, latency_ms: None, last_run_secs: None }
+    }
+
+    fn down(detail: String) -> Self {
+        Self { status: ComponentStatus::Down, detail: Some(detail), ..Self::ok() }
+    }
+}
+
+/// Readiness with the check behind it
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    pub status: Readiness,
+    /// Server version
+    pub version: String,
+    /// `storage`, then each background task by name
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+/// GET /health/ready
+///
+/// Checks the storage backend and background tasks. Answers 503 with the
+/// same body when any of them is down.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "Every component is up", body = ReadinessResponse),
+        (status = 503, description = "Some component is down", body = ReadinessResponse),
+    )
+)]
+pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    let mut components = BTreeMap::new();
+    components.insert("storage".to_string(), storage(&state).await);
+    for (task, health) in state.heartbeats.check() {
+        components.insert(task.to_string(), health);
+    }
+
+    let (code, status) = match components.values().all(|c| c.status == ComponentStatus::Ok) {
+        true => (StatusCode::OK, Readiness::Ok),
+        false => (StatusCode::SERVICE_UNAVAILABLE, Readiness::Degraded),
+    };
+    let response = ReadinessResponse {
+        status,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        components,
+    };
+    (code, [(header::CACHE_CONTROL, "no-store")], Json(response))
+}
+
+/// Ask the storage backend to answer, timing it
+///
+/// Storage errors are logged rather than returned, since the endpoint is
+/// public and they can name hosts and tables.
+async fn storage(state: &AppState) -> ComponentHealth {
+    let started = Instant::now();
+    let result = tokio::time::timeout(STORAGE_TIMEOUT, state.registry.ping()).await;
+    let mut health = match result {
+        Ok(Ok(())) => ComponentHealth::ok(),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Storage readiness check failed");
+            ComponentHealth::down("Storage backend returned an error".to_string())
+        }
+        Err(_) => ComponentHealth::down(format!("No answer within {}s", STORAGE_TIMEOUT.as_secs())),
+    };
+    health.latency_ms = Some(started.elapsed().as_millis() as u64);
+    health
+}
+
+#[cfg(test)]
+mod tests {
+
+    use axum::body::to_bytes;
+
+    use super::*;
+
+    fn state() -> AppState {
+        AppState::for_tests()
+    }
+
+    async fn check(state: AppState) -> (StatusCode, serde_json::Value) {
+        let response = ready(State(state)).await.into_response();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ready_when_every_component_is_up() {
+        let state = state();
+        state.heartbeats.beat("purge", Duration::from_secs(60));
+
+        let (status, body) = check(state).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["components"]["storage"]["status"], "ok");
+        assert!(body["components"]["storage"]["latency_ms"].is_u64());
+        assert_eq!(body["components"]["purge"]["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn stalled_tasks_degrade_readiness() {
+        let state = state();
+        state.heartbeats.beat("purge", Duration::from_secs(60));
+        state.heartbeats.tasks.lock().insert("sync", Heartbeat {
+            interval: Duration::from_secs(1),
+            last: Instant::now() - Duration::from_secs(10),
+        });
+
+        let (status, body) = check(state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["components"]["purge"]["status"], "ok");
+        assert_eq!(body["components"]["sync"]["status"], "down");
+        assert_eq!(body["components"]["sync"]["last_run_secs"], 10);
+    }
+}
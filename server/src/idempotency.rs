@@ -0,0 +1,201 @@
+//! Idempotency keys for `/register`
+//!
+//! A client may send `Idempotency-Key` with a registration. The first
+//! successful response for a (DID, key) pair is kept for a while and
+//! replayed for retries with the same key, so a retry that crosses a slow
+//! response neither registers twice nor trips `if_absent`. Failed attempts
+//! aren't kept; retrying them runs the registration again. Keys are
+//! remembered per session DID, in memory, for [`KEY_TTL_SECS`] and at most
+//! [`MAX_KEYS`] at once.
+
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+
+use crate::backend::Precondition;
+use crate::error::ReachError;
+use crate::types::{RegisterRequest, RegisterResponse};
+
+/// Header carrying the client's key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// How long a response is replayed for
+pub const KEY_TTL_SECS: i64 = 600;
+
+/// Keys remembered at once, across all DIDs; the oldest go first
+pub const MAX_KEYS: usize = 10_000;
+
+/// Longest accepted key
+const MAX_KEY_LEN: usize = 255;
+
+/// (session DID, key)
+type Slot = (String, String);
+
+struct Stored {
+    /// Hash of the request the key was first used with
+    fingerprint: [u8; 32],
+    created_at: i64,
+    /// `None` while the first request is still running
+    response: Option<RegisterResponse>,
+}
+
+/// Recent registration responses by idempotency key
+#[derive(Default)]
+pub struct IdempotencyCache {
+    slots: Mutex<HashMap<Slot, Stored>>,
+}
+
+/// What to do with a request carrying a key
+pub enum Begin<'a> {
+    /// First use of the key: register, then [`Claim::complete`]
+    Claimed(Claim<'a>),
+    /// A retry of a request that already succeeded
+    Replay(RegisterResponse),
+}
+
+/// A key held by the request running under it; released on drop unless the
+/// request succeeded
+pub struct Claim<'a> {
+    cache: &'a IdempotencyCache,
+    slot: Option<Slot>,
+}
+
+impl Claim<'_> {
+    /// Keep `response` for retries
+    pub fn complete(mut self, response: &RegisterResponse) {
+        if let Some(slot) = self.slot.take() {
+            if let Some(stored) = self.cache.slots.lock().get_mut(&slot) {
+                stored.response = Some(response.clone());
+            }
+        }
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            self.cache.slots.lock().remove(&slot);
+        }
+    }
+}
+
+impl IdempotencyCache {
+    /// Claim `key` for a registration by `did`, or find the response to
+    /// replay. Reusing a key for a different request, or while the first
+    /// request with it is still running, is an error.
+    pub fn begin(&self, did: &str, key: String, fingerprint: [u8; 32], now: i64) -> Result<Begin<'_>, ReachError> {
+        let mut slots = self.slots.lock();
+        slots.retain(|_, stored| now - stored.created_at < KEY_TTL_SECS);
+
+        let slot = (did.to_string(), key);
+        if let Some(stored) = slots.get(&slot) {
+            if stored.fingerprint != fingerprint {
+                return Err(ReachError::IdempotencyKeyReused);
+            }
+            return match &stored.response {
+                Some(response) => Ok(Begin::Replay(response.clone())),
+                None => Err(ReachError::IdempotencyInProgress),
+            };
+        }
+
+        if slots.len() >= MAX_KEYS {
+            if let Some(oldest) = slots.iter().min_by_key(|(_, stored)| stored.created_at).map(|(slot, _)| slot.clone()) {
+                slots.remove(&oldest);
+            }
+        }
+        slots.insert(
+            slot.clone(),
+            Stored {
+                fingerprint,
+                created_at: now,
+                response: None,
+            },
+        );
+        Ok(Begin::Claimed(Claim {
+            cache: self,
+            slot: Some(slot),
+        }))
+    }
+}
+
+/// The request's idempotency key, if it sent one
+pub fn key(headers: &HeaderMap) -> Result<Option<String>, ReachError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .ok_or_else(|| {
+            ReachError::InvalidRequest(format!(
+                "Idempotency-Key must be 1 to {} visible ASCII characters",
+                MAX_KEY_LEN
+            ))
+        })?;
+    Ok(Some(key.to_string()))
+}
+
+/// Hash of what a registration asks for, to catch a key reused for a
+/// different request
+pub fn fingerprint(req: &RegisterRequest, precondition: Precondition) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(req).unwrap_or_default());
+    match precondition {
+        Precondition::Always => hasher.update([0u8]),
+        Precondition::Absent => hasher.update([1u8]),
+        Precondition::Version(version) => {
+            hasher.update([2u8]);
+            hasher.update(version.to_be_bytes());
+        }
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(ttl: u64) -> RegisterResponse {
+        RegisterResponse {
+            ok: true,
+            did: "did:key:a".to_string(),
+            expires_at: 1000 + ttl as i64,
+            ttl,
+            version: 1,
+            handle: None,
+            adjustments: Vec::new(),
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn completed_requests_are_replayed() {
+        let cache = IdempotencyCache::default();
+        let Begin::Claimed(claim) = cache.begin("did:key:a", "k".into(), [1; 32], 0).unwrap() else {
+            panic!("first use claims the key");
+        };
+        assert!(matches!(cache.begin("did:key:a", "k".into(), [1; 32], 1), Err(ReachError::IdempotencyInProgress)));
+        claim.complete(&response(60));
+
+        let Begin::Replay(replayed) = cache.begin("did:key:a", "k".into(), [1; 32], 2).unwrap() else {
+            panic!("retry is replayed");
+        };
+        assert_eq!(replayed, response(60));
+        assert!(matches!(cache.begin("did:key:a", "k".into(), [2; 32], 3), Err(ReachError::IdempotencyKeyReused)));
+
+        // Keys are per DID and forgotten after a while
+        assert!(matches!(cache.begin("did:key:b", "k".into(), [2; 32], 3), Ok(Begin::Claimed(_))));
+        assert!(matches!(cache.begin("did:key:a", "k".into(), [2; 32], KEY_TTL_SECS), Ok(Begin::Claimed(_))));
+    }
+
+    #[test]
+    fn failed_requests_release_the_key() {
+        let cache = IdempotencyCache::default();
+        drop(cache.begin("did:key:a", "k".into(), [1; 32], 0).unwrap());
+        assert!(matches!(cache.begin("did:key:a", "k".into(), [1; 32], 1), Ok(Begin::Claimed(_))));
+    }
+}
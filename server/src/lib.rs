@@ -0,0 +1,770 @@
+//! DID-based discovery registry server for AI agents
+//!
+//! The `agent-reach-server` binary wires these modules together from its
+//! command line; the router and storage backends are exposed here so
+//! benchmarks can drive them directly.
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{HeaderName, Request},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, post, MethodRouter},
+    Router,
+};
+use clap::ValueEnum;
+use tower::ServiceBuilder;
+use tower_http::{
+    compression::{
+        predicate::{DefaultPredicate, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tracing::Span;
+
+pub mod admin;
+pub mod api_version;
+pub mod backend;
+pub mod bloom;
+pub mod capacity;
+pub mod changes;
+pub mod codec;
+pub mod cors;
+pub mod delegation;
+pub mod denylist;
+pub mod did;
+pub mod did_document;
+#[cfg(feature = "did-web")]
+pub mod did_web;
+pub mod endpoints;
+pub mod error;
+#[cfg(feature = "federation")]
+pub mod federation;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handle;
+pub mod handlers;
+pub mod health;
+pub mod idempotency;
+pub mod limits;
+pub mod listen;
+pub mod lockout;
+pub mod logging;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub mod lookup_cache;
+pub mod nonce;
+pub mod openapi;
+pub mod protocol;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod registry;
+pub mod seed;
+pub mod selftest;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod sync;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod ttl;
+pub mod types;
+pub mod watch;
+pub mod webfinger;
+
+use cors::CorsConfig;
+use handlers::AppState;
+
+/// Header carrying the request correlation ID; generated when absent
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Read responses smaller than this (bytes) aren't worth compressing
+const COMPRESSION_MIN_SIZE: u16 = 1024;
+
+/// Handling of plain HTTP requests when TLS is enabled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PlainHttp {
+    /// Permanent redirect to the same path over HTTPS
+    #[default]
+    Redirect,
+    /// 400 error without redirecting
+    Reject,
+}
+
+/// Build the router with all routes and middleware
+pub fn app(state: AppState, cors: &CorsConfig, max_body: usize) -> Router {
+    let router = api_routes(&state, cors, max_body).merge(admin_routes(&state));
+    with_middleware(router, state)
+}
+
+/// Router for the public listeners when the admin API has its own listener
+pub fn public_app(state: AppState, cors: &CorsConfig, max_body: usize) -> Router {
+    with_middleware(api_routes(&state, cors, max_body), state)
+}
+
+/// Router for the internal-only listener: just the admin API
+pub fn internal_app(state: AppState) -> Router {
+    with_middleware(admin_routes(&state), state)
+}
+
+/// Every route except the admin API: the versioned routes under their
+/// prefix and again as deprecated unprefixed aliases, plus the few paths
+/// that stay unversioned, with bodies over `max_body` bytes refused
+///
+/// The admin API is left to axum's default limit, as imports carry whole
+/// registries.
+fn api_routes(state: &AppState, cors: &CorsConfig, max_body: usize) -> Router<AppState> {
+    let versioned = versioned_routes(cors);
+
+    let mut router = Router::new()
+        .route("/.well-known/agent-reach", get(handlers::discovery))
+        .route("/version", get(api_version::version))
+        .layer(cors.reads());
+
+    if state.public_url.is_some() {
+        let webfinger = Router::new()
+            .route("/.well-known/webfinger", get(webfinger::webfinger))
+            .layer(cors.reads());
+        router = router.merge(webfinger);
+    }
+
+    let router = router
+        .nest(api_version::PREFIX, versioned.clone())
+        .merge(versioned.layer(from_fn(api_version::deprecated)))
+        .layer(from_fn_with_state(max_body, limits::limit_body));
+
+    // Outside `max_body`, which a full batch of DIDs can exceed
+    if state.replica.is_none() {
+        return router;
+    }
+    let sync = Router::new()
+        .route("/sync/digest", get(sync::digest))
+        .route("/sync/entries", post(sync::entries))
+        .layer(cors::closed())
+        .layer(from_fn_with_state(limits::SYNC_BODY_LIMIT, limits::limit_body));
+    router
+        .nest(api_version::PREFIX, sync.clone())
+        .merge(sync.layer(from_fn(api_version::deprecated)))
+}
+
+/// Routes served under the API version prefix
+fn versioned_routes(cors: &CorsConfig) -> Router<AppState> {
+    let reads = Router::new()
+        .route("/health", get(handlers::health))
+        .route("/health/ready", get(health::ready))
+        .route("/lookup/:did", timed("lookup", get(handlers::lookup)).layer(from_fn(codec::negotiate)))
+        .route("/did/:did", get(did_document::did_document))
+        .route("/resolve", get(handlers::resolve))
+        .route("/stats", get(stats::stats))
+        .route("/changes", get(changes::changes))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::docs))
+        .route("/agents", get(handlers::agents))
+        .route("/agents/:did/history", get(handlers::history))
+        .layer(cors.reads())
+        .layer(CompressionLayer::new().compress_when(DefaultPredicate::new().and(SizeAbove::new(COMPRESSION_MIN_SIZE))));
+
+    let writes = Router::new()
+        .route(
+            "/hello",
+            timed("hello", post(handlers::hello))
+                .layer(from_fn(codec::negotiate))
+                .layer(from_fn_with_state(limits::HANDSHAKE_BODY_LIMIT, limits::limit_body)),
+        )
+        .route(
+            "/proof",
+            timed("proof", post(handlers::proof))
+                .layer(from_fn(codec::negotiate))
+                .layer(from_fn_with_state(limits::HANDSHAKE_BODY_LIMIT, limits::limit_body)),
+        )
+        .route("/register", timed("register", post(handlers::register)).layer(from_fn(codec::negotiate)))
+        .route("/deregister", post(handlers::deregister))
+        .route("/delegations/revoke", post(handlers::revoke_delegation))
+        .route("/session", get(handlers::session))
+        .route("/me", get(handlers::me))
+        .route("/logout", post(handlers::logout))
+        .layer(cors.writes());
+
+    // Outside `reads`, whose compression would wrap the upgrade response
+    let watch = Router::new().route("/watch/:did", get(watch::watch)).layer(cors.reads());
+
+    reads.merge(writes).merge(watch)
+}
+
+/// Time `handler` for the `reach.handler.duration` histogram, labelled `route`
+fn timed(route: &'static str, handler: MethodRouter<AppState>) -> MethodRouter<AppState> {
+    #[cfg(feature = "otel")]
+    let handler = handler.layer(from_fn_with_state(route, telemetry::record_handler));
+    #[cfg(not(feature = "otel"))]
+    let _ = route;
+    handler
+}
+
+/// The admin API, when an admin token is configured
+fn admin_routes(state: &AppState) -> Router<AppState> {
+    if state.admin_token.is_none() {
+        return Router::new();
+    }
+    Router::new()
+        .route("/admin/export", get(admin::export))
+        .route("/admin/import", post(admin::import))
+        .route("/admin/sessions", get(admin::list_sessions))
+        .route("/admin/sessions/:id", delete(admin::revoke_session))
+        .route("/admin/agents/:did", delete(admin::evict))
+        .route("/admin/bans/:did", delete(admin::lift_ban))
+        .route("/admin/selftest", post(selftest::selftest))
+        .layer(cors::closed())
+}
+
+/// Request IDs, tracing and error decoration around `router`
+fn with_middleware(router: Router<AppState>, state: AppState) -> Router {
+    let x_request_id = HeaderName::from_static(REQUEST_ID_HEADER);
+
+    #[cfg(feature = "otel")]
+    let router = router.layer(from_fn(telemetry::record_request));
+
+    router
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(x_request_id.clone(), MakeRequestUuid))
+                .layer(TraceLayer::new_for_http().make_span_with(request_span))
+                .layer(PropagateRequestIdLayer::new(x_request_id))
+                .layer(from_fn(error::attach_request_id)),
+        )
+        .with_state(state)
+}
+
+/// Span for each request, tagged with its request ID so every log line
+/// emitted while handling it can be correlated
+///
+/// Handlers fill in `did` once they know which agent the request is about,
+/// plus `endpoint`, `session_age` (seconds) and `registry_size` where known.
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        http.route = route,
+        otel.name = format!("{} {}", request.method(), route),
+        otel.kind = "server",
+        request_id,
+        did = tracing::field::Empty,
+        endpoint = tracing::field::Empty,
+        session_age = tracing::field::Empty,
+        registry_size = tracing::field::Empty,
+    )
+}
+
+/// Resolves on Ctrl-C or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn test_app() -> Router {
+        app_with_cors(&CorsConfig::default())
+    }
+
+    fn app_with_cors(cors: &CorsConfig) -> Router {
+        app(AppState::for_tests(), cors, limits::DEFAULT_MAX_BODY_BYTES)
+    }
+
+    fn preflight(path: &str, method: &str) -> Request<Body> {
+        Request::options(path)
+            .header("origin", "https://dashboard.example")
+            .header("access-control-request-method", method)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn allowed_origin(response: &axum::response::Response) -> Option<&str> {
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .map(|v| v.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed() {
+        let request = Request::get("/health")
+            .header(REQUEST_ID_HEADER, "trace-me-123")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "trace-me-123");
+    }
+
+    #[tokio::test]
+    async fn error_body_carries_request_id() {
+        let request = Request::get("/lookup/did:key:z6Mkabsent")
+            .header(REQUEST_ID_HEADER, "trace-me-456")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "trace-me-456");
+        assert_eq!(body["error"], "Agent not found");
+    }
+
+    #[tokio::test]
+    async fn discovery_document_is_cacheable() {
+        let request = Request::get("/.well-known/agent-reach").body(Body::empty()).unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+
+        assert!(response.status().is_success());
+        assert!(response.headers()["cache-control"].to_str().unwrap().contains("max-age"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["registry_did"].as_str().unwrap().starts_with("did:key:"));
+        assert_eq!(body["max_ttl"], ttl::DEFAULT_MAX_TTL_SECS);
+        assert_eq!(body["endpoints"]["lookup"], "/v1/lookup/{did}");
+    }
+
+    #[tokio::test]
+    async fn unprefixed_routes_are_deprecated_aliases() {
+        let response = test_app().oneshot(Request::get("/v1/health").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(response.status().is_success());
+        assert!(!response.headers().contains_key("deprecation"));
+
+        let response = test_app().oneshot(Request::get("/health").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(response.headers()["link"], "</v1/health>; rel=\"successor-version\"");
+
+        let request = Request::get("/v1/lookup/did:key:z6Mkabsent").body(Body::empty()).unwrap();
+        let response = test_app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn version_lists_supported_api_versions() {
+        let response = test_app().oneshot(Request::get("/version").body(Body::empty()).unwrap()).await.unwrap();
+
+        assert!(response.status().is_success());
+        assert!(!response.headers().contains_key("deprecation"));
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["api_versions"], serde_json::json!(["v1"]));
+    }
+
+    #[tokio::test]
+    async fn request_id_is_generated_when_absent() {
+        let request = Request::get("/health").body(Body::empty()).unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(request_id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn public_reads_allow_any_origin_by_default() {
+        let response = test_app().oneshot(preflight("/lookup/did:key:z6Mk", "GET")).await.unwrap();
+
+        assert!(response.status().is_success());
+        assert_eq!(allowed_origin(&response), Some("*"));
+    }
+
+    #[tokio::test]
+    async fn listed_origins_are_echoed_on_reads() {
+        let cors = CorsConfig::new(&["https://dashboard.example".into()], false).unwrap();
+        let request = Request::get("/health")
+            .header("origin", "https://dashboard.example")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app_with_cors(&cors).oneshot(request).await.unwrap();
+
+        assert!(response.status().is_success());
+        assert_eq!(allowed_origin(&response), Some("https://dashboard.example"));
+    }
+
+    #[tokio::test]
+    async fn writes_refuse_cross_origin_without_opt_in() {
+        let cors = CorsConfig::new(&["https://dashboard.example".into()], false).unwrap();
+
+        let response = app_with_cors(&cors).oneshot(preflight("/register", "POST")).await.unwrap();
+
+        assert!(response.status().is_success(), "preflight is answered, not 405");
+        assert_eq!(allowed_origin(&response), None);
+    }
+
+    #[tokio::test]
+    async fn writes_allow_listed_origin_with_opt_in() {
+        let cors = CorsConfig::new(&["https://dashboard.example".into()], true).unwrap();
+        let app = app_with_cors(&cors);
+
+        let response = app.clone().oneshot(preflight("/register", "POST")).await.unwrap();
+        assert_eq!(allowed_origin(&response), Some("https://dashboard.example"));
+
+        let other = Request::options("/register")
+            .header("origin", "https://evil.example")
+            .header("access-control-request-method", "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(other).await.unwrap();
+        assert_eq!(allowed_origin(&response), None);
+    }
+
+    async fn body_bytes(response: axum::response::Response) -> axum::body::Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn cbor_and_json_lookups_decode_alike() {
+        let state = AppState::for_tests();
+        state.registry.register(types::RegistryEntry {
+            handle: Some("sensor@example.com".to_string()),
+            endpoints: vec![
+                types::Endpoint { uri: "wss://small-device:8080".to_string(), weight: Some(2), region: None, registered_at: None },
+                types::Endpoint { uri: "wss://backup:8080".to_string(), weight: None, region: None, registered_at: None },
+            ],
+            ..types::RegistryEntry::for_test("did:key:z6Mkcbor", "wss://small-device:8080", 3600)
+        }).await.unwrap();
+        let app = app(state, &CorsConfig::default(), limits::DEFAULT_MAX_BODY_BYTES);
+        let lookup = |accept: &str| {
+            Request::get("/lookup/did:key:z6Mkcbor").header("accept", accept).body(Body::empty()).unwrap()
+        };
+
+        let json = app.clone().oneshot(lookup("application/json")).await.unwrap();
+        assert_eq!(json.headers()["content-type"], "application/json");
+        let json: types::LookupResponse = serde_json::from_slice(&body_bytes(json).await).unwrap();
+
+        let cbor = app.clone().oneshot(lookup("application/cbor")).await.unwrap();
+        assert_eq!(cbor.headers()["content-type"], codec::CBOR);
+        assert_eq!(cbor.headers()["vary"], "accept");
+        let cbor: types::LookupResponse = ciborium::from_reader(body_bytes(cbor).await.as_ref()).unwrap();
+        assert_eq!(cbor, json);
+
+        // Unknown media types get JSON rather than an error
+        let other = app.oneshot(lookup("application/x-protobuf")).await.unwrap();
+        assert!(other.status().is_success());
+        assert_eq!(other.headers()["content-type"], "application/json");
+    }
+
+    #[tokio::test]
+    async fn cbor_register_round_trips() {
+        let state = AppState::for_tests();
+        let session = handlers::AuthenticatedSession {
+            did: "did:key:z6Mkcbor".to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            scope: types::Scope::Write,
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session("cbor-session".to_string(), session).await.unwrap();
+        let registry = state.registry.clone();
+        let app = app(state, &CorsConfig::default(), limits::DEFAULT_MAX_BODY_BYTES);
+
+        let mut body = Vec::new();
+        ciborium::into_writer(&serde_json::json!({ "endpoint": "coap://sensor:5683", "ttl": 600 }), &mut body).unwrap();
+        let request = Request::post("/register")
+            .header("authorization", "Bearer cbor-session")
+            .header("content-type", codec::CBOR)
+            .header("accept", codec::CBOR)
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["content-type"], codec::CBOR);
+        let registered: types::RegisterResponse = ciborium::from_reader(body_bytes(response).await.as_ref()).unwrap();
+        assert_eq!(registered.did, "did:key:z6Mkcbor");
+        assert_eq!(registered.ttl, 600);
+        let entry = registry.lookup("did:key:z6Mkcbor").await.unwrap().unwrap();
+        assert_eq!(entry.endpoint, "coap://sensor:5683");
+        assert_eq!(entry.expires_at, registered.expires_at);
+
+        // Errors stay JSON
+        let request = Request::post("/register")
+            .header("content-type", codec::CBOR)
+            .header("accept", codec::CBOR)
+            .body(Body::from(vec![0xff]))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["content-type"], "application/json");
+    }
+
+    #[tokio::test]
+    async fn msgpack_register_and_lookup_round_trip() {
+        let state = AppState::for_tests();
+        let session = handlers::AuthenticatedSession {
+            did: "did:key:z6Mkmsgpack".to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            scope: types::Scope::Write,
+            protocols: Vec::new(),
+        };
+        state.handshake.store.put_session("msgpack-session".to_string(), session).await.unwrap();
+        let app = app(state, &CorsConfig::default(), limits::DEFAULT_MAX_BODY_BYTES);
+
+        let body = rmp_serde::to_vec_named(&serde_json::json!({ "endpoint": "wss://msgpack:8080", "ttl": 600 })).unwrap();
+        let request = Request::post("/v1/register")
+            .header("authorization", "Bearer msgpack-session")
+            .header("content-type", codec::MSGPACK)
+            .header("accept", codec::MSGPACK)
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["content-type"], codec::MSGPACK);
+        let registered: types::RegisterResponse = rmp_serde::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(registered.did, "did:key:z6Mkmsgpack");
+
+        let request = Request::get("/v1/lookup/did:key:z6Mkmsgpack")
+            .header("accept", "application/x-msgpack")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["content-type"], codec::MSGPACK);
+        let entry: types::LookupResponse = rmp_serde::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(entry.endpoint, "wss://msgpack:8080");
+        assert_eq!(entry.expires_at, registered.expires_at);
+
+        // The handshake speaks it too
+        let did = agent_id::RootKey::generate().did().to_string();
+        let request = Request::post("/v1/hello")
+            .header("content-type", codec::MSGPACK)
+            .header("accept", codec::MSGPACK)
+            .body(Body::from(rmp_serde::to_vec_named(&types::Hello::new(did.clone())).unwrap()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["content-type"], codec::MSGPACK);
+        let challenge: types::Challenge = rmp_serde::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(challenge.audience, did);
+    }
+
+    #[tokio::test]
+    async fn oversized_register_bodies_are_refused_before_auth() {
+        let huge = vec![b' '; 10 * 1024 * 1024];
+
+        // Declared length over the limit
+        let request = Request::post("/register")
+            .header("content-type", "application/json")
+            .header("content-length", huge.len())
+            .body(Body::from(huge.clone()))
+            .unwrap();
+        let response = test_app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["code"], "payload_too_large");
+        assert_eq!(body["limit"], limits::DEFAULT_MAX_BODY_BYTES);
+
+        // No length given; reading stops at the limit
+        let request = Request::post("/register")
+            .header("content-type", "application/json")
+            .body(Body::from(huge))
+            .unwrap();
+        let response = test_app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn body_limit_covers_every_public_route() {
+        let app = app(AppState::for_tests(), &CorsConfig::default(), 16 * 1024);
+        let post = |path: &str, len: usize| {
+            let body = serde_json::json!({ "endpoint": "wss://agent.example", "padding": "x".repeat(len) });
+            Request::post(path)
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&body).unwrap()))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(post("/v1/delegations/revoke", 20 * 1024)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["limit"], 16 * 1024);
+
+        // Under the limit, the request gets as far as authentication
+        let response = app.oneshot(post("/v1/register", 10 * 1024)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn handshake_bodies_have_a_smaller_limit() {
+        let hello = serde_json::json!({ "type": "Hello", "version": "1.0", "did": "x".repeat(limits::HANDSHAKE_BODY_LIMIT) });
+        let request = Request::post("/hello")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&hello).unwrap()))
+            .unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn internal_listener_takes_the_admin_api() {
+        let state = AppState { admin_token: Some(Arc::from("secret")), ..AppState::for_tests() };
+        let export = || {
+            Request::get("/admin/export").header("authorization", "Bearer secret").body(Body::empty()).unwrap()
+        };
+
+        let public = public_app(state.clone(), &CorsConfig::default(), limits::DEFAULT_MAX_BODY_BYTES);
+        assert_eq!(public.clone().oneshot(export()).await.unwrap().status(), axum::http::StatusCode::NOT_FOUND);
+        assert!(public.oneshot(Request::get("/health").body(Body::empty()).unwrap()).await.unwrap().status().is_success());
+
+        let internal = internal_app(state);
+        assert!(internal.clone().oneshot(export()).await.unwrap().status().is_success());
+        let lookup = Request::get("/lookup/did:key:z6Mk").body(Body::empty()).unwrap();
+        assert_eq!(internal.oneshot(lookup).await.unwrap().status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn large_reads_are_compressed() {
+        let state = AppState::for_tests();
+        for i in 0..100 {
+            let did = format!("did:key:z6Mkagent{}", i);
+            let endpoint = format!("https://agents.example.com/inbox/{}", i);
+            state.registry.register(types::RegistryEntry::for_test(&did, &endpoint, 3600)).await.unwrap();
+        }
+        let app = app(state, &CorsConfig::default(), limits::DEFAULT_MAX_BODY_BYTES);
+        let get = |path: &str| Request::get(path).header("accept-encoding", "gzip").body(Body::empty()).unwrap();
+
+        let plain = app.clone().oneshot(Request::get("/agents").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(plain.headers().get("content-encoding").is_none());
+        let plain = body_bytes(plain).await;
+
+        let gzipped = app.clone().oneshot(get("/agents")).await.unwrap();
+        assert!(gzipped.status().is_success());
+        assert_eq!(gzipped.headers()["content-encoding"], "gzip");
+        assert!(body_bytes(gzipped).await.len() < plain.len() / 2);
+
+        // Small responses are sent as they are
+        let health = app.clone().oneshot(get("/health")).await.unwrap();
+        assert!(health.headers().get("content-encoding").is_none());
+
+        // Handshake responses are never compressed
+        let key = agent_id::RootKey::generate();
+        let hello = serde_json::to_vec(&types::Hello::new(key.did().to_string())).unwrap();
+        let request = Request::post("/hello")
+            .header("accept-encoding", "gzip")
+            .header("content-type", "application/json")
+            .body(Body::from(hello))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        assert!(response.headers().get("content-encoding").is_none());
+        let challenge: types::Challenge = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(challenge.audience, key.did().to_string());
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn handler_durations_are_recorded_by_route_and_outcome() {
+        crate::telemetry::test_export();
+        let count = |route: &str, outcome: &str| {
+            crate::telemetry::histogram_counts("reach.handler.duration")
+                .into_iter()
+                .filter(|(attributes, _)| {
+                    attributes.contains(&("route".to_string(), route.to_string()))
+                        && attributes.contains(&("outcome".to_string(), outcome.to_string()))
+                })
+                .map(|(_, count)| count)
+                .sum::<u64>()
+        };
+        let (hello_ok, lookup_error) = (count("hello", "ok"), count("lookup", "error"));
+
+        let key = agent_id::RootKey::generate();
+        let hello = serde_json::to_vec(&types::Hello::new(key.did().to_string())).unwrap();
+        let request = Request::post("/v1/hello")
+            .header("content-type", "application/json")
+            .body(Body::from(hello))
+            .unwrap();
+        assert!(test_app().oneshot(request).await.unwrap().status().is_success());
+        let request = Request::get("/v1/lookup/did:key:z6Mkunknown").body(Body::empty()).unwrap();
+        assert_eq!(test_app().oneshot(request).await.unwrap().status(), axum::http::StatusCode::NOT_FOUND);
+
+        assert!(count("hello", "ok") > hello_ok);
+        assert!(count("lookup", "error") > lookup_error);
+    }
+
+    #[tokio::test]
+    async fn h2c_clients_can_look_up() {
+        use hyper_util::rt::{TokioExecutor, TokioIo};
+
+        let state = AppState::for_tests();
+        state.registry.register(types::RegistryEntry::for_test("did:key:z6Mkh2", "wss://agent.example", 3600)).await.unwrap();
+        let app = app(state, &CorsConfig::default(), limits::DEFAULT_MAX_BODY_BYTES);
+
+        let serve = |h2c: bool| {
+            let app = app.clone();
+            async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                let http = listen::HttpConfig { h2c, ..Default::default() };
+                tokio::spawn(listen::serve_all(vec![(listen::Listener::Tcp(listener), app)], http));
+                addr
+            }
+        };
+        let lookup = |addr: SocketAddr| async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                .await
+                .unwrap();
+            tokio::spawn(connection);
+            let request = Request::get(format!("http://{}/lookup/did:key:z6Mkh2", addr)).body(Body::empty()).unwrap();
+            sender.send_request(request).await
+        };
+
+        let response = lookup(serve(true).await).await.unwrap();
+        assert_eq!(response.version(), axum::http::Version::HTTP_2);
+        assert!(response.status().is_success());
+        let body: types::LookupResponse = serde_json::from_slice(&body_bytes(response.map(Body::new)).await).unwrap();
+        assert_eq!(body.endpoint, "wss://agent.example");
+
+        // Plain listeners speak HTTP/1.1 only unless h2c is enabled
+        assert!(lookup(serve(false).await).await.is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Request size limits
+//!
+//! Bodies are capped before anything parses them, so an oversized `/hello`
+//! or `/register` is refused with a 413 after reading at most the limit.
+//! Every public route shares `--max-body-bytes`, and the handshake has a
+//! tighter limit of its own. Replication between sync peers has its own
+//! limit instead, sized for a full batch. Fields with no natural bound, such
+//! as DIDs and endpoint URIs, get length checks with errors naming the field.
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ReachError;
+use crate::types::RegisterRequest;
+
+/// Body limit for `/hello` and `/proof`
+pub const HANDSHAKE_BODY_LIMIT: usize = 8 * 1024;
+
+/// Default body limit for the public routes; `/register` may list several
+/// endpoints, and a typical registration is well under 1 KiB
+pub const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Longest accepted DID
+pub const MAX_DID_LEN: usize = 512;
+
+/// Longest accepted endpoint URI
+pub const MAX_ENDPOINT_LEN: usize = 2048;
+
+/// Body limit for the sync routes: a full batch of the longest DIDs, quoted
+/// and comma-separated, with room for the rest of the request
+pub const SYNC_BODY_LIMIT: usize = crate::sync::MAX_BATCH * (MAX_DID_LEN + 3) + 1024;
+
+/// Refuse bodies over `max` bytes with a 413, going by `Content-Length` when
+/// the client sends one and otherwise reading no further than the limit
+pub async fn limit_body(State(max): State<usize>, request: Request, next: Next) -> Response {
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > max as u64) {
+        return ReachError::PayloadTooLarge(max).into_response();
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, max).await else {
+        return ReachError::PayloadTooLarge(max).into_response();
+    };
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+}
+
+/// Reject a DID too long to be one
+pub fn check_did(did: &str) -> Result<(), ReachError> {
+    check_len("DID", did, MAX_DID_LEN)
+}
+
+/// Reject registrations with oversized DIDs or endpoint URIs
+pub fn check_register(req: &RegisterRequest) -> Result<(), ReachError> {
+    check_len("endpoint", &req.endpoint, MAX_ENDPOINT_LEN)?;
+    for endpoint in &req.endpoints {
+        check_len("endpoint", &endpoint.uri, MAX_ENDPOINT_LEN)?;
+    }
+    if let Some(delegation) = &req.delegation {
+        check_did(&delegation.did)?;
+    }
+    Ok(())
+}
+
+fn check_len(field: &str, value: &str, max: usize) -> Result<(), ReachError> {
+    if value.len() > max {
+        return Err(ReachError::InvalidRequest(format!(
+            "{} is {} bytes; at most {} are allowed",
+            field,
+            value.len(),
+            max
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::Endpoint;
+
+    fn register_request(endpoint: String) -> RegisterRequest {
+        RegisterRequest {
+            endpoint,
+            ttl: 3600,
+            delegation: None,
+            did: None,
+            certificate: None,
+            handle: None,
+            weight: None,
+            endpoints: Vec::new(),
+            region: None,
+        }
+    }
+
+    #[test]
+    fn long_fields_are_named_in_the_error() {
+        let did = format!("did:key:z{}", "a".repeat(MAX_DID_LEN));
+        let err = check_did(&did).unwrap_err();
+        assert!(err.to_string().contains("DID is"), "{}", err);
+        assert!(check_did("did:key:z6Mkshort").is_ok());
+
+        let long = format!("wss://{}", "a".repeat(MAX_ENDPOINT_LEN));
+        assert!(check_register(&register_request(long.clone())).unwrap_err().to_string().contains("endpoint is"));
+
+        let mut req = register_request("wss://ok".to_string());
+        assert!(check_register(&req).is_ok());
+        req.endpoints.push(Endpoint { uri: long, weight: None, region: None, registered_at: None });
+        assert!(matches!(check_register(&req), Err(ReachError::InvalidRequest(_))));
+    }
+}
@@ -0,0 +1,338 @@
+//! Listeners for the HTTP API
+//!
+//! The API can be served on several TCP addresses at once, IPv4 and IPv6
+//! alike, and on a Unix domain socket. Everything is bound before serving
+//! starts, so an address that can't be bound stops startup instead of
+//! leaving a partly listening server.
+//!
+//! Connections are served by hyper directly rather than `axum::serve`, so
+//! keep-alive and HTTP/2 can be tuned the same way on every listener.
+
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+
+/// Default seconds an HTTP/1.1 connection may wait for its next request
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 60;
+
+/// Default seconds between HTTP/2 keep-alive pings
+pub const DEFAULT_HTTP2_KEEPALIVE_SECS: u64 = 30;
+
+/// Alternative name for `REACH_UNIX_SOCKET`
+pub const UDS_PATH_ENV: &str = "REACH_UDS_PATH";
+
+/// Pause after a failed accept, so running out of file descriptors doesn't
+/// become a busy loop
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// How connections are served, on every listener
+#[derive(Debug, Clone, Copy)]
+pub struct HttpConfig {
+    /// Accept HTTP/2 without TLS, from clients that start with the HTTP/2
+    /// preface (h2c with prior knowledge)
+    pub h2c: bool,
+    /// Keep HTTP/1.1 connections open for further requests
+    pub keep_alive: bool,
+    /// Close HTTP/1.1 connections that go this long without sending the
+    /// next request's headers
+    pub idle_timeout: Option<Duration>,
+    /// Ping HTTP/2 clients this often, closing connections that stop
+    /// answering
+    pub http2_keep_alive: Option<Duration>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            h2c: false,
+            keep_alive: true,
+            idle_timeout: Some(Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS)),
+            http2_keep_alive: Some(Duration::from_secs(DEFAULT_HTTP2_KEEPALIVE_SECS)),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Builder for connections that may speak either HTTP/1.1 or HTTP/2,
+    /// told apart by the connection preface, as over TLS where ALPN
+    /// negotiates HTTP/2
+    pub fn builder(&self) -> Builder<TokioExecutor> {
+        let mut builder = Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .keep_alive(self.keep_alive)
+            .header_read_timeout(self.idle_timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.http2_keep_alive);
+        builder
+    }
+
+    /// How plain listeners serve connections
+    fn protocols(&self) -> Protocols {
+        if self.h2c {
+            return Protocols::Any(self.builder());
+        }
+        let mut builder = http1::Builder::new();
+        builder
+            .timer(TokioTimer::new())
+            .keep_alive(self.keep_alive)
+            .header_read_timeout(self.idle_timeout);
+        Protocols::Http1(builder)
+    }
+}
+
+/// Connection builder of a plain listener
+///
+/// The auto-detecting builder can't be limited to HTTP/1.1 while keeping
+/// upgrades, so HTTP/1.1-only listeners use hyper's own builder.
+enum Protocols {
+    Http1(http1::Builder),
+    Any(Builder<TokioExecutor>),
+}
+
+/// A bound listener
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub async fn tcp(addr: SocketAddr) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        Ok(Self::Tcp(listener))
+    }
+
+    /// Bind a Unix domain socket, replacing a socket file left behind by an
+    /// earlier run
+    #[cfg(unix)]
+    pub fn unix(path: &Path) -> anyhow::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {}", path.display()))?,
+            Ok(_) => anyhow::bail!("Failed to listen on {}: file exists and is not a socket", path.display()),
+            Err(_) => {}
+        }
+        let listener = tokio::net::UnixListener::bind(path)
+            .with_context(|| format!("Failed to listen on {}", path.display()))?;
+        Ok(Self::Unix(listener, path.to_path_buf()))
+    }
+
+    #[cfg(not(unix))]
+    pub fn unix(_path: &Path) -> anyhow::Result<Self> {
+        anyhow::bail!("Unix domain sockets are not supported on this platform")
+    }
+
+    /// Serve `app` until Ctrl-C or SIGTERM, then let open connections
+    /// finish their requests
+    async fn serve(self, app: Router, http: HttpConfig) -> anyhow::Result<()> {
+        let protocols = Arc::new(http.protocols());
+        let (drain, draining) = watch::channel(());
+        let shutdown = crate::shutdown_signal();
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                accepted = self.accept(&app, &protocols, &draining) => {
+                    if let Err(e) = accepted {
+                        tracing::warn!(error = %e, listener = %self, "Failed to accept connection");
+                        tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    }
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+        #[cfg(unix)]
+        if let Self::Unix(_, path) = &self {
+            let _ = std::fs::remove_file(path);
+        }
+        drop(self);
+
+        // Every connection holds a receiver until it has finished
+        drop(draining);
+        let _ = drain.send(());
+        drain.closed().await;
+        Ok(())
+    }
+
+    /// Accept the next connection and serve it on its own task
+    async fn accept(
+        &self,
+        app: &Router,
+        protocols: &Arc<Protocols>,
+        draining: &watch::Receiver<()>,
+    ) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, remote) = listener.accept().await?;
+                let service = app.clone().map_request(move |mut request: Request<Incoming>| {
+                    request.extensions_mut().insert(ConnectInfo(remote));
+                    request
+                });
+                spawn_connection(stream, service, protocols.clone(), draining.clone());
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                spawn_connection(stream, app.clone(), protocols.clone(), draining.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "TCP listener"),
+            },
+            #[cfg(unix)]
+            Self::Unix(_, path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The Unix socket to serve on: the configured one, falling back to a
+/// non-empty `REACH_UDS_PATH` value
+pub fn unix_socket_path(configured: Option<PathBuf>, uds_path: Option<OsString>) -> Option<PathBuf> {
+    configured.or_else(|| uds_path.filter(|path| !path.is_empty()).map(PathBuf::from))
+}
+
+/// Serve each router on its listener until shutdown, failing as soon as any
+/// listener does
+pub async fn serve_all(listeners: Vec<(Listener, Router)>, http: HttpConfig) -> anyhow::Result<()> {
+    let mut tasks = JoinSet::new();
+    for (listener, app) in listeners {
+        tracing::info!("agent-reach-server listening on {}", listener);
+        tasks.spawn(listener.serve(app, http));
+    }
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+/// Serve one connection on its own task
+fn spawn_connection<I, S>(stream: I, service: S, protocols: Arc<Protocols>, draining: watch::Receiver<()>)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: tower::Service<Request<Incoming>, Response = axum::response::Response, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    tokio::spawn(async move {
+        let io = TokioIo::new(stream);
+        let service = TowerToHyperService::new(service);
+        let result: Result<(), Box<dyn std::error::Error + Send + Sync>> = match &*protocols {
+            Protocols::Http1(builder) => {
+                let connection = builder.serve_connection(io, service).with_upgrades();
+                until_drained(connection, |c| c.graceful_shutdown(), draining).await.map_err(Into::into)
+            }
+            Protocols::Any(builder) => {
+                let connection = builder.serve_connection_with_upgrades(io, service);
+                until_drained(connection, |c| c.graceful_shutdown(), draining).await
+            }
+        };
+        if let Err(e) = result {
+            tracing::debug!(error = %e, "Connection ended with an error");
+        }
+    });
+}
+
+/// Drive `connection` to the end, asking it to close once its requests are
+/// answered when shutdown starts
+async fn until_drained<C: Future>(
+    connection: C,
+    graceful_shutdown: impl FnOnce(Pin<&mut C>),
+    mut draining: watch::Receiver<()>,
+) -> C::Output {
+    tokio::pin!(connection);
+    tokio::select! {
+        output = connection.as_mut() => output,
+        _ = draining.changed() => {
+            graceful_shutdown(connection.as_mut());
+            connection.await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn taken_address_names_it_in_the_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        let Err(err) = Listener::tcp(addr).await else {
+            panic!("{} is already bound", addr);
+        };
+        assert!(err.to_string().contains(&addr.to_string()), "{}", err);
+    }
+
+    #[test]
+    fn uds_path_is_a_fallback_for_the_unix_socket() {
+        let configured = Some(PathBuf::from("/run/reach/api.sock"));
+        let uds_path = Some(OsString::from("/tmp/reach.sock"));
+
+        assert_eq!(unix_socket_path(configured.clone(), uds_path.clone()), configured);
+        assert_eq!(unix_socket_path(None, uds_path), Some(PathBuf::from("/tmp/reach.sock")));
+        assert_eq!(unix_socket_path(None, Some(OsString::new())), None);
+        assert_eq!(unix_socket_path(None, None), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_over_a_unix_socket() {
+        use axum::{body::Body, http::{Request, StatusCode}, routing::get};
+        use hyper_util::rt::TokioIo;
+
+        let path = std::env::temp_dir().join(format!("agent-reach-{}.sock", uuid::Uuid::new_v4()));
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        tokio::spawn(Listener::unix(&path).unwrap().serve(app, HttpConfig::default()));
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(connection);
+        let request = Request::get("/health").header("host", "localhost").body(Body::empty()).unwrap();
+        let response = sender.send_request(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"ok");
+
+        // A socket file left behind is replaced rather than failing startup
+        drop(Listener::unix(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,163 @@
+//! Lockout after repeated failed proofs
+//!
+//! Every proof that fails verification costs a signature check. Failures
+//! are counted per (client IP, DID the challenge was issued to) over a
+//! sliding window, never per the DID a proof claims to be from, which the
+//! client could vary. Once a pair reaches the limit, `/proof` answers 429
+//! for a cooldown without verifying anything. The count starts over after
+//! the cooldown and on a successful handshake, so an agent that failed
+//! while its clock was skewed gets back in once it's fixed. Connections
+//! without an IP address, such as over the Unix socket, are counted per
+//! DID.
+//!
+//! The IP is the connection's peer address. Behind a reverse proxy or NAT
+//! every client shares one, so anyone can ask for a challenge for a DID
+//! and fail it until every client there is locked out of that DID. Such
+//! deployments should disable the lockout or raise its limit.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+
+use parking_lot::Mutex;
+
+use crate::error::ReachError;
+
+/// Failed proofs allowed within the window by default
+pub const DEFAULT_MAX_FAILURES: u32 = 5;
+
+/// Window failures are counted over by default (seconds)
+pub const DEFAULT_WINDOW_SECS: u64 = 300;
+
+/// How long a locked-out pair waits by default (seconds)
+pub const DEFAULT_COOLDOWN_SECS: u64 = 300;
+
+/// Pairs tracked at once; those with the oldest activity go first
+const MAX_TRACKED: usize = 10_000;
+
+/// (client IP, DID)
+type Key = (Option<IpAddr>, String);
+
+#[derive(Default)]
+struct Failures {
+    /// When each failure in the window happened, oldest first
+    at: VecDeque<i64>,
+    locked_until: Option<i64>,
+}
+
+impl Failures {
+    fn last_activity(&self) -> i64 {
+        self.at.back().copied().max(self.locked_until).unwrap_or(i64::MIN)
+    }
+}
+
+/// Failed proof counts; a limit of 0 disables the lockout
+pub struct ProofLockout {
+    max_failures: u32,
+    window_secs: i64,
+    cooldown_secs: i64,
+    tracked: Mutex<HashMap<Key, Failures>>,
+}
+
+impl ProofLockout {
+    pub fn new(max_failures: u32, window_secs: u64, cooldown_secs: u64) -> Self {
+        Self {
+            max_failures,
+            window_secs: window_secs as i64,
+            cooldown_secs: cooldown_secs as i64,
+            tracked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fail with 429 while the pair is locked out
+    pub fn check(&self, ip: Option<IpAddr>, did: &str, now: i64) -> Result<(), ReachError> {
+        if self.max_failures == 0 {
+            return Ok(());
+        }
+        let tracked = self.tracked.lock();
+        match tracked.get(&(ip, did.to_string())).and_then(|failures| failures.locked_until) {
+            Some(until) if until > now => Err(ReachError::TooManyFailedProofs((until - now) as u64)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a failed proof, returning whether it locked the pair out
+    pub fn record_failure(&self, ip: Option<IpAddr>, did: &str, now: i64) -> bool {
+        if self.max_failures == 0 {
+            return false;
+        }
+        let mut tracked = self.tracked.lock();
+        if tracked.len() >= MAX_TRACKED {
+            let horizon = now - self.window_secs.max(self.cooldown_secs);
+            tracked.retain(|_, failures| failures.last_activity() > horizon);
+            if tracked.len() >= MAX_TRACKED {
+                if let Some(oldest) = tracked.iter().min_by_key(|(_, f)| f.last_activity()).map(|(key, _)| key.clone()) {
+                    tracked.remove(&oldest);
+                }
+            }
+        }
+
+        let failures = tracked.entry((ip, did.to_string())).or_default();
+        if failures.locked_until.is_some_and(|until| until <= now) {
+            failures.locked_until = None;
+        }
+        failures.at.push_back(now);
+        while failures.at.front().is_some_and(|&at| at <= now - self.window_secs) {
+            failures.at.pop_front();
+        }
+        if failures.at.len() < self.max_failures as usize {
+            return false;
+        }
+        failures.at.clear();
+        failures.locked_until = Some(now + self.cooldown_secs);
+        true
+    }
+
+    /// Forget the pair's failures after a successful handshake
+    pub fn record_success(&self, ip: Option<IpAddr>, did: &str) {
+        if self.max_failures > 0 {
+            self.tracked.lock().remove(&(ip, did.to_string()));
+        }
+    }
+}
+
+impl Default for ProofLockout {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FAILURES, DEFAULT_WINDOW_SECS, DEFAULT_COOLDOWN_SECS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+
+    #[test]
+    fn repeated_failures_lock_out_until_the_cooldown_ends() {
+        let lockout = ProofLockout::new(3, 60, 120);
+        assert!(!lockout.record_failure(IP, "did:key:a", 0));
+        assert!(!lockout.record_failure(IP, "did:key:a", 10));
+        assert!(lockout.record_failure(IP, "did:key:a", 20));
+
+        let err = lockout.check(IP, "did:key:a", 30).unwrap_err();
+        assert!(matches!(err, ReachError::TooManyFailedProofs(110)));
+        // Other pairs are unaffected
+        assert!(lockout.check(None, "did:key:a", 30).is_ok());
+        assert!(lockout.check(IP, "did:key:b", 30).is_ok());
+
+        // After the cooldown the count starts over
+        assert!(lockout.check(IP, "did:key:a", 140).is_ok());
+        assert!(!lockout.record_failure(IP, "did:key:a", 140));
+    }
+
+    #[test]
+    fn failures_age_out_and_success_resets() {
+        let lockout = ProofLockout::new(2, 60, 120);
+        lockout.record_failure(IP, "did:key:a", 0);
+        assert!(!lockout.record_failure(IP, "did:key:a", 60), "the first failure left the window");
+
+        lockout.record_success(IP, "did:key:a");
+        assert!(!lockout.record_failure(IP, "did:key:a", 61));
+        assert!(lockout.check(IP, "did:key:a", 61).is_ok());
+    }
+}
@@ -0,0 +1,65 @@
+//! Log output setup
+//!
+//! Formatting comes from `agent-reach-logging`, shared with the MCP server.
+//! With the `otel` feature, spans and metrics are also exported over OTLP
+//! when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+pub use agent_reach_logging::error_chain;
+use agent_reach_logging::{fmt_layer, LogFormat};
+
+/// Keeps trace and metric export alive; call [`LogGuard::shutdown`] before
+/// exiting so pending spans and metrics are flushed
+#[must_use]
+pub struct LogGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+    #[cfg(feature = "otel")]
+    meters: Option<opentelemetry_sdk::metrics::SdkMeterProvider>,
+}
+
+impl LogGuard {
+    pub fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush traces: {}", e);
+            }
+        }
+        #[cfg(feature = "otel")]
+        if let Some(meters) = self.meters {
+            if let Err(e) = meters.shutdown() {
+                eprintln!("Failed to flush metrics: {}", e);
+            }
+        }
+    }
+}
+
+/// Install the global subscriber, honouring `RUST_LOG` and `REACH_LOG_FORMAT`
+pub fn init(default_filter: &str) -> anyhow::Result<LogGuard> {
+    #[cfg(feature = "otel")]
+    let provider = crate::telemetry::tracer_provider()?;
+    #[cfg(feature = "otel")]
+    let meters = crate::telemetry::meter_provider()?;
+    #[cfg(feature = "otel")]
+    let otel_layer = provider.as_ref().map(|provider| {
+        use opentelemetry::trace::TracerProvider;
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(crate::telemetry::SERVICE_NAME))
+    });
+    #[cfg(not(feature = "otel"))]
+    let otel_layer: Option<tracing_subscriber::layer::Identity> = None;
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| default_filter.into()))
+        .with(fmt_layer(LogFormat::from_env(), std::io::stdout))
+        .with(otel_layer)
+        .init();
+
+    Ok(LogGuard {
+        #[cfg(feature = "otel")]
+        provider,
+        #[cfg(feature = "otel")]
+        meters,
+    })
+}
@@ -0,0 +1,357 @@
+//! In-process lookup cache in front of a storage backend
+//!
+//! Found entries are cached until they expire, misses for a short
+//! configurable time, and concurrent lookups of the same DID share a single
+//! storage fetch. Writes made through the cache update or drop the affected
+//! entry; writes from another process sharing the storage are not seen, so
+//! replicas sharing a database should run without the cache.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::OnceCell;
+
+use crate::backend::{Precondition, RegistryBackend};
+use crate::error::ReachError;
+use crate::types::{RegistryEntry, StatusCounts};
+
+/// Cached DIDs kept before the cache is pruned
+const MAX_CACHED: usize = 10_000;
+
+enum Cached {
+    /// Served until the registration expires
+    Found(RegistryEntry),
+    /// Served until the instant passes
+    Missing(Instant),
+}
+
+impl Cached {
+    fn is_fresh(&self, now: i64) -> bool {
+        match self {
+            Cached::Found(entry) => entry.expires_at > now,
+            Cached::Missing(until) => Instant::now() < *until,
+        }
+    }
+}
+
+/// A storage fetch other lookups of the same DID can wait on
+type Flight = Arc<OnceCell<Option<RegistryEntry>>>;
+
+pub struct CachedRegistry {
+    inner: Arc<dyn RegistryBackend>,
+    negative_ttl: Duration,
+    cached: RwLock<HashMap<String, Cached>>,
+    in_flight: Mutex<HashMap<String, Flight>>,
+    /// Bumped on every write, so a fetch that raced one isn't cached
+    generation: AtomicU64,
+}
+
+impl CachedRegistry {
+    pub fn new(inner: Arc<dyn RegistryBackend>, negative_ttl: Duration) -> Self {
+        Self {
+            inner,
+            negative_ttl,
+            cached: RwLock::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn cached(&self, did: &str) -> Option<Option<RegistryEntry>> {
+        let now = chrono::Utc::now().timestamp();
+        match self.cached.read().get(did)? {
+            cached if !cached.is_fresh(now) => None,
+            Cached::Found(entry) => Some(Some(entry.clone())),
+            Cached::Missing(_) => Some(None),
+        }
+    }
+
+    fn store(&self, did: &str, entry: Option<RegistryEntry>) {
+        let cached = match entry {
+            Some(entry) => Cached::Found(entry),
+            None => Cached::Missing(Instant::now() + self.negative_ttl),
+        };
+
+        let mut map = self.cached.write();
+        if map.len() >= MAX_CACHED && !map.contains_key(did) {
+            let now = chrono::Utc::now().timestamp();
+            map.retain(|_, cached| cached.is_fresh(now));
+            if map.len() >= MAX_CACHED {
+                map.clear();
+            }
+        }
+        map.insert(did.to_string(), cached);
+    }
+
+    /// Forget a DID after a write, and stop later lookups joining a fetch
+    /// that started before it
+    fn invalidate(&self, did: &str) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.cached.write().remove(did);
+        self.in_flight.lock().remove(did);
+    }
+
+    async fn fetch(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        let flight = self.in_flight.lock().entry(did.to_string()).or_default().clone();
+
+        let generation = self.generation.load(Ordering::Acquire);
+        let result = flight
+            .get_or_try_init(|| async {
+                let entry = self.inner.lookup(did).await?;
+                if self.generation.load(Ordering::Acquire) == generation {
+                    self.store(did, entry.clone());
+                }
+                Ok(entry)
+            })
+            .await
+            .cloned();
+
+        let mut in_flight = self.in_flight.lock();
+        if in_flight.get(did).is_some_and(|current| Arc::ptr_eq(current, &flight)) {
+            in_flight.remove(did);
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl RegistryBackend for CachedRegistry {
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        Ok(self.register_evicting(entry, precondition).await?.0)
+    }
+
+    async fn register_evicting(
+        &self,
+        entry: RegistryEntry,
+        precondition: Precondition,
+    ) -> Result<(Option<u64>, Vec<RegistryEntry>), ReachError> {
+        let did = entry.did.clone();
+        let result = self.inner.register_evicting(entry, precondition).await;
+        self.invalidate(&did);
+        if let Ok((_, evicted)) = &result {
+            evicted.iter().for_each(|entry| self.invalidate(&entry.did));
+        }
+        result
+    }
+
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        match self.cached(did) {
+            Some(entry) => Ok(entry),
+            None => self.fetch(did).await,
+        }
+    }
+
+    fn might_contain(&self, did: &str) -> bool {
+        self.inner.might_contain(did)
+    }
+
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.resolve_handle(handle).await
+    }
+
+    async fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Result<Option<String>, ReachError> {
+        self.inner.endpoint_claimant(endpoint, did).await
+    }
+
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+        let result = self.inner.touch(did, last_seen).await;
+        self.invalidate(did);
+        result
+    }
+
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+        let result = self.inner.deregister(did).await;
+        self.invalidate(did);
+        result
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let now = chrono::Utc::now().timestamp();
+        self.cached.write().retain(|_, cached| cached.is_fresh(now));
+        self.inner.purge_expired().await
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+
+    async fn ping(&self) -> Result<(), ReachError> {
+        self.inner.ping().await
+    }
+
+    async fn status_counts(&self, now: i64, idle_before: i64) -> Result<StatusCounts, ReachError> {
+        self.inner.status_counts(now, idle_before).await
+    }
+
+    async fn check_admission(&self, did: &str, now: i64) -> Result<(), ReachError> {
+        self.inner.check_admission(did, now).await
+    }
+
+    async fn live_count(&self, now: i64) -> Result<usize, ReachError> {
+        self.inner.live_count(now).await
+    }
+
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.soonest_expiring(now).await
+    }
+
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.list().await
+    }
+
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.all_entries().await
+    }
+
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.history(did).await
+    }
+
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+        self.inner.revoke_certificates(subject, delegate, revoked_at, expires_at).await
+    }
+
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+        self.inner.certificates_revoked_at(subject, delegate, now).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::registry::Registry;
+
+    /// In-memory storage that counts lookups and answers them slowly
+    #[derive(Default)]
+    struct SlowStorage {
+        registry: Registry,
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl RegistryBackend for SlowStorage {
+        async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+            RegistryBackend::register_when(&self.registry, entry, precondition).await
+        }
+
+        async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            RegistryBackend::lookup(&self.registry, did).await
+        }
+
+        async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+            RegistryBackend::resolve_handle(&self.registry, handle).await
+        }
+
+        async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+            RegistryBackend::touch(&self.registry, did, last_seen).await
+        }
+
+        async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+            RegistryBackend::deregister(&self.registry, did).await
+        }
+
+        async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+            RegistryBackend::purge_expired(&self.registry).await
+        }
+
+        async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+            RegistryBackend::list(&self.registry).await
+        }
+
+        async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+            RegistryBackend::all_entries(&self.registry).await
+        }
+
+        async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+            RegistryBackend::history(&self.registry, did).await
+        }
+
+        async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+            RegistryBackend::revoke_certificates(&self.registry, subject, delegate, revoked_at, expires_at).await
+        }
+
+        async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+            RegistryBackend::certificates_revoked_at(&self.registry, subject, delegate, now).await
+        }
+    }
+
+    fn cached(negative_ttl: Duration) -> (Arc<SlowStorage>, CachedRegistry) {
+        let storage = Arc::new(SlowStorage::default());
+        let cache = CachedRegistry::new(storage.clone(), negative_ttl);
+        (storage, cache)
+    }
+
+    fn entry(did: &str, endpoint: &str) -> RegistryEntry {
+        RegistryEntry::for_test(did, endpoint, 3600)
+    }
+
+    #[tokio::test]
+    async fn backend_harness() {
+        let (_, cache) = cached(Duration::from_secs(5));
+        crate::backend::harness::run(&cache).await;
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_share_one_fetch() {
+        let (storage, cache) = cached(Duration::from_secs(5));
+        storage.register(entry("did:key:a", "wss://one")).await.unwrap();
+        let cache = Arc::new(cache);
+
+        let lookups: Vec<_> = (0..16)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move { cache.lookup("did:key:a").await.unwrap() })
+            })
+            .collect();
+        for lookup in lookups {
+            assert_eq!(lookup.await.unwrap().unwrap().endpoint, "wss://one");
+        }
+
+        assert_eq!(storage.lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn misses_are_cached_until_registration() {
+        let (storage, cache) = cached(Duration::from_secs(60));
+
+        assert!(cache.lookup("did:key:a").await.unwrap().is_none());
+        assert!(cache.lookup("did:key:a").await.unwrap().is_none());
+        assert_eq!(storage.lookups.load(Ordering::SeqCst), 1);
+
+        cache.register(entry("did:key:a", "wss://one")).await.unwrap();
+        assert_eq!(cache.lookup("did:key:a").await.unwrap().unwrap().endpoint, "wss://one");
+    }
+
+    #[tokio::test]
+    async fn misses_expire_after_negative_ttl() {
+        let (storage, cache) = cached(Duration::from_millis(10));
+
+        assert!(cache.lookup("did:key:a").await.unwrap().is_none());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(cache.lookup("did:key:a").await.unwrap().is_none());
+
+        assert_eq!(storage.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn writes_replace_cached_entries() {
+        let (storage, cache) = cached(Duration::from_secs(5));
+        cache.register(entry("did:key:a", "wss://one")).await.unwrap();
+        cache.lookup("did:key:a").await.unwrap();
+
+        cache.register(entry("did:key:a", "wss://two")).await.unwrap();
+        assert_eq!(cache.lookup("did:key:a").await.unwrap().unwrap().endpoint, "wss://two");
+
+        cache.deregister("did:key:a").await.unwrap();
+        assert!(cache.lookup("did:key:a").await.unwrap().is_none());
+        assert_eq!(storage.lookups.load(Ordering::SeqCst), 3);
+    }
+}
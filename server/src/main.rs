@@ -1,17 +1,39 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::{routing::{get, post}, Router};
-use clap::Parser;
-use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use clap::{Parser, Subcommand, ValueEnum};
 
-mod error;
-mod handlers;
-mod registry;
-mod types;
+use agent_reach_server::backend::{self, RegistryBackend};
+use agent_reach_server::cors::CorsConfig;
+use agent_reach_server::handlers::{self, AppState, HandshakeState};
+use agent_reach_server::{
+    app, capacity, changes, internal_app, limits, listen, lockout, logging, nonce, protocol, public_app,
+    registry, seed, snapshot, stats, sync, ttl, watch, webfinger, PlainHttp,
+};
+#[cfg(feature = "did-web")]
+use agent_reach_server::did_web;
+#[cfg(feature = "federation")]
+use agent_reach_server::federation;
+#[cfg(feature = "grpc")]
+use agent_reach_server::{grpc, shutdown_signal};
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+use agent_reach_server::lookup_cache;
+#[cfg(feature = "postgres")]
+use agent_reach_server::postgres;
+#[cfg(feature = "redis")]
+use agent_reach_server::redis;
+#[cfg(feature = "sqlite")]
+use agent_reach_server::sqlite;
+#[cfg(feature = "otel")]
+use agent_reach_server::telemetry;
+#[cfg(feature = "tls")]
+use agent_reach_server::tls;
 
-use handlers::{AppState, HandshakeState};
+/// How often expired registrations are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Parser)]
 #[command(name = "agent-reach-server")]
@@ -20,41 +42,614 @@ struct Cli {
     /// Port to listen on
     #[arg(short, long, default_value = "3001")]
     port: u16,
+
+    /// Addresses to listen on instead of 0.0.0.0 on --port (comma-separated,
+    /// e.g. `[::]:3001,127.0.0.1:3002`)
+    #[arg(long, env = "REACH_LISTEN", value_delimiter = ',')]
+    listen: Vec<SocketAddr>,
+
+    /// Unix domain socket to serve the API on as well (`REACH_UDS_PATH` is
+    /// also read)
+    #[arg(long, alias = "uds-path", env = "REACH_UNIX_SOCKET")]
+    unix_socket: Option<PathBuf>,
+
+    /// Internal-only address for the /admin endpoints, which are then left
+    /// off the other listeners
+    #[arg(long, env = "REACH_INTERNAL_ADDR", requires = "admin_token")]
+    internal_addr: Option<SocketAddr>,
+
+    /// Accept HTTP/2 without TLS from clients that start with the HTTP/2
+    /// preface (h2c); HTTPS negotiates HTTP/2 regardless
+    #[arg(long, env = "REACH_H2C")]
+    h2c: bool,
+
+    /// Close HTTP/1.1 connections after each response
+    #[arg(long, env = "REACH_NO_KEEP_ALIVE")]
+    no_keep_alive: bool,
+
+    /// Seconds an HTTP/1.1 connection may wait for its next request (0 for
+    /// no limit)
+    #[arg(long, env = "REACH_IDLE_TIMEOUT", default_value_t = listen::DEFAULT_IDLE_TIMEOUT_SECS)]
+    idle_timeout: u64,
+
+    /// Seconds between HTTP/2 keep-alive pings; connections that stop
+    /// answering are closed (0 disables)
+    #[arg(long, env = "REACH_HTTP2_KEEPALIVE", default_value_t = listen::DEFAULT_HTTP2_KEEPALIVE_SECS)]
+    http2_keepalive: u64,
+
+    /// Number of past registrations to keep per DID
+    #[arg(long, default_value_t = registry::DEFAULT_HISTORY_LIMIT)]
+    history_limit: usize,
+
+    /// Shortest registration TTL in seconds
+    #[arg(long, env = "REACH_MIN_TTL", default_value_t = ttl::DEFAULT_MIN_TTL_SECS)]
+    min_ttl: u64,
+
+    /// Longest registration TTL in seconds
+    #[arg(long, env = "REACH_MAX_TTL", default_value_t = ttl::DEFAULT_MAX_TTL_SECS)]
+    max_ttl: u64,
+
+    /// Whether a TTL outside the bounds is clamped to them or rejected
+    #[arg(long, env = "REACH_TTL_MODE", value_enum, default_value_t)]
+    ttl_mode: ttl::TtlMode,
+
+    /// Regions endpoints may be tagged with (comma-separated; any when unset)
+    #[arg(long, env = "REACH_REGIONS", value_delimiter = ',')]
+    regions: Vec<String>,
+
+    /// Reject registering an endpoint another live DID has registered
+    #[arg(long, env = "REACH_UNIQUE_ENDPOINTS")]
+    unique_endpoints: bool,
+
+    /// Registry changes kept for GET /changes
+    #[arg(long, env = "REACH_CHANGE_LOG_SIZE", default_value_t = changes::DEFAULT_RETAINED)]
+    change_log_size: usize,
+
+    /// GET /watch sockets open at once
+    #[arg(long, env = "REACH_MAX_WATCHERS", default_value_t = watch::DEFAULT_MAX_WATCHERS)]
+    max_watchers: usize,
+
+    /// Maximum entries held by the in-memory registry; the least recently
+    /// looked-up entry is evicted when full (unlimited when unset)
+    #[arg(long, env = "REACH_MAX_ENTRIES")]
+    max_entries: Option<usize>,
+
+    /// Maximum live registrations with any storage; new DIDs are refused
+    /// with 503 when full (unlimited when unset)
+    #[arg(long, env = "REACH_CAPACITY")]
+    capacity: Option<usize>,
+
+    /// What a new DID's registration does when the registry is full
+    #[arg(long, env = "REACH_CAPACITY_POLICY", value_enum, default_value_t)]
+    capacity_policy: capacity::CapacityPolicy,
+
+    /// Percent of --capacity at which a warning is logged
+    #[arg(long, env = "REACH_CAPACITY_HIGH_WATER", default_value_t = capacity::DEFAULT_HIGH_WATER_PERCENT)]
+    capacity_high_water: u8,
+
+    /// Storage backend (defaults to postgres when a database URL is set, memory otherwise)
+    #[arg(long, env = "REACH_STORAGE", value_enum)]
+    storage: Option<Storage>,
+
+    /// Largest request body the public routes accept, in bytes; bigger ones
+    /// get 413
+    #[arg(
+        long,
+        env = "REACH_MAX_BODY_BYTES",
+        default_value_t = limits::DEFAULT_MAX_BODY_BYTES as u64,
+        value_parser = clap::value_parser!(u64).range(limits::HANDSHAKE_BODY_LIMIT as u64..),
+    )]
+    max_body_bytes: u64,
+
+    /// Seconds a database backend remembers that a DID is not registered
+    #[arg(long, env = "REACH_NEGATIVE_LOOKUP_TTL", default_value_t = 5)]
+    negative_lookup_ttl: u64,
+
+    /// Send every lookup to the database; use when several servers share it,
+    /// since the cache only sees writes made through this process
+    #[arg(long, env = "REACH_NO_LOOKUP_CACHE")]
+    no_lookup_cache: bool,
+
+    /// SQLite database file
+    #[arg(long, env = "REACH_SQLITE_PATH", default_value = "reach.db")]
+    sqlite_path: PathBuf,
+
+    /// PostgreSQL connection URL
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: Option<String>,
+
+    /// JSON file of {did, endpoint, ttl} entries to register at startup
+    #[arg(long, env = "REACH_SEED_FILE")]
+    seed_file: Option<PathBuf>,
+
+    /// Bearer token for the /admin endpoints (disabled when unset)
+    #[arg(long, env = "REACH_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// Sessions a DID may hold at once; a new handshake ends the oldest
+    #[arg(long, env = "REACH_MAX_SESSIONS_PER_DID", default_value_t = handlers::DEFAULT_MAX_SESSIONS_PER_DID)]
+    max_sessions_per_did: usize,
+
+    /// Random bytes in each challenge nonce, at least 16 (the handshake
+    /// library's default when unset)
+    #[arg(long, env = "REACH_NONCE_BYTES")]
+    nonce_bytes: Option<usize>,
+
+    /// Registry protocols to negotiate in the handshake, preferred first
+    /// (comma-separated)
+    #[arg(long, env = "REACH_PROTOCOLS", value_delimiter = ',', default_value = protocol::BASELINE)]
+    protocols: Vec<String>,
+
+    /// Protocols a session must have agreed on to register (comma-separated;
+    /// any of --protocols when unset)
+    #[arg(long, env = "REACH_REGISTER_PROTOCOLS", value_delimiter = ',')]
+    register_protocols: Vec<String>,
+
+    /// Failed proofs from one client for one DID before /proof answers 429
+    /// (0 disables the lockout)
+    #[arg(long, env = "REACH_PROOF_MAX_FAILURES", default_value_t = lockout::DEFAULT_MAX_FAILURES)]
+    proof_max_failures: u32,
+
+    /// Seconds failed proofs are counted over
+    #[arg(long, env = "REACH_PROOF_FAILURE_WINDOW", default_value_t = lockout::DEFAULT_WINDOW_SECS)]
+    proof_failure_window: u64,
+
+    /// Seconds a locked-out client waits before trying again
+    #[arg(long, env = "REACH_PROOF_LOCKOUT", default_value_t = lockout::DEFAULT_COOLDOWN_SECS)]
+    proof_lockout: u64,
+
+    /// Require the admin token for GET /stats
+    #[arg(long, env = "REACH_PRIVATE_STATS")]
+    private_stats: bool,
+
+    /// Origins allowed to call the API from a browser (comma-separated;
+    /// `*` allows any, for local development)
+    #[arg(long, env = "REACH_CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Vec<String>,
+
+    /// Also allow the listed origins to run handshakes and register
+    #[arg(long, env = "REACH_CORS_ALLOW_WRITES", requires = "cors_origins")]
+    cors_allow_writes: bool,
+
+    /// Redis connection URL; Redis storage also holds handshake sessions
+    #[arg(long, env = "REDIS_URL", default_value = "redis://127.0.0.1/")]
+    redis_url: String,
+
+    /// PEM certificate chain; serves HTTPS on --port when set with --tls-key
+    #[arg(long, env = "REACH_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key matching --tls-cert
+    #[arg(long, env = "REACH_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Port for a plain HTTP listener alongside TLS
+    #[arg(long, env = "REACH_HTTP_PORT", requires = "tls_cert")]
+    http_port: Option<u16>,
+
+    /// What the plain HTTP listener does with requests
+    #[arg(long, env = "REACH_PLAIN_HTTP", value_enum, default_value_t)]
+    plain_http: PlainHttp,
+
+    /// Address for the gRPC interface (disabled when unset)
+    #[arg(long, env = "REACH_GRPC_ADDR")]
+    grpc_addr: Option<SocketAddr>,
+
+    /// Peer registries to ask about DIDs not registered here
+    /// (comma-separated base URLs)
+    #[arg(long, env = "REACH_PEERS", value_delimiter = ',')]
+    peers: Vec<String>,
+
+    /// Milliseconds to wait for a peer to answer a forwarded lookup
+    #[arg(long, env = "REACH_PEER_TIMEOUT_MS", default_value_t = 500)]
+    peer_timeout_ms: u64,
+
+    /// Don't forward lookups that already crossed this many registries
+    #[arg(long, env = "REACH_PEER_MAX_HOPS", default_value_t = 2)]
+    peer_max_hops: u32,
+
+    /// Keep entries found at peers until they expire
+    #[arg(long, env = "REACH_CACHE_PEER_LOOKUPS")]
+    cache_peer_lookups: bool,
+
+    /// Base URL clients reach this registry at, e.g. https://reach.example;
+    /// enables WebFinger for handles on its domain
+    #[arg(long, env = "REACH_PUBLIC_URL")]
+    public_url: Option<String>,
+
+    /// Registries to replicate with (comma-separated base URLs)
+    #[arg(long, env = "REACH_SYNC_PEERS", value_delimiter = ',')]
+    sync_peers: Vec<String>,
+
+    /// Seconds between pulls from each sync peer
+    #[arg(long, env = "REACH_SYNC_INTERVAL", default_value_t = 30)]
+    sync_interval: u64,
+
+    /// Seconds a deregistration is remembered so it reaches sync peers
+    #[arg(long, env = "REACH_SYNC_TOMBSTONE_TTL", default_value_t = sync::DEFAULT_TOMBSTONE_TTL_SECS)]
+    sync_tombstone_ttl: u64,
+
+    /// Let agents run the handshake as did:web DIDs, fetching their
+    /// documents over HTTPS
+    #[arg(long, env = "REACH_DID_WEB")]
+    did_web: bool,
+
+    /// Milliseconds to wait for a did:web document
+    #[arg(long, env = "REACH_DID_WEB_TIMEOUT_MS", default_value_t = 3_000)]
+    did_web_timeout_ms: u64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Storage {
+    Memory,
+    Sqlite,
+    Postgres,
+    Redis,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import a JSON snapshot into the configured storage backend and exit
+    Import {
+        /// Snapshot file
+        file: PathBuf,
+
+        /// Keep existing registrations (merge) or clear them first (replace)
+        #[arg(long, value_enum, default_value_t)]
+        mode: snapshot::ImportMode,
+    },
+}
+
+impl Cli {
+    fn storage(&self) -> Storage {
+        self.storage.unwrap_or(if self.database_url.is_some() {
+            Storage::Postgres
+        } else {
+            Storage::Memory
+        })
+    }
+
+    /// Connection settings for every HTTP listener
+    fn http_config(&self) -> listen::HttpConfig {
+        let secs = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+        listen::HttpConfig {
+            h2c: self.h2c,
+            keep_alive: !self.no_keep_alive,
+            idle_timeout: secs(self.idle_timeout),
+            http2_keep_alive: secs(self.http2_keepalive),
+        }
+    }
+
+    /// `--unix-socket`, falling back to `REACH_UDS_PATH`
+    fn unix_socket(&self) -> Option<PathBuf> {
+        listen::unix_socket_path(self.unix_socket.clone(), std::env::var_os(listen::UDS_PATH_ENV))
+    }
+}
+
+/// Select the storage backend from the CLI configuration
+async fn open_registry(cli: &Cli) -> anyhow::Result<Arc<dyn RegistryBackend>> {
+    if cli.max_entries.is_some() && cli.storage() != Storage::Memory {
+        tracing::warn!("--max-entries only applies to the in-memory registry; ignoring it");
+    }
+
+    match cli.storage() {
+        Storage::Memory => {
+            tracing::info!(max_entries = cli.max_entries, "Using in-memory registry");
+            let registry = registry::Registry::with_history_limit(cli.history_limit)
+                .with_capacity(cli.max_entries);
+            Ok(Arc::new(registry))
+        }
+        #[cfg(feature = "sqlite")]
+        Storage::Sqlite => {
+            tracing::info!(path = %cli.sqlite_path.display(), "Using SQLite registry");
+            let registry = sqlite::SqliteRegistry::open(&cli.sqlite_path, cli.history_limit).await?;
+            Ok(with_lookup_cache(cli, Arc::new(registry)))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Storage::Sqlite => anyhow::bail!("SQLite storage requires building with the sqlite feature"),
+        #[cfg(feature = "postgres")]
+        Storage::Postgres => {
+            let url = cli.database_url.as_deref()
+                .ok_or_else(|| anyhow::anyhow!("PostgreSQL storage requires --database-url"))?;
+            tracing::info!("Using PostgreSQL registry");
+            let registry = postgres::PostgresRegistry::connect(url, cli.history_limit).await?;
+            Ok(with_lookup_cache(cli, Arc::new(registry)))
+        }
+        #[cfg(not(feature = "postgres"))]
+        Storage::Postgres => anyhow::bail!("PostgreSQL storage requires building with the postgres feature"),
+        #[cfg(feature = "redis")]
+        Storage::Redis => {
+            tracing::info!("Using Redis registry");
+            Ok(Arc::new(redis::RedisStore::connect(&cli.redis_url, cli.history_limit).await?))
+        }
+        #[cfg(not(feature = "redis"))]
+        Storage::Redis => anyhow::bail!("Redis storage requires building with the redis feature"),
+    }
+}
+
+/// Peer registries for lookup misses, if any are configured
+#[cfg(feature = "federation")]
+fn open_peers(cli: &Cli) -> anyhow::Result<Option<Arc<dyn backend::PeerLookup>>> {
+    if cli.peers.is_empty() {
+        return Ok(None);
+    }
+    let federation = federation::Federation::new(
+        &cli.peers,
+        Duration::from_millis(cli.peer_timeout_ms),
+        cli.peer_max_hops,
+        cli.cache_peer_lookups,
+    )?;
+    tracing::info!(peers = cli.peers.len(), "Forwarding lookup misses to peers");
+    Ok(Some(Arc::new(federation)))
+}
+
+#[cfg(not(feature = "federation"))]
+fn open_peers(_cli: &Cli) -> anyhow::Result<Option<Arc<dyn backend::PeerLookup>>> {
+    Ok(None)
+}
+
+/// Resolver for did:web agents, if enabled
+#[cfg(feature = "did-web")]
+fn open_did_web(cli: &Cli) -> Option<Arc<dyn backend::DidResolver>> {
+    if !cli.did_web {
+        return None;
+    }
+    tracing::info!("Accepting did:web identities");
+    Some(Arc::new(did_web::Resolver::new(Duration::from_millis(cli.did_web_timeout_ms))))
+}
+
+#[cfg(not(feature = "did-web"))]
+fn open_did_web(_cli: &Cli) -> Option<Arc<dyn backend::DidResolver>> {
+    None
+}
+
+/// Put the lookup cache in front of a database backend unless disabled
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn with_lookup_cache(cli: &Cli, registry: Arc<dyn RegistryBackend>) -> Arc<dyn RegistryBackend> {
+    if cli.no_lookup_cache {
+        return registry;
+    }
+    let negative_ttl = std::time::Duration::from_secs(cli.negative_lookup_ttl);
+    tracing::info!(negative_ttl_secs = cli.negative_lookup_ttl, "Caching lookups in process");
+    Arc::new(lookup_cache::CachedRegistry::new(registry, negative_ttl))
+}
+
+/// Select where handshake sessions live: in Redis when the registry is, so
+/// replicas share them, otherwise in memory
+async fn open_handshake(cli: &Cli) -> anyhow::Result<HandshakeState> {
+    #[cfg(feature = "redis")]
+    if cli.storage() == Storage::Redis {
+        let store = redis::RedisStore::connect(&cli.redis_url, cli.history_limit).await?;
+        return Ok(HandshakeState::with_store(Arc::new(store)));
+    }
+
+    let _ = cli;
+    Ok(HandshakeState::new())
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
 
     // Initialize tracing
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::try_from_default_env()
-            .unwrap_or_else(|_| "agent_reach_server=info,tower_http=debug".into()))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let logs = match logging::init("agent_reach_server=info,tower_http=debug") {
+        Ok(logs) => logs,
+        Err(e) => {
+            eprintln!("Failed to initialise logging: {:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = run(cli).await;
+    if let Err(e) = &result {
+        tracing::error!(error = %logging::error_chain(e), "agent-reach-server failed");
+    }
+    logs.shutdown();
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let cors = CorsConfig::new(&cli.cors_origins, cli.cors_allow_writes)?;
+    let max_body = cli.max_body_bytes as usize;
+    let ttl = ttl::TtlPolicy::new(cli.min_ttl, cli.max_ttl, cli.ttl_mode)?;
+
+    // Check the certificate before touching storage so a bad pair fails fast
+    #[cfg(feature = "tls")]
+    let tls = match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::Tls::load(cert, key)?),
+        _ => None,
+    };
+    #[cfg(feature = "tls")]
+    if tls.is_some() && cli.listen.len() > 1 {
+        anyhow::bail!("With TLS, --listen takes a single address");
+    }
+    #[cfg(not(feature = "tls"))]
+    if cli.tls_cert.is_some() {
+        anyhow::bail!("TLS requires building with the tls feature");
+    }
+    #[cfg(not(feature = "grpc"))]
+    if cli.grpc_addr.is_some() {
+        anyhow::bail!("gRPC requires building with the grpc feature");
+    }
+    #[cfg(not(feature = "federation"))]
+    if !cli.peers.is_empty() {
+        anyhow::bail!("Peers require building with the federation feature");
+    }
+    #[cfg(not(feature = "federation"))]
+    if !cli.sync_peers.is_empty() {
+        anyhow::bail!("Sync requires building with the federation feature");
+    }
+    #[cfg(not(feature = "did-web"))]
+    if cli.did_web {
+        anyhow::bail!("did:web requires building with the did-web feature");
+    }
+
+    if let Some(Command::Import { file, mode }) = &cli.command {
+        if cli.storage() == Storage::Memory {
+            anyhow::bail!("Importing into the in-memory registry has no effect; pass --storage");
+        }
+        let registry = open_registry(&cli).await?;
+        let report = snapshot::Snapshot::read(file)?.import(registry.as_ref(), *mode).await?;
+        for rejected in &report.errors {
+            tracing::warn!(did = %rejected.did, reason = %rejected.reason, "Rejected snapshot entry");
+        }
+        tracing::info!(
+            added = report.added,
+            skipped = report.skipped,
+            rejected = report.rejected,
+            "Imported snapshot {}", file.display()
+        );
+        return Ok(());
+    }
+
+    let public_url = cli.public_url.as_deref().map(webfinger::PublicUrl::parse).transpose()?.map(Arc::new);
 
     // Create state
+    let changes = Arc::new(changes::ChangeLog::new(cli.change_log_size));
+    let mut registry: Arc<dyn RegistryBackend> =
+        Arc::new(changes::RecordingRegistry::new(open_registry(&cli).await?, changes.clone()));
+    let replica = if cli.sync_peers.is_empty() {
+        None
+    } else {
+        let replica = Arc::new(sync::Replica::new(registry, Duration::from_secs(cli.sync_tombstone_ttl)));
+        let loaded = replica.load().await?;
+        tracing::info!(peers = cli.sync_peers.len(), loaded, "Replicating with sync peers");
+        registry = replica.clone();
+        Some(replica)
+    };
+    if let Some(max) = cli.capacity {
+        tracing::info!(capacity = max, policy = ?cli.capacity_policy, "Capping live registrations");
+        #[cfg(feature = "otel")]
+        telemetry::set_registry_capacity(max);
+        registry = Arc::new(capacity::CappedRegistry::new(registry, max, cli.capacity_policy, cli.capacity_high_water));
+    }
+    if let Some(path) = &cli.seed_file {
+        let seeded = seed::load_file(path, registry.as_ref()).await?;
+        tracing::info!(seeded, "Seeded registry from {}", path.display());
+    }
+    let mut handshake = open_handshake(&cli).await?;
+    handshake.max_sessions_per_did = cli.max_sessions_per_did.max(1);
+    handshake.did_web = open_did_web(&cli);
+    handshake.nonce = nonce::NoncePolicy::new(cli.nonce_bytes)?;
+    handshake.protocols = protocol::ProtocolPolicy::new(cli.protocols.clone(), cli.register_protocols.clone())?;
+    handshake.lockout = lockout::ProofLockout::new(cli.proof_max_failures, cli.proof_failure_window, cli.proof_lockout);
     let state = AppState {
-        registry: registry::Registry::new(),
-        handshake: Arc::new(HandshakeState::new()),
-    };
-
-    // Build router
-    let app = Router::new()
-        .route("/health", get(|| async { "ok" }))
-        .route("/hello", post(handlers::hello))
-        .route("/proof", post(handlers::proof))
-        .route("/register", post(handlers::register))
-        .route("/deregister", post(handlers::deregister))
-        .route("/lookup/:did", get(handlers::lookup))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], cli.port));
-    tracing::info!("agent-reach-server listening on {}", addr);
-    
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
-
-    Ok(())
+        registry: registry.clone(),
+        handshake: Arc::new(handshake),
+        admin_token: cli.admin_token.as_deref().map(Arc::from),
+        ttl,
+        regions: cli.regions.iter().map(|r| r.trim().to_string()).collect(),
+        unique_endpoints: cli.unique_endpoints,
+        stats: Arc::new(stats::Stats::new(cli.private_stats).with_capacity(cli.capacity)),
+        changes,
+        watchers: Arc::new(watch::Watchers::new(cli.max_watchers)),
+        idempotency: Default::default(),
+        denylist: Default::default(),
+        peers: open_peers(&cli)?,
+        replica,
+        public_url,
+        heartbeats: Default::default(),
+    };
+
+    #[cfg(feature = "federation")]
+    if let Some(replica) = &state.replica {
+        let interval = Duration::from_secs(cli.sync_interval.max(1));
+        tokio::spawn(sync::run(replica.clone(), cli.sync_peers.clone(), interval, state.heartbeats.clone()));
+    }
+
+    // Periodically drop expired registrations and sessions
+    let handshake = state.handshake.clone();
+    let heartbeats = state.heartbeats.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match registry.purge_expired().await {
+                Ok(removed) if removed.is_empty() => {}
+                Ok(removed) => tracing::debug!(removed = removed.len(), "Purged expired registrations"),
+                Err(e) => tracing::warn!(error = %e, "Failed to purge expired registrations"),
+            }
+            match handshake.store.purge_expired_sessions(chrono::Utc::now().timestamp()).await {
+                Ok(swept) => tracing::debug!(swept, "Swept expired sessions"),
+                Err(e) => tracing::warn!(error = %e, "Failed to sweep expired sessions"),
+            }
+            #[cfg(feature = "otel")]
+            if telemetry::wants_registry_size() {
+                let size = match registry.size_hint() {
+                    Some(size) => Ok(size),
+                    None => registry.all_entries().await.map(|entries| entries.len()),
+                };
+                match size {
+                    Ok(size) => telemetry::set_registry_size(size),
+                    Err(e) => tracing::warn!(error = %e, "Failed to measure registry size"),
+                }
+            }
+            heartbeats.beat("purge", PURGE_INTERVAL);
+        }
+    });
+
+    #[cfg(feature = "grpc")]
+    let grpc = match cli.grpc_addr {
+        Some(grpc_addr) => {
+            let listener = tokio::net::TcpListener::bind(grpc_addr).await?;
+            tracing::info!("gRPC listening on {}", grpc_addr);
+            Some(grpc::serve(state.clone(), listener, shutdown_signal()))
+        }
+        None => None,
+    };
+
+    // Bind every listener before serving, so one bad address stops startup
+    let (app, mut listeners) = match cli.internal_addr {
+        Some(internal_addr) => {
+            let internal = listen::Listener::tcp(internal_addr).await?;
+            (public_app(state.clone(), &cors, max_body), vec![(internal, internal_app(state))])
+        }
+        None => (app(state, &cors, max_body), Vec::new()),
+    };
+    if let Some(path) = cli.unix_socket() {
+        listeners.push((listen::Listener::unix(&path)?, app.clone()));
+    }
+    let addrs = if cli.listen.is_empty() {
+        vec![SocketAddr::from(([0, 0, 0, 0], cli.port))]
+    } else {
+        cli.listen.clone()
+    };
+    // The TLS server binds its own address
+    #[cfg(feature = "tls")]
+    let (tls, addrs) = match tls {
+        Some(tls) => (Some((tls, addrs[0])), Vec::new()),
+        None => (None, addrs),
+    };
+    for addr in addrs {
+        listeners.push((listen::Listener::tcp(addr).await?, app.clone()));
+    }
+
+    let http_config = cli.http_config();
+    let http = async {
+        #[cfg(feature = "tls")]
+        if let Some((tls, addr)) = tls {
+            tracing::info!("agent-reach-server listening on {} (TLS)", addr);
+            let plain = cli.http_port
+                .map(|port| (SocketAddr::from(([0, 0, 0, 0], port)), cli.plain_http));
+            tokio::try_join!(tls.serve(app, addr, plain, http_config), listen::serve_all(listeners, http_config))?;
+            return Ok(());
+        }
+
+        listen::serve_all(listeners, http_config).await
+    };
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = grpc {
+        tokio::try_join!(http, grpc)?;
+        return Ok(());
+    }
+
+    http.await
 }
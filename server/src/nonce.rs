@@ -0,0 +1,109 @@
+//! Handshake nonce length
+//!
+//! Challenges carry whatever nonce the handshake library generates unless
+//! `--nonce-bytes` is set, in which case the registry replaces it with that
+//! many random bytes, base64 encoded, before the challenge is hashed and
+//! stored. On `/proof` the stored challenge must still carry a nonce of the
+//! configured length, and the agent's counter-challenge nonce must be at
+//! least [`MIN_NONCE_BYTES`].
+
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
+    Engine,
+};
+use rand::RngCore;
+
+use crate::error::ReachError;
+use crate::types::Challenge;
+
+/// Shortest nonce accepted, in bytes
+pub const MIN_NONCE_BYTES: usize = 16;
+
+/// Longest nonce `--nonce-bytes` may ask for
+pub const MAX_NONCE_BYTES: usize = 256;
+
+/// How challenge nonces are generated and checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoncePolicy {
+    /// Nonce length in bytes; the library's own nonce when unset
+    bytes: Option<usize>,
+}
+
+impl NoncePolicy {
+    pub fn new(bytes: Option<usize>) -> anyhow::Result<Self> {
+        if let Some(bytes) = bytes {
+            if !(MIN_NONCE_BYTES..=MAX_NONCE_BYTES).contains(&bytes) {
+                anyhow::bail!(
+                    "--nonce-bytes must be between {} and {}, not {}",
+                    MIN_NONCE_BYTES,
+                    MAX_NONCE_BYTES,
+                    bytes
+                );
+            }
+        }
+        Ok(Self { bytes })
+    }
+
+    /// Give a fresh challenge a nonce of the configured length
+    pub fn apply(&self, challenge: &mut Challenge) {
+        if let Some(bytes) = self.bytes {
+            let mut nonce = vec![0u8; bytes];
+            rand::thread_rng().fill_bytes(&mut nonce);
+            challenge.nonce = STANDARD.encode(nonce);
+        }
+    }
+
+    /// Check a stored challenge's nonce has the configured length; one
+    /// issued under another setting, such as by a replica sharing the
+    /// session store, is refused
+    pub fn check_challenge(&self, challenge: &Challenge) -> Result<(), ReachError> {
+        match self.bytes {
+            Some(bytes) if decoded_len(&challenge.nonce) != Some(bytes) => Err(ReachError::InvalidChallenge),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Check a nonce chosen by the agent, such as its counter-challenge's
+pub fn check_agent_nonce(nonce: &str) -> Result<(), ReachError> {
+    match decoded_len(nonce) {
+        Some(len) if len >= MIN_NONCE_BYTES => Ok(()),
+        Some(len) => Err(ReachError::HandshakeError(format!(
+            "Counter-challenge nonce is {} bytes; at least {} are required",
+            len, MIN_NONCE_BYTES
+        ))),
+        None => Err(ReachError::HandshakeError("Counter-challenge nonce is not base64".into())),
+    }
+}
+
+/// Decoded length of a base64 nonce, padded or not, standard or URL-safe
+fn decoded_len(nonce: &str) -> Option<usize> {
+    [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(nonce).ok())
+        .map(|bytes| bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_length_is_bounded() {
+        assert!(NoncePolicy::new(None).is_ok());
+        assert!(NoncePolicy::new(Some(MIN_NONCE_BYTES)).is_ok());
+        let err = NoncePolicy::new(Some(8)).unwrap_err();
+        assert!(err.to_string().contains("--nonce-bytes"), "{}", err);
+        assert!(NoncePolicy::new(Some(MAX_NONCE_BYTES + 1)).is_err());
+    }
+
+    #[test]
+    fn agent_nonces_need_the_minimum_length() {
+        assert!(check_agent_nonce(&STANDARD.encode([7u8; MIN_NONCE_BYTES])).is_ok());
+        assert!(check_agent_nonce(&URL_SAFE_NO_PAD.encode([7u8; 32])).is_ok());
+
+        let err = check_agent_nonce(&STANDARD.encode([7u8; MIN_NONCE_BYTES - 1])).unwrap_err();
+        assert!(err.to_string().contains("at least 16"), "{}", err);
+        assert!(matches!(check_agent_nonce("not base64!"), Err(ReachError::HandshakeError(_))));
+    }
+}
@@ -0,0 +1,336 @@
+use axum::{response::Html, Json};
+use serde::Serialize;
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi, ToSchema,
+};
+
+use crate::snapshot::{ImportMode, ImportReport, RejectedEntry, Snapshot};
+use crate::types::*;
+use crate::changes::{Change, ChangeKind, ChangesResponse};
+use crate::stats::StatsResponse;
+use crate::sync::{EntriesRequest, EntriesResponse, SyncDigest, SyncEntry, Version};
+use crate::did_document::{DidDocument, Service, VerificationMethod};
+use crate::webfinger::{Jrd, Link};
+use crate::selftest::{SelftestResponse, StageResult};
+use crate::watch::WatchEvent;
+use crate::admin::{EvictResponse, SessionSummary};
+use crate::api_version::VersionResponse;
+use crate::health::{ComponentHealth, ComponentStatus, Readiness, ReadinessResponse};
+use crate::{admin, api_version, changes, did_document, handlers, health, stats, selftest, sync, watch, webfinger};
+
+/// OpenAPI document for the registry HTTP API
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "agent-reach",
+        description = "DID-based discovery registry for AI agents. Paths other than `/version` and \
+                       `/.well-known/*` are served under `/v1`; unprefixed aliases are deprecated."
+    ),
+    paths(
+        handlers::health,
+        health::ready,
+        handlers::discovery,
+        api_version::version,
+        handlers::hello,
+        handlers::proof,
+        handlers::register,
+        handlers::deregister,
+        handlers::revoke_delegation,
+        handlers::session,
+        handlers::me,
+        handlers::logout,
+        handlers::lookup,
+        handlers::resolve,
+        did_document::did_document,
+        webfinger::webfinger,
+        handlers::agents,
+        handlers::history,
+        stats::stats,
+        changes::changes,
+        watch::watch,
+        sync::digest,
+        sync::entries,
+        admin::export,
+        admin::import,
+        admin::list_sessions,
+        admin::revoke_session,
+        admin::evict,
+        admin::lift_ban,
+        selftest::selftest,
+    ),
+    components(schemas(
+        Hello,
+        Challenge,
+        Proof,
+        CounterChallenge,
+        ProofAccepted,
+        CounterProof,
+        ErrorResponse,
+        ReadinessResponse,
+        Readiness,
+        ComponentHealth,
+        ComponentStatus,
+        DiscoveryDocument,
+        DiscoveryEndpoints,
+        VersionResponse,
+        RegisterRequest,
+        RegistrationDelegation,
+        DelegationCertificate,
+        DelegatedOperation,
+        DeregisterRequest,
+        RevokeDelegationRequest,
+        Endpoint,
+        RegisterResponse,
+        LookupResponse,
+        PublicKey,
+        Pick,
+        Sort,
+        ResolveResponse,
+        DidDocument,
+        VerificationMethod,
+        Service,
+        Jrd,
+        Link,
+        AgentsResponse,
+        HistoryResponse,
+        HistoryEntry,
+        DeregisterResponse,
+        SessionResponse,
+        Scope,
+        AgentStatus,
+        RegistryEntry,
+        StatsResponse,
+        ChangesResponse,
+        WatchEvent,
+        Change,
+        ChangeKind,
+        StatusCounts,
+        SyncDigest,
+        Version,
+        EntriesRequest,
+        EntriesResponse,
+        SyncEntry,
+        Snapshot,
+        ImportMode,
+        ImportReport,
+        RejectedEntry,
+        SessionSummary,
+        EvictResponse,
+        SelftestResponse,
+        StageResult,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "handshake", description = "agent-id handshake authentication"),
+        (name = "registration", description = "Manage your own registration (requires session)"),
+        (name = "lookup", description = "Public lookups"),
+        (name = "sync", description = "Replication between registry instances"),
+        (name = "admin", description = "Operator endpoints (requires admin token)"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Bearer auth schemes: session tokens from `/proof` and the admin token
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("Session ID from POST /proof"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "admin",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("Token configured with --admin-token"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// GET /openapi.json
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// GET /docs
+///
+/// Swagger UI for the OpenAPI document. Assets are loaded from a CDN so the
+/// server doesn't need to bundle them.
+pub async fn docs() -> Html<&'static str> {
+    Html(SWAGGER_UI)
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>agent-reach API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+// ============================================================================
+// Handshake message schemas
+//
+// These mirror the agent-id-handshake wire types, which don't implement
+// ToSchema themselves.
+// ============================================================================
+
+/// Starts a handshake
+#[derive(Serialize, ToSchema)]
+pub struct Hello {
+    /// Always "Hello"
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[schema(example = "1.0")]
+    pub version: String,
+    #[schema(example = "did:key:z6Mk...")]
+    pub did: String,
+    #[schema(example = json!(["aip/1.0"]))]
+    pub protocols: Vec<String>,
+    /// Unix timestamp (milliseconds)
+    pub timestamp: i64,
+    /// `scope:read` for a session that can't change the registry
+    #[schema(example = json!(["scope:write"]))]
+    pub capabilities: Option<Vec<String>>,
+}
+
+/// Challenge to be signed by the agent
+#[derive(Serialize, ToSchema)]
+pub struct Challenge {
+    /// Always "Challenge"
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub version: String,
+    /// Base64 random nonce
+    pub nonce: String,
+    /// Unix timestamp (milliseconds)
+    pub timestamp: i64,
+    /// DID of the agent being challenged
+    pub audience: String,
+    /// DID of the registry
+    pub issuer: String,
+    pub domain: Option<String>,
+    pub session_pubkey: Option<String>,
+    /// Delegation document (see agent-id)
+    pub delegation: Option<serde_json::Value>,
+}
+
+/// Signed response to a challenge
+#[derive(Serialize, ToSchema)]
+pub struct Proof {
+    /// Always "Proof"
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub version: String,
+    pub challenge_hash: String,
+    pub responder_did: String,
+    pub signing_key: String,
+    /// Base64 Ed25519 signature over the challenge hash
+    pub signature: String,
+    /// Delegation document (see agent-id)
+    pub delegation: Option<serde_json::Value>,
+    pub counter_challenge: Option<CounterChallenge>,
+}
+
+/// Challenge for the registry to prove its own identity
+#[derive(Serialize, ToSchema)]
+pub struct CounterChallenge {
+    pub nonce: String,
+    pub timestamp: i64,
+    pub audience: String,
+}
+
+/// Successful handshake
+#[derive(Serialize, ToSchema)]
+pub struct ProofAccepted {
+    /// Always "ProofAccepted"
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub version: String,
+    /// Bearer token for authenticated endpoints
+    pub session_id: String,
+    pub counter_proof: CounterProof,
+    /// Unix timestamp (milliseconds)
+    pub session_expires_at: i64,
+}
+
+/// Registry's signature over the counter-challenge
+#[derive(Serialize, ToSchema)]
+pub struct CounterProof {
+    pub challenge_hash: String,
+    pub responder_did: String,
+    pub signing_key: String,
+    pub signature: String,
+}
+
+/// Error body returned by every endpoint
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    #[schema(example = "Agent not found")]
+    pub error: String,
+    /// The request's `X-Request-Id`, for correlating with server logs
+    #[schema(example = "0192f0c4-6f1e-7c43-9a1d-5e2b8f0a4c11")]
+    pub request_id: Option<String>,
+    /// Stable error code, on errors that have one (e.g. `expired`)
+    #[schema(example = "expired")]
+    pub code: Option<String>,
+    /// When the registration expired (Unix seconds), on `410 Gone` lookups
+    #[schema(example = 1700000000)]
+    pub expires_at: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_covers_all_routes() {
+        let spec = ApiDoc::openapi();
+        for path in [
+            "/health",
+            "/version",
+            "/hello",
+            "/proof",
+            "/register",
+            "/deregister",
+            "/delegations/revoke",
+            "/me",
+            "/lookup/{did}",
+            "/resolve",
+            "/agents",
+            "/agents/{did}/history",
+            "/admin/export",
+            "/admin/import",
+            "/admin/sessions",
+            "/admin/sessions/{id}",
+        ] {
+            assert!(spec.paths.paths.contains_key(path), "missing {}", path);
+        }
+
+        let json = serde_json::to_value(&spec).unwrap();
+        let status = &json["components"]["schemas"]["AgentStatus"]["enum"];
+        assert_eq!(status, &serde_json::json!(["online", "expired"]));
+    }
+}
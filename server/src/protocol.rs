@@ -0,0 +1,142 @@
+//! Registry protocol negotiation
+//!
+//! A Hello lists the protocols the agent speaks: the `agent-id` handshake
+//! protocol (`aip/1.0`) and the registry protocols it understands, such as
+//! `reach/1`. The registry keeps those it also supports (`--protocols`) and
+//! records them on the session; a Hello sharing none of them fails with
+//! `400`. A Hello naming no registry protocol at all comes from a client
+//! written before negotiation existed and is taken to speak [`BASELINE`].
+//! With `--register-protocols`, only sessions that agreed on one of those
+//! may register.
+
+use crate::error::ReachError;
+
+/// Registry protocol every client written before negotiation speaks
+pub const BASELINE: &str = "reach/1";
+
+/// Handshake protocols, which are listed alongside registry protocols in a
+/// Hello but not negotiated here
+pub const HANDSHAKE: &[&str] = &["aip/1.0"];
+
+/// Which registry protocols the server speaks and which may register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolPolicy {
+    supported: Vec<String>,
+    /// Every supported protocol may register when unset
+    register: Option<Vec<String>>,
+}
+
+impl Default for ProtocolPolicy {
+    fn default() -> Self {
+        Self {
+            supported: vec![BASELINE.to_string()],
+            register: None,
+        }
+    }
+}
+
+impl ProtocolPolicy {
+    pub fn new(supported: Vec<String>, register: Vec<String>) -> anyhow::Result<Self> {
+        if supported.is_empty() {
+            anyhow::bail!("--protocols must name at least one protocol");
+        }
+        if let Some(unknown) = register.iter().find(|protocol| !supported.contains(protocol)) {
+            anyhow::bail!("--register-protocols names {}, which isn't in --protocols", unknown);
+        }
+        Ok(Self {
+            supported,
+            register: (!register.is_empty()).then_some(register),
+        })
+    }
+
+    /// Registry protocols the server speaks, in preference order
+    pub fn supported(&self) -> &[String] {
+        &self.supported
+    }
+
+    /// The registry protocols both sides of a Hello speak, in the server's
+    /// order
+    pub fn negotiate(&self, offered: &[String]) -> Result<Vec<String>, ReachError> {
+        let mut offered: Vec<&str> = offered
+            .iter()
+            .map(String::as_str)
+            .filter(|protocol| !HANDSHAKE.contains(protocol))
+            .collect();
+        if offered.is_empty() {
+            offered.push(BASELINE);
+        }
+
+        let agreed: Vec<String> = self
+            .supported
+            .iter()
+            .filter(|protocol| offered.contains(&protocol.as_str()))
+            .cloned()
+            .collect();
+        if agreed.is_empty() {
+            return Err(ReachError::UnsupportedProtocol(self.supported.clone()));
+        }
+        Ok(agreed)
+    }
+
+    /// Fail unless a session that agreed on `protocols` may register;
+    /// sessions stored before negotiation agreed on [`BASELINE`]
+    pub fn check_register(&self, protocols: &[String]) -> Result<(), ReachError> {
+        let Some(register) = &self.register else {
+            return Ok(());
+        };
+        let allowed = if protocols.is_empty() {
+            register.iter().any(|protocol| protocol == BASELINE)
+        } else {
+            protocols.iter().any(|protocol| register.contains(protocol))
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(ReachError::ProtocolNotAllowed(register.clone()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn agreed_protocols_follow_server_order() {
+        let policy = ProtocolPolicy::new(strings(&["reach/2", "reach/1"]), Vec::new()).unwrap();
+
+        let agreed = policy.negotiate(&strings(&["aip/1.0", "reach/1", "reach/2", "reach/9"])).unwrap();
+
+        assert_eq!(agreed, ["reach/2", "reach/1"]);
+    }
+
+    #[test]
+    fn hello_without_registry_protocols_speaks_the_baseline() {
+        let policy = ProtocolPolicy::default();
+        assert_eq!(policy.negotiate(&strings(&["aip/1.0"])).unwrap(), [BASELINE]);
+        assert_eq!(policy.negotiate(&[]).unwrap(), [BASELINE]);
+
+        let newer = ProtocolPolicy::new(strings(&["reach/2"]), Vec::new()).unwrap();
+        assert!(matches!(newer.negotiate(&[]), Err(ReachError::UnsupportedProtocol(_))));
+    }
+
+    #[test]
+    fn disjoint_protocols_are_rejected() {
+        let err = ProtocolPolicy::default().negotiate(&strings(&["reach/9"])).unwrap_err();
+        assert!(matches!(&err, ReachError::UnsupportedProtocol(supported) if supported == &strings(&[BASELINE])));
+    }
+
+    #[test]
+    fn registration_can_be_restricted() {
+        let policy = ProtocolPolicy::new(strings(&["reach/2", "reach/1"]), strings(&["reach/2"])).unwrap();
+
+        assert!(policy.check_register(&strings(&["reach/2", "reach/1"])).is_ok());
+        assert!(matches!(policy.check_register(&strings(&["reach/1"])), Err(ReachError::ProtocolNotAllowed(_))));
+        assert!(policy.check_register(&[]).is_err());
+        assert!(ProtocolPolicy::new(strings(&["reach/1"]), strings(&["reach/2"])).is_err());
+    }
+}
@@ -0,0 +1,656 @@
+use agent_id_handshake::protocol::DEFAULT_TIMESTAMP_TOLERANCE_MS;
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+
+use crate::backend::{HandshakeBackend, Precondition, RegistryBackend};
+use crate::error::ReachError;
+use crate::handlers::{AuthenticatedSession, SESSION_TTL_SECS};
+use crate::types::{PendingChallenge, RegistryEntry};
+
+/// Prefix for every key this server writes
+const KEY_PREFIX: &str = "reach:";
+
+/// How long expired agents and sessions are kept before Redis drops them,
+/// so callers see 410 / "session expired" rather than 404 / 401 at first
+const EXPIRED_RETENTION_SECS: i64 = 300;
+
+/// How long a challenge can wait for its proof
+const CHALLENGE_TTL_SECS: i64 = DEFAULT_TIMESTAMP_TOLERANCE_MS / 1000;
+
+/// Upsert an agent hash and record history in one round trip
+///
+/// KEYS: agent hash, history list, expiry index, handle key, version counter.
+/// ARGV: did, endpoint, registered_at, expires_at, if_absent, now,
+/// history_limit, retention, history entry JSON, last_seen, handle (empty
+/// for none), agent key prefix, endpoints JSON (empty for none), expected
+/// version (empty for any), registered_by (empty for none), history
+/// retention.
+///
+/// Returns the stored version, 0 when `if_absent` found a live entry or the
+/// stored version isn't the expected one, and -1 when another DID's live
+/// entry holds the handle.
+const STORE_SCRIPT: &str = r"
+if ARGV[5] == '1' then
+  local expires_at = redis.call('HGET', KEYS[1], 'expires_at')
+  if expires_at and tonumber(expires_at) > tonumber(ARGV[6]) then
+    return 0
+  end
+end
+local version = tonumber(redis.call('HGET', KEYS[1], 'version') or '0')
+if ARGV[14] ~= '' then
+  if redis.call('EXISTS', KEYS[1]) == 0 or version ~= tonumber(ARGV[14]) then
+    return 0
+  end
+end
+if ARGV[11] ~= '' then
+  local holder = redis.call('GET', KEYS[4])
+  if holder and holder ~= ARGV[1] then
+    local holder_key = ARGV[12] .. holder
+    if redis.call('HGET', holder_key, 'handle') == ARGV[11] then
+      local holder_expires = redis.call('HGET', holder_key, 'expires_at')
+      if holder_expires and tonumber(holder_expires) > tonumber(ARGV[6]) then
+        return -1
+      end
+      redis.call('HDEL', holder_key, 'handle')
+    end
+  end
+end
+-- One counter for every DID, so versions aren't reused after a
+-- deregistration; raised past a version stored before it was kept
+local issued = redis.call('INCR', KEYS[5])
+if issued <= version then
+  issued = version + 1
+  redis.call('SET', KEYS[5], issued)
+end
+version = issued
+redis.call('HSET', KEYS[1], 'did', ARGV[1], 'endpoint', ARGV[2],
+  'registered_at', ARGV[3], 'expires_at', ARGV[4], 'last_seen', ARGV[10],
+  'version', version)
+if ARGV[11] ~= '' then
+  redis.call('HSET', KEYS[1], 'handle', ARGV[11])
+  redis.call('SET', KEYS[4], ARGV[1])
+  redis.call('EXPIREAT', KEYS[4], tonumber(ARGV[4]) + tonumber(ARGV[8]))
+else
+  redis.call('HDEL', KEYS[1], 'handle')
+end
+if ARGV[13] ~= '' then
+  redis.call('HSET', KEYS[1], 'endpoints', ARGV[13])
+else
+  redis.call('HDEL', KEYS[1], 'endpoints')
+end
+redis.call('EXPIREAT', KEYS[1], tonumber(ARGV[4]) + tonumber(ARGV[8]))
+redis.call('ZADD', KEYS[3], ARGV[4], ARGV[1])
+local limit = tonumber(ARGV[7])
+if limit > 0 then
+  local latest = redis.call('LINDEX', KEYS[2], 0)
+  local last = latest and cjson.decode(latest)
+  if not last or last['endpoint'] ~= ARGV[2] or (last['registered_by'] or '') ~= ARGV[15] then
+    redis.call('LPUSH', KEYS[2], ARGV[9])
+    redis.call('LTRIM', KEYS[2], 0, limit - 1)
+  else
+    last['expires_at'] = tonumber(ARGV[4])
+    redis.call('LSET', KEYS[2], 0, cjson.encode(last))
+  end
+  redis.call('EXPIREAT', KEYS[2], tonumber(ARGV[4]) + tonumber(ARGV[16]))
+end
+return version
+";
+
+/// Set `last_seen` on an existing agent hash without recreating it
+///
+/// KEYS: agent hash. ARGV: last_seen.
+const TOUCH_SCRIPT: &str = r"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+  return 0
+end
+redis.call('HSET', KEYS[1], 'last_seen', ARGV[1])
+return 1
+";
+
+/// Delete agents whose TTL has passed and drop them from the expiry index,
+/// returning their hashes as they were (empty for one Redis already dropped)
+///
+/// KEYS: expiry index. ARGV: now, agent key prefix.
+const PURGE_SCRIPT: &str = r"
+local dids = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1])
+local hashes = {}
+for _, did in ipairs(dids) do
+  redis.call('ZREM', KEYS[1], did)
+  table.insert(hashes, redis.call('HGETALL', ARGV[2] .. did))
+  redis.call('DEL', ARGV[2] .. did)
+end
+return hashes
+";
+
+/// Start the version counter above every version the per-DID hash of
+/// earlier releases issued, then drop the hash
+///
+/// KEYS: version counter, per-DID versions hash.
+const MIGRATE_VERSIONS_SCRIPT: &str = r"
+local highest = tonumber(redis.call('GET', KEYS[1]) or '0')
+for _, issued in ipairs(redis.call('HVALS', KEYS[2])) do
+  highest = math.max(highest, tonumber(issued))
+end
+redis.call('SET', KEYS[1], highest)
+redis.call('DEL', KEYS[2])
+return highest
+";
+
+/// Delete a DID's oldest sessions beyond a limit, returning how many
+///
+/// KEYS: the DID's session index. ARGV: sessions to keep, session key
+/// prefix, oldest score still alive. Sessions Redis already dropped are
+/// pruned from the index first so they don't count.
+const TRIM_SESSIONS_SCRIPT: &str = r"
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', '(' .. ARGV[3])
+local excess = redis.call('ZCARD', KEYS[1]) - tonumber(ARGV[1])
+if excess <= 0 then
+  return 0
+end
+local ids = redis.call('ZRANGE', KEYS[1], 0, excess - 1)
+for _, id in ipairs(ids) do
+  redis.call('DEL', ARGV[2] .. id)
+end
+redis.call('ZREM', KEYS[1], unpack(ids))
+return #ids
+";
+
+/// Redis-backed registry and handshake store
+///
+/// Lets several server replicas share registrations and sessions. Agents are
+/// hashes under `reach:agent:<did>` with a sorted-set index by expiry,
+/// handles map to their DID under `reach:handle:<handle>`, history is a
+/// capped list per DID, challenges and sessions are JSON strings, and each
+/// DID's session IDs are indexed under `reach:sessions-by-did:<did>`.
+/// Versions come from the single counter `reach:version`. Every per-DID
+/// key carries a Redis TTL, history's running
+/// [`HISTORY_RETENTION_SECS`](crate::registry::HISTORY_RETENTION_SECS) past
+/// the last registration's expiry, so nothing depends on the purge task
+/// running.
+#[derive(Clone)]
+pub struct RedisStore {
+    conn: ConnectionManager,
+    history_limit: usize,
+    store_script: Script,
+    touch_script: Script,
+    purge_script: Script,
+    trim_sessions_script: Script,
+}
+
+impl RedisStore {
+    /// Connect to Redis; the multiplexed connection reconnects on its own and
+    /// is shared by all requests
+    pub async fn connect(url: &str, history_limit: usize) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+        let mut conn = ConnectionManager::new(client).await?;
+        let _: i64 = Script::new(MIGRATE_VERSIONS_SCRIPT)
+            .key(version_counter_key())
+            .key(format!("{}versions", KEY_PREFIX))
+            .invoke_async(&mut conn)
+            .await?;
+
+        Ok(Self {
+            conn,
+            history_limit,
+            store_script: Script::new(STORE_SCRIPT),
+            touch_script: Script::new(TOUCH_SCRIPT),
+            purge_script: Script::new(PURGE_SCRIPT),
+            trim_sessions_script: Script::new(TRIM_SESSIONS_SCRIPT),
+        })
+    }
+
+    async fn store(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        let expected = match precondition {
+            Precondition::Version(version) => version.to_string(),
+            _ => String::new(),
+        };
+        let history = serde_json::to_string(&entry).map_err(|e| ReachError::Internal(e.to_string()))?;
+
+        let stored: i64 = self.store_script
+            .key(agent_key(&entry.did))
+            .key(history_key(&entry.did))
+            .key(index_key())
+            .key(handle_key(entry.handle.as_deref().unwrap_or_default()))
+            .key(version_counter_key())
+            .arg(&entry.did)
+            .arg(&entry.endpoint)
+            .arg(entry.registered_at)
+            .arg(entry.expires_at)
+            .arg((precondition == Precondition::Absent) as i64)
+            .arg(chrono::Utc::now().timestamp())
+            .arg(self.history_limit)
+            .arg(EXPIRED_RETENTION_SECS)
+            .arg(history)
+            .arg(entry.last_seen)
+            .arg(entry.handle.as_deref().unwrap_or_default())
+            .arg(agent_key(""))
+            .arg(crate::endpoints::to_json(&entry.endpoints)?.unwrap_or_default())
+            .arg(expected)
+            .arg(entry.registered_by.as_deref().unwrap_or_default())
+            .arg(crate::registry::HISTORY_RETENTION_SECS)
+            .invoke_async(&mut self.conn.clone())
+            .await
+            .map_err(db_error)?;
+
+        match stored {
+            -1 => Err(ReachError::HandleTaken),
+            0 => Ok(None),
+            version => Ok(Some(version as u64)),
+        }
+    }
+
+    /// Entries whose index score (expiry) is at least `min`, sorted by DID
+    async fn entries_expiring_after(&self, min: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        let mut conn = self.conn.clone();
+
+        let dids: Vec<String> = conn
+            .zrangebyscore(index_key(), min, "+inf")
+            .await
+            .map_err(db_error)?;
+        if dids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Fetch every hash in a single pipelined round trip
+        let mut pipe = redis::pipe();
+        for did in &dids {
+            pipe.hgetall(agent_key(did));
+        }
+        let hashes: Vec<std::collections::HashMap<String, String>> =
+            pipe.query_async(&mut conn).await.map_err(db_error)?;
+
+        // Hashes dropped by their TTL come back empty
+        let mut entries = Vec::with_capacity(hashes.len());
+        for fields in hashes {
+            entries.extend(entry_from_hash(fields)?);
+        }
+        entries.sort_by(|a, b| a.did.cmp(&b.did));
+        Ok(entries)
+    }
+}
+
+fn agent_key(did: &str) -> String {
+    format!("{}agent:{}", KEY_PREFIX, did)
+}
+
+fn history_key(did: &str) -> String {
+    format!("{}history:{}", KEY_PREFIX, did)
+}
+
+fn handle_key(handle: &str) -> String {
+    format!("{}handle:{}", KEY_PREFIX, handle)
+}
+
+fn index_key() -> String {
+    format!("{}agents-by-expiry", KEY_PREFIX)
+}
+
+/// Last version issued to any DID
+fn version_counter_key() -> String {
+    format!("{}version", KEY_PREFIX)
+}
+
+/// DIDs can't contain spaces, so the pair can't be confused with another
+fn revocation_key(subject: &str, delegate: &str) -> String {
+    format!("{}revocation:{} {}", KEY_PREFIX, subject, delegate)
+}
+
+fn challenge_key(hash: &str) -> String {
+    format!("{}challenge:{}", KEY_PREFIX, hash)
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("{}session:{}", KEY_PREFIX, session_id)
+}
+
+/// Sorted set of a DID's session IDs, scored by creation time (ms)
+fn did_sessions_key(did: &str) -> String {
+    format!("{}sessions-by-did:{}", KEY_PREFIX, did)
+}
+
+fn db_error(e: redis::RedisError) -> ReachError {
+    ReachError::Internal(e.to_string())
+}
+
+fn json_error(e: serde_json::Error) -> ReachError {
+    ReachError::Internal(e.to_string())
+}
+
+/// Decode an agent hash; an empty hash means the key doesn't exist
+fn entry_from_hash(fields: std::collections::HashMap<String, String>) -> Result<Option<RegistryEntry>, ReachError> {
+    if fields.is_empty() {
+        return Ok(None);
+    }
+
+    let field = |name: &str| {
+        fields.get(name)
+            .cloned()
+            .ok_or_else(|| ReachError::Internal(format!("Agent hash is missing {}", name)))
+    };
+    let timestamp = |name: &str| {
+        field(name)?.parse::<i64>()
+            .map_err(|e| ReachError::Internal(format!("Invalid {}: {}", name, e)))
+    };
+
+    let registered_at = timestamp("registered_at")?;
+    let last_seen = match fields.contains_key("last_seen") {
+        true => timestamp("last_seen")?,
+        false => registered_at,
+    };
+    Ok(Some(RegistryEntry {
+        did: field("did")?,
+        endpoint: field("endpoint")?,
+        registered_at,
+        expires_at: timestamp("expires_at")?,
+        last_seen,
+        handle: fields.get("handle").cloned(),
+        endpoints: crate::endpoints::from_json(fields.get("endpoints").map(String::as_str))?,
+        version: match fields.get("version") {
+            Some(version) => version.parse()
+                .map_err(|e| ReachError::Internal(format!("Invalid version: {}", e)))?,
+            None => 0,
+        },
+        registered_by: None,
+    }))
+}
+
+#[async_trait]
+impl RegistryBackend for RedisStore {
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        self.store(entry, precondition).await
+    }
+
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        let fields = self.conn.clone().hgetall(agent_key(did)).await.map_err(db_error)?;
+        entry_from_hash(fields)
+    }
+
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        let did: Option<String> = self.conn.clone().get(handle_key(handle)).await.map_err(db_error)?;
+        let Some(did) = did else {
+            return Ok(None);
+        };
+        let entry = self.lookup(&did).await?;
+        Ok(entry.filter(|entry| entry.handle.as_deref() == Some(handle)))
+    }
+
+    async fn ping(&self) -> Result<(), ReachError> {
+        let _: String = redis::cmd("PING").query_async(&mut self.conn.clone()).await.map_err(db_error)?;
+        Ok(())
+    }
+
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+        let touched: i64 = self.touch_script
+            .key(agent_key(did))
+            .arg(last_seen)
+            .invoke_async(&mut self.conn.clone())
+            .await
+            .map_err(db_error)?;
+
+        Ok(touched == 1)
+    }
+
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+        let (deleted, _): (i64, i64) = redis::pipe()
+            .atomic()
+            .del(agent_key(did))
+            .zrem(index_key(), did)
+            .query_async(&mut self.conn.clone())
+            .await
+            .map_err(db_error)?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let hashes: Vec<std::collections::HashMap<String, String>> = self.purge_script
+            .key(index_key())
+            .arg(chrono::Utc::now().timestamp())
+            .arg(agent_key(""))
+            .invoke_async(&mut self.conn.clone())
+            .await
+            .map_err(db_error)?;
+
+        // Entries whose hash Redis already dropped can't be reported
+        let mut removed = Vec::with_capacity(hashes.len());
+        for fields in hashes {
+            removed.extend(entry_from_hash(fields)?);
+        }
+        Ok(removed)
+    }
+
+    async fn live_count(&self, now: i64) -> Result<usize, ReachError> {
+        self.conn.clone()
+            .zcount(index_key(), format!("({}", now), "+inf")
+            .await
+            .map_err(db_error)
+    }
+
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        let dids: Vec<String> = self.conn.clone()
+            .zrangebyscore_limit(index_key(), format!("({}", now), "+inf", 0, 1)
+            .await
+            .map_err(db_error)?;
+        match dids.first() {
+            Some(did) => self.lookup(did).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let now = chrono::Utc::now().timestamp();
+        // Index scores are expiry times, so this skips expired agents
+        let mut entries = self.entries_expiring_after(&format!("({}", now)).await?;
+        entries.retain(|entry| entry.expires_at > now);
+        Ok(entries)
+    }
+
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.entries_expiring_after("-inf").await
+    }
+
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        let items: Vec<String> = self.conn.clone()
+            .lrange(history_key(did), 0, -1)
+            .await
+            .map_err(db_error)?;
+
+        items.iter()
+            .map(|item| serde_json::from_str(item).map_err(json_error))
+            .collect()
+    }
+
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+        // Redis drops the hash once the revocation expires
+        let key = revocation_key(subject, delegate);
+        redis::pipe()
+            .atomic()
+            .hset_multiple(&key, &[("revoked_at", revoked_at), ("expires_at", expires_at)])
+            .expire_at(&key, expires_at)
+            .query_async::<()>(&mut self.conn.clone())
+            .await
+            .map_err(db_error)
+    }
+
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+        let (revoked_at, expires_at): (Option<i64>, Option<i64>) = redis::pipe()
+            .hget(revocation_key(subject, delegate), "revoked_at")
+            .hget(revocation_key(subject, delegate), "expires_at")
+            .query_async(&mut self.conn.clone())
+            .await
+            .map_err(db_error)?;
+        Ok(revoked_at.filter(|_| expires_at.is_some_and(|expires_at| expires_at > now)))
+    }
+}
+
+#[async_trait]
+impl HandshakeBackend for RedisStore {
+    async fn put_challenge(&self, hash: String, challenge: PendingChallenge) -> Result<(), ReachError> {
+        let value = serde_json::to_string(&challenge).map_err(json_error)?;
+        self.conn.clone()
+            .set_ex::<_, _, ()>(challenge_key(&hash), value, CHALLENGE_TTL_SECS as u64)
+            .await
+            .map_err(db_error)
+    }
+
+    async fn take_challenge(&self, hash: &str) -> Result<Option<PendingChallenge>, ReachError> {
+        let value: Option<String> = self.conn.clone()
+            .get_del(challenge_key(hash))
+            .await
+            .map_err(db_error)?;
+
+        value.map(|v| serde_json::from_str(&v).map_err(json_error)).transpose()
+    }
+
+    async fn put_session(&self, session_id: String, session: AuthenticatedSession) -> Result<(), ReachError> {
+        let value = serde_json::to_string(&session).map_err(json_error)?;
+        let ttl = SESSION_TTL_SECS + EXPIRED_RETENTION_SECS;
+        let index = did_sessions_key(&session.did);
+        redis::pipe()
+            .atomic()
+            .set_ex(session_key(&session_id), value, ttl as u64)
+            .zadd(&index, &session_id, chrono::Utc::now().timestamp_millis())
+            .expire(&index, ttl)
+            .query_async::<()>(&mut self.conn.clone())
+            .await
+            .map_err(db_error)
+    }
+
+    async fn session(&self, session_id: &str) -> Result<Option<AuthenticatedSession>, ReachError> {
+        let value: Option<String> = self.conn.clone()
+            .get(session_key(session_id))
+            .await
+            .map_err(db_error)?;
+
+        value.map(|v| serde_json::from_str(&v).map_err(json_error)).transpose()
+    }
+
+    async fn sessions(&self) -> Result<Vec<(String, AuthenticatedSession)>, ReachError> {
+        let prefix = session_key("");
+        let mut conn = self.conn.clone();
+        let mut keys = Vec::new();
+        let mut iter: redis::AsyncIter<String> = conn
+            .scan_match(format!("{}*", prefix))
+            .await
+            .map_err(db_error)?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        drop(iter);
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Sessions that expired between the scan and the read come back as nil
+        let values: Vec<Option<String>> = conn.mget(&keys).await.map_err(db_error)?;
+        let mut sessions = Vec::new();
+        for (key, value) in keys.iter().zip(values) {
+            let Some(value) = value else { continue };
+            let session = serde_json::from_str(&value).map_err(json_error)?;
+            sessions.push((key[prefix.len()..].to_string(), session));
+        }
+        Ok(sessions)
+    }
+
+    async fn remove_session(&self, session_id: &str) -> Result<bool, ReachError> {
+        let Some(session) = self.session(session_id).await? else {
+            return Ok(false);
+        };
+        let (removed, _): (u64, u64) = redis::pipe()
+            .atomic()
+            .del(session_key(session_id))
+            .zrem(did_sessions_key(&session.did), session_id)
+            .query_async(&mut self.conn.clone())
+            .await
+            .map_err(db_error)?;
+        Ok(removed > 0)
+    }
+
+    async fn trim_sessions(&self, did: &str, keep: usize) -> Result<usize, ReachError> {
+        let alive_since = (chrono::Utc::now().timestamp() - SESSION_TTL_SECS - EXPIRED_RETENTION_SECS) * 1000;
+        let trimmed: usize = self.trim_sessions_script
+            .key(did_sessions_key(did))
+            .arg(keep)
+            .arg(session_key(""))
+            .arg(alive_since)
+            .invoke_async(&mut self.conn.clone())
+            .await
+            .map_err(db_error)?;
+        Ok(trimmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Scope;
+
+    /// Runs against the Redis server in `TEST_REDIS_URL`; skipped when unset
+    #[tokio::test]
+    async fn backend_harness() {
+        let Ok(url) = std::env::var("TEST_REDIS_URL") else {
+            eprintln!("TEST_REDIS_URL not set, skipping Redis tests");
+            return;
+        };
+
+        let store = RedisStore::connect(&url, crate::registry::DEFAULT_HISTORY_LIMIT)
+            .await
+            .expect("connect to test Redis");
+        crate::backend::harness::run(&store).await;
+    }
+
+    #[tokio::test]
+    async fn sessions_and_challenges_round_trip() {
+        let Ok(url) = std::env::var("TEST_REDIS_URL") else {
+            return;
+        };
+
+        let store = RedisStore::connect(&url, 0).await.expect("connect to test Redis");
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let session = AuthenticatedSession {
+            did: "did:key:test".to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            scope: Scope::Read,
+            protocols: Vec::new(),
+        };
+
+        store.put_session(session_id.clone(), session).await.unwrap();
+        let found = store.session(&session_id).await.unwrap().expect("stored session");
+        assert_eq!(found.did, "did:key:test");
+        assert_eq!(found.scope, Scope::Read);
+        let listed = store.sessions().await.unwrap();
+        assert!(listed.iter().any(|(id, session)| *id == session_id && session.did == "did:key:test"));
+        assert!(store.remove_session(&session_id).await.unwrap());
+        assert!(store.session(&session_id).await.unwrap().is_none());
+
+        let did = format!("did:key:test-{}", uuid::Uuid::new_v4());
+        let ids: Vec<String> = (0..3).map(|_| uuid::Uuid::new_v4().to_string()).collect();
+        for id in &ids {
+            let session = AuthenticatedSession {
+                did: did.clone(),
+                created_at: chrono::Utc::now().timestamp(),
+                scope: Scope::Write,
+                protocols: Vec::new(),
+            };
+            store.put_session(id.clone(), session).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        assert_eq!(store.trim_sessions(&did, 2).await.unwrap(), 1);
+        assert!(store.session(&ids[0]).await.unwrap().is_none());
+        assert!(store.session(&ids[2]).await.unwrap().is_some());
+
+        let key = agent_id::RootKey::generate();
+        let hello = crate::types::Hello::new(key.did().to_string());
+        let challenge = agent_id_handshake::protocol::Verifier::new(key.did())
+            .handle_hello(&hello)
+            .unwrap();
+        let hash = uuid::Uuid::new_v4().to_string();
+        let pending = PendingChallenge { challenge: challenge.clone(), scope: Scope::Read, protocols: Vec::new() };
+        store.put_challenge(hash.clone(), pending).await.unwrap();
+        let taken = store.take_challenge(&hash).await.unwrap().expect("stored challenge");
+        assert_eq!(taken.challenge.nonce, challenge.nonce);
+        assert_eq!(taken.scope, Scope::Read);
+        // Challenges can only be answered once
+        assert!(store.take_challenge(&hash).await.unwrap().is_none());
+    }
+}
@@ -1,52 +1,668 @@
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use parking_lot::RwLock;
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 
-use crate::types::RegistryEntry;
+use crate::backend::{Precondition, RegistryBackend};
+use crate::bloom::BloomFilter;
+use crate::delegation::Revocations;
+use crate::error::ReachError;
+use crate::types::{RegistryEntry, StatusCounts};
+
+/// Default number of past registrations kept per DID
+pub const DEFAULT_HISTORY_LIMIT: usize = 10;
+
+/// How long a DID's history is kept once it has no entry, counted from
+/// when its last registration expired
+pub const HISTORY_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Number of independently locked shards
+const SHARD_COUNT: usize = 16;
+
+/// One shard of a key -> DID index
+type IndexShard = HashMap<String, Arc<str>>;
+
+/// Which of `count` shards a key belongs to
+pub(crate) fn shard_of(key: &str, count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % count
+}
+
+/// Key -> DID index split across independently locked shards, so updates
+/// of different keys don't wait on each other
+struct Index {
+    shards: Box<[RwLock<IndexShard>]>,
+}
+
+impl Index {
+    fn new() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Shard holding a key
+    fn shard(&self, key: &str) -> &RwLock<IndexShard> {
+        &self.shards[shard_of(key, self.shards.len())]
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<str>> {
+        self.shard(key).read().get(key).cloned()
+    }
+
+    fn insert(&self, key: String, did: Arc<str>) {
+        self.shard(&key).write().insert(key, did);
+    }
+
+    /// Drop `key` if it points at `did` and `kept` says the DID no longer
+    /// has it. `kept` runs with the key's shard locked, so a new claim of
+    /// the key can't slip in between.
+    fn remove_if(&self, key: &str, did: &str, kept: impl FnOnce() -> bool) {
+        let mut shard = self.shard(key).write();
+        if shard.get(key).is_some_and(|holder| **holder == *did) && !kept() {
+            shard.remove(key);
+        }
+    }
+}
+
+/// A stored entry and when it was last registered or looked up
+struct Slot {
+    entry: RegistryEntry,
+    /// Tick of the registry's access clock; updated under a read lock
+    last_access: AtomicU64,
+}
+
+/// A shard's slots, indexed by expiry
+#[derive(Default)]
+struct Entries {
+    slots: HashMap<Arc<str>, Slot>,
+    /// `(expires_at, DID)` of every slot, soonest first
+    expiries: BTreeSet<(i64, Arc<str>)>,
+}
+
+/// One independently locked part of the registry
+struct Shard {
+    entries: RwLock<Entries>,
+    /// Access tick -> DID of every slot, least recent first. A slot's
+    /// `last_access` only changes while this is locked, so the two agree.
+    accesses: Mutex<BTreeMap<u64, Arc<str>>>,
+}
+
+impl Shard {
+    fn new() -> Self {
+        Self {
+            entries: RwLock::new(Entries::default()),
+            accesses: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Store a slot, returning the DID's previous one
+    fn insert(&self, entries: &mut Entries, did: Arc<str>, slot: Slot) -> Option<Slot> {
+        let previous = self.remove(entries, &did);
+        entries.expiries.insert((slot.entry.expires_at, did.clone()));
+        self.accesses.lock().insert(slot.last_access.load(Ordering::Relaxed), did.clone());
+        entries.slots.insert(did, slot);
+        previous
+    }
+
+    fn remove(&self, entries: &mut Entries, did: &str) -> Option<Slot> {
+        let (did, slot) = entries.slots.remove_entry(did)?;
+        self.accesses.lock().remove(&slot.last_access.load(Ordering::Relaxed));
+        entries.expiries.remove(&(slot.entry.expires_at, did));
+        Some(slot)
+    }
+
+    /// Move a slot to the most recently used end
+    fn record_access(&self, did: &Arc<str>, slot: &Slot, tick: u64) {
+        let mut accesses = self.accesses.lock();
+        accesses.remove(&slot.last_access.swap(tick, Ordering::Relaxed));
+        accesses.insert(tick, did.clone());
+    }
+}
 
 /// In-memory registry of DID -> endpoint mappings
+///
+/// Entries are split across shards keyed by a hash of the DID, so a
+/// registration only blocks lookups that land on the same shard.
 #[derive(Clone)]
 pub struct Registry {
-    inner: Arc<RwLock<HashMap<String, RegistryEntry>>>,
+    shards: Arc<[Shard]>,
+    /// Past registrations per DID, newest first
+    history: Arc<RwLock<HashMap<Arc<str>, VecDeque<RegistryEntry>>>>,
+    history_limit: usize,
+    /// Maximum number of stored entries (unlimited when `None`)
+    capacity: Option<usize>,
+    /// Monotonic counter ordering accesses for eviction
+    clock: Arc<AtomicU64>,
+    /// Last version issued to any DID, so a re-registration never reuses
+    /// a version without anything kept per DID
+    versions: Arc<AtomicU64>,
+    /// Handle -> DID of its latest claimant; only valid while that DID's
+    /// entry still carries the handle
+    handles: Arc<Index>,
+    /// Endpoint -> DID that most recently registered it; only valid while
+    /// that DID's entry still has the endpoint
+    claimants: Arc<Index>,
+    /// Every DID ever registered, checked before taking a shard lock
+    seen: Arc<BloomFilter>,
+    /// Serializes inserts of new DIDs while a capacity is set, so the
+    /// count check and eviction can't race
+    admission: Arc<Mutex<()>>,
+    /// Delegation certificates their subjects have revoked
+    revocations: Arc<Revocations>,
 }
 
 impl Registry {
     pub fn new() -> Self {
+        Self::with_history_limit(DEFAULT_HISTORY_LIMIT)
+    }
+
+    /// Create a registry keeping at most `history_limit` past registrations per DID
+    pub fn with_history_limit(history_limit: usize) -> Self {
         Self {
-            inner: Arc::new(RwLock::new(HashMap::new())),
+            shards: (0..SHARD_COUNT).map(|_| Shard::new()).collect(),
+            history: Arc::new(RwLock::new(HashMap::new())),
+            history_limit,
+            capacity: None,
+            clock: Arc::new(AtomicU64::new(0)),
+            versions: Arc::new(AtomicU64::new(0)),
+            handles: Arc::new(Index::new()),
+            claimants: Arc::new(Index::new()),
+            seen: Arc::new(BloomFilter::new()),
+            admission: Arc::new(Mutex::new(())),
+            revocations: Arc::new(Revocations::default()),
+        }
+    }
+
+    /// Bound the number of stored entries; when full, registering a new DID
+    /// evicts an expired entry if there is one, otherwise the least recently
+    /// looked-up entry
+    pub fn with_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Shard holding a DID
+    fn shard(&self, did: &str) -> &Shard {
+        &self.shards[shard_of(did, self.shards.len())]
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The DID's key in its shard, so the history and handle indexes share
+    /// one allocation per DID rather than each holding a copy
+    fn intern(&self, did: &str) -> Arc<str> {
+        match self.shard(did).entries.read().slots.get_key_value(did) {
+            Some((key, _)) => key.clone(),
+            None => Arc::from(did),
+        }
+    }
+
+    fn slot(&self, entry: RegistryEntry) -> Slot {
+        Slot {
+            entry,
+            last_access: AtomicU64::new(self.tick()),
         }
     }
 
     /// Register or update an agent's endpoint
+    #[cfg(test)]
     pub fn register(&self, entry: RegistryEntry) {
-        let mut map = self.inner.write();
-        map.insert(entry.did.clone(), entry);
+        self.register_when(entry, Precondition::Always);
+    }
+
+    /// Register if `precondition` holds for the DID's stored entry,
+    /// returning the version the entry was stored with
+    pub fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Option<u64> {
+        let (stored, displaced) = self.store(entry, precondition);
+        displaced.iter().for_each(|entry| self.unindex(entry));
+        stored
+    }
+
+    /// [`register_when`](Self::register_when), also returning the entries
+    /// it replaced or evicted, whose index keys may need dropping. No index
+    /// locks are taken apart from the endpoint's.
+    fn store(&self, mut entry: RegistryEntry, precondition: Precondition) -> (Option<u64>, Vec<RegistryEntry>) {
+        let now = chrono::Utc::now().timestamp();
+        let holds = |entries: &Entries| {
+            let stored = entries.slots.get(entry.did.as_str()).map(|slot| &slot.entry);
+            match precondition {
+                Precondition::Always => true,
+                Precondition::Absent => stored.is_none_or(|stored| stored.expires_at <= now),
+                Precondition::Version(version) => stored.is_some_and(|stored| stored.version == version),
+            }
+        };
+        let shard = self.shard(&entry.did);
+        if !holds(&shard.entries.read()) {
+            return (None, Vec::new());
+        }
+
+        let (_admission, evicted) = self.make_room(&entry.did);
+        let mut displaced: Vec<RegistryEntry> = evicted.into_iter().collect();
+        let mut entries = shard.entries.write();
+        if !holds(&entries) {
+            return (None, displaced);
+        }
+
+        let did = match entries.slots.get_key_value(entry.did.as_str()) {
+            Some((key, _)) => key.clone(),
+            None => Arc::from(entry.did.as_str()),
+        };
+        // Taken under the shard lock, so a DID's versions increase in the
+        // order its writes land
+        let version = self.versions.fetch_add(1, Ordering::Relaxed) + 1;
+        entry.version = version;
+        self.record_history(&did, &entry);
+        self.seen.insert(&entry.did);
+        let endpoint = entry.endpoint.clone();
+        displaced.extend(shard.insert(&mut entries, did.clone(), self.slot(entry)).map(|slot| slot.entry));
+        drop(entries);
+        self.claimants.insert(endpoint, did);
+        (Some(version), displaced)
+    }
+
+    /// Check the entry's handle is free for its DID, taking it from an
+    /// expired holder. The returned guard of the handle's index shard must
+    /// be held until the entry is inserted, so concurrent claims of one
+    /// handle are serialized while claims of others go ahead.
+    fn claim_handle(
+        &self,
+        entry: &RegistryEntry,
+    ) -> Result<Option<RwLockWriteGuard<'_, IndexShard>>, ReachError> {
+        let Some(handle) = &entry.handle else {
+            return Ok(None);
+        };
+
+        let handles = self.handles.shard(handle).write();
+        if let Some(holder) = handles.get(handle).filter(|holder| ***holder != *entry.did) {
+            let mut entries = self.shard(holder).entries.write();
+            let held = entries
+                .slots
+                .get_mut(holder)
+                .filter(|slot| slot.entry.handle.as_ref() == Some(handle));
+            if let Some(slot) = held {
+                if slot.entry.expires_at > chrono::Utc::now().timestamp() {
+                    return Err(ReachError::HandleTaken);
+                }
+                slot.entry.handle = None;
+            }
+        }
+        Ok(Some(handles))
+    }
+
+    /// Store an entry while holding its handle's claim, then index the
+    /// handle if the entry was stored. Also returns the entries of other
+    /// DIDs evicted to make room.
+    fn store_claiming_handle(
+        &self,
+        entry: RegistryEntry,
+        precondition: Precondition,
+    ) -> Result<(Option<u64>, Vec<RegistryEntry>), ReachError> {
+        let claim = self.claim_handle(&entry)?;
+        let (did, handle) = (entry.did.clone(), entry.handle.clone());
+        let (stored, displaced) = self.store(entry, precondition);
+        if let (Some(mut handles), Some(handle), Some(_)) = (claim, handle, stored) {
+            handles.insert(handle, self.intern(&did));
+        }
+        // The claim is released, so the handle index can be locked again
+        displaced.iter().for_each(|entry| self.unindex(entry));
+        let evicted = displaced.into_iter().filter(|entry| entry.did != did).collect();
+        Ok((stored, evicted))
+    }
+
+    /// Drop the handle and endpoint keys of an entry that was removed or
+    /// replaced, unless the DID's current entry still has them
+    fn unindex(&self, gone: &RegistryEntry) {
+        let current = |has: &dyn Fn(&RegistryEntry) -> bool| {
+            self.shard(&gone.did)
+                .entries
+                .read()
+                .slots
+                .get(gone.did.as_str())
+                .is_some_and(|slot| has(&slot.entry))
+        };
+        if let Some(handle) = &gone.handle {
+            self.handles.remove_if(handle, &gone.did, || current(&|entry| entry.handle.as_ref() == Some(handle)));
+        }
+        self.claimants.remove_if(&gone.endpoint, &gone.did, || current(&|entry| entry.endpoint == gone.endpoint));
+    }
+
+    /// Look up the entry registered with a handle
+    pub fn resolve_handle(&self, handle: &str) -> Option<RegistryEntry> {
+        let did = self.handles.get(handle)?;
+        self.lookup(&did).filter(|entry| entry.handle.as_deref() == Some(handle))
+    }
+
+    /// Another DID whose live entry has `endpoint`, found through the index
+    /// of each endpoint's latest registrant
+    pub fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Option<String> {
+        let claimant = self.claimants.get(endpoint)?;
+        if *claimant == *did {
+            return None;
+        }
+        let now = chrono::Utc::now().timestamp();
+        self.lookup(&claimant)
+            .filter(|entry| entry.endpoint == endpoint && entry.expires_at > now)
+            .map(|entry| entry.did)
+    }
+
+    /// If a capacity is set and `did` would be a new entry in a full
+    /// registry, evict one entry, returning it. The returned guard must be
+    /// held until the new entry is inserted.
+    fn make_room(&self, did: &str) -> (Option<parking_lot::MutexGuard<'_, ()>>, Option<RegistryEntry>) {
+        let Some(capacity) = self.capacity else {
+            return (None, None);
+        };
+        let guard = self.admission.lock();
+        let mut evicted = None;
+        if !self.shard(did).entries.read().slots.contains_key(did) && self.len() >= capacity {
+            evicted = self.evict_one();
+        }
+        (Some(guard), evicted)
+    }
+
+    /// Remove an expired entry, or failing that the least recently accessed
+    /// one, looking only at the front of each shard's indexes
+    fn evict_one(&self) -> Option<RegistryEntry> {
+        let now = chrono::Utc::now().timestamp();
+        let expired = self.shards.iter().find_map(|shard| {
+            let entries = shard.entries.read();
+            let (expires_at, did) = entries.expiries.first()?;
+            (*expires_at <= now).then(|| did.clone())
+        });
+        let victim = expired.or_else(|| {
+            self.shards
+                .iter()
+                .filter_map(|shard| shard.accesses.lock().first_key_value().map(|(tick, did)| (*tick, did.clone())))
+                .min()
+                .map(|(_, did)| did)
+        });
+
+        let did = victim?;
+        tracing::debug!(did = %did, "Evicting registry entry at capacity");
+        let shard = self.shard(&did);
+        let evicted = shard.remove(&mut shard.entries.write(), &did);
+        evicted.map(|slot| slot.entry)
+    }
+
+    /// Append an entry to the DID's history if its endpoint, or who
+    /// registered it, changed
+    fn record_history(&self, did: &Arc<str>, entry: &RegistryEntry) {
+        if self.history_limit == 0 {
+            return;
+        }
+
+        let mut history = self.history.write();
+        let past = history.entry(did.clone()).or_default();
+        if let Some(last) = past
+            .front_mut()
+            .filter(|last| last.endpoint == entry.endpoint && last.registered_by == entry.registered_by)
+        {
+            // Keep the registration's expiry current, so retention counts
+            // from the last refresh
+            last.expires_at = entry.expires_at;
+            return;
+        }
+
+        past.push_front(entry.clone());
+        past.truncate(self.history_limit);
+    }
+
+    /// Whether the DID may have been registered; `false` is certain and
+    /// costs no lock
+    pub fn might_contain(&self, did: &str) -> bool {
+        self.seen.might_contain(did)
     }
 
     /// Look up an agent by DID
     pub fn lookup(&self, did: &str) -> Option<RegistryEntry> {
-        let map = self.inner.read();
-        map.get(did).cloned()
+        if !self.might_contain(did) {
+            return None;
+        }
+        let shard = self.shard(did);
+        let entries = shard.entries.read();
+        let (key, slot) = entries.slots.get_key_value(did)?;
+        shard.record_access(key, slot, self.tick());
+        Some(slot.entry.clone())
+    }
+
+    /// Record a successful handshake by a registered DID
+    pub fn touch(&self, did: &str, last_seen: i64) -> bool {
+        let mut entries = self.shard(did).entries.write();
+        match entries.slots.get_mut(did) {
+            Some(slot) => {
+                slot.entry.last_seen = last_seen;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Past registrations for a DID, newest first
+    pub fn history(&self, did: &str) -> Vec<RegistryEntry> {
+        let history = self.history.read();
+        history
+            .get(did)
+            .map(|past| past.iter().cloned().collect())
+            .unwrap_or_default()
     }
 
     /// Remove an agent's registration
     pub fn deregister(&self, did: &str) -> bool {
-        let mut map = self.inner.write();
-        map.remove(did).is_some()
+        let shard = self.shard(did);
+        let removed = shard.remove(&mut shard.entries.write(), did);
+        removed.map(|slot| self.unindex(&slot.entry)).is_some()
     }
 
-    /// Remove expired entries (call periodically)
-    pub fn cleanup_expired(&self) {
+    /// Remove expired entries (call periodically), taking them from the
+    /// front of each shard's expiry index
+    pub fn purge_expired(&self) -> Vec<RegistryEntry> {
         let now = chrono::Utc::now().timestamp();
-        let mut map = self.inner.write();
-        map.retain(|_, entry| entry.expires_at > now);
+        self.revocations.purge_expired(now);
+        let mut removed = Vec::new();
+        for shard in self.shards.iter() {
+            let mut entries = shard.entries.write();
+            while let Some((_, did)) = entries.expiries.first().filter(|(expires_at, _)| *expires_at <= now) {
+                let did = did.clone();
+                removed.extend(shard.remove(&mut entries, &did).map(|slot| slot.entry));
+            }
+        }
+        self.prune_history(now - HISTORY_RETENTION_SECS);
+
+        removed.iter().for_each(|entry| self.unindex(entry));
+        removed
+    }
+
+    /// Drop the history of DIDs with no entry whose registrations all
+    /// expired at or before `cutoff`
+    fn prune_history(&self, cutoff: i64) {
+        let lapsed = |past: &VecDeque<RegistryEntry>| past.iter().all(|entry| entry.expires_at <= cutoff);
+        let stale: Vec<Arc<str>> = self
+            .history
+            .read()
+            .iter()
+            .filter(|(_, past)| lapsed(past))
+            .map(|(did, _)| did.clone())
+            .collect();
+
+        for did in stale {
+            // Shard before history, the order a registration takes them in
+            let entries = self.shard(&did).entries.read();
+            if entries.slots.contains_key(&*did) {
+                continue;
+            }
+            let mut history = self.history.write();
+            if history.get(&did).is_some_and(lapsed) {
+                history.remove(&did);
+            }
+        }
+    }
+
+    /// All non-expired entries
+    pub fn list(&self) -> Vec<RegistryEntry> {
+        let now = chrono::Utc::now().timestamp();
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .entries
+                    .read()
+                    .slots
+                    .values()
+                    .filter(|slot| slot.entry.expires_at > now)
+                    .map(|slot| slot.entry.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Every entry, including expired ones not yet purged
+    pub fn all_entries(&self) -> Vec<RegistryEntry> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .entries
+                    .read()
+                    .slots
+                    .values()
+                    .map(|slot| slot.entry.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Entries by status, without copying them
+    pub fn status_counts(&self, now: i64, idle_before: i64) -> StatusCounts {
+        let mut counts = StatusCounts::default();
+        for shard in self.shards.iter() {
+            for slot in shard.entries.read().slots.values() {
+                counts.add(&slot.entry, now, idle_before);
+            }
+        }
+        counts
+    }
+
+    /// Number of live entries; only the expired ones, which purges keep
+    /// few, are walked
+    pub fn live_count(&self, now: i64) -> usize {
+        let first_live = (now.saturating_add(1), Arc::<str>::from(""));
+        self.shards
+            .iter()
+            .map(|shard| {
+                let entries = shard.entries.read();
+                entries.slots.len() - entries.expiries.range(..&first_live).count()
+            })
+            .sum()
+    }
+
+    /// The live entry expiring soonest, from each shard's expiry index
+    pub fn soonest_expiring(&self, now: i64) -> Option<RegistryEntry> {
+        let first_live = (now.saturating_add(1), Arc::<str>::from(""));
+        self.shards
+            .iter()
+            .filter_map(|shard| {
+                let entries = shard.entries.read();
+                let (_, did) = entries.expiries.range(&first_live..).next()?;
+                entries.slots.get(did).map(|slot| slot.entry.clone())
+            })
+            .min_by_key(|entry| entry.expires_at)
     }
 
     /// Get count of registered agents
-    #[allow(dead_code)]
     pub fn len(&self) -> usize {
-        self.inner.read().len()
+        self.shards.iter().map(|shard| shard.entries.read().slots.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.entries.read().slots.is_empty())
+    }
+}
+
+#[async_trait]
+impl RegistryBackend for Registry {
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        Ok(self.store_claiming_handle(entry, precondition)?.0)
+    }
+
+    async fn register_evicting(
+        &self,
+        entry: RegistryEntry,
+        precondition: Precondition,
+    ) -> Result<(Option<u64>, Vec<RegistryEntry>), ReachError> {
+        self.store_claiming_handle(entry, precondition)
+    }
+
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        Ok(Registry::lookup(self, did))
+    }
+
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        Ok(Registry::resolve_handle(self, handle))
+    }
+
+    async fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Result<Option<String>, ReachError> {
+        Ok(Registry::endpoint_claimant(self, endpoint, did))
+    }
+
+    fn might_contain(&self, did: &str) -> bool {
+        Registry::might_contain(self, did)
+    }
+
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+        Ok(Registry::touch(self, did, last_seen))
+    }
+
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+        Ok(Registry::deregister(self, did))
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        Ok(Registry::purge_expired(self))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    async fn status_counts(&self, now: i64, idle_before: i64) -> Result<StatusCounts, ReachError> {
+        Ok(Registry::status_counts(self, now, idle_before))
+    }
+
+    async fn live_count(&self, now: i64) -> Result<usize, ReachError> {
+        Ok(Registry::live_count(self, now))
+    }
+
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        Ok(Registry::soonest_expiring(self, now))
+    }
+
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        Ok(Registry::list(self))
+    }
+
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        Ok(Registry::all_entries(self))
+    }
+
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        Ok(Registry::history(self, did))
+    }
+
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+        self.revocations.revoke(subject, delegate, revoked_at, expires_at);
+        Ok(())
+    }
+
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+        Ok(self.revocations.revoked_at(subject, delegate, now))
     }
 }
 
@@ -55,3 +671,330 @@ impl Default for Registry {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(did: &str, endpoint: &str) -> RegistryEntry {
+        RegistryEntry::for_test(did, endpoint, 3600)
+    }
+
+    fn endpoints(entries: &[RegistryEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.endpoint.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn backend_harness() {
+        crate::backend::harness::run(&Registry::new()).await;
+    }
+
+    #[test]
+    fn history_is_newest_first() {
+        let registry = Registry::new();
+        registry.register(entry("did:key:a", "wss://one"));
+        registry.register(entry("did:key:a", "wss://two"));
+        registry.register(entry("did:key:a", "wss://three"));
+
+        let history = registry.history("did:key:a");
+        assert_eq!(endpoints(&history), ["wss://three", "wss://two", "wss://one"]);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let registry = Registry::with_history_limit(2);
+        registry.register(entry("did:key:a", "wss://one"));
+        registry.register(entry("did:key:a", "wss://two"));
+        registry.register(entry("did:key:a", "wss://three"));
+
+        let history = registry.history("did:key:a");
+        assert_eq!(endpoints(&history), ["wss://three", "wss://two"]);
+    }
+
+    #[test]
+    fn history_skips_unchanged_endpoint() {
+        let registry = Registry::new();
+        registry.register(entry("did:key:a", "wss://one"));
+        registry.register(entry("did:key:a", "wss://one"));
+
+        assert_eq!(registry.history("did:key:a").len(), 1);
+    }
+
+    #[test]
+    fn purge_removes_only_expired() {
+        let registry = Registry::new();
+        registry.register(entry("did:key:live", "wss://live"));
+        let mut expired = entry("did:key:old", "wss://old");
+        expired.expires_at = expired.registered_at - 1;
+        registry.register(expired);
+
+        assert_eq!(endpoints(&registry.purge_expired()), ["wss://old"]);
+
+        assert!(registry.lookup("did:key:live").is_some());
+        assert!(registry.lookup("did:key:old").is_none());
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn concurrent_readers_and_writer() {
+        let registry = Registry::new();
+        for i in 0..100 {
+            registry.register(entry(&format!("did:key:{}", i), "wss://initial"));
+        }
+
+        let writer = {
+            let registry = registry.clone();
+            std::thread::spawn(move || {
+                for round in 0..50 {
+                    for i in 0..100 {
+                        let endpoint = format!("wss://round-{}", round);
+                        registry.register(entry(&format!("did:key:{}", i), &endpoint));
+                    }
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        for i in 0..100 {
+                            let did = format!("did:key:{}", i);
+                            let found = registry.lookup(&did).expect("entry present");
+                            assert_eq!(found.did, did);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(registry.len(), 100);
+        for i in 0..100 {
+            let found = registry.lookup(&format!("did:key:{}", i)).unwrap();
+            assert_eq!(found.endpoint, "wss://round-49");
+        }
+    }
+
+    #[test]
+    fn concurrent_handle_claims_have_one_winner_each() {
+        let registry = Registry::new();
+        let claims: Vec<_> = (0..8)
+            .map(|i| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    (0..20)
+                        .filter(|h| {
+                            let mut claim = entry(&format!("did:key:{}", i), "wss://agent");
+                            claim.did = format!("did:key:{}-{}", i, h);
+                            claim.handle = Some(format!("agent{}@example.com", h));
+                            registry
+                                .store_claiming_handle(claim, Precondition::Always)
+                                .is_ok()
+                        })
+                        .count()
+                })
+            })
+            .collect();
+        let won: usize = claims.into_iter().map(|claim| claim.join().unwrap()).sum();
+
+        assert_eq!(won, 20);
+        for h in 0..20 {
+            let handle = format!("agent{}@example.com", h);
+            let holder = registry.resolve_handle(&handle).expect("handle claimed");
+            assert!(holder.did.ends_with(&format!("-{}", h)));
+        }
+    }
+
+    #[test]
+    fn full_registry_evicts_least_recently_looked_up() {
+        let registry = Registry::new().with_capacity(Some(3));
+        registry.register(entry("did:key:a", "wss://a"));
+        registry.register(entry("did:key:b", "wss://b"));
+        registry.register(entry("did:key:c", "wss://c"));
+        registry.lookup("did:key:a");
+        registry.lookup("did:key:c");
+
+        registry.register(entry("did:key:d", "wss://d"));
+
+        assert_eq!(registry.len(), 3);
+        assert!(registry.lookup("did:key:b").is_none());
+        for did in ["did:key:a", "did:key:c", "did:key:d"] {
+            assert!(registry.lookup(did).is_some(), "{} was evicted", did);
+        }
+    }
+
+    #[test]
+    fn full_registry_evicts_expired_first() {
+        let registry = Registry::new().with_capacity(Some(3));
+        registry.register(entry("did:key:a", "wss://a"));
+        let mut expired = entry("did:key:b", "wss://b");
+        expired.expires_at = expired.registered_at - 1;
+        registry.register(expired);
+        registry.register(entry("did:key:c", "wss://c"));
+        registry.lookup("did:key:b");
+
+        registry.register(entry("did:key:d", "wss://d"));
+
+        assert!(registry.lookup("did:key:b").is_none());
+        assert!(registry.lookup("did:key:a").is_some());
+    }
+
+    #[test]
+    fn eviction_indexes_follow_every_change() {
+        let registry = Registry::new();
+        for i in 0..20 {
+            registry.register(entry(&format!("did:key:{}", i), "wss://one"));
+        }
+        for i in 0..20 {
+            let mut renewed = entry(&format!("did:key:{}", i), "wss://two");
+            renewed.expires_at += i;
+            registry.register(renewed);
+            registry.lookup(&format!("did:key:{}", 19 - i));
+        }
+        let mut expired = entry("did:key:3", "wss://three");
+        expired.expires_at = expired.registered_at - 1;
+        registry.register(expired);
+        registry.deregister("did:key:5");
+        registry.purge_expired();
+
+        assert_eq!(registry.len(), 18);
+        for shard in registry.shards.iter() {
+            let entries = shard.entries.read();
+            let accesses = shard.accesses.lock();
+            assert_eq!(entries.expiries.len(), entries.slots.len());
+            assert_eq!(accesses.len(), entries.slots.len());
+            for (did, slot) in &entries.slots {
+                assert!(entries.expiries.contains(&(slot.entry.expires_at, did.clone())));
+                assert_eq!(accesses.get(&slot.last_access.load(Ordering::Relaxed)), Some(did));
+            }
+        }
+    }
+
+    #[test]
+    fn updating_a_full_registry_evicts_nothing() {
+        let registry = Registry::new().with_capacity(Some(2));
+        registry.register(entry("did:key:a", "wss://a"));
+        registry.register(entry("did:key:b", "wss://b"));
+
+        registry.register(entry("did:key:a", "wss://a2"));
+
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.lookup("did:key:a").unwrap().endpoint, "wss://a2");
+    }
+
+    #[test]
+    fn absent_did_skips_the_shard_lock() {
+        let registry = Registry::new();
+        registry.register(entry("did:key:present", "wss://present"));
+        let absent = "did:key:never-registered";
+        assert!(!registry.might_contain(absent));
+
+        // With the shard write-locked, a lookup that took the lock would block
+        let _locked = registry.shard(absent).entries.write();
+        let (done, finished) = std::sync::mpsc::channel();
+        let reader = registry.clone();
+        std::thread::spawn(move || done.send(reader.lookup(absent)).unwrap());
+
+        let found = finished.recv_timeout(std::time::Duration::from_secs(1));
+        assert_eq!(found, Ok(None), "lookup of an absent DID waited for the lock");
+    }
+
+    #[test]
+    fn deregistered_did_falls_through_to_lookup() {
+        let registry = Registry::new();
+        registry.register(entry("did:key:a", "wss://one"));
+        registry.deregister("did:key:a");
+
+        assert!(registry.might_contain("did:key:a"));
+        assert!(registry.lookup("did:key:a").is_none());
+    }
+
+    #[test]
+    fn history_survives_deregistration() {
+        let registry = Registry::new();
+        registry.register(entry("did:key:a", "wss://one"));
+        registry.deregister("did:key:a");
+
+        assert!(registry.lookup("did:key:a").is_none());
+        assert_eq!(endpoints(&registry.history("did:key:a")), ["wss://one"]);
+    }
+
+    #[test]
+    fn history_is_dropped_after_retention() {
+        let registry = Registry::new();
+        let lapsed = |did| RegistryEntry {
+            expires_at: chrono::Utc::now().timestamp() - HISTORY_RETENTION_SECS - 10,
+            ..entry(did, "wss://one")
+        };
+        registry.register(lapsed("did:key:gone"));
+        // Refreshing the same endpoint keeps its history row current
+        registry.register(lapsed("did:key:live"));
+        registry.register(entry("did:key:live", "wss://one"));
+        registry.register(entry("did:key:recent", "wss://one"));
+        registry.deregister("did:key:recent");
+
+        registry.purge_expired();
+        assert!(registry.history("did:key:gone").is_empty());
+        assert_eq!(endpoints(&registry.history("did:key:live")), ["wss://one"]);
+        assert_eq!(endpoints(&registry.history("did:key:recent")), ["wss://one"]);
+        assert!(!registry.history.read().contains_key("did:key:gone"));
+    }
+
+    #[tokio::test]
+    async fn indexes_share_one_did_allocation() {
+        let registry = Registry::new();
+        let claimed = |endpoint| RegistryEntry { handle: Some("alice@example.com".to_string()), ..entry("did:key:a", endpoint) };
+        RegistryBackend::register(&registry, claimed("wss://one")).await.unwrap();
+        RegistryBackend::register(&registry, claimed("wss://two")).await.unwrap();
+
+        let entries = registry.shard("did:key:a").entries.read();
+        let (key, _) = entries.slots.get_key_value("did:key:a").unwrap();
+        let history = registry.history.read();
+        let (history_key, _) = history.get_key_value("did:key:a").unwrap();
+        assert!(Arc::ptr_eq(key, history_key));
+        assert!(Arc::ptr_eq(key, &registry.handles.get("alice@example.com").unwrap()));
+        assert!(Arc::ptr_eq(key, &registry.claimants.get("wss://two").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn index_keys_go_with_their_entries() {
+        let registry = Registry::new().with_capacity(Some(3));
+        let claimed = |did, handle: &str, endpoint| RegistryEntry { handle: Some(handle.to_string()), ..entry(did, endpoint) };
+        RegistryBackend::register(&registry, claimed("did:key:a", "a@example.com", "wss://a")).await.unwrap();
+        RegistryBackend::register(&registry, claimed("did:key:b", "b@example.com", "wss://b")).await.unwrap();
+        let mut expired = claimed("did:key:c", "c@example.com", "wss://c");
+        expired.expires_at = expired.registered_at - 1;
+        RegistryBackend::register(&registry, expired).await.unwrap();
+
+        // Replaced: a moves to a new handle and endpoint
+        RegistryBackend::register(&registry, claimed("did:key:a", "a2@example.com", "wss://a2")).await.unwrap();
+        assert!(registry.handles.get("a@example.com").is_none());
+        assert!(registry.claimants.get("wss://a").is_none());
+        assert!(registry.claimants.get("wss://a2").is_some());
+
+        // Evicted: a new DID at capacity takes the expired c's place
+        RegistryBackend::register(&registry, claimed("did:key:d", "d@example.com", "wss://d")).await.unwrap();
+        assert!(registry.lookup("did:key:c").is_none());
+        assert!(registry.handles.get("c@example.com").is_none());
+        assert!(registry.claimants.get("wss://c").is_none());
+
+        // Deregistered, then purged
+        registry.deregister("did:key:b");
+        assert!(registry.handles.get("b@example.com").is_none());
+        assert!(registry.claimants.get("wss://b").is_none());
+        let mut lapsed = claimed("did:key:d", "d@example.com", "wss://d");
+        lapsed.expires_at = lapsed.registered_at - 1;
+        RegistryBackend::register(&registry, lapsed).await.unwrap();
+        assert_eq!(endpoints(&registry.purge_expired()), ["wss://d"]);
+        assert!(registry.handles.get("d@example.com").is_none());
+        assert!(registry.claimants.get("wss://d").is_none());
+
+        assert_eq!(registry.resolve_handle("a2@example.com").unwrap().did, "did:key:a");
+    }
+}
@@ -0,0 +1,95 @@
+//! Pre-populating the registry at startup
+//!
+//! Seeded entries are stored exactly like registrations made over the API:
+//! they expire after their TTL and can be deregistered by their owner.
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::backend::RegistryBackend;
+use crate::error::ReachError;
+use crate::snapshot;
+use crate::types::{default_ttl, RegistryEntry};
+
+#[derive(Debug, Deserialize)]
+pub struct SeedEntry {
+    pub did: String,
+    pub endpoint: String,
+    /// Time-to-live in seconds (default: 3600)
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+}
+
+/// Read a seed file (a JSON array of entries) and register its entries
+///
+/// Fails if the file can't be read or parsed; individual invalid entries
+/// are logged and skipped. Returns how many entries were registered.
+pub async fn load_file(path: &Path, backend: &dyn RegistryBackend) -> anyhow::Result<usize> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read seed file {}", path.display()))?;
+    let entries: Vec<SeedEntry> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse seed file {}", path.display()))?;
+
+    Ok(seed(entries, backend).await?)
+}
+
+/// Register seed entries with `registered_at = now`
+pub async fn seed(entries: Vec<SeedEntry>, backend: &dyn RegistryBackend) -> Result<usize, ReachError> {
+    let now = chrono::Utc::now().timestamp();
+    let mut seeded = 0;
+
+    for seed in entries {
+        let entry = RegistryEntry {
+            did: seed.did,
+            endpoint: seed.endpoint,
+            registered_at: now,
+            expires_at: now.saturating_add(seed.ttl.min(i64::MAX as u64) as i64),
+            last_seen: now,
+            handle: None,
+            endpoints: Vec::new(),
+            version: 0,
+            registered_by: None,
+        };
+        if let Err(reason) = snapshot::validate(&entry) {
+            warn!(did = %entry.did, %reason, "Skipping invalid seed entry");
+            continue;
+        }
+
+        backend.register(entry).await?;
+        seeded += 1;
+    }
+
+    Ok(seeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+
+    #[tokio::test]
+    async fn seeded_entries_are_immediately_lookupable() {
+        let first = agent_id::RootKey::generate().did().to_string();
+        let second = agent_id::RootKey::generate().did().to_string();
+        let entries: Vec<SeedEntry> = serde_json::from_value(serde_json::json!([
+            {"did": first, "endpoint": "wss://first"},
+            {"did": second, "endpoint": "https://second", "ttl": 60},
+            {"did": "not-a-did", "endpoint": "wss://skipped"},
+        ]))
+        .unwrap();
+
+        let registry = Registry::new();
+        assert_eq!(seed(entries, &registry).await.unwrap(), 2);
+
+        let first_entry = Registry::lookup(&registry, &first).expect("first seeded");
+        assert_eq!(first_entry.endpoint, "wss://first");
+        assert_eq!(first_entry.expires_at - first_entry.registered_at, 3600);
+
+        let second_entry = Registry::lookup(&registry, &second).expect("second seeded");
+        assert_eq!(second_entry.expires_at - second_entry.registered_at, 60);
+        assert!(Registry::lookup(&registry, "not-a-did").is_none());
+    }
+}
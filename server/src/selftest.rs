@@ -0,0 +1,218 @@
+//! Handshake self-test for `POST /admin/selftest`
+//!
+//! Runs Hello and Proof against this server's own handlers with a throwaway
+//! key, so monitoring can check challenge generation, challenge storage,
+//! proof verification and session storage in one call, without an outside
+//! client holding an identity. The session it gets is ended straight away,
+//! and the handshake isn't counted in the handshake metrics, logged as a
+//! session or recorded as a sighting of the key.
+
+use std::future::Future;
+use std::time::Instant;
+
+use agent_id::RootKey;
+use agent_id_handshake::messages::Hello;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::error::ReachError;
+use crate::handlers::{self, AppState, ProofFrom};
+
+/// How one stage of the self-test went
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StageResult {
+    /// `hello`, `sign`, `proof` or `session`
+    #[schema(example = "proof")]
+    pub stage: &'static str,
+    pub ok: bool,
+    /// Time the server took for the stage (milliseconds)
+    #[schema(example = 0.42)]
+    pub duration_ms: f64,
+    /// What went wrong, when not ok
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a handshake self-test
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SelftestResponse {
+    /// Whether every stage passed
+    pub ok: bool,
+    /// DID of the throwaway key the handshake ran as
+    pub did: String,
+    /// Total time across the stages run (milliseconds)
+    pub duration_ms: f64,
+    /// Stages in the order they ran, ending at the first failure
+    pub stages: Vec<StageResult>,
+}
+
+/// Stages run so far
+#[derive(Default)]
+struct Stages(Vec<StageResult>);
+
+impl Stages {
+    /// Time `run` as `stage`, recording whether it passed
+    async fn run<T>(&mut self, stage: &'static str, run: impl Future<Output = Result<T, ReachError>>) -> Option<T> {
+        let started = Instant::now();
+        let result = run.await;
+        let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+        let (value, error) = match result {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        self.0.push(StageResult { stage, ok: error.is_none(), duration_ms, error });
+        value
+    }
+}
+
+/// POST /admin/selftest
+///
+/// Complete a handshake with this server as a freshly generated did:key and
+/// report each stage's outcome and timing. Answers `503` when a stage fails,
+/// with the same body.
+#[utoipa::path(
+    post,
+    path = "/admin/selftest",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every stage passed", body = SelftestResponse),
+        (status = 403, description = "Missing or wrong admin token", body = crate::openapi::ErrorResponse),
+        (status = 503, description = "A stage failed", body = SelftestResponse),
+    ),
+    security(("admin" = []))
+)]
+pub async fn selftest(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<SelftestResponse>), ReachError> {
+    crate::admin::require_admin(&headers, &state)?;
+
+    let key = RootKey::generate();
+    let did = key.did().to_string();
+    let mut stages = Stages::default();
+    run(&state, &key, &mut stages).await;
+
+    let ok = stages.0.iter().all(|stage| stage.ok);
+    let response = SelftestResponse {
+        ok,
+        did,
+        duration_ms: stages.0.iter().map(|stage| stage.duration_ms).sum(),
+        stages: stages.0,
+    };
+    if ok {
+        info!(duration_ms = response.duration_ms, "Handshake self-test passed");
+        Ok((StatusCode::OK, Json(response)))
+    } else {
+        warn!(stages = ?response.stages, "Handshake self-test failed");
+        Ok((StatusCode::SERVICE_UNAVAILABLE, Json(response)))
+    }
+}
+
+/// Hello, then sign the challenge and send Proof, then read the session back
+/// and end it, stopping at the first stage that fails
+async fn run(state: &AppState, key: &RootKey, stages: &mut Stages) {
+    let mut hello = Hello::new(key.did().to_string());
+    hello.protocols.extend(state.handshake.protocols.supported().iter().cloned());
+    // A read-only session, so the test key could never register
+    hello.capabilities = Some(vec![crate::types::Scope::Read.capability().to_string()]);
+
+    let Some(Json(challenge)) = stages.run("hello", handlers::hello(State(state.clone()), Json(hello))).await else {
+        return;
+    };
+
+    let signed = async {
+        agent_id_handshake::protocol::sign_proof(&challenge, &key.did(), key, Some(challenge.issuer.clone()))
+            .map_err(|e| ReachError::Internal(format!("Failed to sign the proof: {}", e)))
+    };
+    let Some(proof) = stages.run("sign", signed).await else {
+        return;
+    };
+    let Some(accepted) = stages.run("proof", handlers::accept_proof(state, ProofFrom::Selftest, proof)).await else {
+        return;
+    };
+
+    let store = &state.handshake.store;
+    stages
+        .run("session", async {
+            let found = store.session(&accepted.session_id).await;
+            store.remove_session(&accepted.session_id).await?;
+            match found? {
+                Some(session) if session.did == key.did().to_string() => Ok(()),
+                Some(_) => Err(ReachError::Internal("Stored session belongs to another DID".into())),
+                None => Err(ReachError::Internal("Session was not stored".into())),
+            }
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::header;
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    fn admin_state() -> AppState {
+        AppState {
+            admin_token: Some(Arc::from("admin-secret")),
+            ..AppState::for_tests()
+        }
+    }
+
+    #[tokio::test]
+    async fn healthy_servers_pass_every_stage() {
+        let state = admin_state();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer admin-secret".parse().unwrap());
+
+        let (status, Json(report)) = selftest(State(state.clone()), headers).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(report.ok, "{:?}", report);
+        assert_eq!(report.stages.iter().map(|s| s.stage).collect::<Vec<_>>(), ["hello", "sign", "proof", "session"]);
+        assert!(report.stages.iter().all(|s| s.ok && s.error.is_none()));
+        assert!(report.did.starts_with("did:key:z6Mk"));
+        assert!(state.handshake.store.sessions().await.unwrap().is_empty(), "the session is ended");
+
+        let refused = selftest(State(state), HeaderMap::new()).await.unwrap_err().into_response();
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn selftests_leave_last_seen_alone() {
+        let state = admin_state();
+        let key = RootKey::generate();
+        let registered_at = chrono::Utc::now().timestamp() - 600;
+        state
+            .registry
+            .register(crate::types::RegistryEntry::new(&key.did().to_string(), "wss://agent", registered_at, registered_at + 3600))
+            .await
+            .unwrap();
+
+        let mut stages = Stages::default();
+        run(&state, &key, &mut stages).await;
+        assert!(stages.0.iter().all(|s| s.ok), "{:?}", stages.0);
+        let entry = state.registry.lookup(&key.did().to_string()).await.unwrap().unwrap();
+        assert_eq!(entry.last_seen, registered_at);
+    }
+
+    #[tokio::test]
+    async fn failed_stages_are_reported() {
+        let mut stages = Stages::default();
+        assert_eq!(stages.run("hello", async { Ok::<_, ReachError>(1) }).await, Some(1));
+        assert_eq!(stages.run("proof", async { Err::<(), _>(ReachError::InvalidChallenge) }).await, None);
+
+        let json = serde_json::to_value(&stages.0).unwrap();
+        assert_eq!(json[0]["ok"], true);
+        assert!(json[0].get("error").is_none());
+        assert_eq!(json[1]["ok"], false);
+        assert_eq!(json[1]["error"], "Invalid or expired challenge");
+    }
+}
@@ -0,0 +1,274 @@
+//! JSON snapshots of the registry contents
+//!
+//! Used for backups and to move registrations between storage backends,
+//! either through `GET /admin/export` / `POST /admin/import` or the `import`
+//! subcommand.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::backend::RegistryBackend;
+use crate::error::ReachError;
+use crate::types::RegistryEntry;
+
+/// Current snapshot format version
+///
+/// Bump this when the entry format changes, and keep older versions
+/// importable.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Snapshot {
+    #[schema(example = 1)]
+    pub version: u32,
+    /// Unix timestamp (seconds)
+    pub exported_at: i64,
+    pub agents: Vec<RegistryEntry>,
+}
+
+/// How an import treats registrations already in the registry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Keep existing live registrations; only add DIDs that are absent
+    #[default]
+    Merge,
+    /// Remove every existing registration, then load the snapshot
+    Replace,
+    /// Overwrite registrations with the same DID and load expired entries
+    /// too, so every entry expires when it originally would have
+    Restore,
+}
+
+/// Query parameters for `GET /admin/export`
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// Also include expired entries that haven't been purged yet
+    #[serde(default)]
+    pub include_expired: bool,
+}
+
+/// Query parameters for `POST /admin/import`
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+/// Outcome of an import
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportReport {
+    /// Entries stored
+    pub added: usize,
+    /// Valid entries not stored: already expired (unless restoring), or
+    /// (when merging) the DID already has a live registration
+    pub skipped: usize,
+    /// Entries that failed validation or couldn't be stored
+    pub rejected: usize,
+    pub errors: Vec<RejectedEntry>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RejectedEntry {
+    pub did: String,
+    pub reason: String,
+}
+
+impl ImportReport {
+    fn reject(&mut self, did: String, reason: String) {
+        self.rejected += 1;
+        self.errors.push(RejectedEntry { did, reason });
+    }
+}
+
+impl Snapshot {
+    /// Snapshot of every live registration, or with `include_expired` of
+    /// every stored one, ordered by DID
+    pub async fn export(backend: &dyn RegistryBackend, include_expired: bool) -> Result<Self, ReachError> {
+        let mut agents = if include_expired {
+            backend.all_entries().await?
+        } else {
+            backend.list().await?
+        };
+        agents.sort_by(|a, b| a.did.cmp(&b.did));
+
+        Ok(Self {
+            version: SNAPSHOT_VERSION,
+            exported_at: chrono::Utc::now().timestamp(),
+            agents,
+        })
+    }
+
+    /// Read a snapshot file
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse snapshot {}", path.display()))
+    }
+
+    /// Validate every entry, then load the valid ones
+    ///
+    /// Nothing is modified if the snapshot version is unsupported.
+    pub async fn import(self, backend: &dyn RegistryBackend, mode: ImportMode) -> Result<ImportReport, ReachError> {
+        if self.version == 0 || self.version > SNAPSHOT_VERSION {
+            return Err(ReachError::InvalidRequest(format!(
+                "Unsupported snapshot version {} (expected at most {})",
+                self.version, SNAPSHOT_VERSION
+            )));
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let mut report = ImportReport::default();
+        let mut valid = Vec::with_capacity(self.agents.len());
+        let mut handles = HashSet::new();
+
+        for entry in self.agents {
+            let checked = validate(&entry).and_then(|()| match &entry.handle {
+                Some(handle) if entry.expires_at > now && !handles.insert(handle.clone()) => {
+                    Err("Handle is held by an earlier entry in the snapshot".to_string())
+                }
+                _ => Ok(()),
+            });
+            match checked {
+                Ok(()) if entry.expires_at <= now && mode != ImportMode::Restore => report.skipped += 1,
+                Ok(()) => valid.push(entry),
+                Err(reason) => report.reject(entry.did, reason),
+            }
+        }
+
+        if mode == ImportMode::Replace {
+            for existing in backend.list().await? {
+                backend.deregister(&existing.did).await?;
+            }
+            backend.purge_expired().await?;
+        }
+
+        // A failed store is reported against its entry rather than ending
+        // the import, which after a replace would leave the registry half
+        // loaded
+        for entry in valid {
+            let did = entry.did.clone();
+            let stored = match mode {
+                ImportMode::Merge => backend.register_if_absent(entry).await,
+                ImportMode::Replace | ImportMode::Restore => backend.register(entry).await.map(|()| true),
+            };
+            match stored {
+                Ok(true) => report.added += 1,
+                Ok(false) => report.skipped += 1,
+                Err(e) => report.reject(did, e.to_string()),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Check an entry is well-formed, returning the reason if not
+pub fn validate(entry: &RegistryEntry) -> Result<(), String> {
+    entry.did.parse::<agent_id::Did>()
+        .map_err(|_| "Invalid DID".to_string())?;
+    if entry.endpoint.trim().is_empty() {
+        return Err("Empty endpoint".to_string());
+    }
+    if entry.expires_at < entry.registered_at {
+        return Err("Expires before it was registered".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::capacity::{CappedRegistry, CapacityPolicy, DEFAULT_HIGH_WATER_PERCENT};
+    use crate::registry::Registry;
+
+    fn did() -> String {
+        agent_id::RootKey::generate().did().to_string()
+    }
+
+    fn entry(did: &str, endpoint: &str, expires_in: i64) -> RegistryEntry {
+        let now = chrono::Utc::now().timestamp();
+        RegistryEntry::new(did, endpoint, now - 120, now + expires_in)
+    }
+
+    fn snapshot(agents: Vec<RegistryEntry>) -> Snapshot {
+        Snapshot { version: SNAPSHOT_VERSION, exported_at: 0, agents }
+    }
+
+    #[tokio::test]
+    async fn merge_keeps_existing_and_reports_counts() {
+        let (existing, new, expired) = (did(), did(), did());
+        let registry = Registry::new();
+        Registry::register(&registry, entry(&existing, "wss://current", 3600));
+
+        let report = snapshot(vec![
+            entry(&existing, "wss://from-snapshot", 3600),
+            entry(&new, "wss://new", 3600),
+            entry(&expired, "wss://gone", -60),
+            entry("not-a-did", "wss://bad", 3600),
+        ])
+        .import(&registry, ImportMode::Merge)
+        .await
+        .unwrap();
+
+        assert_eq!((report.added, report.skipped, report.rejected), (1, 2, 1));
+        assert_eq!(report.errors[0].did, "not-a-did");
+        assert_eq!(Registry::lookup(&registry, &existing).unwrap().endpoint, "wss://current");
+        assert!(Registry::lookup(&registry, &new).is_some());
+        assert!(Registry::lookup(&registry, &expired).is_none());
+    }
+
+    #[tokio::test]
+    async fn replace_removes_entries_missing_from_snapshot() {
+        let (old, kept) = (did(), did());
+        let registry = Registry::new();
+        Registry::register(&registry, entry(&old, "wss://old", 3600));
+        Registry::register(&registry, entry(&kept, "wss://before", 3600));
+
+        let report = snapshot(vec![entry(&kept, "wss://after", 3600)])
+            .import(&registry, ImportMode::Replace)
+            .await
+            .unwrap();
+
+        assert_eq!(report.added, 1);
+        assert!(Registry::lookup(&registry, &old).is_none());
+        assert_eq!(Registry::lookup(&registry, &kept).unwrap().endpoint, "wss://after");
+    }
+
+    #[tokio::test]
+    async fn replace_reports_entries_it_cannot_store() {
+        let (first, second, third) = (did(), did(), did());
+        let registry = CappedRegistry::new(Arc::new(Registry::new()), 1, CapacityPolicy::Reject, DEFAULT_HIGH_WATER_PERCENT);
+        registry.register(entry(&did(), "wss://old", 3600)).await.unwrap();
+
+        let held = |did: &str, endpoint| RegistryEntry { handle: Some("alice@example.com".to_string()), ..entry(did, endpoint, 3600) };
+        let report = snapshot(vec![held(&first, "wss://first"), held(&second, "wss://second"), entry(&third, "wss://third", 3600)])
+            .import(&registry, ImportMode::Replace)
+            .await
+            .unwrap();
+
+        assert_eq!((report.added, report.rejected), (1, 2));
+        assert_eq!(report.errors[0].did, second);
+        assert_eq!(report.errors[1].did, third);
+        assert!(registry.lookup(&first).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn unsupported_version_changes_nothing() {
+        let registry = Registry::new();
+        let mut future = snapshot(vec![entry(&did(), "wss://new", 3600)]);
+        future.version = SNAPSHOT_VERSION + 1;
+
+        assert!(future.import(&registry, ImportMode::Replace).await.is_err());
+        assert_eq!(Registry::len(&registry), 0);
+    }
+}
@@ -0,0 +1,422 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+
+use crate::backend::{Precondition, RegistryBackend};
+use crate::error::ReachError;
+use crate::types::{RegistryEntry, StatusCounts};
+
+/// Maximum pooled connections
+const MAX_CONNECTIONS: u32 = 4;
+
+/// SQLite-backed registry
+///
+/// Same schema as the PostgreSQL backend: entries in `agents` keyed by DID,
+/// past registrations in `agent_history` trimmed to `history_limit` rows per
+/// DID and purged once the DID has had no entry for
+/// [`HISTORY_RETENTION_SECS`](crate::registry::HISTORY_RETENTION_SECS).
+/// The database runs in WAL mode so lookups don't block on writes.
+pub struct SqliteRegistry {
+    pool: SqlitePool,
+    history_limit: usize,
+}
+
+impl SqliteRegistry {
+    /// Open (creating if needed) the database file and run pending migrations
+    pub async fn open(path: &Path, history_limit: usize) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect_with(options)
+            .await?;
+
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+
+        Ok(Self { pool, history_limit })
+    }
+
+    /// Store an entry if `precondition` holds for the stored row and record
+    /// history, returning the version it was stored with
+    async fn store(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+
+        // An expired holder gives up the handle; a live one keeps it and the
+        // insert below fails on the unique index
+        if let Some(handle) = &entry.handle {
+            sqlx::query("UPDATE agents SET handle = NULL WHERE handle = ?1 AND did <> ?2 AND expires_at <= ?3")
+                .bind(handle)
+                .bind(&entry.did)
+                .bind(chrono::Utc::now().timestamp())
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let endpoints = crate::endpoints::to_json(&entry.endpoints)?;
+        // Taken from one counter, so a DID's versions never repeat even
+        // after its row is deleted; rolled back if nothing is stored
+        let issued: i64 = sqlx::query_scalar("UPDATE agent_version_counter SET version = version + 1 RETURNING version")
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let stored: Result<Option<i64>, sqlx::Error> = match precondition {
+            // Only an existing row at that version is replaced
+            Precondition::Version(version) => {
+                sqlx::query_scalar(
+                    "UPDATE agents SET
+                        endpoint = ?2,
+                        registered_at = ?3,
+                        expires_at = ?4,
+                        last_seen = ?5,
+                        handle = ?6,
+                        endpoints = ?7,
+                        version = ?9
+                     WHERE did = ?1 AND version = ?8
+                     RETURNING version",
+                )
+                .bind(&entry.did)
+                .bind(&entry.endpoint)
+                .bind(entry.registered_at)
+                .bind(entry.expires_at)
+                .bind(entry.last_seen)
+                .bind(&entry.handle)
+                .bind(&endpoints)
+                .bind(version as i64)
+                .bind(issued)
+                .fetch_optional(&mut *tx)
+                .await
+            }
+            _ => {
+                sqlx::query_scalar(
+                    "INSERT INTO agents (did, endpoint, registered_at, expires_at, last_seen, handle, endpoints, version)
+                     VALUES (?1, ?2, ?3, ?4, ?7, ?8, ?9, ?10)
+                     ON CONFLICT (did) DO UPDATE SET
+                        endpoint = excluded.endpoint,
+                        registered_at = excluded.registered_at,
+                        expires_at = excluded.expires_at,
+                        last_seen = excluded.last_seen,
+                        handle = excluded.handle,
+                        endpoints = excluded.endpoints,
+                        version = excluded.version
+                     WHERE NOT ?5 OR agents.expires_at <= ?6
+                     RETURNING version",
+                )
+                .bind(&entry.did)
+                .bind(&entry.endpoint)
+                .bind(entry.registered_at)
+                .bind(entry.expires_at)
+                .bind(precondition == Precondition::Absent)
+                .bind(now)
+                .bind(entry.last_seen)
+                .bind(&entry.handle)
+                .bind(&endpoints)
+                .bind(issued)
+                .fetch_optional(&mut *tx)
+                .await
+            }
+        };
+        let Some(version) = stored.map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => ReachError::HandleTaken,
+            _ => db_error(e),
+        })?
+        else {
+            return Ok(None);
+        };
+
+        if self.history_limit > 0 {
+            // Only record a new history row when the endpoint, or who
+            // registered it, changed; otherwise keep the latest row's
+            // expiry current, so retention counts from the last refresh
+            sqlx::query(
+                "UPDATE agent_history SET expires_at = ?3
+                 WHERE id = (SELECT id FROM agent_history WHERE did = ?1 ORDER BY id DESC LIMIT 1)
+                    AND endpoint = ?2 AND registered_by IS ?4",
+            )
+            .bind(&entry.did)
+            .bind(&entry.endpoint)
+            .bind(entry.expires_at)
+            .bind(&entry.registered_by)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+            sqlx::query(
+                "INSERT INTO agent_history (did, endpoint, registered_at, expires_at, registered_by)
+                 SELECT ?1, ?2, ?3, ?4, ?5
+                 WHERE NOT EXISTS (
+                    SELECT 1 FROM (
+                        SELECT endpoint, registered_by FROM agent_history
+                        WHERE did = ?1 ORDER BY id DESC LIMIT 1
+                    ) latest WHERE latest.endpoint = ?2 AND latest.registered_by IS ?5
+                 )",
+            )
+            .bind(&entry.did)
+            .bind(&entry.endpoint)
+            .bind(entry.registered_at)
+            .bind(entry.expires_at)
+            .bind(&entry.registered_by)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+            sqlx::query(
+                "DELETE FROM agent_history
+                 WHERE did = ?1 AND id NOT IN (
+                    SELECT id FROM agent_history WHERE did = ?1 ORDER BY id DESC LIMIT ?2
+                 )",
+            )
+            .bind(&entry.did)
+            .bind(self.history_limit as i64)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+
+        tx.commit().await.map_err(db_error)?;
+        Ok(Some(version as u64))
+    }
+}
+
+fn db_error(e: sqlx::Error) -> ReachError {
+    ReachError::Internal(e.to_string())
+}
+
+fn entry_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<RegistryEntry, ReachError> {
+    Ok(RegistryEntry {
+        did: row.try_get("did").map_err(db_error)?,
+        endpoint: row.try_get("endpoint").map_err(db_error)?,
+        registered_at: row.try_get("registered_at").map_err(db_error)?,
+        expires_at: row.try_get("expires_at").map_err(db_error)?,
+        last_seen: row.try_get("last_seen").map_err(db_error)?,
+        handle: row.try_get("handle").map_err(db_error)?,
+        endpoints: crate::endpoints::from_json(row.try_get("endpoints").map_err(db_error)?)?,
+        version: row.try_get::<i64, _>("version").map_err(db_error)? as u64,
+        registered_by: row.try_get("registered_by").map_err(db_error)?,
+    })
+}
+
+#[async_trait]
+impl RegistryBackend for SqliteRegistry {
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        self.store(entry, precondition).await
+    }
+
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        let row = sqlx::query(
+            "SELECT did, endpoint, registered_at, expires_at, last_seen, handle, endpoints, version, NULL AS registered_by FROM agents WHERE did = ?1",
+        )
+        .bind(did)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        row.as_ref().map(entry_from_row).transpose()
+    }
+
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        let row = sqlx::query(
+            "SELECT did, endpoint, registered_at, expires_at, last_seen, handle, endpoints, version, NULL AS registered_by FROM agents WHERE handle = ?1",
+        )
+        .bind(handle)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        row.as_ref().map(entry_from_row).transpose()
+    }
+
+    async fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Result<Option<String>, ReachError> {
+        sqlx::query_scalar("SELECT did FROM agents WHERE endpoint = ?1 AND did <> ?2 AND expires_at > ?3 LIMIT 1")
+            .bind(endpoint)
+            .bind(did)
+            .bind(chrono::Utc::now().timestamp())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    async fn ping(&self) -> Result<(), ReachError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await.map_err(db_error)?;
+        Ok(())
+    }
+
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+        let result = sqlx::query("UPDATE agents SET last_seen = ?2 WHERE did = ?1")
+            .bind(did)
+            .bind(last_seen)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+        let result = sqlx::query("DELETE FROM agents WHERE did = ?1")
+            .bind(did)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let now = chrono::Utc::now().timestamp();
+        sqlx::query("DELETE FROM delegation_revocations WHERE expires_at <= ?1")
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        let purged = sqlx::query(
+            "DELETE FROM agents WHERE expires_at <= ?1
+             RETURNING did, endpoint, registered_at, expires_at, last_seen, handle, endpoints, version, NULL AS registered_by",
+        )
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        sqlx::query(
+            "DELETE FROM agent_history
+             WHERE did IN (SELECT did FROM agent_history GROUP BY did HAVING MAX(expires_at) <= ?1)
+                AND NOT EXISTS (SELECT 1 FROM agents WHERE agents.did = agent_history.did)",
+        )
+        .bind(now - crate::registry::HISTORY_RETENTION_SECS)
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        purged.iter().map(entry_from_row).collect()
+    }
+
+    async fn status_counts(&self, now: i64, idle_before: i64) -> Result<StatusCounts, ReachError> {
+        let row = sqlx::query(
+            "SELECT COUNT(*) FILTER (WHERE expires_at > ?1 AND last_seen >= ?2) AS online,
+                    COUNT(*) FILTER (WHERE expires_at > ?1 AND last_seen < ?2) AS idle,
+                    COUNT(*) FILTER (WHERE expires_at <= ?1) AS expired
+             FROM agents",
+        )
+        .bind(now)
+        .bind(idle_before)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        let count = |column: &str| row.try_get::<i64, _>(column).map(|n| n as usize).map_err(db_error);
+        Ok(StatusCounts {
+            online: count("online")?,
+            idle: count("idle")?,
+            expired: count("expired")?,
+        })
+    }
+
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        let row = sqlx::query(
+            "SELECT did, endpoint, registered_at, expires_at, last_seen, handle, endpoints, version, NULL AS registered_by FROM agents
+             WHERE expires_at > ?1 ORDER BY expires_at LIMIT 1",
+        )
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        row.as_ref().map(entry_from_row).transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let rows = sqlx::query(
+            "SELECT did, endpoint, registered_at, expires_at, last_seen, handle, endpoints, version, NULL AS registered_by FROM agents
+             WHERE expires_at > ?1 ORDER BY did",
+        )
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        rows.iter().map(entry_from_row).collect()
+    }
+
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let rows = sqlx::query(
+            "SELECT did, endpoint, registered_at, expires_at, last_seen, handle, endpoints, version, NULL AS registered_by FROM agents ORDER BY did",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        rows.iter().map(entry_from_row).collect()
+    }
+
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        let rows = sqlx::query(
+            "SELECT did, endpoint, registered_at, expires_at, registered_at AS last_seen, NULL AS handle, NULL AS endpoints, 0 AS version, registered_by FROM agent_history
+             WHERE did = ?1 ORDER BY id DESC",
+        )
+        .bind(did)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        rows.iter().map(entry_from_row).collect()
+    }
+
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+        sqlx::query(
+            "INSERT INTO delegation_revocations (subject, delegate, revoked_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (subject, delegate) DO UPDATE SET
+                revoked_at = MAX(delegation_revocations.revoked_at, excluded.revoked_at),
+                expires_at = MAX(delegation_revocations.expires_at, excluded.expires_at)",
+        )
+        .bind(subject)
+        .bind(delegate)
+        .bind(revoked_at)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+        sqlx::query_scalar(
+            "SELECT revoked_at FROM delegation_revocations
+             WHERE subject = ?1 AND delegate = ?2 AND expires_at > ?3",
+        )
+        .bind(subject)
+        .bind(delegate)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn backend_harness() {
+        let path = std::env::temp_dir().join(format!("agent-reach-test-{}.db", uuid::Uuid::new_v4()));
+
+        let registry = SqliteRegistry::open(&path, crate::registry::DEFAULT_HISTORY_LIMIT)
+            .await
+            .expect("open test database");
+        crate::backend::harness::run(&registry).await;
+
+        drop(registry);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Registry summary for `GET /stats`
+//!
+//! Counts come from cheap sources where the backend has them: the in-memory
+//! registry walks its shards without copying entries, the SQL backends run a
+//! single aggregate query, and recent registrations are kept as per-minute
+//! counters. The endpoint is public unless the server runs with
+//! `--private-stats`, in which case it needs the admin token.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use axum::{extract::State, http::HeaderMap, Json};
+use parking_lot::Mutex;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::error::ReachError;
+use crate::handlers::AppState;
+use crate::types::StatusCounts;
+
+/// Live entries without a handshake for this long count as idle (seconds)
+pub const IDLE_AFTER_SECS: i64 = 3600;
+
+/// Window covered by `registrations_last_hour` (seconds)
+const RECENT_WINDOW_SECS: i64 = 3600;
+
+/// Counters kept while the server runs
+pub struct Stats {
+    started: Instant,
+    /// Whether `GET /stats` needs the admin token
+    private: bool,
+    /// Registrations per minute (minute, count), oldest first
+    recent: Mutex<VecDeque<(i64, u64)>>,
+    /// `--capacity`, if set
+    capacity: Option<usize>,
+}
+
+impl Stats {
+    pub fn new(private: bool) -> Self {
+        Self {
+            started: Instant::now(),
+            private,
+            recent: Mutex::new(VecDeque::new()),
+            capacity: None,
+        }
+    }
+
+    /// Report the registry's capacity alongside its size
+    pub fn with_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Count a successful registration made at `now`
+    pub fn record_registration(&self, now: i64) {
+        let minute = now.div_euclid(60);
+        let mut recent = self.recent.lock();
+        match recent.back_mut() {
+            Some((last, count)) if *last == minute => *count += 1,
+            _ => recent.push_back((minute, 1)),
+        }
+        prune(&mut recent, now);
+    }
+
+    /// Registrations in the hour before `now`, to the minute
+    pub fn registrations_since_hour_ago(&self, now: i64) -> u64 {
+        let mut recent = self.recent.lock();
+        prune(&mut recent, now);
+        recent.iter().map(|(_, count)| count).sum()
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+fn prune(recent: &mut VecDeque<(i64, u64)>, now: i64) {
+    let oldest = (now - RECENT_WINDOW_SECS).div_euclid(60);
+    while recent.front().is_some_and(|(minute, _)| *minute <= oldest) {
+        recent.pop_front();
+    }
+}
+
+/// Registry summary
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatsResponse {
+    /// Stored entries, including expired ones not yet purged
+    pub total_entries: usize,
+    pub entries: StatusCounts,
+    /// Unexpired entries, which count against `capacity`
+    pub live_entries: usize,
+    /// Most live entries the registry accepts; absent when unlimited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<usize>,
+    /// Successful registrations in the last hour
+    pub registrations_last_hour: u64,
+    /// Unexpired handshake sessions; absent when the session store can't
+    /// count them cheaply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_sessions: Option<usize>,
+    /// Challenges issued and not yet answered; absent when the session
+    /// store can't count them cheaply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_challenges: Option<usize>,
+    /// Seconds since the server started
+    pub uptime_secs: u64,
+}
+
+/// GET /stats
+///
+/// Entry counts by status, recent registrations, handshake activity and
+/// uptime. Public unless the server runs with `--private-stats`.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "lookup",
+    responses(
+        (status = 200, description = "Registry summary", body = StatsResponse),
+        (status = 403, description = "Stats are private and the admin token is missing or wrong", body = crate::openapi::ErrorResponse),
+    ),
+    security((), ("admin" = []))
+)]
+pub async fn stats(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<StatsResponse>, ReachError> {
+    if state.stats.private {
+        crate::admin::require_admin(&headers, &state)?;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let entries = state.registry.status_counts(now, now - IDLE_AFTER_SECS).await?;
+    let handshakes = state.handshake.store.counts(now);
+
+    Ok(Json(StatsResponse {
+        total_entries: entries.online + entries.idle + entries.expired,
+        live_entries: entries.online + entries.idle,
+        capacity: state.stats.capacity,
+        entries,
+        registrations_last_hour: state.stats.registrations_since_hour_ago(now),
+        active_sessions: handshakes.map(|h| h.active_sessions),
+        pending_challenges: handshakes.map(|h| h.pending_challenges),
+        uptime_secs: state.stats.started.elapsed().as_secs(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::header;
+
+    use super::*;
+    use crate::types::RegistryEntry;
+
+    fn state(private: bool) -> AppState {
+        AppState {
+            admin_token: Some(Arc::from("admin-secret")),
+            stats: Arc::new(Stats::new(private)),
+            ..AppState::for_tests()
+        }
+    }
+
+    fn entry(did: &str, expires_in: i64, seen_ago: i64) -> RegistryEntry {
+        let now = chrono::Utc::now().timestamp();
+        RegistryEntry::new(did, "wss://agent", now - seen_ago, now + expires_in)
+    }
+
+    #[tokio::test]
+    async fn stats_count_entries_by_status() {
+        let state = state(false);
+        state.registry.register(entry("did:key:online", 3600, 0)).await.unwrap();
+        state.registry.register(entry("did:key:idle", 7 * 86400, 2 * IDLE_AFTER_SECS)).await.unwrap();
+        state.registry.register(entry("did:key:expired", -60, 600)).await.unwrap();
+
+        let Json(stats) = stats(State(state), HeaderMap::new()).await.unwrap();
+        assert_eq!(stats.total_entries, 3);
+        assert_eq!(stats.live_entries, 2);
+        assert_eq!(stats.capacity, None);
+        assert_eq!(stats.entries, StatusCounts { online: 1, idle: 1, expired: 1 });
+        assert_eq!(stats.active_sessions, Some(0));
+        assert_eq!(stats.pending_challenges, Some(0));
+    }
+
+    #[tokio::test]
+    async fn private_stats_need_the_admin_token() {
+        let state = state(true);
+        let denied = stats(State(state.clone()), HeaderMap::new()).await;
+        assert!(matches!(denied, Err(ReachError::AdminForbidden)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer admin-secret".parse().unwrap());
+        assert!(stats(State(state), headers).await.is_ok());
+    }
+
+    #[test]
+    fn registrations_age_out_after_an_hour() {
+        let stats = Stats::default();
+        let start = 1_700_000_000;
+        stats.record_registration(start);
+        stats.record_registration(start + 30);
+        stats.record_registration(start + 1800);
+
+        assert_eq!(stats.registrations_since_hour_ago(start + 1800), 3);
+        assert_eq!(stats.registrations_since_hour_ago(start + 3700), 1);
+        assert_eq!(stats.registrations_since_hour_ago(start + 7200), 0);
+    }
+}
@@ -0,0 +1,625 @@
+//! Anti-entropy replication between registry instances
+//!
+//! With `--sync-peers`, every write made through this server is versioned
+//! and the server regularly pulls from each peer: it fetches the peer's
+//! digest (`GET /sync/digest`, one version per DID), asks for the DIDs where
+//! the peer's version is newer (`POST /sync/entries`) and stores what comes
+//! back. Servers that list each other converge on the same registrations.
+//!
+//! Versions order writes by `registered_at` (or the time of a
+//! deregistration), then by a revision that every write to the DID bumps,
+//! so the last writer wins. A deregistration leaves a tombstone for
+//! `--sync-tombstone-ttl` so that it propagates instead of being undone by a
+//! peer that still has the entry. Versions are kept in memory: after a
+//! restart, stored entries start again at revision 0 and tombstones are
+//! forgotten. `last_seen` is not replicated.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{extract::State, Json};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::backend::{Precondition, RegistryBackend};
+use crate::error::ReachError;
+use crate::handlers::AppState;
+use crate::types::{RegistryEntry, StatusCounts};
+
+/// Default time a deregistration is remembered (seconds)
+pub const DEFAULT_TOMBSTONE_TTL_SECS: u64 = 86400;
+
+/// Most DIDs asked for in one `POST /sync/entries`
+pub const MAX_BATCH: usize = 500;
+
+/// Position of a DID's latest write in the replicated history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Version {
+    /// `registered_at` of the entry, or when it was deregistered
+    pub at: i64,
+    /// Writes to the DID so far, on any replica
+    pub revision: u64,
+    /// The DID was deregistered
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// Fingerprint of the entry, so replicas order same-second writes alike
+    pub hash: u64,
+    /// When the entry expires, or the tombstone is dropped
+    pub expires_at: i64,
+}
+
+impl Version {
+    fn of(entry: &RegistryEntry, revision: u64) -> Self {
+        let json = serde_json::to_vec(entry).unwrap_or_default();
+        let digest = Sha256::digest(&json);
+        Self {
+            at: entry.registered_at,
+            revision,
+            deleted: false,
+            hash: u64::from_be_bytes(digest[..8].try_into().expect("8 bytes")),
+            expires_at: entry.expires_at,
+        }
+    }
+
+    fn tombstone(at: i64, revision: u64, ttl: i64) -> Self {
+        Self {
+            at,
+            revision,
+            deleted: true,
+            hash: 0,
+            expires_at: at + ttl,
+        }
+    }
+
+    #[cfg(feature = "federation")]
+    fn is_newer_than(&self, other: &Version) -> bool {
+        (self.at, self.revision, self.deleted, self.hash) > (other.at, other.revision, other.deleted, other.hash)
+    }
+}
+
+/// Versions every write and applies newer writes pulled from peers
+///
+/// Local writes and applied ones are serialized, so a version always
+/// describes what storage holds. Lookups pass straight through.
+pub struct Replica {
+    inner: Arc<dyn RegistryBackend>,
+    /// Latest version per DID, including tombstones
+    versions: RwLock<HashMap<String, Version>>,
+    tombstone_ttl: i64,
+    writes: tokio::sync::Mutex<()>,
+}
+
+impl Replica {
+    pub fn new(inner: Arc<dyn RegistryBackend>, tombstone_ttl: Duration) -> Self {
+        Self {
+            inner,
+            versions: RwLock::default(),
+            tombstone_ttl: tombstone_ttl.as_secs() as i64,
+            writes: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Version the entries already in storage, returning how many there are
+    pub async fn load(&self) -> Result<usize, ReachError> {
+        let entries = self.inner.all_entries().await?;
+        let mut versions = self.versions.write();
+        for entry in &entries {
+            versions.insert(entry.did.clone(), Version::of(entry, 0));
+        }
+        Ok(entries.len())
+    }
+
+    fn next_revision(&self, did: &str) -> u64 {
+        self.versions.read().get(did).map_or(0, |v| v.revision) + 1
+    }
+
+    fn record(&self, did: &str, version: Version) {
+        self.versions.write().insert(did.to_string(), version);
+    }
+
+    /// Versions of live entries and tombstones
+    pub fn digest(&self, now: i64) -> HashMap<String, Version> {
+        self.versions
+            .read()
+            .iter()
+            .filter(|(_, version)| version.expires_at > now)
+            .map(|(did, version)| (did.clone(), *version))
+            .collect()
+    }
+
+    /// DIDs whose version in a peer's digest is newer than ours
+    #[cfg(feature = "federation")]
+    pub fn wanted(&self, digest: &HashMap<String, Version>, now: i64) -> Vec<String> {
+        let versions = self.versions.read();
+        digest
+            .iter()
+            .filter(|(_, theirs)| theirs.expires_at > now)
+            .filter(|(did, theirs)| versions.get(*did).is_none_or(|ours| theirs.is_newer_than(ours)))
+            .map(|(did, _)| did.clone())
+            .collect()
+    }
+
+    /// The current write for each of `dids` that has a live version
+    pub async fn entries(&self, dids: &[String]) -> Result<Vec<SyncEntry>, ReachError> {
+        let now = chrono::Utc::now().timestamp();
+        let mut entries = Vec::new();
+        for did in dids {
+            let Some(version) = self.versions.read().get(did).copied() else { continue };
+            if version.expires_at <= now {
+                continue;
+            }
+            let entry = if version.deleted {
+                None
+            } else {
+                match self.inner.lookup(did).await? {
+                    Some(entry) => Some(entry),
+                    None => continue,
+                }
+            };
+            entries.push(SyncEntry { did: did.clone(), version, entry });
+        }
+        Ok(entries)
+    }
+
+    /// Store the writes that are newer than ours, returning how many. A
+    /// write that can't be stored is logged and skipped, so it doesn't hold
+    /// up the rest.
+    #[cfg(feature = "federation")]
+    pub async fn apply(&self, entries: Vec<SyncEntry>) -> usize {
+        let _write = self.writes.lock().await;
+        let now = chrono::Utc::now().timestamp();
+        let mut applied = 0;
+        for SyncEntry { did, version, entry } in entries {
+            let ours = self.versions.read().get(&did).copied();
+            if version.expires_at <= now || ours.is_some_and(|ours| !version.is_newer_than(&ours)) {
+                continue;
+            }
+            let stored = match entry {
+                _ if version.deleted => self.inner.deregister(&did).await.map(|_| ()),
+                Some(entry) if entry.did == did => self.inner.register(entry).await,
+                _ => {
+                    tracing::warn!(did = %did, "Skipping a synced write without an entry for its DID");
+                    continue;
+                }
+            };
+            if let Err(e) = stored {
+                tracing::warn!(did = %did, error = %e, "Skipping a synced write that couldn't be stored");
+                continue;
+            }
+            self.record(&did, version);
+            applied += 1;
+        }
+        applied
+    }
+}
+
+#[async_trait]
+impl RegistryBackend for Replica {
+    async fn register_when(&self, entry: RegistryEntry, precondition: Precondition) -> Result<Option<u64>, ReachError> {
+        let _write = self.writes.lock().await;
+        let stored = self.inner.register_when(entry.clone(), precondition).await?;
+        if stored.is_some() {
+            self.record(&entry.did, Version::of(&entry, self.next_revision(&entry.did)));
+        }
+        Ok(stored)
+    }
+
+    async fn lookup(&self, did: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.lookup(did).await
+    }
+
+    async fn resolve_handle(&self, handle: &str) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.resolve_handle(handle).await
+    }
+
+    async fn endpoint_claimant(&self, endpoint: &str, did: &str) -> Result<Option<String>, ReachError> {
+        self.inner.endpoint_claimant(endpoint, did).await
+    }
+
+    fn might_contain(&self, did: &str) -> bool {
+        self.inner.might_contain(did)
+    }
+
+    async fn touch(&self, did: &str, last_seen: i64) -> Result<bool, ReachError> {
+        self.inner.touch(did, last_seen).await
+    }
+
+    async fn deregister(&self, did: &str) -> Result<bool, ReachError> {
+        let _write = self.writes.lock().await;
+        let removed = self.inner.deregister(did).await?;
+        if removed {
+            let now = chrono::Utc::now().timestamp();
+            self.record(did, Version::tombstone(now, self.next_revision(did), self.tombstone_ttl));
+        }
+        Ok(removed)
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        let _write = self.writes.lock().await;
+        let removed = self.inner.purge_expired().await?;
+        let now = chrono::Utc::now().timestamp();
+        self.versions.write().retain(|_, version| version.expires_at > now);
+        Ok(removed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+
+    async fn ping(&self) -> Result<(), ReachError> {
+        self.inner.ping().await
+    }
+
+    async fn status_counts(&self, now: i64, idle_before: i64) -> Result<StatusCounts, ReachError> {
+        self.inner.status_counts(now, idle_before).await
+    }
+
+    async fn check_admission(&self, did: &str, now: i64) -> Result<(), ReachError> {
+        self.inner.check_admission(did, now).await
+    }
+
+    async fn live_count(&self, now: i64) -> Result<usize, ReachError> {
+        self.inner.live_count(now).await
+    }
+
+    async fn soonest_expiring(&self, now: i64) -> Result<Option<RegistryEntry>, ReachError> {
+        self.inner.soonest_expiring(now).await
+    }
+
+    async fn list(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.list().await
+    }
+
+    async fn all_entries(&self) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.all_entries().await
+    }
+
+    async fn history(&self, did: &str) -> Result<Vec<RegistryEntry>, ReachError> {
+        self.inner.history(did).await
+    }
+
+    async fn revoke_certificates(&self, subject: &str, delegate: &str, revoked_at: i64, expires_at: i64) -> Result<(), ReachError> {
+        self.inner.revoke_certificates(subject, delegate, revoked_at, expires_at).await
+    }
+
+    async fn certificates_revoked_at(&self, subject: &str, delegate: &str, now: i64) -> Result<Option<i64>, ReachError> {
+        self.inner.certificates_revoked_at(subject, delegate, now).await
+    }
+}
+
+/// Versions a replica holds
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SyncDigest {
+    /// Latest version per DID, for live entries and tombstones
+    pub versions: HashMap<String, Version>,
+}
+
+/// Request body for `POST /sync/entries`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EntriesRequest {
+    /// DIDs to fetch (at most 500)
+    pub dids: Vec<String>,
+}
+
+/// A DID's latest write
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SyncEntry {
+    pub did: String,
+    pub version: Version,
+    /// The stored entry; absent for a deregistration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<RegistryEntry>,
+}
+
+/// Latest writes for the requested DIDs
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EntriesResponse {
+    /// DIDs without a live version are left out
+    pub entries: Vec<SyncEntry>,
+}
+
+fn replica(state: &AppState) -> Result<&Replica, ReachError> {
+    state.replica.as_deref().ok_or(ReachError::NotFound)
+}
+
+/// GET /sync/digest
+///
+/// The version of every DID this replica has written or deleted. Only
+/// served with `--sync-peers`.
+#[utoipa::path(
+    get,
+    path = "/sync/digest",
+    tag = "sync",
+    responses((status = 200, description = "Versions by DID", body = SyncDigest))
+)]
+pub async fn digest(State(state): State<AppState>) -> Result<Json<SyncDigest>, ReachError> {
+    let now = chrono::Utc::now().timestamp();
+    Ok(Json(SyncDigest { versions: replica(&state)?.digest(now) }))
+}
+
+/// POST /sync/entries
+///
+/// The latest write for each requested DID: the entry, or a tombstone for a
+/// deregistration. Only served with `--sync-peers`.
+#[utoipa::path(
+    post,
+    path = "/sync/entries",
+    tag = "sync",
+    request_body = EntriesRequest,
+    responses(
+        (status = 200, description = "Latest writes", body = EntriesResponse),
+        (status = 400, description = "Too many DIDs requested", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn entries(
+    State(state): State<AppState>,
+    Json(request): Json<EntriesRequest>,
+) -> Result<Json<EntriesResponse>, ReachError> {
+    if request.dids.len() > MAX_BATCH {
+        return Err(ReachError::InvalidRequest(format!("at most {} DIDs per request", MAX_BATCH)));
+    }
+    let entries = replica(&state)?.entries(&request.dids).await?;
+    Ok(Json(EntriesResponse { entries }))
+}
+
+/// Pull from every peer each `interval`, forever, beating `sync` after
+/// each round in which some peer answered
+#[cfg(feature = "federation")]
+pub async fn run(replica: Arc<Replica>, peers: Vec<String>, interval: Duration, heartbeats: Arc<crate::health::Heartbeats>) {
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to build sync client");
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if pull_all(&replica, &client, &peers).await {
+            heartbeats.beat("sync", interval);
+        }
+    }
+}
+
+/// Pull from each of `peers` once, returning whether any of them answered
+#[cfg(feature = "federation")]
+pub async fn pull_all(replica: &Replica, client: &reqwest::Client, peers: &[String]) -> bool {
+    let mut synced = false;
+    for peer in peers {
+        match pull(replica, client, peer).await {
+            Ok(0) => synced = true,
+            Ok(applied) => {
+                synced = true;
+                tracing::debug!(peer = %peer, applied, "Pulled changes from peer");
+            }
+            Err(e) => tracing::warn!(peer = %peer, error = %e, "Failed to sync with peer"),
+        }
+    }
+    synced
+}
+
+/// Fetch and store the writes `peer` (a base URL) has that are newer than
+/// ours, returning how many were applied
+#[cfg(feature = "federation")]
+pub async fn pull(replica: &Replica, client: &reqwest::Client, peer: &str) -> anyhow::Result<usize> {
+    let peer = peer.trim_end_matches('/');
+    let digest: SyncDigest = client
+        .get(format!("{}{}/sync/digest", peer, crate::api_version::PREFIX))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let wanted = replica.wanted(&digest.versions, chrono::Utc::now().timestamp());
+    let mut applied = 0;
+    for dids in wanted.chunks(MAX_BATCH) {
+        let response: EntriesResponse = client
+            .post(format!("{}{}/sync/entries", peer, crate::api_version::PREFIX))
+            .json(&EntriesRequest { dids: dids.to_vec() })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        applied += replica.apply(response.entries).await;
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+
+    #[cfg(feature = "federation")]
+    fn entry(did: &str, endpoint: &str, registered_at: i64) -> RegistryEntry {
+        RegistryEntry::new(did, endpoint, registered_at, registered_at + 3600)
+    }
+
+    fn replica() -> Replica {
+        Replica::new(Arc::new(Registry::new()), Duration::from_secs(DEFAULT_TOMBSTONE_TTL_SECS))
+    }
+
+    #[tokio::test]
+    async fn backend_harness() {
+        crate::backend::harness::run(&replica()).await;
+    }
+
+    #[tokio::test]
+    async fn a_full_batch_fits_the_body_limit() {
+        use tower::ServiceExt;
+
+        let replica = Arc::new(replica());
+        let state = AppState {
+            registry: replica.clone(),
+            replica: Some(replica),
+            ..AppState::for_tests()
+        };
+        let app = crate::app(state, &crate::cors::CorsConfig::default(), crate::limits::DEFAULT_MAX_BODY_BYTES);
+        let did = |i: usize| format!("did:key:{i:0>width$}", width = crate::limits::MAX_DID_LEN - 8);
+        let post = |dids: Vec<String>| {
+            let body = serde_json::to_vec(&EntriesRequest { dids }).unwrap();
+            axum::http::Request::post("/v1/sync/entries")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body))
+                .unwrap()
+        };
+
+        let full = (0..MAX_BATCH).map(did).collect();
+        let response = app.clone().oneshot(post(full)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let over = (0..=MAX_BATCH).map(did).collect();
+        let response = app.oneshot(post(over)).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(feature = "federation")]
+    #[test]
+    fn later_writes_win() {
+        let now = chrono::Utc::now().timestamp();
+        let first = Version::of(&entry("did:key:a", "wss://one", now), 1);
+        let later = Version::of(&entry("did:key:a", "wss://two", now + 1), 1);
+        let rewritten = Version::of(&entry("did:key:a", "wss://two", now), 2);
+        let deleted = Version::tombstone(now, 1, 60);
+
+        assert!(later.is_newer_than(&first));
+        assert!(rewritten.is_newer_than(&first));
+        assert!(deleted.is_newer_than(&first));
+        assert!(!first.is_newer_than(&first));
+    }
+
+    #[cfg(feature = "federation")]
+    #[tokio::test]
+    async fn stale_writes_are_not_applied() {
+        let now = chrono::Utc::now().timestamp();
+        let replica = replica();
+        replica.register(entry("did:key:a", "wss://new", now)).await.unwrap();
+
+        let stale = entry("did:key:a", "wss://old", now - 60);
+        let applied = replica
+            .apply(vec![SyncEntry {
+                did: "did:key:a".to_string(),
+                version: Version::of(&stale, 5),
+                entry: Some(stale),
+            }])
+            .await;
+        assert_eq!(applied, 0);
+        assert_eq!(replica.lookup("did:key:a").await.unwrap().unwrap().endpoint, "wss://new");
+    }
+
+    #[cfg(feature = "federation")]
+    #[tokio::test]
+    async fn writes_that_cant_be_stored_are_skipped() {
+        let now = chrono::Utc::now().timestamp();
+        let replica = replica();
+        let mut holder = entry("did:key:a", "wss://a", now);
+        holder.handle = Some("alice@example.com".to_string());
+        replica.register(holder).await.unwrap();
+
+        // The handle is taken here, so storing b fails; c still goes in
+        let mut taken = entry("did:key:b", "wss://b", now);
+        taken.handle = Some("alice@example.com".to_string());
+        let fine = entry("did:key:c", "wss://c", now);
+        let writes = [taken, fine]
+            .into_iter()
+            .map(|entry| SyncEntry {
+                did: entry.did.clone(),
+                version: Version::of(&entry, 1),
+                entry: Some(entry),
+            })
+            .chain([SyncEntry {
+                did: "did:key:d".to_string(),
+                version: Version::of(&entry("did:key:d", "wss://d", now), 1),
+                entry: Some(entry("did:key:e", "wss://e", now)),
+            }])
+            .collect();
+
+        assert_eq!(replica.apply(writes).await, 1);
+        assert!(replica.lookup("did:key:b").await.unwrap().is_none());
+        assert_eq!(replica.lookup("did:key:c").await.unwrap().unwrap().endpoint, "wss://c");
+        assert!(replica.lookup("did:key:e").await.unwrap().is_none());
+        let wanted = replica.wanted(&[("did:key:b".to_string(), Version::of(&entry("did:key:b", "wss://b", now), 1))].into(), now);
+        assert_eq!(wanted, ["did:key:b"], "a skipped write is asked for again");
+    }
+
+    /// A replica served over HTTP, and its URL
+    #[cfg(feature = "federation")]
+    async fn node() -> (Arc<Replica>, String) {
+        let replica = Arc::new(replica());
+        let state = AppState {
+            registry: replica.clone(),
+            replica: Some(replica.clone()),
+            ..AppState::for_tests()
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = crate::app(state, &crate::cors::CorsConfig::default(), crate::limits::DEFAULT_MAX_BODY_BYTES);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (replica, url)
+    }
+
+    #[cfg(feature = "federation")]
+    #[tokio::test]
+    async fn a_round_counts_only_when_a_peer_answers() {
+        let client = reqwest::Client::new();
+        let unreachable = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let (_, peer) = node().await;
+        let local = replica();
+
+        assert!(!pull_all(&local, &client, std::slice::from_ref(&unreachable)).await);
+        assert!(pull_all(&local, &client, &[unreachable, peer]).await);
+    }
+
+    #[cfg(feature = "federation")]
+    #[tokio::test]
+    async fn partitioned_replicas_converge() {
+        async fn endpoints(replica: &Replica) -> Vec<(String, String)> {
+            let mut entries: Vec<_> =
+                replica.list().await.unwrap().into_iter().map(|e| (e.did, e.endpoint)).collect();
+            entries.sort();
+            entries
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let client = reqwest::Client::new();
+        let (a, a_url) = node().await;
+        let (b, b_url) = node().await;
+
+        // Both start with the same entry
+        a.register(entry("did:key:shared", "wss://shared", now - 100)).await.unwrap();
+        assert_eq!(pull(&b, &client, &a_url).await.unwrap(), 1);
+
+        // Partitioned writes on each side, including the same DID twice
+        a.deregister("did:key:shared").await.unwrap();
+        a.register(entry("did:key:a", "wss://a", now)).await.unwrap();
+        b.register(entry("did:key:b", "wss://b", now)).await.unwrap();
+        a.register(entry("did:key:both", "wss://from-a", now - 10)).await.unwrap();
+        b.register(entry("did:key:both", "wss://from-b", now)).await.unwrap();
+
+        pull(&a, &client, &b_url).await.unwrap();
+        pull(&b, &client, &a_url).await.unwrap();
+
+        let expected = vec![
+            ("did:key:a".to_string(), "wss://a".to_string()),
+            ("did:key:b".to_string(), "wss://b".to_string()),
+            ("did:key:both".to_string(), "wss://from-b".to_string()),
+        ];
+        assert_eq!(endpoints(&a).await, expected);
+        assert_eq!(endpoints(&b).await, expected);
+        assert_eq!(a.digest(now), b.digest(now));
+
+        // Nothing left to exchange
+        assert_eq!(pull(&a, &client, &b_url).await.unwrap(), 0);
+        assert_eq!(pull(&b, &client, &a_url).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,276 @@
+//! OpenTelemetry trace and metric export (the `otel` feature)
+//!
+//! Enabled at runtime by `OTEL_EXPORTER_OTLP_ENDPOINT`; the standard
+//! `OTEL_*` variables configure the exporter and resource. Without it no
+//! provider is installed and every recording function returns after a
+//! single check.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use axum::{extract::{MatchedPath, Request, State}, middleware::Next, response::Response};
+use opentelemetry::{
+    metrics::{Counter, Histogram, MeterProvider, ObservableGauge},
+    KeyValue,
+};
+use opentelemetry_otlp::{MetricExporter, SpanExporter};
+use opentelemetry_sdk::{metrics::SdkMeterProvider, trace::SdkTracerProvider, Resource};
+
+/// Env var that switches export on
+pub const ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Service name reported when `OTEL_SERVICE_NAME` is unset
+pub const SERVICE_NAME: &str = "agent-reach-server";
+
+/// Instruments, set once when metric export is enabled
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Last measured registry size, reported by the `reach.registry.size` gauge
+static REGISTRY_SIZE: AtomicU64 = AtomicU64::new(0);
+
+/// `--capacity`, reported by the `reach.registry.capacity` gauge; 0 when unset
+static REGISTRY_CAPACITY: AtomicU64 = AtomicU64::new(0);
+
+struct Metrics {
+    request_duration: Histogram<f64>,
+    handler_duration: Histogram<f64>,
+    errors: Counter<u64>,
+    handshake_duration: Histogram<f64>,
+    failed_proofs: Counter<u64>,
+    lockouts: Counter<u64>,
+    _registry_size: ObservableGauge<u64>,
+    _registry_capacity: ObservableGauge<u64>,
+}
+
+fn enabled() -> bool {
+    std::env::var_os(ENDPOINT_ENV).is_some()
+}
+
+fn resource() -> Resource {
+    let mut resource = Resource::builder()
+        .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")));
+    if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name(SERVICE_NAME);
+    }
+    resource.build()
+}
+
+/// Build a batching OTLP (gRPC) tracer provider, or `None` when no endpoint
+/// is configured
+pub fn tracer_provider() -> anyhow::Result<Option<SdkTracerProvider>> {
+    if !enabled() {
+        return Ok(None);
+    }
+
+    let exporter = SpanExporter::builder().with_tonic().build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource())
+        .build();
+    Ok(Some(provider))
+}
+
+/// Build a periodic OTLP (gRPC) meter provider and create the server's
+/// instruments, or `None` when no endpoint is configured
+pub fn meter_provider() -> anyhow::Result<Option<SdkMeterProvider>> {
+    if !enabled() {
+        return Ok(None);
+    }
+
+    let exporter = MetricExporter::builder().with_tonic().build()?;
+    let provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(exporter)
+        .with_resource(resource())
+        .build();
+
+    let _ = METRICS.set(instruments(&provider));
+    Ok(Some(provider))
+}
+
+/// Create the server's instruments on `provider`
+fn instruments(provider: &SdkMeterProvider) -> Metrics {
+    let meter = provider.meter(SERVICE_NAME);
+    Metrics {
+        request_duration: meter
+            .f64_histogram("http.server.request.duration")
+            .with_unit("s")
+            .with_description("Duration of HTTP requests by route and status")
+            .build(),
+        handler_duration: meter
+            .f64_histogram("reach.handler.duration")
+            .with_unit("s")
+            .with_description("Wall time of the handshake, register and lookup handlers by route and outcome")
+            .build(),
+        errors: meter
+            .u64_counter("reach.errors")
+            .with_description("Requests that ended in a 4xx or 5xx status")
+            .build(),
+        handshake_duration: meter
+            .f64_histogram("reach.handshake.duration")
+            .with_unit("s")
+            .with_description("Time from issuing a challenge to accepting its proof")
+            .build(),
+        failed_proofs: meter
+            .u64_counter("reach.proof.failures")
+            .with_description("Proofs that failed verification")
+            .build(),
+        lockouts: meter
+            .u64_counter("reach.proof.lockouts")
+            .with_description("Clients locked out of /proof after repeated failures")
+            .build(),
+        _registry_size: meter
+            .u64_observable_gauge("reach.registry.size")
+            .with_description("Stored registrations, including expired ones not yet purged")
+            .with_callback(|observer| observer.observe(REGISTRY_SIZE.load(Ordering::Relaxed), &[]))
+            .build(),
+        _registry_capacity: meter
+            .u64_observable_gauge("reach.registry.capacity")
+            .with_description("Most live registrations accepted, when a capacity is set")
+            .with_callback(|observer| match REGISTRY_CAPACITY.load(Ordering::Relaxed) {
+                0 => {}
+                capacity => observer.observe(capacity, &[]),
+            })
+            .build(),
+    }
+}
+
+/// Middleware recording request duration and errors per route
+pub async fn record_request(request: Request, next: Next) -> Response {
+    let Some(metrics) = METRICS.get() else {
+        return next.run(request).await;
+    };
+
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let status = response.status();
+    let attributes = [
+        KeyValue::new("http.request.method", method),
+        KeyValue::new("http.route", route),
+        KeyValue::new("http.response.status_code", i64::from(status.as_u16())),
+    ];
+    metrics.request_duration.record(started.elapsed().as_secs_f64(), &attributes);
+    if status.is_client_error() || status.is_server_error() {
+        metrics.errors.add(1, &attributes);
+    }
+    response
+}
+
+/// Middleware timing one handler, wrapped directly around it so the time
+/// covers body extraction, storage locks and `did:web` resolution but not
+/// the layers outside it
+pub async fn record_handler(State(route): State<&'static str>, request: Request, next: Next) -> Response {
+    let Some(metrics) = METRICS.get() else {
+        return next.run(request).await;
+    };
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let status = response.status();
+    let outcome = if status.is_client_error() || status.is_server_error() { "error" } else { "ok" };
+    let attributes = [KeyValue::new("route", route), KeyValue::new("outcome", outcome)];
+    metrics.handler_duration.record(started.elapsed().as_secs_f64(), &attributes);
+    response
+}
+
+/// Record a completed handshake, given when its challenge was issued (ms)
+pub fn record_handshake(challenge_timestamp_ms: i64) {
+    if let Some(metrics) = METRICS.get() {
+        let elapsed_ms = chrono::Utc::now().timestamp_millis() - challenge_timestamp_ms;
+        metrics.handshake_duration.record(elapsed_ms.max(0) as f64 / 1000.0, &[]);
+    }
+}
+
+/// Record a proof that failed verification, and whether it locked the
+/// client out
+pub fn record_failed_proof(locked_out: bool) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.failed_proofs.add(1, &[]);
+        if locked_out {
+            metrics.lockouts.add(1, &[]);
+        }
+    }
+}
+
+/// Whether the registry size should be measured for export
+pub fn wants_registry_size() -> bool {
+    METRICS.get().is_some()
+}
+
+pub fn set_registry_size(size: usize) {
+    REGISTRY_SIZE.store(size as u64, Ordering::Relaxed);
+}
+
+pub fn set_registry_capacity(capacity: usize) {
+    REGISTRY_CAPACITY.store(capacity as u64, Ordering::Relaxed);
+}
+
+/// Export to memory instead of OTLP, for tests, and return the provider
+/// and exporter. Every test shares them, so counts include other tests'
+/// recordings.
+#[cfg(test)]
+pub(crate) fn test_export() -> &'static (SdkMeterProvider, opentelemetry_sdk::metrics::InMemoryMetricExporter) {
+    static EXPORT: OnceLock<(SdkMeterProvider, opentelemetry_sdk::metrics::InMemoryMetricExporter)> = OnceLock::new();
+    EXPORT.get_or_init(|| {
+        let exporter = opentelemetry_sdk::metrics::InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder().with_periodic_exporter(exporter.clone()).build();
+        assert!(METRICS.set(instruments(&provider)).is_ok(), "metrics were already installed");
+        (provider, exporter)
+    })
+}
+
+/// Recordings of the histogram `name` so far, as each data point's
+/// attributes and count
+#[cfg(test)]
+pub(crate) fn histogram_counts(name: &str) -> Vec<(Vec<(String, String)>, u64)> {
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+
+    let (provider, exporter) = test_export();
+    provider.force_flush().unwrap();
+    let exported = exporter.get_finished_metrics().unwrap();
+    // Cumulative temporality, so the latest export holds every recording
+    let Some(AggregatedMetrics::F64(MetricData::Histogram(histogram))) = exported
+        .iter()
+        .flat_map(|resource| resource.scope_metrics())
+        .flat_map(|scope| scope.metrics())
+        .filter(|metric| metric.name() == name)
+        .map(|metric| metric.data())
+        .last()
+    else {
+        return Vec::new();
+    };
+    histogram
+        .data_points()
+        .map(|point| {
+            let attributes = point
+                .attributes()
+                .map(|kv| (kv.key.to_string(), kv.value.as_str().into_owned()))
+                .collect();
+            (attributes, point.count())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn handshake_durations_are_recorded() {
+        test_export();
+        let before: u64 = histogram_counts("reach.handshake.duration").iter().map(|(_, count)| count).sum();
+
+        record_handshake(chrono::Utc::now().timestamp_millis() - 1500);
+
+        let after: u64 = histogram_counts("reach.handshake.duration").iter().map(|(_, count)| count).sum();
+        assert!(after > before, "{before} -> {after}");
+    }
+}
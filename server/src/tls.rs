@@ -0,0 +1,221 @@
+//! Native TLS termination with rustls
+//!
+//! The certificate and key are validated at startup and polled for changes
+//! afterwards, so renewals (e.g. by certbot) are picked up without a restart.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::State,
+    http::{header, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use rustls::ServerConfig;
+
+use crate::error::ReachError;
+use crate::listen::HttpConfig;
+use crate::PlainHttp;
+
+/// How often the certificate and key files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long in-flight requests get to finish on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// A certificate/key pair and the live rustls configuration built from it
+pub struct Tls {
+    cert: PathBuf,
+    key: PathBuf,
+    config: RustlsConfig,
+}
+
+impl Tls {
+    /// Load and validate the PEM files; fails on unreadable files or a key
+    /// that does not match the certificate
+    pub fn load(cert: &Path, key: &Path) -> anyhow::Result<Self> {
+        let config = server_config(cert, key)?;
+        Ok(Self {
+            cert: cert.to_path_buf(),
+            key: key.to_path_buf(),
+            config: RustlsConfig::from_config(Arc::new(config)),
+        })
+    }
+
+    /// Serve `app` over HTTPS on `addr`, plus an optional plain HTTP listener
+    /// that redirects or rejects, until Ctrl-C or SIGTERM
+    ///
+    /// HTTP/2 is offered through ALPN.
+    pub async fn serve(
+        self,
+        app: Router,
+        addr: SocketAddr,
+        plain: Option<(SocketAddr, PlainHttp)>,
+        http: HttpConfig,
+    ) -> anyhow::Result<()> {
+        let handle = Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                crate::shutdown_signal().await;
+                handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+            }
+        });
+        let config = self.config.clone();
+        tokio::spawn(self.watch());
+
+        let mut https = axum_server::bind_rustls(addr, config).handle(handle.clone());
+        *https.http_builder() = http.builder();
+        let https = https.serve(app.into_make_service_with_connect_info::<SocketAddr>());
+
+        match plain {
+            Some((plain_addr, policy)) => {
+                tracing::info!("Plain HTTP listener on {} ({:?})", plain_addr, policy);
+                let mut plain = axum_server::bind(plain_addr).handle(handle);
+                *plain.http_builder() = http.builder();
+                let http = plain.serve(plain_app(policy, addr.port()).into_make_service());
+                tokio::try_join!(https, http)?;
+            }
+            None => https.await?,
+        }
+        Ok(())
+    }
+
+    /// Reload the configuration whenever either file's modification time changes
+    ///
+    /// A renewal that fails to load is logged and the previous certificate
+    /// stays in use.
+    async fn watch(self) {
+        let mut last = self.modified();
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let current = self.modified();
+            if current == last {
+                continue;
+            }
+            last = current;
+            match server_config(&self.cert, &self.key) {
+                Ok(config) => {
+                    self.config.reload_from_config(Arc::new(config));
+                    tracing::info!(cert = %self.cert.display(), "Reloaded TLS certificate");
+                }
+                Err(e) => tracing::warn!(
+                    error = %crate::logging::error_chain(&e),
+                    "Keeping previous TLS certificate"
+                ),
+            }
+        }
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        (mtime(&self.cert), mtime(&self.key))
+    }
+}
+
+/// Build a rustls server configuration from PEM certificate chain and key files
+fn server_config(cert: &Path, key: &Path) -> anyhow::Result<ServerConfig> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("Invalid certificate in {}: {}", cert.display(), e))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", cert.display());
+    }
+    let private_key = rustls_pemfile::private_key(&mut open(key)?)
+        .map_err(|e| anyhow::anyhow!("Invalid private key in {}: {}", key.display(), e))?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", key.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+        .map_err(|e| anyhow::anyhow!("{} does not match {}: {}", key.display(), cert.display(), e))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Router for the plain HTTP listener
+fn plain_app(policy: PlainHttp, https_port: u16) -> Router {
+    Router::new()
+        .fallback(plain_http)
+        .with_state((policy, https_port))
+}
+
+async fn plain_http(
+    State((policy, https_port)): State<(PlainHttp, u16)>,
+    headers: axum::http::HeaderMap,
+    uri: Uri,
+) -> Response {
+    let location = headers.get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|host| https_location(host, https_port, &uri));
+    match (policy, location) {
+        (PlainHttp::Redirect, Some(location)) => Redirect::permanent(&location).into_response(),
+        _ => ReachError::InvalidRequest("HTTPS required".into()).into_response(),
+    }
+}
+
+/// The `https://` URL for a plain request, swapping in the HTTPS port
+fn https_location(host: &str, https_port: u16, uri: &Uri) -> Option<String> {
+    let authority: axum::http::uri::Authority = host.parse().ok()?;
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Some(match https_port {
+        443 => format!("https://{}{}", authority.host(), path),
+        port => format!("https://{}:{}{}", authority.host(), port, path),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/tls").join(name)
+    }
+
+    #[test]
+    fn loads_matching_pair() {
+        assert!(server_config(&testdata("cert.pem"), &testdata("key.pem")).is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatched_key() {
+        let err = server_config(&testdata("cert.pem"), &testdata("other-key.pem")).unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_missing_file() {
+        let err = server_config(&testdata("missing.pem"), &testdata("key.pem")).unwrap_err();
+        assert!(err.to_string().contains("Cannot read"), "{}", err);
+    }
+
+    #[test]
+    fn redirect_keeps_path_and_swaps_port() {
+        let uri: Uri = "/lookup/did:key:z6Mk?x=1".parse().unwrap();
+
+        assert_eq!(
+            https_location("reach.example:8080", 8443, &uri).unwrap(),
+            "https://reach.example:8443/lookup/did:key:z6Mk?x=1"
+        );
+        assert_eq!(
+            https_location("reach.example", 443, &uri).unwrap(),
+            "https://reach.example/lookup/did:key:z6Mk?x=1"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Bounds on how long a registration may live
+//!
+//! Without them a client could register with an enormous TTL and leave an
+//! effectively permanent entry, or with zero and leave one that is expired
+//! on arrival.
+
+use crate::error::ReachError;
+
+/// Shortest TTL a registration gets by default (seconds)
+pub const DEFAULT_MIN_TTL_SECS: u64 = 60;
+
+/// Longest TTL a registration gets by default (seconds, 7 days)
+pub const DEFAULT_MAX_TTL_SECS: u64 = 7 * 24 * 3600;
+
+/// What to do with a requested TTL outside the bounds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TtlMode {
+    /// Register with the nearest bound
+    #[default]
+    Clamp,
+    /// Fail the registration with 400
+    Reject,
+}
+
+/// Allowed range for registration TTLs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtlPolicy {
+    pub min: u64,
+    pub max: u64,
+    pub mode: TtlMode,
+}
+
+impl Default for TtlPolicy {
+    fn default() -> Self {
+        Self {
+            min: DEFAULT_MIN_TTL_SECS,
+            max: DEFAULT_MAX_TTL_SECS,
+            mode: TtlMode::default(),
+        }
+    }
+}
+
+impl TtlPolicy {
+    pub fn new(min: u64, max: u64, mode: TtlMode) -> anyhow::Result<Self> {
+        if min == 0 {
+            anyhow::bail!("--min-ttl must be at least 1 second");
+        }
+        if min > max {
+            anyhow::bail!("--min-ttl ({}) is greater than --max-ttl ({})", min, max);
+        }
+        Ok(Self { min, max, mode })
+    }
+
+    /// The TTL a registration requesting `ttl` actually gets
+    pub fn apply(&self, ttl: u64) -> Result<u64, ReachError> {
+        if (self.min..=self.max).contains(&ttl) {
+            return Ok(ttl);
+        }
+        match self.mode {
+            TtlMode::Clamp => Ok(ttl.clamp(self.min, self.max)),
+            TtlMode::Reject => Err(ReachError::InvalidRequest(format!(
+                "ttl must be between {} and {} seconds",
+                self.min, self.max
+            ))),
+        }
+    }
+}
+
+/// When a registration made at `now` with `ttl` expires, saturating rather
+/// than overflowing
+pub fn expires_at(now: i64, ttl: u64) -> i64 {
+    now.saturating_add(i64::try_from(ttl).unwrap_or(i64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mode: TtlMode) -> TtlPolicy {
+        TtlPolicy::new(60, 3600, mode).unwrap()
+    }
+
+    #[test]
+    fn in_range_ttl_is_kept() {
+        for mode in [TtlMode::Clamp, TtlMode::Reject] {
+            assert_eq!(policy(mode).apply(60).unwrap(), 60);
+            assert_eq!(policy(mode).apply(3600).unwrap(), 3600);
+        }
+    }
+
+    #[test]
+    fn clamp_moves_ttl_to_nearest_bound() {
+        assert_eq!(policy(TtlMode::Clamp).apply(0).unwrap(), 60);
+        assert_eq!(policy(TtlMode::Clamp).apply(59).unwrap(), 60);
+        assert_eq!(policy(TtlMode::Clamp).apply(3601).unwrap(), 3600);
+        assert_eq!(policy(TtlMode::Clamp).apply(u64::MAX).unwrap(), 3600);
+    }
+
+    #[test]
+    fn reject_fails_outside_bounds() {
+        assert!(matches!(policy(TtlMode::Reject).apply(59), Err(ReachError::InvalidRequest(_))));
+        assert!(matches!(policy(TtlMode::Reject).apply(3601), Err(ReachError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn invalid_bounds_are_refused() {
+        assert!(TtlPolicy::new(0, 3600, TtlMode::Clamp).is_err());
+        assert!(TtlPolicy::new(3600, 60, TtlMode::Clamp).is_err());
+    }
+
+    #[test]
+    fn expiry_saturates() {
+        assert_eq!(expires_at(1_000, 60), 1_060);
+        assert_eq!(expires_at(1_000, u64::MAX), i64::MAX);
+        assert_eq!(expires_at(i64::MAX - 1, 60), i64::MAX);
+    }
+}
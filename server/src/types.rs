@@ -1,61 +1,554 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Handshake messages, exactly as `agent-id-handshake` puts them on the
+/// wire. The registry and its clients all (de)serialize these directly, so a
+/// field added to the protocol reaches everyone with the crate upgrade.
+pub use agent_id_handshake::messages::{Challenge, Hello, Proof, ProofAccepted};
+
+/// What a session may do: `read` sessions can check their own status but
+/// not change the registry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    #[default]
+    Write,
+}
+
+impl Scope {
+    /// Hello capability asking for this scope
+    pub fn capability(self) -> &'static str {
+        match self {
+            Scope::Read => "scope:read",
+            Scope::Write => "scope:write",
+        }
+    }
+}
+
+/// A challenge waiting for its proof, with the scope asked for in Hello and
+/// the registry protocols agreed on
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingChallenge {
+    #[serde(flatten)]
+    pub challenge: Challenge,
+    #[serde(default)]
+    pub scope: Scope,
+    #[serde(default)]
+    pub protocols: Vec<String>,
+}
 
 /// Registration request (authenticated by session)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct RegisterRequest {
     /// Where to reach this agent (any URI format)
+    #[schema(example = "wss://my-agent:8080")]
     pub endpoint: String,
-    /// Time-to-live in seconds (default: 3600)
+    /// Time-to-live in seconds (default: 3600); clamped to or checked
+    /// against the server's bounds
     #[serde(default = "default_ttl")]
+    #[schema(default = 3600)]
     pub ttl: u64,
+    /// Register on behalf of another DID instead of the session's
+    #[serde(default)]
+    pub delegation: Option<RegistrationDelegation>,
+    /// DID to register; another DID than the session's needs a `certificate`
+    /// from it
+    #[serde(default)]
+    #[schema(example = "did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F")]
+    pub did: Option<String>,
+    /// Standing delegation from `did` to the session's DID; not allowed with
+    /// a `delegation`
+    #[serde(default)]
+    pub certificate: Option<DelegationCertificate>,
+    /// Human-readable `name@domain` that resolves to this DID; unique among
+    /// live registrations, and dropped if a later registration omits it
+    #[serde(default)]
+    #[schema(example = "alice@example.com")]
+    pub handle: Option<String>,
+    /// Weight of `endpoint` among `endpoints` (default 1)
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// Region `endpoint` serves, for `?region=` lookups
+    #[serde(default)]
+    #[schema(example = "us-east")]
+    pub region: Option<String>,
+    /// Further endpoints sharing the agent's traffic with `endpoint`; not
+    /// allowed with a `delegation`
+    #[serde(default)]
+    pub endpoints: Vec<Endpoint>,
+}
+
+/// One of several endpoints an agent can be reached at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Endpoint {
+    #[schema(example = "wss://inbox-2.example.com")]
+    pub uri: String,
+    /// Relative share of `pick=weighted` lookups (default 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// Region the endpoint serves, from the server's allowlist if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "eu-west")]
+    pub region: Option<String>,
+    /// When the endpoint joined the registration (Unix seconds); kept while
+    /// later registrations list it again, and set by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub registered_at: Option<i64>,
 }
 
-fn default_ttl() -> u64 {
+/// Authority to register a sub-agent's endpoint from a controller's session
+///
+/// Both signatures cover the canonical delegation message (see the server
+/// README).
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RegistrationDelegation {
+    /// Sub-agent DID the entry is stored under
+    #[schema(example = "did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F")]
+    pub did: String,
+    /// When the signatures were made (Unix seconds, within 5 minutes of server time)
+    pub issued_at: i64,
+    /// Sub-agent's base64 Ed25519 signature
+    pub subject_signature: String,
+    /// Session DID's base64 Ed25519 signature
+    pub delegator_signature: String,
+}
+
+/// Standing permission from a worker agent for a controller to act for it
+///
+/// Unlike a [`RegistrationDelegation`], which both parties sign for one
+/// registration, the worker signs a certificate once and the controller
+/// presents it until it expires or the worker revokes it. The signature
+/// covers the canonical certificate message (see the server README).
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DelegationCertificate {
+    /// Worker DID granting the delegation
+    #[schema(example = "did:key:z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F")]
+    pub subject: String,
+    /// Controller DID allowed to act for the worker
+    pub delegate: String,
+    /// What the controller may do
+    pub operations: Vec<DelegatedOperation>,
+    /// Unix seconds
+    pub issued_at: i64,
+    /// Unix seconds
+    pub expires_at: i64,
+    /// Worker's base64 Ed25519 signature
+    pub signature: String,
+}
+
+/// Something a [`DelegationCertificate`] lets its delegate do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DelegatedOperation {
+    Register,
+    Deregister,
+}
+
+impl DelegatedOperation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DelegatedOperation::Register => "register",
+            DelegatedOperation::Deregister => "deregister",
+        }
+    }
+}
+
+/// Deregistration request; without a body the session's own entry goes
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct DeregisterRequest {
+    /// DID to deregister; another DID than the session's needs a `certificate`
+    #[serde(default)]
+    pub did: Option<String>,
+    /// Standing delegation from `did` to the session's DID
+    #[serde(default)]
+    pub certificate: Option<DelegationCertificate>,
+}
+
+/// Revocation of the delegations a worker gave a controller
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RevokeDelegationRequest {
+    /// Controller DID whose certificates from the session's DID stop working
+    pub delegate: String,
+}
+
+pub fn default_ttl() -> u64 {
     3600
 }
 
+/// Registration query parameters
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct RegisterParams {
+    /// Only register if the DID has no live registration
+    #[serde(default)]
+    pub if_absent: bool,
+    /// Validate the registration and report what would be stored, without
+    /// storing it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
 /// Registration response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct RegisterResponse {
     pub ok: bool,
     pub did: String,
     pub expires_at: i64,
+    /// TTL the registration got, after applying the server's bounds (seconds)
+    pub ttl: u64,
+    /// Version of the stored entry, for `If-Match` on the next registration;
+    /// 0 for a dry run
+    #[serde(default)]
+    pub version: u64,
+    /// Handle as stored, after normalization
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+    /// How the server changed the request, such as clamping the TTL
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjustments: Vec<String>,
+    /// Whether this was a dry run that stored nothing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+/// Registry metadata served at `/.well-known/agent-reach`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiscoveryDocument {
+    /// The registry's own DID, which signs handshake counter-proofs
+    pub registry_did: String,
+    /// Server version
+    #[schema(example = "0.1.0")]
+    pub version: String,
+    /// Handshake protocols the registry speaks
+    #[schema(example = json!(["aip/1.0"]))]
+    pub supported_protocols: Vec<String>,
+    /// Shortest registration TTL (seconds)
+    pub min_ttl: u64,
+    /// Longest registration TTL (seconds)
+    pub max_ttl: u64,
+    /// How long a session from `/proof` stays valid (seconds)
+    pub session_ttl: i64,
+    pub endpoints: DiscoveryEndpoints,
+}
+
+/// Paths of the core endpoints, relative to the registry's base URL
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiscoveryEndpoints {
+    pub hello: String,
+    pub proof: String,
+    pub register: String,
+    /// Path template; substitute the URL-encoded DID for `{did}`
+    pub lookup: String,
+    /// Path template; substitute the URL-encoded handle for `{handle}`
+    pub resolve: String,
+    /// WebSocket path template for following a DID's registration
+    pub watch: String,
+}
+
+/// Lookup query parameters
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct LookupParams {
+    /// Return a single endpoint chosen by weight instead of them all
+    pub pick: Option<Pick>,
+    /// Return the endpoint serving this region, or any endpoint if none does
+    pub region: Option<String>,
+    /// Order of `endpoints`; registration order when unset
+    pub sort: Option<Sort>,
+}
+
+/// How a lookup orders several endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// Most recently added endpoint first
+    Freshness,
+}
+
+/// How a lookup chooses among several endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Pick {
+    Weighted,
 }
 
 /// Lookup response
-#[derive(Debug, Serialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct LookupResponse {
     pub did: String,
     pub endpoint: String,
     pub status: AgentStatus,
     pub registered_at: i64,
     pub expires_at: i64,
+    /// Seconds left until `expires_at` by the registry's clock when it
+    /// answered, 0 once it's due
+    #[serde(default)]
+    pub ttl_remaining_secs: i64,
+    /// When the agent last completed a handshake (Unix seconds)
+    pub last_seen: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+    /// Every endpoint, `endpoint` first, when the agent registered several
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<Endpoint>,
+    /// Region of `endpoint`, when the lookup selected one that has a region
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Base URL of the peer registry the entry came from, when it isn't
+    /// registered here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The entry's version, for `If-Match` on `/register`
+    #[serde(default)]
+    pub version: u64,
+    /// Key behind a `did:key` DID; left out when the DID doesn't decode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<PublicKey>,
+    /// Algorithm of `public_key`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "Ed25519")]
+    pub key_type: Option<String>,
+}
+
+/// An agent's public key, decoded from its DID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PublicKey {
+    /// Raw key bytes, standard base64
+    pub base64: String,
+    /// Multicodec-prefixed key in base58btc multibase, as in the DID
+    #[schema(example = "z6MkkCZkbDtaJA44BnE36aczhKyrgTjixJu2uqHNPPLU5S6F")]
+    pub multibase: String,
+}
+
+impl PublicKey {
+    /// The key behind a `did:key` DID, or `None` for other DIDs
+    pub fn from_did(did: &str) -> Option<Self> {
+        let parsed: agent_id::Did = did.parse().ok()?;
+        let key = parsed.public_key().ok()?;
+        Some(Self {
+            base64: STANDARD.encode(key.to_bytes()),
+            multibase: parsed.key_id(),
+        })
+    }
+}
+
+impl From<RegistryEntry> for LookupResponse {
+    fn from(entry: RegistryEntry) -> Self {
+        let public_key = PublicKey::from_did(&entry.did);
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            ttl_remaining_secs: entry.expires_at.saturating_sub(now).max(0),
+            key_type: public_key.as_ref().map(|_| "Ed25519".to_string()),
+            public_key,
+            status: entry.status(),
+            did: entry.did,
+            endpoint: entry.endpoint,
+            registered_at: entry.registered_at,
+            expires_at: entry.expires_at,
+            last_seen: entry.last_seen,
+            handle: entry.handle,
+            endpoints: entry.endpoints,
+            region: None,
+            source: None,
+            version: entry.version,
+        }
+    }
+}
+
+/// Handle resolution query parameters
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ResolveParams {
+    /// Handle to resolve
+    #[param(example = "alice@example.com")]
+    pub handle: String,
+}
+
+/// Handle resolution response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResolveResponse {
+    /// The handle, normalized to lowercase
+    pub handle: String,
+    pub did: String,
+    pub agent: LookupResponse,
+}
+
+/// Agent listing query parameters
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct AgentsParams {
+    /// Live registrations to skip, in DID order
+    #[serde(default)]
+    pub offset: usize,
+    /// Most registrations to return (max 1000); all of them when unset
+    pub limit: Option<usize>,
+}
+
+/// Agent listing response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AgentsResponse {
+    /// Ordered by DID
+    pub agents: Vec<LookupResponse>,
+    /// Live registrations in all, for paging with `offset` and `limit`
+    pub total: usize,
+}
+
+/// Registration history response (newest first)
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryResponse {
+    pub did: String,
+    pub history: Vec<HistoryEntry>,
+}
+
+/// A past registration
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    pub endpoint: String,
+    pub registered_at: i64,
+    pub expires_at: i64,
+    /// Controller DID that registered the entry under a delegation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registered_by: Option<String>,
 }
 
 /// Deregistration response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct DeregisterResponse {
     pub ok: bool,
 }
 
+/// The caller's authenticated session
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SessionResponse {
+    pub did: String,
+    pub scope: Scope,
+    /// Registry protocols agreed on in the handshake
+    #[schema(example = json!(["reach/1"]))]
+    pub protocols: Vec<String>,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+    /// Unix timestamp (seconds)
+    pub expires_at: i64,
+    /// Seconds until `expires_at`
+    pub remaining_secs: i64,
+}
+
 /// Agent status
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum AgentStatus {
     Online,
     Expired,
 }
 
+/// Stored entries by status
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct StatusCounts {
+    /// Live entries whose agent completed a handshake recently
+    pub online: usize,
+    /// Live entries whose agent hasn't completed a handshake for a while
+    pub idle: usize,
+    /// Expired entries not yet removed by the sweeper
+    pub expired: usize,
+}
+
+impl StatusCounts {
+    /// Count one entry as of `now`; `idle_before` as in
+    /// [`crate::backend::RegistryBackend::status_counts`]
+    pub fn add(&mut self, entry: &RegistryEntry, now: i64, idle_before: i64) {
+        if entry.expires_at <= now {
+            self.expired += 1;
+        } else if entry.last_seen < idle_before {
+            self.idle += 1;
+        } else {
+            self.online += 1;
+        }
+    }
+}
+
 /// Internal registry entry
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(from = "StoredEntry")]
 pub struct RegistryEntry {
     pub did: String,
     pub endpoint: String,
     pub registered_at: i64,
     pub expires_at: i64,
+    /// When the agent last completed a handshake; defaults to `registered_at`
+    pub last_seen: i64,
+    /// Normalized `name@domain` handle, if registered with one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+    /// Every endpoint, `endpoint` first, when the agent registered several;
+    /// empty otherwise
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<Endpoint>,
+    /// Bumped by every registration of the DID; the backend assigns it
+    pub version: u64,
+    /// Controller DID that registered the entry under a delegation; kept in
+    /// history only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registered_by: Option<String>,
+}
+
+/// Serialized form of [`RegistryEntry`], accepting entries written before
+/// `last_seen` or `version` existed
+#[derive(Deserialize)]
+struct StoredEntry {
+    did: String,
+    endpoint: String,
+    registered_at: i64,
+    expires_at: i64,
+    last_seen: Option<i64>,
+    #[serde(default)]
+    handle: Option<String>,
+    #[serde(default)]
+    endpoints: Vec<Endpoint>,
+    #[serde(default)]
+    version: u64,
+    #[serde(default)]
+    registered_by: Option<String>,
+}
+
+impl From<StoredEntry> for RegistryEntry {
+    fn from(stored: StoredEntry) -> Self {
+        Self {
+            last_seen: stored.last_seen.unwrap_or(stored.registered_at),
+            did: stored.did,
+            endpoint: stored.endpoint,
+            registered_at: stored.registered_at,
+            expires_at: stored.expires_at,
+            handle: stored.handle,
+            endpoints: stored.endpoints,
+            version: stored.version,
+            registered_by: stored.registered_by,
+        }
+    }
 }
 
 impl RegistryEntry {
+    /// An entry with no handle, extra endpoints or delegation, last seen
+    /// when it was registered; the backend assigns `version`
+    pub fn new(did: &str, endpoint: &str, registered_at: i64, expires_at: i64) -> Self {
+        Self {
+            did: did.to_string(),
+            endpoint: endpoint.to_string(),
+            registered_at,
+            expires_at,
+            last_seen: registered_at,
+            handle: None,
+            endpoints: Vec::new(),
+            version: 0,
+            registered_by: None,
+        }
+    }
+
+    /// An entry registered now that expires `expires_in` seconds from now
+    #[cfg(test)]
+    pub(crate) fn for_test(did: &str, endpoint: &str, expires_in: i64) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self::new(did, endpoint, now, now + expires_in)
+    }
+
     pub fn status(&self) -> AgentStatus {
         let now = chrono::Utc::now().timestamp();
         if now > self.expires_at {
@@ -0,0 +1,364 @@
+//! Live lookups over WebSocket
+//!
+//! `GET /watch/:did` upgrades to a WebSocket that first sends the DID's
+//! registration as it stands, then one message for every change to it,
+//! recorded by the same [`ChangeLog`](crate::changes::ChangeLog) as
+//! `/changes`, which hands each watcher only its own DID's changes.
+//! Routing agents use it to follow a peer's endpoint without polling.
+//! Expiry is reported when the TTL passes rather than when the purge gets
+//! to the entry. Clients send nothing; closing the socket ends the watch.
+//! At most `--max-watchers` sockets are open at once.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    response::Response,
+};
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::changes::{Change, ChangeKind};
+use crate::error::ReachError;
+use crate::handlers::AppState;
+use crate::types::{AgentStatus, LookupResponse, RegistryEntry};
+
+/// Watch sockets open at once by default
+pub const DEFAULT_MAX_WATCHERS: usize = 1024;
+
+/// Caps the watch sockets open at once
+pub struct Watchers {
+    open: Arc<Semaphore>,
+}
+
+impl Watchers {
+    pub fn new(max: usize) -> Self {
+        Self { open: Arc::new(Semaphore::new(max)) }
+    }
+
+    /// A slot for one socket, held until it closes
+    fn admit(&self) -> Result<OwnedSemaphorePermit, ReachError> {
+        self.open.clone().try_acquire_owned().map_err(|_| ReachError::TooManyWatchers)
+    }
+}
+
+impl Default for Watchers {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_WATCHERS)
+    }
+}
+
+/// One message on a watch socket, sent as a JSON text frame
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum WatchEvent {
+    /// The registration as it stands: sent first, and again if the watcher
+    /// fell too far behind the change log to be sent each change
+    Current {
+        /// Whether the DID has a live registration
+        registered: bool,
+        /// The stored entry, also for one that expired but isn't purged yet
+        #[serde(skip_serializing_if = "Option::is_none")]
+        agent: Option<LookupResponse>,
+    },
+    /// The DID registered, refreshed, deregistered or expired
+    Change {
+        kind: ChangeKind,
+        /// Position in `/changes`; absent for an expiry reported when the
+        /// TTL passed
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        /// Unix timestamp (seconds) of the change
+        at: i64,
+        /// The entry stored, or the last one for `deregister` and `expire`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        agent: Option<LookupResponse>,
+    },
+}
+
+/// GET /watch/:did
+///
+/// Upgrade to a WebSocket streaming [`WatchEvent`]s for one DID. No
+/// authentication required.
+#[utoipa::path(
+    get,
+    path = "/watch/{did}",
+    tag = "lookup",
+    params(("did" = String, Path, description = "DID to watch (URL-encoded)")),
+    responses(
+        (status = 101, description = "Switched to a WebSocket carrying `WatchEvent` JSON messages", body = WatchEvent),
+        (status = 400, description = "Malformed DID, or not a WebSocket upgrade", body = crate::openapi::ErrorResponse),
+        (status = 503, description = "`--max-watchers` sockets are already open", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn watch(
+    State(state): State<AppState>,
+    Path(did): Path<String>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ReachError> {
+    let did = urlencoding::decode(&did)
+        .map_err(|_| ReachError::InvalidDid("not valid percent-encoding".into()))?
+        .into_owned();
+    crate::limits::check_did(&did)?;
+    crate::did::split(&did)?;
+    let slot = state.watchers.admit()?;
+
+    Ok(upgrade.on_upgrade(move |socket| async move {
+        let _slot = slot;
+        if let Err(e) = Watch::new(state, did.clone(), socket).run().await {
+            debug!(did = %did, error = %e, "Watch ended");
+        }
+    }))
+}
+
+/// What woke a watcher
+enum Wake {
+    /// A change to the DID, or `None` if the watcher fell behind
+    Changed(Option<Change>),
+    Expired,
+    /// The client sent a message, which is ignored
+    Received,
+    Closed,
+}
+
+/// One watch socket and what it has told the client
+struct Watch {
+    state: AppState,
+    did: String,
+    socket: WebSocket,
+    /// The live entry, whose expiry is still to be reported
+    live: Option<RegistryEntry>,
+    /// An expiry went out when the TTL passed, so the purge's is skipped
+    expiry_sent: bool,
+}
+
+impl Watch {
+    fn new(state: AppState, did: String, socket: WebSocket) -> Self {
+        Self { state, did, socket, live: None, expiry_sent: false }
+    }
+
+    async fn run(mut self) -> Result<(), axum::Error> {
+        // Subscribed before the first read, so a change in between is sent
+        let mut changes = self.state.changes.watch_did(&self.did);
+        self.send_current().await?;
+
+        loop {
+            let deadline = self.live.as_ref().map(|entry| entry.expires_at);
+            let wake = tokio::select! {
+                change = changes.recv() => Wake::Changed(change),
+                message = self.socket.recv() => match message {
+                    None | Some(Err(_)) | Some(Ok(Message::Close(_))) => Wake::Closed,
+                    Some(Ok(_)) => Wake::Received,
+                },
+                _ = until_expiry(deadline) => Wake::Expired,
+            };
+            match wake {
+                Wake::Changed(Some(change)) => self.send_change(change).await?,
+                Wake::Changed(None) => self.send_current().await?,
+                Wake::Expired => self.send_expiry().await?,
+                Wake::Received => {}
+                Wake::Closed => return Ok(()),
+            }
+        }
+    }
+
+    async fn send_current(&mut self) -> Result<(), axum::Error> {
+        let entry = self.state.registry.lookup(&self.did).await.map_err(axum::Error::new)?;
+        let registered = entry.as_ref().is_some_and(|entry| entry.status() == AgentStatus::Online);
+        self.live = entry.clone().filter(|_| registered);
+        self.expiry_sent = entry.is_some() && !registered;
+        self.send(WatchEvent::Current {
+            registered,
+            agent: entry.map(LookupResponse::from),
+        })
+        .await
+    }
+
+    async fn send_change(&mut self, change: Change) -> Result<(), axum::Error> {
+        match change.kind {
+            ChangeKind::Register | ChangeKind::Refresh => {
+                self.live = change.entry.clone();
+                self.expiry_sent = false;
+            }
+            ChangeKind::Deregister => self.live = None,
+            ChangeKind::Expire if self.expiry_sent => {
+                self.expiry_sent = false;
+                return Ok(());
+            }
+            ChangeKind::Expire => self.live = None,
+        }
+        self.send(WatchEvent::Change {
+            kind: change.kind,
+            seq: Some(change.seq),
+            at: change.at,
+            agent: change.entry.map(LookupResponse::from),
+        })
+        .await
+    }
+
+    async fn send_expiry(&mut self) -> Result<(), axum::Error> {
+        let Some(entry) = self.live.take() else {
+            return Ok(());
+        };
+        self.expiry_sent = true;
+        self.send(WatchEvent::Change {
+            kind: ChangeKind::Expire,
+            seq: None,
+            at: entry.expires_at,
+            agent: Some(entry.into()),
+        })
+        .await
+    }
+
+    async fn send(&mut self, event: WatchEvent) -> Result<(), axum::Error> {
+        let text = serde_json::to_string(&event).map_err(axum::Error::new)?;
+        self.socket.send(Message::Text(text)).await
+    }
+}
+
+/// Sleep until an entry expiring at `expires_at` counts as expired, or
+/// forever without one
+async fn until_expiry(expires_at: Option<i64>) {
+    match expires_at {
+        Some(expires_at) => {
+            let remaining = expires_at + 1 - chrono::Utc::now().timestamp();
+            tokio::time::sleep(Duration::from_secs(remaining.max(0) as u64)).await
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{routing::get, Router};
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite;
+
+    use super::*;
+    use crate::changes::{ChangeLog, RecordingRegistry};
+    use crate::registry::Registry;
+
+    type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// Serve `/watch` on an ephemeral port
+    async fn serve() -> (AppState, String) {
+        let changes = Arc::new(ChangeLog::default());
+        let state = AppState {
+            registry: Arc::new(RecordingRegistry::new(Arc::new(Registry::new()), changes.clone())),
+            changes,
+            ..AppState::for_tests()
+        };
+        let app = Router::new().route("/watch/:did", get(watch)).with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (state, url)
+    }
+
+    async fn next(socket: &mut Socket) -> serde_json::Value {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.expect("event sent");
+        match message.unwrap().unwrap() {
+            tungstenite::Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn watchers_follow_one_did() {
+        let (state, url) = serve().await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("{}/watch/did:key:a", url)).await.unwrap();
+        assert_eq!(next(&mut socket).await, serde_json::json!({ "event": "current", "registered": false }));
+
+        // Registering after the watch started is pushed; other DIDs aren't
+        state.registry.register(RegistryEntry::for_test("did:key:b", "wss://b", 3600)).await.unwrap();
+        state.registry.register(RegistryEntry::for_test("did:key:a", "wss://a", 3600)).await.unwrap();
+        let event = next(&mut socket).await;
+        assert_eq!(event["event"], "change");
+        assert_eq!(event["kind"], "register");
+        assert_eq!(event["seq"], 2);
+        assert_eq!(event["agent"]["endpoint"], "wss://a");
+
+        state.registry.register(RegistryEntry::for_test("did:key:a", "wss://moved", 3600)).await.unwrap();
+        let event = next(&mut socket).await;
+        assert_eq!((event["kind"].as_str(), event["agent"]["endpoint"].as_str()), (Some("refresh"), Some("wss://moved")));
+
+        state.registry.deregister("did:key:a").await.unwrap();
+        assert_eq!(next(&mut socket).await["kind"], "deregister");
+
+        // A late watcher starts from the registration as it stands
+        state.registry.register(RegistryEntry::for_test("did:key:a", "wss://back", 3600)).await.unwrap();
+        assert_eq!(next(&mut socket).await["kind"], "register");
+        let (mut late, _) = tokio_tungstenite::connect_async(format!("{}/watch/did%3Akey%3Aa", url)).await.unwrap();
+        let event = next(&mut late).await;
+        assert_eq!(event["registered"], true);
+        assert_eq!(event["agent"]["endpoint"], "wss://back");
+    }
+
+    #[tokio::test]
+    async fn expiry_is_sent_when_the_ttl_passes() {
+        let (state, url) = serve().await;
+        state.registry.register(RegistryEntry::for_test("did:key:a", "wss://a", 1)).await.unwrap();
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("{}/watch/did:key:a", url)).await.unwrap();
+        assert_eq!(next(&mut socket).await["registered"], true);
+
+        let event = next(&mut socket).await;
+        assert_eq!(event["kind"], "expire");
+        assert!(event.get("seq").is_none(), "{}", event);
+        assert_eq!(event["agent"]["endpoint"], "wss://a");
+
+        // The purge doesn't report it again
+        state.registry.purge_expired().await.unwrap();
+        state.registry.register(RegistryEntry::for_test("did:key:a", "wss://again", 3600)).await.unwrap();
+        assert_eq!(next(&mut socket).await["kind"], "register");
+    }
+
+    #[tokio::test]
+    async fn open_watches_are_capped() {
+        let state = AppState {
+            watchers: Arc::new(Watchers::new(1)),
+            ..AppState::for_tests()
+        };
+        let app = Router::new().route("/watch/:did", get(watch)).with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/watch/did:key:a", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (mut first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        next(&mut first).await;
+        let err = tokio_tungstenite::connect_async(&url).await.unwrap_err();
+        let tungstenite::Error::Http(response) = err else {
+            panic!("expected an HTTP refusal, got {:?}", err);
+        };
+        assert_eq!(response.status(), 503);
+
+        // Closing a watch frees its slot
+        first.close(None).await.unwrap();
+        let reopened = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if tokio_tungstenite::connect_async(&url).await.is_ok() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        reopened.await.expect("slot freed");
+    }
+
+    #[tokio::test]
+    async fn malformed_dids_are_refused_before_upgrading() {
+        let (_, url) = serve().await;
+        let err = tokio_tungstenite::connect_async(format!("{}/watch/not-a-did", url)).await.unwrap_err();
+        let tungstenite::Error::Http(response) = err else {
+            panic!("expected an HTTP refusal, got {:?}", err);
+        };
+        assert_eq!(response.status(), 400);
+    }
+}
@@ -0,0 +1,290 @@
+//! WebFinger for registered agents
+//!
+//! `GET /.well-known/webfinger?resource=` (RFC 7033) answers for registered
+//! DIDs and for `acct:name@domain` handles on this registry's own domain, so
+//! discovery tooling that already speaks WebFinger can find agents. Only
+//! served with `--public-url`, which supplies that domain and the base of
+//! the links in each document.
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::api_version;
+use crate::did_document::DID_JSON;
+use crate::error::ReachError;
+use crate::handlers::AppState;
+use crate::types::{AgentStatus, RegistryEntry};
+
+/// Media type of a JSON Resource Descriptor
+pub const JRD_JSON: &str = "application/jrd+json";
+
+/// Link relation for the agent's DID
+const DID_REL: &str = "urn:agent-reach:did";
+
+/// Link relation for each registered endpoint
+const ENDPOINT_REL: &str = "urn:agent-reach:endpoint";
+
+/// Where this registry is reachable from outside
+#[derive(Debug, Clone)]
+pub struct PublicUrl {
+    /// Base URL without a trailing slash
+    pub base: String,
+    /// Lowercase host, without a port
+    pub domain: String,
+}
+
+impl PublicUrl {
+    /// Parse an `http(s)://host[:port][/path]` base URL
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        let base = url.trim().trim_end_matches('/');
+        let rest = base
+            .strip_prefix("https://")
+            .or_else(|| base.strip_prefix("http://"))
+            .ok_or_else(|| anyhow::anyhow!("Public URL must start with https:// or http://: {}", url))?;
+        let authority = rest.split('/').next().unwrap_or_default();
+        let domain = authority.rsplit_once(':').map_or(authority, |(host, _)| host);
+        if domain.is_empty() || authority.contains(['@', '?', '#']) {
+            anyhow::bail!("Public URL has no valid host: {}", url);
+        }
+        Ok(Self {
+            base: base.to_string(),
+            domain: domain.to_ascii_lowercase(),
+        })
+    }
+}
+
+/// WebFinger query parameters
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct WebFingerParams {
+    /// `acct:name@domain` or a DID
+    #[param(example = "acct:alice@reach.example")]
+    pub resource: Option<String>,
+}
+
+/// JSON Resource Descriptor for a registered agent
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Jrd {
+    /// The requested resource
+    #[schema(example = "acct:alice@reach.example")]
+    pub subject: String,
+    /// The agent's DID and handle, other than the subject
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    pub links: Vec<Link>,
+    /// Why the resource couldn't be described; only on errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One link in a JRD
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Link {
+    #[schema(example = "urn:agent-reach:endpoint")]
+    pub rel: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    pub href: String,
+}
+
+impl Jrd {
+    fn describe(subject: String, entry: RegistryEntry, public: &PublicUrl) -> Self {
+        let did = entry.did;
+        let aliases = std::iter::once(did.clone())
+            .chain(entry.handle.map(|handle| format!("acct:{}", handle)))
+            .filter(|alias| *alias != subject)
+            .collect();
+
+        let mut links = vec![
+            Link {
+                rel: DID_REL.to_string(),
+                type_: None,
+                href: did.clone(),
+            },
+            Link {
+                rel: "self".to_string(),
+                type_: Some(DID_JSON.to_string()),
+                href: format!("{}{}/did/{}", public.base, api_version::PREFIX, urlencoding::encode(&did)),
+            },
+        ];
+        let uris: Vec<String> = if entry.endpoints.is_empty() {
+            vec![entry.endpoint]
+        } else {
+            entry.endpoints.into_iter().map(|endpoint| endpoint.uri).collect()
+        };
+        links.extend(uris.into_iter().map(|uri| Link {
+            rel: ENDPOINT_REL.to_string(),
+            type_: None,
+            href: uri,
+        }));
+
+        Self {
+            subject,
+            aliases,
+            links,
+            error: None,
+        }
+    }
+}
+
+/// GET /.well-known/webfinger?resource=
+///
+/// The agent registered under an `acct:` handle on this registry's domain,
+/// or under a DID, as a JRD. No authentication required. Only served with
+/// `--public-url`.
+#[utoipa::path(
+    get,
+    path = "/.well-known/webfinger",
+    tag = "lookup",
+    params(WebFingerParams),
+    responses(
+        (status = 200, description = "Agent found", body = Jrd, content_type = "application/jrd+json"),
+        (status = 400, description = "Missing or unsupported resource", body = Jrd, content_type = "application/jrd+json"),
+        (status = 404, description = "No live registration for the resource", body = Jrd, content_type = "application/jrd+json"),
+    )
+)]
+pub async fn webfinger(State(state): State<AppState>, Query(params): Query<WebFingerParams>) -> Response {
+    let subject = params.resource.unwrap_or_default();
+    let (status, jrd) = match describe(&state, &subject).await {
+        Ok(jrd) => (StatusCode::OK, jrd),
+        Err(e) => {
+            let status = match e {
+                ReachError::InvalidRequest(_) | ReachError::InvalidDid(_) | ReachError::UnsupportedDidMethod(_) => StatusCode::BAD_REQUEST,
+                ReachError::NotFound | ReachError::Expired(_) => StatusCode::NOT_FOUND,
+                _ => return e.into_response(),
+            };
+            let jrd = Jrd {
+                subject,
+                aliases: Vec::new(),
+                links: Vec::new(),
+                error: Some(e.to_string()),
+            };
+            (status, jrd)
+        }
+    };
+    (status, [(header::CONTENT_TYPE, JRD_JSON)], Json(jrd)).into_response()
+}
+
+async fn describe(state: &AppState, resource: &str) -> Result<Jrd, ReachError> {
+    let public = state.public_url.as_deref().ok_or(ReachError::NotFound)?;
+
+    let (subject, entry) = if let Some(handle) = resource.strip_prefix("acct:") {
+        let handle = crate::handle::normalize(handle)?;
+        // Handles on other domains are theirs to answer for
+        if handle.rsplit_once('@').map(|(_, domain)| domain) != Some(public.domain.as_str()) {
+            return Err(ReachError::NotFound);
+        }
+        let entry = state.registry.resolve_handle(&handle).await?;
+        (format!("acct:{}", handle), entry)
+    } else if resource.starts_with("did:") {
+        (resource.to_string(), state.registry.lookup(resource).await?)
+    } else if resource.is_empty() {
+        return Err(ReachError::InvalidRequest("resource is required".into()));
+    } else {
+        return Err(ReachError::InvalidRequest("resource must be an acct: URI or a DID".into()));
+    };
+
+    let entry = entry.ok_or(ReachError::NotFound)?;
+    if entry.status() == AgentStatus::Expired {
+        return Err(ReachError::Expired(entry.expires_at));
+    }
+    Ok(Jrd::describe(subject, entry, public))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::to_bytes;
+
+    use super::*;
+
+    fn state() -> AppState {
+        AppState {
+            public_url: Some(Arc::new(PublicUrl::parse("https://Reach.example/").unwrap())),
+            ..AppState::for_tests()
+        }
+    }
+
+    async fn register(state: &AppState, did: &str, handle: Option<&str>, ttl: i64) {
+        let now = chrono::Utc::now().timestamp();
+        state
+            .registry
+            .register(RegistryEntry {
+                registered_at: now - 10,
+                handle: handle.map(str::to_string),
+                ..RegistryEntry::for_test(did, "wss://agent.example", ttl)
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn finger(state: &AppState, resource: &str) -> (StatusCode, Jrd) {
+        let params = WebFingerParams { resource: Some(resource.to_string()) };
+        let response = webfinger(State(state.clone()), Query(params)).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], JRD_JSON);
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn href<'a>(jrd: &'a Jrd, rel: &str) -> Option<&'a str> {
+        jrd.links.iter().find(|link| link.rel == rel).map(|link| link.href.as_str())
+    }
+
+    #[tokio::test]
+    async fn handles_on_our_domain_are_described() {
+        let state = state();
+        register(&state, "did:key:z6Mkalice", Some("alice@reach.example"), 3600).await;
+
+        let (status, jrd) = finger(&state, "acct:Alice@reach.example").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(jrd.subject, "acct:alice@reach.example");
+        assert_eq!(jrd.aliases, ["did:key:z6Mkalice"]);
+        assert_eq!(href(&jrd, DID_REL), Some("did:key:z6Mkalice"));
+        assert_eq!(href(&jrd, ENDPOINT_REL), Some("wss://agent.example"));
+        assert_eq!(href(&jrd, "self"), Some("https://Reach.example/v1/did/did%3Akey%3Az6Mkalice"));
+    }
+
+    #[tokio::test]
+    async fn dids_are_described() {
+        let state = state();
+        register(&state, "did:key:z6Mkbob", Some("bob@reach.example"), 3600).await;
+
+        let (status, jrd) = finger(&state, "did:key:z6Mkbob").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(jrd.subject, "did:key:z6Mkbob");
+        assert_eq!(jrd.aliases, ["acct:bob@reach.example"]);
+        assert_eq!(href(&jrd, ENDPOINT_REL), Some("wss://agent.example"));
+    }
+
+    #[tokio::test]
+    async fn unknown_resources_are_jrd_errors() {
+        let state = state();
+        register(&state, "did:key:z6Mkcarol", Some("carol@elsewhere.example"), 3600).await;
+        register(&state, "did:key:z6Mkgone", None, -1).await;
+
+        for resource in ["acct:nobody@reach.example", "acct:carol@elsewhere.example", "did:key:z6Mkgone"] {
+            let (status, jrd) = finger(&state, resource).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", resource);
+            assert_eq!(jrd.subject, resource);
+            assert!(jrd.links.is_empty());
+            assert!(jrd.error.is_some());
+        }
+        let (status, _) = finger(&state, "https://reach.example/alice").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn public_urls_give_the_domain() {
+        assert_eq!(PublicUrl::parse("https://Reach.Example:8443/base/").unwrap().domain, "reach.example");
+        assert_eq!(PublicUrl::parse("http://localhost:3001").unwrap().base, "http://localhost:3001");
+        assert!(PublicUrl::parse("reach.example").is_err());
+        assert!(PublicUrl::parse("https://").is_err());
+    }
+}